@@ -0,0 +1,11416 @@
+// Copyright 2020 Parity Technologies (UK) Ltd.
+// This file is part of Polkadot.
+
+// Polkadot is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// Polkadot is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with Polkadot.  If not, see <http://www.gnu.org/licenses/>.
+
+//! The bitfield distribution subsystem.
+//!
+//! In case this node is a validator, gossips its own signed availability bitfield
+//! for a particular relay parent.
+//! Independently of that, gossips on received messages from peers to other interested peers.
+
+use parity_scale_codec::{Compact, Decode, DecodeAll, Encode};
+use futures::{channel::{mpsc, oneshot}, future::{self, FusedFuture}, select, FutureExt, StreamExt};
+use futures_timer::Delay;
+
+use log::{debug, trace, warn};
+use rand::{seq::SliceRandom, Rng, SeedableRng};
+use rand_chacha::ChaChaRng;
+use polkadot_subsystem::messages::{
+	AllMessages, AvailabilitySummary, BitfieldDistributionMessage, BitfieldSource, CoverageReport, DistributeError,
+	NetworkBridgeEvent, NetworkBridgeMessage, ObservedRole, PeerId, ProvisionableData, ProvisionerMessage,
+	ReputationChange, RuntimeApiMessage, RuntimeApiRequest, StatusReport, ValidationVerdict,
+};
+use polkadot_subsystem::{
+	ActiveLeavesUpdate, FromOverseer, OverseerSignal, SpawnedSubsystem, Subsystem, SubsystemContext,
+};
+use node_primitives::{ProtocolId, View};
+use polkadot_primitives::v1::{
+	AvailabilityBitfield, Hash, SessionIndex, SignedAvailabilityBitfield, SigningContext, ValidatorId, ValidatorIndex,
+};
+use prometheus_endpoint::{self as prometheus, PrometheusError, Registry};
+
+use std::collections::{hash_map::Entry, HashMap, HashSet, VecDeque};
+use std::convert::TryFrom;
+use std::io::{self, Read};
+use sp_core::hashing::blake2_256;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+const COST_SIGNATURE_INVALID: ReputationChange =
+	ReputationChange::new(-100, "Bitfield signature invalid");
+const COST_VALIDATOR_INDEX_INVALID: ReputationChange =
+	ReputationChange::new(-100, "Bitfield validator index invalid");
+const COST_MISSING_PEER_SESSION_KEY: ReputationChange =
+	ReputationChange::new(-133, "Missing peer session key");
+const COST_NOT_INTERESTED: ReputationChange =
+	ReputationChange::new(-51, "Not interested in that parent hash");
+const COST_RELAY_PARENT_UNKNOWN_BUT_IN_VIEW: ReputationChange =
+	ReputationChange::new(-5, "Too many bitfields of a relay parent in our view we don't work on yet");
+const COST_MESSAGE_NOT_DECODABLE: ReputationChange =
+	ReputationChange::new(-100, "Bitfield message not decodable");
+const COST_UNDECODABLE_STREAM: ReputationChange =
+	ReputationChange::new_fatal("Bitfield messages keep being not decodable");
+const COST_UNSUPPORTED_VERSION: ReputationChange =
+	ReputationChange::new(-20, "Bitfield message of an unsupported protocol version");
+const COST_OVERSIZED_MESSAGE: ReputationChange =
+	ReputationChange::new(-100, "Bitfield message exceeds the maximum size");
+const COST_INCONSISTENT_BITFIELD_LENGTH: ReputationChange =
+	ReputationChange::new(-100, "Bitfield length differs from the others of the relay parent");
+const COST_WRONG_SESSION: ReputationChange =
+	ReputationChange::new(-100, "Bitfield signed for a different session");
+const COST_CONTEXT_MISMATCH: ReputationChange =
+	ReputationChange::new(-100, "Bitfield of a relay parent its signing context is not of");
+const COST_EQUIVOCATION: ReputationChange =
+	ReputationChange::new(-500, "Validator signed two different bitfields for the same relay parent");
+const COST_REPEATED_EQUIVOCATION: ReputationChange =
+	ReputationChange::new(-20, "Bitfield of a validator which equivocated already");
+const COST_APPARENT_FLOOD: ReputationChange =
+	ReputationChange::new(-500, "Peer appears to be flooding us with bitfields");
+const COST_VIEW_CHANGE_NOT_CONNECTED: ReputationChange =
+	ReputationChange::new(-10, "View change of a peer which is not connected");
+const COST_USELESS_VIEW_CHANGE: ReputationChange =
+	ReputationChange::new(-10, "Repeated view changes to relay parents we don't know");
+const COST_VIEW_FLAPPING: ReputationChange =
+	ReputationChange::new(-50, "Peer changes its view too often");
+const COST_VIEW_BEYOND_QUOTA: ReputationChange =
+	ReputationChange::new(-5, "Peer view holds more relay parents than we track");
+const COST_OVERSIZED_VIEW: ReputationChange =
+	ReputationChange::new(-200, "Peer view holds absurdly many relay parents");
+const COST_NOT_AUTHORITY: ReputationChange =
+	ReputationChange::new(-10, "Bitfield of a peer not in the authority set");
+
+const GAIN_VALID_MESSAGE_FIRST: ReputationChange =
+	ReputationChange::new(15, "Valid message with new information");
+const GAIN_VALID_MESSAGE: ReputationChange =
+	ReputationChange::new(10, "Valid message");
+const GAIN_USEFUL_VIEW_CHANGE: ReputationChange =
+	ReputationChange::new(5, "View change to relay parents we work on");
+const GAIN_GOOD_STANDING: ReputationChange =
+	ReputationChange::new(5, "Valid bitfields and no misbehaviour for a while");
+
+/// Reputation changes applied to peers depending on the bitfield gossip they send us.
+///
+/// The `Default` implementation yields the values used on live networks.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ReputationConfig {
+	/// Applied for a bitfield with an invalid signature.
+	pub cost_signature_invalid: ReputationChange,
+	/// Applied for a bitfield signed by a validator index outside of the validator set.
+	pub cost_validator_index_invalid: ReputationChange,
+	/// Applied for a bitfield of a relay parent with an empty validator set.
+	pub cost_missing_peer_session_key: ReputationChange,
+	/// Applied for a bitfield of a relay parent we are not interested in.
+	pub cost_not_interested: ReputationChange,
+	/// Applied for a bitfield of a relay parent in our view we were not told to work on yet,
+	/// beyond `Config::max_early_messages` of them. This is rather our fault than the peer's.
+	pub cost_relay_parent_unknown_but_in_view: ReputationChange,
+	/// Applied for a message which can not be decoded.
+	pub cost_message_not_decodable: ReputationChange,
+	/// Applied instead of `cost_message_not_decodable` for every message beyond
+	/// `Config::max_undecodable_messages` consecutive ones which can not be decoded.
+	/// Low enough for the peer to be disconnected, as we can't ask the network bridge to.
+	pub cost_undecodable_stream: ReputationChange,
+	/// Applied for a message of a protocol version we don't support.
+	pub cost_unsupported_version: ReputationChange,
+	/// Applied for a message larger than `Config::max_message_size`.
+	pub cost_oversized_message: ReputationChange,
+	/// Applied for a bitfield signed for the session before the one of its relay parent.
+	pub cost_wrong_session: ReputationChange,
+	/// Applied for a bitfield of another length than the first one of its relay parent.
+	pub cost_inconsistent_bitfield_length: ReputationChange,
+	/// Applied for a bitfield of a relay parent whose signing context is of another one.
+	pub cost_context_mismatch: ReputationChange,
+	/// Applied for relaying a second, different bitfield of the same validator.
+	pub cost_equivocation: ReputationChange,
+	/// Applied for any further bitfield of a validator which equivocated for the relay parent.
+	pub cost_repeated_equivocation: ReputationChange,
+	/// Applied for every message of a peer exceeding the flood threshold.
+	pub cost_apparent_flood: ReputationChange,
+	/// Applied for a view change of a peer we never saw connect.
+	pub cost_view_change_not_connected: ReputationChange,
+	/// Applied for every view change to only unknown relay parents beyond
+	/// `Config::max_useless_view_changes` consecutive ones.
+	pub cost_useless_view_change: ReputationChange,
+	/// Applied for every view change of a peer beyond `Config::max_view_changes`
+	/// within `Config::view_change_window`.
+	pub cost_view_flapping: ReputationChange,
+	/// Applied for a view change to more than `Config::max_peer_view_len` relay parents.
+	pub cost_view_beyond_quota: ReputationChange,
+	/// Applied for a view change to more than `Config::max_acceptable_view_len` relay parents.
+	pub cost_oversized_view: ReputationChange,
+	/// Applied for a bitfield of a peer which is no current authority,
+	/// if only those may submit bitfields.
+	pub cost_not_authority: ReputationChange,
+	/// Applied for a valid bitfield we did not know about yet.
+	pub gain_valid_message_first: ReputationChange,
+	/// Applied once per peer for an exact copy of a valid bitfield we knew about already.
+	pub gain_valid_message: ReputationChange,
+	/// Applied for a view change to a relay parent we work on, once per peer and relay parent.
+	pub gain_useful_view_change: ReputationChange,
+	/// Applied every `Config::good_standing_interval` to a peer which delivered valid bitfields
+	/// and earned no penalty within it, to counteract the decay of its reputation.
+	pub gain_good_standing: ReputationChange,
+}
+
+impl Default for ReputationConfig {
+	fn default() -> Self {
+		Self {
+			cost_signature_invalid: COST_SIGNATURE_INVALID,
+			cost_validator_index_invalid: COST_VALIDATOR_INDEX_INVALID,
+			cost_missing_peer_session_key: COST_MISSING_PEER_SESSION_KEY,
+			cost_not_interested: COST_NOT_INTERESTED,
+			cost_relay_parent_unknown_but_in_view: COST_RELAY_PARENT_UNKNOWN_BUT_IN_VIEW,
+			cost_message_not_decodable: COST_MESSAGE_NOT_DECODABLE,
+			cost_undecodable_stream: COST_UNDECODABLE_STREAM,
+			cost_unsupported_version: COST_UNSUPPORTED_VERSION,
+			cost_oversized_message: COST_OVERSIZED_MESSAGE,
+			cost_wrong_session: COST_WRONG_SESSION,
+			cost_inconsistent_bitfield_length: COST_INCONSISTENT_BITFIELD_LENGTH,
+			cost_context_mismatch: COST_CONTEXT_MISMATCH,
+			cost_equivocation: COST_EQUIVOCATION,
+			cost_repeated_equivocation: COST_REPEATED_EQUIVOCATION,
+			cost_apparent_flood: COST_APPARENT_FLOOD,
+			cost_view_change_not_connected: COST_VIEW_CHANGE_NOT_CONNECTED,
+			cost_useless_view_change: COST_USELESS_VIEW_CHANGE,
+			cost_view_flapping: COST_VIEW_FLAPPING,
+			cost_view_beyond_quota: COST_VIEW_BEYOND_QUOTA,
+			cost_oversized_view: COST_OVERSIZED_VIEW,
+			cost_not_authority: COST_NOT_AUTHORITY,
+			gain_valid_message_first: GAIN_VALID_MESSAGE_FIRST,
+			gain_valid_message: GAIN_VALID_MESSAGE,
+			gain_useful_view_change: GAIN_USEFUL_VIEW_CHANGE,
+			gain_good_standing: GAIN_GOOD_STANDING,
+		}
+	}
+}
+
+/// Tunable parameters of the bitfield distribution subsystem.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Config {
+	/// The length of the sliding window in which the messages of a peer are counted.
+	pub flood_window: Duration,
+	/// The number of messages a peer may send within `flood_window`
+	/// before any further message is considered a flood.
+	pub flood_threshold: usize,
+	/// The number of consecutive view changes of a peer to only relay parents
+	/// we don't know that are tolerated before it gets penalized.
+	pub max_useless_view_changes: usize,
+	/// The length of the sliding window in which the view changes of a peer are counted.
+	pub view_change_window: Duration,
+	/// The number of view changes a peer may do within `view_change_window`
+	/// before any further one is considered flapping.
+	pub max_view_changes: usize,
+	/// The maximum number of peers tracked. Beyond that, the least recently active peer
+	/// is untracked until it sends a view change or message again.
+	pub max_peers: usize,
+	/// The maximum number of relay parents of a peer's view we track, any beyond are ignored.
+	pub max_peer_view_len: usize,
+	/// The maximum number of relay parents of a peer's view considered at all. A view beyond
+	/// that is ignored altogether, rather than capped at `max_peer_view_len`.
+	pub max_acceptable_view_len: usize,
+	/// The interval in which our own bitfields are sent to interested peers
+	/// which did not get them yet.
+	pub regossip_interval: Duration,
+	/// The protocol identifier to gossip bitfields under.
+	pub protocol_id: ProtocolId,
+	/// For how long after `StopWork` bitfields of a relay parent are
+	/// ignored instead of penalized, as peers may still be propagating them.
+	/// A `StartWork` of the relay parent within it is taken for a reordered one and ignored as well.
+	pub stopped_grace_period: Duration,
+	/// The maximum number of relay parents worked on at the same time.
+	///
+	/// Beyond that, the least recently active relay parent is dropped
+	/// as if the overseer had sent `StopWork` for it.
+	pub max_relay_parents: usize,
+	/// To how many of the interested peers a bitfield is relayed.
+	///
+	/// All of them by default, a bounded fan-out such as `FanOut::Sqrt` saves bandwidth
+	/// on large validator sets but leaves full propagation to the relays of other peers.
+	pub fan_out: FanOut,
+	/// The seed of the randomness used to pick the peers a bitfield is relayed to.
+	///
+	/// Drawn from the OS entropy source if `None`, which should
+	/// only be overridden to get reproducible results in tests.
+	pub rng_seed: Option<[u8; 32]>,
+	/// The maximum size of an encoded message of a peer, larger ones are not decoded.
+	pub max_message_size: usize,
+	/// The number of consecutive messages of a peer which can not be decoded that are
+	/// tolerated before it is considered to only send garbage.
+	pub max_undecodable_messages: usize,
+	/// The maximum number of bitfields relayed to the network bridge while handling
+	/// a single message, any further ones are deferred to the following iterations.
+	pub max_sends_per_iteration: usize,
+	/// The maximum number of deferred bitfield relays. Beyond that, the network bridge
+	/// can't keep up and the oldest one is dropped, its receivers are sent the bitfields of
+	/// its relay parent again along with the next catch up batches.
+	pub max_deferred_sends: usize,
+	/// How long to wait before handing bitfields buffered for a lagging provisioner over
+	/// again. Bitfields are not handed over while the queue to the overseer is full, so gossip
+	/// goes on regardless.
+	pub provisioner_retry_interval: Duration,
+	/// The maximum number of bitfields buffered for a lagging provisioner, the oldest one
+	/// is dropped beyond that.
+	pub max_pending_provisions: usize,
+	/// The maximum number of bitfields sent to a peer catching up on its view change
+	/// at once, any further ones are sent in the following iterations.
+	pub max_catch_up_messages: usize,
+	/// The maximum number of bitfields buffered for a relay parent in our view we were
+	/// not told to work on yet, which are processed once we are.
+	pub max_early_messages: usize,
+	/// Whether to not gossip bitfields without any bit set, which tell peers nothing.
+	/// They are handed to the provisioner nonetheless.
+	pub skip_empty_bitfields: bool,
+	/// The maximum number of gossip messages, received back to back, whose bitfields
+	/// have their signatures checked at once.
+	pub max_verification_batch: usize,
+	/// Whether to only observe the gossip, for nodes which are no validators: valid bitfields
+	/// are handed to the provisioner, but never relayed nor sent to peers catching up.
+	pub observer: bool,
+	/// The window in which penalties of a peer identical to one already reported are held back,
+	/// to be reported as a single one summing them up once the window ends.
+	pub penalty_coalescing_window: Duration,
+	/// How often registering with the network bridge at startup is attempted before giving up.
+	pub max_registration_attempts: usize,
+	/// The delay before the first retry of registering with the network bridge,
+	/// doubled with every further one.
+	pub registration_backoff: Duration,
+	/// The maximum of the random delay before a bitfield received from a peer is relayed,
+	/// so nodes receiving it at the same time don't all relay it at once.
+	/// Our own bitfields are always relayed right away.
+	pub max_relay_jitter: Duration,
+	/// Whether to compress the gossip messages we send, which all peers need to understand.
+	/// Compressed messages of peers are only accepted if so, they are of an unsupported
+	/// version otherwise.
+	pub compress_gossip: bool,
+	/// Whether to tell the provisioner once bitfields of more than 2/3 of the validators
+	/// of a relay parent were handed to it.
+	pub notify_quorum: bool,
+	/// For how long a relayed bitfield is remembered, even beyond its relay parent being
+	/// dropped, so a peer replaying it once the relay parent is worked on again is ignored.
+	pub replay_window: Duration,
+	/// The maximum number of relayed bitfields remembered, the oldest ones are forgotten first.
+	pub max_replay_cache: usize,
+	/// The number of full peers which need to be connected before our own bitfields are sent
+	/// to any, they are held back until then. Bitfields of peers are relayed regardless.
+	pub min_peers_for_own_bitfields: usize,
+	/// Only one in this many messages of peers is traced on receipt, to keep the log volume
+	/// of a busy node down. They are counted by the metrics regardless.
+	pub trace_sampling: usize,
+	/// How long after starting to work on a relay parent bitfields of more than 2/3 of its
+	/// validators are expected to be known, those still missing are logged afterwards.
+	/// Zero disables the deadline.
+	pub quorum_deadline: Duration,
+	/// The interval in which peers delivering valid bitfields and earning no penalty
+	/// are rewarded with the `ReputationConfig::gain_good_standing`. Zero disables the reward.
+	pub good_standing_interval: Duration,
+}
+
+impl Default for Config {
+	fn default() -> Self {
+		Self {
+			flood_window: Duration::from_secs(1),
+			// way more than a peer relaying every bitfield of a large validator set
+			// for a handful of relay parents would ever need.
+			flood_threshold: 2_000,
+			// peers may learn about new blocks before us, so allow some slack
+			max_useless_view_changes: 3,
+			view_change_window: Duration::from_secs(10),
+			// a new block every few seconds plus some forks
+			max_view_changes: 20,
+			// way more than the peers the network bridge connects to
+			max_peers: 1_000,
+			// twice the heads the network bridge allows in a view
+			max_peer_view_len: 10,
+			// no honest peer comes anywhere close
+			max_acceptable_view_len: 1_000,
+			regossip_interval: Duration::from_secs(3),
+			protocol_id: BitfieldDistribution::DEFAULT_PROTOCOL_ID,
+			stopped_grace_period: Duration::from_secs(6),
+			// way more than the number of leaves we would ever work on
+			max_relay_parents: 64,
+			// a bounded fan-out delivers no bitfield for sure, so it is opt-in
+			fan_out: FanOut::All,
+			rng_seed: None,
+			// enough for catching up on the bitfields of a large validator set
+			// for a handful of relay parents
+			max_message_size: 1024 * 1024,
+			// a peer sending the odd broken message keeps decodable ones in between
+			max_undecodable_messages: 10,
+			max_sends_per_iteration: 64,
+			// a relay of every validator of a large validator set for a handful of relay parents
+			max_deferred_sends: 4_096,
+			// a lagging provisioner catches up within a fraction of a second
+			provisioner_retry_interval: Duration::from_millis(100),
+			// a bitfield of every validator of a large validator set for a handful of relay parents
+			max_pending_provisions: 4_096,
+			// well within the `max_message_size` of the receiving peer
+			max_catch_up_messages: 1024,
+			// a bitfield of every validator of a large validator set
+			max_early_messages: 1024,
+			skip_empty_bitfields: false,
+			max_verification_batch: 16,
+			observer: false,
+			// a peer repeating itself is usually flooding us within the `flood_window`
+			penalty_coalescing_window: Duration::from_secs(1),
+			// retrying for a few seconds in total
+			max_registration_attempts: 6,
+			registration_backoff: Duration::from_millis(100),
+			max_relay_jitter: Duration::from_secs(0),
+			// until all peers are known to understand compressed messages
+			compress_gossip: false,
+			// until the provisioner acts on it
+			notify_quorum: false,
+			// way longer than a relay parent is usually worked on
+			replay_window: Duration::from_secs(60),
+			// a bitfield of every validator of a large validator set for a handful of relay parents
+			max_replay_cache: 10_000,
+			// sent to whoever is connected
+			min_peers_for_own_bitfields: 0,
+			// all of them
+			trace_sampling: 1,
+			// operators opt in to being alerted
+			quorum_deadline: Duration::from_secs(0),
+			// decaying reputations is up to the network bridge
+			good_standing_interval: Duration::from_secs(0),
+		}
+	}
+}
+
+impl Config {
+	/// The bytes to send to peers for the encoding of a message, compressed if so configured.
+	fn wire_bytes(&self, bytes: Vec<u8>) -> Vec<u8> {
+		if self.compress_gossip {
+			VersionedBitfieldGossipMessage::compress(bytes)
+		} else {
+			bytes
+		}
+	}
+
+	/// Whether `bitfield` is gossiped to peers at all.
+	fn gossips(&self, bitfield: &AvailabilityBitfield) -> bool {
+		!self.observer && (!self.skip_empty_bitfields || bitfield.count_ones() > 0)
+	}
+}
+
+/// The number of interested peers a bitfield is relayed to.
+///
+/// Peers not picked are not tracked as having the bitfield, and only get it relayed by
+/// other peers, or when catching up on a later view change; only our own bitfields are
+/// re-gossiped.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FanOut {
+	/// Relay to all interested peers.
+	All,
+	/// Relay to the square root of the number of interested peers, rounded up.
+	Sqrt,
+	/// Relay to at most the given number of peers.
+	AtMost(usize),
+}
+
+impl FanOut {
+	/// The number of peers out of `interested` to relay a bitfield to.
+	fn limit(&self, interested: usize) -> usize {
+		match *self {
+			FanOut::All => interested,
+			FanOut::Sqrt => (interested as f64).sqrt().ceil() as usize,
+			FanOut::AtMost(max) => interested.min(max),
+		}
+	}
+}
+
+/// Checked signed availability bitfield that is distributed
+/// to other peers.
+#[derive(Encode, Decode, Debug, Clone, PartialEq, Eq)]
+pub struct BitfieldGossipMessage {
+	/// The relay parent this message is relative to.
+	pub relay_parent: Hash,
+	/// The actual signed availability bitfield.
+	pub signed_availability: SignedAvailabilityBitfield,
+}
+
+impl BitfieldGossipMessage {
+	/// Encode the message in the current version of the wire format.
+	fn encode_versioned(&self) -> Vec<u8> {
+		VersionedBitfieldGossipMessage::V1(self.clone()).encode()
+	}
+}
+
+/// The bitfield gossip wire format, prefixed with the protocol version.
+#[derive(Encode, Decode, Debug, Clone, PartialEq, Eq)]
+pub enum VersionedBitfieldGossipMessage {
+	/// The first version of the protocol.
+	#[codec(index = "1")]
+	V1(BitfieldGossipMessage),
+	/// The second version of the protocol, which allows to send
+	/// several bitfields at once.
+	#[codec(index = "2")]
+	V2(Vec<BitfieldGossipMessage>),
+	/// The zstd compressed encoding of a `V1` or `V2` message.
+	#[codec(index = "3")]
+	Compressed(Vec<u8>),
+}
+
+impl VersionedBitfieldGossipMessage {
+	/// The protocol versions this node understands.
+	const SUPPORTED_VERSIONS: &'static [u8] = &[1, 2, 3];
+
+	/// The zstd compression level of the messages we send.
+	const COMPRESSION_LEVEL: i32 = 3;
+
+	/// Compress the encoding of a `V1` or `V2` message, unless that doesn't make it any smaller.
+	fn compress(bytes: Vec<u8>) -> Vec<u8> {
+		match zstd::block::compress(&bytes, Self::COMPRESSION_LEVEL) {
+			Ok(compressed) => {
+				let compressed = VersionedBitfieldGossipMessage::Compressed(compressed).encode();
+				if compressed.len() < bytes.len() {
+					compressed
+				} else {
+					bytes
+				}
+			}
+			Err(e) => {
+				debug!(target: "bitd", "Failed to compress a gossip message: {:?}", e);
+				bytes
+			}
+		}
+	}
+
+	/// Decompress a `Compressed` message, which may not decompress to more than `max_size` bytes,
+	/// any other message is left as is.
+	fn decompressed(self, max_size: usize) -> Result<Self, parity_scale_codec::Error> {
+		let compressed = match self {
+			VersionedBitfieldGossipMessage::Compressed(compressed) => compressed,
+			message => return Ok(message),
+		};
+		let bytes = Self::decompress(&compressed, max_size)
+			.map_err(|_| parity_scale_codec::Error::from("invalid compressed message"))?;
+		match Self::decode_all(&bytes)? {
+			VersionedBitfieldGossipMessage::Compressed(_) => Err("nested compressed message".into()),
+			message => Ok(message),
+		}
+	}
+
+	/// Decompress at most `max_size` bytes, growing the buffer along with the output
+	/// rather than allocating `max_size` bytes for every message, however small.
+	fn decompress(compressed: &[u8], max_size: usize) -> io::Result<Vec<u8>> {
+		let mut decoder = zstd::stream::read::Decoder::new(compressed)?;
+		// no message we accept needs a larger window, so don't allocate one either
+		decoder.window_log_max(max_size.max(1 << 10).next_power_of_two().trailing_zeros())?;
+		let mut bytes = Vec::new();
+		decoder.take(max_size as u64 + 1).read_to_end(&mut bytes)?;
+		if bytes.len() > max_size {
+			return Err(io::Error::new(io::ErrorKind::InvalidData, "decompressed beyond the maximum size"));
+		}
+		Ok(bytes)
+	}
+
+	/// Assemble the encoding of the messages with the given plain encodings,
+	/// as `V1` for a single one and as a `V2` batch otherwise.
+	fn encode_from_parts(parts: &[Arc<Vec<u8>>]) -> Vec<u8> {
+		let len = parts.iter().map(|part| part.len()).sum::<usize>();
+		let mut bytes = Vec::with_capacity(len + 5);
+		if let [single] = parts {
+			bytes.push(1);
+			bytes.extend_from_slice(single);
+		} else {
+			bytes.push(2);
+			Compact(parts.len() as u32).encode_to(&mut bytes);
+			for part in parts {
+				bytes.extend_from_slice(part);
+			}
+		}
+		bytes
+	}
+
+	/// Whether the encoded message starts with a version we understand.
+	fn has_supported_version(bytes: &[u8]) -> bool {
+		bytes
+			.first()
+			.map_or(false, |version| Self::SUPPORTED_VERSIONS.contains(version))
+	}
+}
+
+/// Why a gossip message of a peer could not be decoded.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DecodeError {
+	/// The message is larger than the maximum message size.
+	Oversized,
+	/// The message is of a protocol version we don't support.
+	UnsupportedVersion,
+	/// The message is no valid encoding, has trailing bytes, or doesn't decompress to a valid one.
+	Undecodable,
+}
+
+/// Decode a gossip message of a peer into the bitfields it carries, exactly like the ones
+/// received from the network bridge, e.g. for fuzzing the wire format.
+///
+/// Messages larger than `max_message_size` are rejected before decoding them,
+/// and so are compressed ones decompressing to more than that. Compressed messages
+/// are of an unsupported version unless `accept_compressed`.
+pub fn try_decode_gossip(
+	bytes: &[u8],
+	max_message_size: usize,
+	accept_compressed: bool,
+) -> Result<Vec<BitfieldGossipMessage>, DecodeError> {
+	if bytes.len() > max_message_size {
+		return Err(DecodeError::Oversized);
+	}
+	// trailing bytes are rejected as well, nothing should be smuggled along
+	let message = VersionedBitfieldGossipMessage::decode_all(bytes);
+	if let Ok(VersionedBitfieldGossipMessage::Compressed(_)) = message {
+		if !accept_compressed {
+			return Err(DecodeError::UnsupportedVersion);
+		}
+	}
+	let message = message.and_then(|message| message.decompressed(max_message_size));
+	match message {
+		Ok(VersionedBitfieldGossipMessage::V1(bitfield)) => Ok(vec![bitfield]),
+		Ok(VersionedBitfieldGossipMessage::V2(bitfields)) => Ok(bitfields),
+		Err(_) if !bytes.is_empty() && !VersionedBitfieldGossipMessage::has_supported_version(bytes) => {
+			Err(DecodeError::UnsupportedVersion)
+		}
+		Ok(VersionedBitfieldGossipMessage::Compressed(_)) | Err(_) => Err(DecodeError::Undecodable),
+	}
+}
+
+/// Data used to track information of peers and relay parents the
+/// overseer ordered us to work on.
+#[derive(Default, Clone)]
+struct Tracker {
+	/// track all active peers and their views
+	/// to determine what is relevant to them.
+	peer_views: HashMap<PeerId, View>,
+
+	/// The roles of all active peers, light clients are never sent any bitfields.
+	peer_roles: HashMap<PeerId, ObservedRole>,
+
+	/// When each of the active peers last changed its view or sent a message,
+	/// according to the `activity_clock`.
+	peer_activity: HashMap<PeerId, u64>,
+
+	/// Counts the view changes and messages of all peers, to order them by recency.
+	activity_clock: u64,
+
+	/// Connected peers untracked beyond `max_peers`, along with their roles.
+	evicted_peers: HashMap<PeerId, ObservedRole>,
+
+	/// Our current view.
+	view: View,
+
+	/// Additional data particular to a relay parent.
+	per_relay_parent: HashMap<Hash, PerRelayParentData>,
+
+	/// Arrival times of the recent messages of each peer.
+	peer_rates: HashMap<PeerId, RateState>,
+
+	/// Times of the recent view changes of each peer.
+	view_change_rates: HashMap<PeerId, RateState>,
+
+	/// The number of consecutive messages of each peer which could not be decoded.
+	undecodable_messages: HashMap<PeerId, usize>,
+
+	/// The number of messages of peers to receive before the next one is traced.
+	messages_until_trace: usize,
+
+	/// Verifies the signatures of all bitfields.
+	signature_verifier: SharedVerifier,
+
+	/// The only peers bitfields are accepted from, if restricted to them.
+	authority_set: Option<Arc<dyn AuthoritySet>>,
+
+	/// The number of consecutive view changes of each peer
+	/// to only relay parents we don't know.
+	useless_view_changes: HashMap<PeerId, usize>,
+
+	/// Relay parents we stopped working on, with the time they were stopped.
+	recently_stopped: HashMap<Hash, Instant>,
+
+	/// The bitfields relayed recently, including those of relay parents dropped since.
+	replay_cache: ReplayCache,
+
+	/// The validator sets of the current and the previous session.
+	///
+	/// As the validators are queried along with the signing context of every relay parent,
+	/// this only keeps a single copy of them per session.
+	validators_by_session: HashMap<SessionIndex, Arc<Vec<ValidatorId>>>,
+
+	/// The configuration the subsystem was started with.
+	config: Config,
+
+	/// The randomness used to pick the peers a bitfield is relayed to.
+	rng: FanOutRng,
+
+	/// Whether any of our own bitfields were held back for too few peers being connected.
+	own_bitfields_held: bool,
+
+	/// Relayed bitfields, along with their receivers, which exceeded the
+	/// `max_sends_per_iteration` of the iteration they were relayed in.
+	deferred_sends: VecDeque<DeferredSend>,
+
+	/// Bitfields for the provisioner there was no room for in the queue to the overseer,
+	/// in the order they became known.
+	pending_provisions: VecDeque<Provision>,
+
+	/// The number of bitfields relayed in the current iteration.
+	sends_this_iteration: usize,
+
+	/// Relay parents, per peer, of view changes which needed more than
+	/// `max_catch_up_messages` to catch up on, or of dropped deferred relays,
+	/// continued in the following iterations.
+	pending_catch_up: HashMap<PeerId, HashSet<Hash>>,
+
+	/// Bitfields, along with the peers they came from, of relay parents in our view
+	/// we were not told to work on yet.
+	early_messages: HashMap<Hash, Vec<(PeerId, BitfieldGossipMessage)>>,
+
+	/// Those notified of every bitfield handed to the provisioner.
+	subscribers: Vec<mpsc::Sender<(Hash, SignedAvailabilityBitfield)>>,
+
+	/// Relay parents we were told to forget, bitfields of which are not interesting anymore.
+	forgotten: HashSet<Hash>,
+
+	/// Where the relays delayed by the `max_relay_jitter` go once they are due.
+	jittered_relays: Option<mpsc::UnboundedSender<JitteredRelay>>,
+
+	/// Where the relay parents go once their `quorum_deadline` passed,
+	/// along with when we started working on them.
+	quorum_deadlines: Option<mpsc::UnboundedSender<(Hash, Instant)>>,
+
+	/// Penalties reported within the `penalty_coalescing_window`, by peer and reason.
+	recent_penalties: HashMap<(PeerId, &'static str), RecentPenalty>,
+
+	/// How peers behaved within the current `good_standing_interval`.
+	peer_standing: HashMap<PeerId, PeerStanding>,
+}
+
+/// The randomness used to pick the peers a bitfield is relayed to,
+/// seeded from the OS entropy source by default.
+#[derive(Clone)]
+struct FanOutRng(ChaChaRng);
+
+impl FanOutRng {
+	fn new(seed: Option<[u8; 32]>) -> Self {
+		match seed {
+			Some(seed) => FanOutRng(ChaChaRng::from_seed(seed)),
+			None => Self::default(),
+		}
+	}
+}
+
+impl Default for FanOutRng {
+	fn default() -> Self {
+		FanOutRng(ChaChaRng::from_entropy())
+	}
+}
+
+impl Tracker {
+	/// Note an incoming message of `peer`.
+	///
+	/// Returns `false` if the peer exceeded the flood threshold.
+	fn note_peer_message(&mut self, peer: &PeerId) -> bool {
+		self.peer_rates
+			.entry(peer.clone())
+			.or_default()
+			.note_event(Instant::now(), self.config.flood_window, self.config.flood_threshold)
+	}
+
+	/// Note a view change or message of `peer`, tracking it again if it was evicted.
+	///
+	/// Returns `false` if the peer is not connected.
+	fn note_peer_activity(&mut self, peer: &PeerId) -> bool {
+		if let Some(role) = self.evicted_peers.remove(peer) {
+			trace!(target: "bitd", "Tracking evicted peer {:?} again", peer);
+			self.peer_views.insert(peer.clone(), View::default());
+			self.peer_roles.insert(peer.clone(), role);
+		} else if !self.peer_views.contains_key(peer) {
+			return false;
+		}
+		self.activity_clock += 1;
+		self.peer_activity.insert(peer.clone(), self.activity_clock);
+		self.evict_idle_peers();
+		true
+	}
+
+	/// Untrack the least recently active peers until no more than `max_peers` are left.
+	fn evict_idle_peers(&mut self) {
+		while self.peer_views.len() > self.config.max_peers {
+			let peer_activity = &self.peer_activity;
+			let idlest = self.peer_views
+				.keys()
+				.min_by_key(|peer| peer_activity.get(*peer).copied().unwrap_or_default())
+				.cloned();
+			if let Some(peer) = idlest {
+				debug!(target: "bitd", "Tracking more than {} peers, evicting {:?}", self.config.max_peers, peer);
+				let role = self.peer_roles.get(&peer).cloned().unwrap_or(ObservedRole::Full);
+				self.forget_peer(&peer);
+				self.evicted_peers.insert(peer, role);
+			}
+		}
+	}
+
+	/// The `peers` we still track, a relay deferred to peers which disconnected or
+	/// were evicted meanwhile would not reach them, nor is it noted as sent to them anymore.
+	fn still_tracked(&self, mut peers: Vec<PeerId>) -> Vec<PeerId> {
+		peers.retain(|peer| {
+			let tracked = self.peer_views.contains_key(peer);
+			if !tracked {
+				trace!(target: "bitd", "Not relaying a deferred bitfield to untracked peer {:?}", peer);
+			}
+			tracked
+		});
+		peers
+	}
+
+	/// Drop all we track of `peer`.
+	fn forget_peer(&mut self, peer: &PeerId) {
+		self.peer_views.remove(peer);
+		self.peer_roles.remove(peer);
+		self.peer_activity.remove(peer);
+		self.peer_rates.remove(peer);
+		self.view_change_rates.remove(peer);
+		self.useless_view_changes.remove(peer);
+		self.undecodable_messages.remove(peer);
+		self.peer_standing.remove(peer);
+		self.pending_catch_up.remove(peer);
+		for job_data in self.per_relay_parent.values_mut() {
+			job_data.message_sent_to_peer.remove(peer);
+		}
+	}
+
+	/// Cap the `view` of a peer at `Config::max_peer_view_len` relay parents,
+	/// preferring the ones we work on, then the ones in our view.
+	fn cap_peer_view(&self, view: View) -> View {
+		let mut relay_parents = view.0;
+		// stable, so the order given by the peer is kept otherwise
+		relay_parents.sort_by_key(|relay_parent| (
+			!self.per_relay_parent.contains_key(relay_parent),
+			!self.view.contains(relay_parent),
+		));
+		relay_parents.truncate(self.config.max_peer_view_len);
+		View(relay_parents)
+	}
+
+	/// Notify the subscribers of a bitfield handed to the provisioner,
+	/// forgetting those which dropped their receiver.
+	///
+	/// A subscriber not keeping up misses the bitfield rather than holding us up.
+	fn notify_subscribers(&mut self, relay_parent: Hash, signed_availability: &SignedAvailabilityBitfield) {
+		for subscriber in self.subscribers.iter_mut() {
+			if let Err(e) = subscriber.try_send((relay_parent, signed_availability.clone())) {
+				if e.is_full() {
+					trace!(target: "bitd", "Subscriber missed a bitfield of relay parent {:?}", relay_parent);
+				}
+			}
+		}
+		self.subscribers.retain(|subscriber| !subscriber.is_closed());
+	}
+
+	/// Note a reputation `change` of `peer` happening at `now`.
+	///
+	/// Returns `false` for a penalty identical to one reported within the `penalty_coalescing_window`,
+	/// which is held back to be reported along with them by `take_coalesced_penalties`.
+	fn note_reputation_change(&mut self, peer: &PeerId, change: ReputationChange, now: Instant) -> bool {
+		if change.value < 0 {
+			if let Some(standing) = self.standing_of(peer) {
+				standing.penalized = true;
+			}
+		}
+		if change.value >= 0 || self.config.penalty_coalescing_window == Duration::from_secs(0) {
+			return true;
+		}
+		match self.recent_penalties.entry((peer.clone(), change.reason)) {
+			Entry::Occupied(mut entry) => {
+				entry.get_mut().held_back += 1;
+				false
+			}
+			Entry::Vacant(entry) => {
+				entry.insert(RecentPenalty { reported_at: now, change, held_back: 0 });
+				true
+			}
+		}
+	}
+
+	/// The standing of `peer` within the current `good_standing_interval`,
+	/// or `None` if peers are not rewarded for it.
+	fn standing_of(&mut self, peer: &PeerId) -> Option<&mut PeerStanding> {
+		if self.config.good_standing_interval == Duration::from_secs(0) {
+			return None;
+		}
+		Some(self.peer_standing.entry(peer.clone()).or_default())
+	}
+
+	/// Note that `peer` delivered a valid bitfield we rewarded it for.
+	fn note_valid_bitfield(&mut self, peer: &PeerId) {
+		if let Some(standing) = self.standing_of(peer) {
+			standing.delivered_valid_bitfields = true;
+		}
+	}
+
+	/// Take the connected peers which delivered valid bitfields and earned no penalty
+	/// within the `good_standing_interval` just ended, starting the next one.
+	fn take_peers_in_good_standing(&mut self) -> Vec<PeerId> {
+		let peer_views = &self.peer_views;
+		self.peer_standing
+			.drain()
+			.filter(|(peer, standing)| {
+				standing.delivered_valid_bitfields && !standing.penalized && peer_views.contains_key(peer)
+			})
+			.map(|(peer, _)| peer)
+			.collect()
+	}
+
+	/// Take the penalties whose `penalty_coalescing_window` ended at `now`,
+	/// each summing up the identical ones held back within it.
+	fn take_coalesced_penalties(&mut self, now: Instant) -> Vec<(PeerId, ReputationChange)> {
+		let window = self.config.penalty_coalescing_window;
+		let mut coalesced = Vec::new();
+		self.recent_penalties.retain(|(peer, _), penalty| {
+			if now.duration_since(penalty.reported_at) < window {
+				return true;
+			}
+			if penalty.held_back > 0 {
+				coalesced.push((peer.clone(), ReputationChange::new(
+					penalty.change.value.saturating_mul(penalty.held_back),
+					penalty.change.reason,
+				)));
+			}
+			false
+		});
+		coalesced
+	}
+
+	/// Whether to trace the message of a peer just received, one in `Config::trace_sampling` is.
+	fn sample_trace(&mut self) -> bool {
+		if self.messages_until_trace > 0 {
+			self.messages_until_trace -= 1;
+			return false;
+		}
+		self.messages_until_trace = self.config.trace_sampling.saturating_sub(1);
+		true
+	}
+
+	/// A summary of the state of the subsystem.
+	fn status(&self) -> StatusReport {
+		StatusReport {
+			relay_parents: self.per_relay_parent.len() as u32,
+			peers: self.peer_views.len() as u32,
+			bitfields: self.per_relay_parent
+				.values()
+				.map(PerRelayParentData::known_bitfield_count)
+				.sum::<usize>() as u32,
+			processing: self.is_processing(),
+		}
+	}
+
+	/// The checks of a bitfield of `relay_parent` received from a peer up to its signature check,
+	/// i.e. all the cheap ones, yielding what the signature is checked against and whether
+	/// the bitfield is an equivocation if it turns out validly signed.
+	fn precheck(
+		&self,
+		relay_parent: Hash,
+		signed_availability: &SignedAvailabilityBitfield,
+	) -> Result<(&PerRelayParentData, &ValidatorId, bool), ValidationVerdict> {
+		let stopped_recently = self.recently_stopped
+			.get(&relay_parent)
+			.map_or(false, |stopped| stopped.elapsed() < self.config.stopped_grace_period);
+		if stopped_recently
+			|| !self.view.contains(&relay_parent)
+			|| self.forgotten.contains(&relay_parent)
+		{
+			return Err(ValidationVerdict::NotInterested);
+		}
+		let job_data = match self.per_relay_parent.get(&relay_parent) {
+			Some(job_data) => job_data,
+			None => return Err(ValidationVerdict::NotWorkedOn),
+		};
+		// the signature is only good for the relay parent the signing context is of
+		if job_data.signing_context.parent_hash != relay_parent {
+			return Err(ValidationVerdict::ContextMismatch);
+		}
+		// Use the (untrusted) validator index provided by the signed payload
+		// and see if that one actually signed the availability bitset.
+		let validator = match validator_at(&job_data.validator_set, signed_availability.validator_index()) {
+			Some(validator) => validator,
+			None => return Err(ValidationVerdict::InvalidIndex),
+		};
+		if job_data.equivocators.contains(validator) {
+			return Err(ValidationVerdict::Equivocation);
+		}
+
+		// Only relay a message of a validator once, unless the validator superseded it,
+		// see `UpdateOwnBitfield`. A copy of a superseded one is no news, any other
+		// differing bitfield is an equivocation.
+		let payload = signed_availability.payload();
+		let is_equivocation = match job_data.known_message_of(validator) {
+			Some(known) if known.signed_availability.payload() == payload => return Err(ValidationVerdict::Known),
+			Some(known) if supersedes(known.signed_availability.payload(), payload) => {
+				return Err(ValidationVerdict::Known)
+			}
+			Some(known) => !supersedes(payload, known.signed_availability.payload()),
+			None => false,
+		};
+		// a bitfield relayed before its relay parent was dropped and worked on again
+		let replay_key = ReplayCache::key(relay_parent, signed_availability);
+		if self.replay_cache.contains(&replay_key, Instant::now(), self.config.replay_window) {
+			return Err(ValidationVerdict::Known);
+		}
+		Ok((job_data, validator, is_equivocation))
+	}
+
+	/// What becomes of a bitfield of `relay_parent` received from a peer, without doing anything about it.
+	///
+	/// The signature is only verified once all cheap checks passed, unless `signature_valid`
+	/// has the outcome of verifying it along with others already.
+	fn check(
+		&self,
+		relay_parent: Hash,
+		signed_availability: &SignedAvailabilityBitfield,
+		signature_valid: Option<bool>,
+	) -> ValidationVerdict {
+		let (job_data, validator, is_equivocation) = match self.precheck(relay_parent, signed_availability) {
+			Ok(prechecked) => prechecked,
+			Err(verdict) => return verdict,
+		};
+
+		// Everything cheap passed, so now do the expensive part.
+		// A differing bitfield is only an equivocation if the validator actually signed it.
+		let verifier = &self.signature_verifier.0;
+		let signature_valid = signature_valid.unwrap_or_else(|| {
+			verifier.verify(signed_availability, &job_data.signing_context, validator)
+		});
+		if !signature_valid {
+			// The signature covers the session, so a bitfield replayed across
+			// a session change only verifies with the context of the previous one.
+			// That takes a second verification, which would double the cost of spamming
+			// invalid signatures, so it is only tried while we still work on relay parents
+			// of the previous session, i.e. around a session change. Otherwise a replayed
+			// bitfield is penalized like any other invalid signature.
+			let previous_session = job_data.signing_context.session_index
+				.checked_sub(1)
+				.filter(|session_index| self.works_on_session(*session_index))
+				.map(|session_index| SigningContext {
+					session_index,
+					parent_hash: job_data.signing_context.parent_hash,
+				});
+			if previous_session.map_or(false, |signing_context| {
+				verifier.verify(signed_availability, &signing_context, validator)
+			}) {
+				return ValidationVerdict::WrongSession;
+			}
+			return ValidationVerdict::InvalidSignature;
+		}
+		if is_equivocation {
+			return ValidationVerdict::Equivocation;
+		}
+		// all validators sign bitfields of the availability cores of the same relay parent
+		if job_data.bitfield_len.map_or(false, |len| len != signed_availability.payload().0.len()) {
+			return ValidationVerdict::InconsistentLength;
+		}
+		// there is one bitfield per validator of the set at most, more means the set is stale
+		if !job_data.one_per_validator.contains_key(validator)
+			&& job_data.one_per_validator.len() >= job_data.validator_set.len()
+		{
+			return ValidationVerdict::ValidatorSetMismatch;
+		}
+		ValidationVerdict::Accepted
+	}
+
+	/// Whether we work on a relay parent of `session_index`.
+	fn works_on_session(&self, session_index: SessionIndex) -> bool {
+		self.per_relay_parent.values().any(|job_data| job_data.signing_context.session_index == session_index)
+	}
+
+	/// Whether bitfields of `peer` are accepted, i.e. they are not restricted to
+	/// the authority set or the peer is part of it.
+	fn accepts_bitfields_of(&self, peer: &PeerId) -> bool {
+		self.authority_set.as_ref().map_or(true, |authority_set| authority_set.is_authority(peer))
+	}
+
+	/// Whether relays or catch-ups are pending, to be sent in the following iterations.
+	///
+	/// Pending provisions are retried on a timer instead, a stuck provisioner would
+	/// keep the subsystem busy otherwise.
+	fn is_processing(&self) -> bool {
+		!self.deferred_sends.is_empty() || !self.pending_catch_up.is_empty()
+	}
+
+	/// Buffer what is due for the lagging provisioner, a bitfield replacing a buffered one of the
+	/// same validator it supersedes, or dropping the oldest one once too many are buffered.
+	fn buffer_provision(&mut self, metrics: &Metrics, provision: Provision) {
+		if let Provision::Bitfield(relay_parent, signed) = &provision {
+			let superseded = self.pending_provisions.iter_mut().find(|pending| match pending {
+				Provision::Bitfield(hash, pending) => {
+					hash == relay_parent && pending.validator_index() == signed.validator_index()
+				}
+				Provision::Quorum(_) => false,
+			});
+			if let Some(pending) = superseded {
+				*pending = provision;
+				metrics.on_provision_dropped();
+				return;
+			}
+		}
+		if self.pending_provisions.len() >= self.config.max_pending_provisions {
+			debug!(
+				target: "bitd",
+				"More than {} bitfields buffered for the provisioner, dropping the oldest",
+				self.config.max_pending_provisions,
+			);
+			self.pending_provisions.pop_front();
+			metrics.on_provision_dropped();
+		}
+		self.pending_provisions.push_back(provision);
+	}
+
+	/// Note a view change of `peer`.
+	///
+	/// Returns `false` if the peer changed its view too often recently.
+	fn note_peer_view_change(&mut self, peer: &PeerId) -> bool {
+		self.view_change_rates
+			.entry(peer.clone())
+			.or_default()
+			.note_event(Instant::now(), self.config.view_change_window, self.config.max_view_changes)
+	}
+
+	/// Note that we stopped working on `relay_parent`.
+	fn note_stopped(&mut self, relay_parent: Hash) {
+		let now = Instant::now();
+		let grace_period = self.config.stopped_grace_period;
+		self.recently_stopped
+			.retain(|_, stopped| now.duration_since(*stopped) < grace_period);
+		self.recently_stopped.insert(relay_parent, now);
+	}
+
+	/// Stop working on the least recently active relay parents
+	/// until no more than `max_relay_parents` are left.
+	fn evict_inactive_relay_parents(&mut self) {
+		while self.per_relay_parent.len() > self.config.max_relay_parents {
+			let oldest = self.per_relay_parent
+				.iter()
+				.min_by_key(|(_, job_data)| job_data.last_activity)
+				.map(|(relay_parent, _)| *relay_parent);
+			if let Some(relay_parent) = oldest {
+				warn!(
+					target: "bitd",
+					"Working on more than {} relay parents, dropping the least recently active {}",
+					self.config.max_relay_parents,
+					relay_parent
+				);
+				self.per_relay_parent.remove(&relay_parent);
+				self.note_stopped(relay_parent);
+			}
+		}
+	}
+
+	/// Drop all we know about `relay_parent`, which is not interesting anymore.
+	fn forget_relay_parent(&mut self, relay_parent: Hash) {
+		self.per_relay_parent.remove(&relay_parent);
+		self.early_messages.remove(&relay_parent);
+		self.pending_provisions.retain(|provision| provision.relay_parent() != relay_parent);
+		self.pending_catch_up.retain(|_, relay_parents| {
+			relay_parents.remove(&relay_parent);
+			!relay_parents.is_empty()
+		});
+		// until it leaves our view
+		self.forgotten.insert(relay_parent);
+	}
+
+	/// Give up on a deferred relay. Its receivers no longer count as having the
+	/// bitfield, and catch up on the bitfields of its relay parent instead.
+	fn drop_deferred_send(&mut self, deferred: DeferredSend) {
+		let job_data = match self.per_relay_parent.get_mut(&deferred.relay_parent) {
+			Some(job_data) => job_data,
+			None => return,
+		};
+		for peer in deferred.peers {
+			if let Some(sent) = job_data.message_sent_to_peer.get_mut(&peer) {
+				sent.remove(&deferred.validator);
+			}
+			if self.peer_views.contains_key(&peer) {
+				self.pending_catch_up.entry(peer).or_default().insert(deferred.relay_parent);
+			}
+		}
+	}
+
+	/// Whether we stopped working on `relay_parent` within the grace period.
+	fn stopped_recently(&mut self, relay_parent: &Hash) -> bool {
+		match self.recently_stopped.get(relay_parent) {
+			Some(stopped) if stopped.elapsed() < self.config.stopped_grace_period => true,
+			Some(_) => {
+				self.recently_stopped.remove(relay_parent);
+				false
+			}
+			None => false,
+		}
+	}
+}
+
+/// A bitfield received from a peer, to be relayed after a random delay.
+#[derive(Debug)]
+struct JitteredRelay {
+	origin: PeerId,
+	validator: ValidatorId,
+	message: BitfieldGossipMessage,
+}
+
+/// A penalty reported recently, along with the number of identical ones held back since.
+#[derive(Debug, Clone)]
+struct RecentPenalty {
+	reported_at: Instant,
+	change: ReputationChange,
+	held_back: i32,
+}
+
+/// How a peer behaved within the current `good_standing_interval`.
+#[derive(Debug, Clone, Copy, Default)]
+struct PeerStanding {
+	delivered_valid_bitfields: bool,
+	penalized: bool,
+}
+
+/// Sliding window of the times of recent events, e.g. messages, of a single peer.
+#[derive(Debug, Clone, Default)]
+struct RateState {
+	timestamps: VecDeque<Instant>,
+}
+
+impl RateState {
+	/// Note an event happening at `now`.
+	///
+	/// Returns `false` without noting it, if there already were
+	/// `threshold` events within the last `window`.
+	fn note_event(&mut self, now: Instant, window: Duration, threshold: usize) -> bool {
+		while let Some(oldest) = self.timestamps.front() {
+			if now.duration_since(*oldest) < window {
+				break;
+			}
+			self.timestamps.pop_front();
+		}
+
+		if self.timestamps.len() >= threshold {
+			return false;
+		}
+		self.timestamps.push_back(now);
+		true
+	}
+}
+
+/// Identifies a bitfield by its relay parent, validator index and the hash of its payload.
+type ReplayKey = (Hash, ValidatorIndex, [u8; 32]);
+
+/// The recently relayed bitfields, along with the times they were relayed, oldest first.
+#[derive(Debug, Clone, Default)]
+struct ReplayCache {
+	seen: HashMap<ReplayKey, Instant>,
+	order: VecDeque<ReplayKey>,
+}
+
+impl ReplayCache {
+	fn key(relay_parent: Hash, signed_availability: &SignedAvailabilityBitfield) -> ReplayKey {
+		(
+			relay_parent,
+			signed_availability.validator_index(),
+			blake2_256(&signed_availability.payload().encode()),
+		)
+	}
+
+	/// Note a bitfield relayed at `now`, forgetting those relayed more than `window` ago
+	/// as well as the oldest ones beyond `capacity`.
+	fn note(&mut self, key: ReplayKey, now: Instant, window: Duration, capacity: usize) {
+		while let Some(oldest) = self.order.front() {
+			let expired = self.seen.get(oldest).map_or(true, |seen| now.duration_since(*seen) >= window);
+			if !expired && self.order.len() < capacity {
+				break;
+			}
+			if let Some(oldest) = self.order.pop_front() {
+				self.seen.remove(&oldest);
+			}
+		}
+
+		if capacity > 0 && window > Duration::from_secs(0) {
+			if let Entry::Vacant(entry) = self.seen.entry(key) {
+				entry.insert(now);
+				self.order.push_back(key);
+			}
+		}
+	}
+
+	/// Whether the bitfield was relayed within the last `window`.
+	fn contains(&self, key: &ReplayKey, now: Instant, window: Duration) -> bool {
+		self.seen.get(key).map_or(false, |seen| now.duration_since(*seen) < window)
+	}
+}
+
+/// Data for a particular relay parent.
+#[derive(Debug, Clone, Default)]
+struct PerRelayParentData {
+	/// Signing context for a particular relay parent.
+	signing_context: SigningContext,
+
+	/// Set of validators for a particular relay parent.
+	/// Shared by all relay parents of the same session.
+	validator_set: Arc<Vec<ValidatorId>>,
+
+	/// Set of validators for a particular relay parent for which we
+	/// received a valid `BitfieldGossipMessage`.
+	/// Also serves as the list of known messages for peers connecting
+	/// after bitfield gossips were already received.
+	/// The messages are kept along with their plain encoding,
+	/// which is shared by all the sends of them.
+	one_per_validator: HashMap<ValidatorId, (BitfieldGossipMessage, Arc<Vec<u8>>)>,
+
+	/// Where each of the messages in `one_per_validator` came from,
+	/// i.e. the peer delivering it first or a snapshot.
+	delivered_by: HashMap<ValidatorId, BitfieldSource>,
+
+	/// Avoid duplicate message transmission to our peers.
+	message_sent_to_peer: HashMap<PeerId, HashSet<ValidatorId>>,
+
+	/// Peers rewarded for a view change adding the relay parent already, kept across disconnects.
+	view_change_rewarded: HashSet<PeerId>,
+
+	/// The bitfield we distributed ourselves, if any, for periodic re-gossip.
+	own_bitfield: Option<(ValidatorId, BitfieldGossipMessage)>,
+
+	/// When we started working on the relay parent or last got a new bitfield for it.
+	last_activity: Option<Instant>,
+
+	/// When we started working on the relay parent, to measure how quickly bitfields arrive.
+	started: Option<Instant>,
+
+	/// When the first bitfield of the relay parent became known.
+	first_bitfield_at: Option<Instant>,
+
+	/// When bitfields of more than 2/3 of the validators became known.
+	quorum_at: Option<Instant>,
+
+	/// Cancels the timer of the `quorum_deadline` once the quorum is reached.
+	quorum_deadline: Option<future::AbortHandle>,
+
+	/// Whether the provisioner was told about the quorum already, if it is to be.
+	quorum_notified: bool,
+
+	/// The number of bits of the first valid bitfield, which all others must have as well.
+	bitfield_len: Option<usize>,
+
+	/// Validators which signed two different bitfields, any further one of them is worthless.
+	equivocators: HashSet<ValidatorId>,
+}
+
+impl PerRelayParentData {
+	/// Whether `peer` still needs the bitfield of `validator`, as we neither sent it to the peer
+	/// nor received it from the peer. A peer we never sent anything to needs every bitfield.
+	fn needs_message(&self, peer: &PeerId, validator: &ValidatorId) -> bool {
+		self.message_sent_to_peer
+			.get(peer)
+			.map_or(true, |sent| !sent.contains(validator))
+	}
+
+	/// Remember a valid bitfield `message` of `validator` received from a peer.
+	fn insert_message(&mut self, validator: ValidatorId, message: BitfieldGossipMessage) {
+		let encoded = Arc::new(message.encode());
+		let _ = self.one_per_validator.insert(validator, (message, encoded));
+	}
+
+	/// Remember a bitfield of a snapshot, checked like one received from a peer.
+	///
+	/// A bitfield of a validator we know one of already is ignored.
+	fn import_bitfield(
+		&mut self,
+		signature_verifier: &dyn SignatureVerifier,
+		relay_parent: Hash,
+		signed_availability: SignedAvailabilityBitfield,
+	) -> Result<(), RejectReason> {
+		if self.signing_context.parent_hash != relay_parent {
+			return Err(RejectReason::ContextMismatch);
+		}
+		let validator_set = self.validator_set.clone();
+		let validator = validator_at(&validator_set, signed_availability.validator_index())
+			.ok_or(RejectReason::ValidatorIndexInvalid)?;
+		if self.equivocators.contains(validator) {
+			return Ok(());
+		}
+		if self.known_message_of(validator).is_some() {
+			return Ok(());
+		}
+		if self.one_per_validator.len() >= self.validator_set.len() {
+			return Err(RejectReason::ValidatorSetMismatch);
+		}
+		if !signature_verifier.verify(&signed_availability, &self.signing_context, validator) {
+			return Err(RejectReason::SignatureInvalid);
+		}
+		let bitfield_len = signed_availability.payload().0.len();
+		if *self.bitfield_len.get_or_insert(bitfield_len) != bitfield_len {
+			return Err(RejectReason::InconsistentLength);
+		}
+
+		self.insert_message(validator.clone(), BitfieldGossipMessage { relay_parent, signed_availability });
+		self.delivered_by.insert(validator.clone(), BitfieldSource::Snapshot);
+		Ok(())
+	}
+
+	/// The bitfield of the given validator we know of, either received from a peer or our own one.
+	fn known_message_of(&self, validator: &ValidatorId) -> Option<&BitfieldGossipMessage> {
+		self.one_per_validator.get(validator).map(|(message, _)| message).or_else(|| match self.own_bitfield {
+			Some((ref own_validator, ref message)) if own_validator == validator => Some(message),
+			_ => None,
+		})
+	}
+
+	/// Where all the bitfields we know of came from, ordered by validator index.
+	fn bitfield_sources(&self) -> Vec<(ValidatorIndex, BitfieldSource)> {
+		let own_validator = self.own_bitfield.as_ref().map(|(validator, _)| validator);
+		let mut sources = self.one_per_validator
+			.iter()
+			// our own bitfield may have been gossiped to us as well
+			.filter(|(validator, _)| Some(*validator) != own_validator)
+			.filter_map(|(validator, (message, _))| {
+				let source = self.delivered_by.get(validator)?;
+				Some((message.signed_availability.validator_index(), source.clone()))
+			})
+			.chain(self.own_bitfield.iter().map(|(_, message)| {
+				(message.signed_availability.validator_index(), BitfieldSource::Local)
+			}))
+			.collect::<Vec<_>>();
+		sources.sort_by_key(|(validator_index, _)| *validator_index);
+		sources
+	}
+
+	/// How many cores any of the bitfields we know of, including our own, reports available.
+	fn availability_summary(&self) -> AvailabilitySummary {
+		let bitfields = self.known_bitfields();
+		let cores = self.bitfield_len.unwrap_or(0);
+		let mut available = vec![false; cores];
+		for bitfield in bitfields.iter() {
+			for (available, bit) in available.iter_mut().zip(bitfield.payload().0.iter()) {
+				*available |= *bit;
+			}
+		}
+		AvailabilitySummary {
+			cores,
+			available_cores: available.into_iter().filter(|available| *available).count(),
+			validators: bitfields.len(),
+		}
+	}
+
+	/// All the bitfields we know of, including our own, ordered by validator index.
+	fn known_bitfields(&self) -> Vec<SignedAvailabilityBitfield> {
+		let own_validator = self.own_bitfield.as_ref().map(|(validator, _)| validator);
+		let mut bitfields = self.one_per_validator
+			.iter()
+			// our own bitfield may have been gossiped to us as well
+			.filter(|(validator, _)| Some(*validator) != own_validator)
+			.map(|(_, (message, _))| message)
+			.chain(self.own_bitfield.iter().map(|(_, message)| message))
+			.map(|message| message.signed_availability.clone())
+			.collect::<Vec<_>>();
+		bitfields.sort_by_key(|bitfield| bitfield.validator_index());
+		bitfields
+	}
+
+	/// Whether we know a bitfield of the validator at `index`, including our own.
+	fn has_bitfield(&self, index: ValidatorIndex) -> bool {
+		let own_validator = self.own_bitfield.as_ref().map(|(validator, _)| validator);
+		validator_at(&self.validator_set, index)
+			.map_or(false, |validator| {
+				self.one_per_validator.contains_key(validator) || Some(validator) == own_validator
+			})
+	}
+
+	/// The number of validators we have a bitfield of, including ourselves.
+	fn known_bitfield_count(&self) -> usize {
+		self.validator_set.len() - self.missing_validators().len()
+	}
+
+	/// Note that a new bitfield became known, recording the time since we started working
+	/// on the relay parent when it is the first one or completes a quorum.
+	fn record_latencies(&mut self, metrics: &Metrics) {
+		let started = match self.started {
+			Some(started) => started,
+			None => return,
+		};
+		let now = Instant::now();
+		if self.first_bitfield_at.is_none() {
+			self.first_bitfield_at = Some(now);
+			metrics.on_first_bitfield(now - started);
+		}
+		if self.quorum_at.is_none() && self.known_bitfield_count() * 3 > self.validator_set.len() * 2 {
+			self.quorum_at = Some(now);
+			metrics.on_bitfield_quorum(now - started);
+			if let Some(quorum_deadline) = self.quorum_deadline.take() {
+				quorum_deadline.abort();
+			}
+		}
+	}
+
+	/// The indices of all validators we don't have a bitfield of yet.
+	fn missing_validators(&self) -> Vec<ValidatorIndex> {
+		let own_validator = self.own_bitfield.as_ref().map(|(validator, _)| validator);
+		self.validator_set
+			.iter()
+			.enumerate()
+			.filter(|(_, validator)| {
+				!self.one_per_validator.contains_key(validator) && Some(*validator) != own_validator
+			})
+			.filter_map(|(index, _)| ValidatorIndex::try_from(index).ok())
+			.collect()
+	}
+}
+
+/// Reasons for rejecting a bitfield gossip message, used as metric labels.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum RejectReason {
+	SignatureInvalid,
+	ValidatorIndexInvalid,
+	NotInterested,
+	UntrackedRelayParent,
+	Undecodable,
+	UnsupportedVersion,
+	WrongSession,
+	Oversized,
+	InconsistentLength,
+	ValidatorSetMismatch,
+	ContextMismatch,
+	NotAuthority,
+}
+
+impl RejectReason {
+	fn label(&self) -> &'static str {
+		match self {
+			RejectReason::SignatureInvalid => "signature_invalid",
+			RejectReason::ValidatorIndexInvalid => "validator_index_invalid",
+			RejectReason::NotInterested => "not_interested",
+			RejectReason::UntrackedRelayParent => "untracked_relay_parent",
+			RejectReason::Undecodable => "undecodable",
+			RejectReason::UnsupportedVersion => "unsupported_version",
+			RejectReason::WrongSession => "wrong_session",
+			RejectReason::Oversized => "oversized",
+			RejectReason::InconsistentLength => "inconsistent_length",
+			RejectReason::ValidatorSetMismatch => "validator_set_mismatch",
+			RejectReason::ContextMismatch => "context_mismatch",
+			RejectReason::NotAuthority => "not_authority",
+		}
+	}
+}
+
+/// The buckets, in seconds, of the bitfield latency histograms.
+const LATENCY_BUCKETS: [f64; 9] = [0.05, 0.1, 0.25, 0.5, 1.0, 2.0, 4.0, 8.0, 16.0];
+
+#[derive(Clone)]
+struct MetricsInner {
+	relayed_bitfields: prometheus::Counter<prometheus::U64>,
+	received_bitfields: prometheus::Counter<prometheus::U64>,
+	rejected_bitfields: prometheus::CounterVec<prometheus::U64>,
+	untracked_relay_parent_bitfields: prometheus::Counter<prometheus::U64>,
+	tracked_relay_parents: prometheus::Gauge<prometheus::U64>,
+	first_bitfield_latency: prometheus::Histogram,
+	quorum_latency: prometheus::Histogram,
+	quorum_timeouts: prometheus::Counter<prometheus::U64>,
+	dropped_provisions: prometheus::Counter<prometheus::U64>,
+}
+
+/// Bitfield distribution metrics.
+///
+/// All methods are no-ops unless the metrics were registered with a Prometheus registry.
+#[derive(Default, Clone)]
+pub struct Metrics(Option<MetricsInner>);
+
+impl Metrics {
+	/// Register the bitfield distribution metrics with the given registry,
+	/// or create inert metrics if there is none.
+	pub fn register(registry: Option<&Registry>) -> Result<Self, PrometheusError> {
+		let registry = match registry {
+			Some(registry) => registry,
+			None => return Ok(Metrics(None)),
+		};
+
+		let metrics = MetricsInner {
+			relayed_bitfields: prometheus::register(
+				prometheus::Counter::new(
+					"parachain_bitfields_relayed_total",
+					"Number of valid availability bitfields relayed to other peers.",
+				)?,
+				registry,
+			)?,
+			received_bitfields: prometheus::register(
+				prometheus::Counter::new(
+					"parachain_bitfields_received_total",
+					"Number of availability bitfields received from other peers.",
+				)?,
+				registry,
+			)?,
+			rejected_bitfields: prometheus::register(
+				prometheus::CounterVec::new(
+					prometheus::Opts::new(
+						"parachain_bitfields_rejected_total",
+						"Number of availability bitfields received from other peers and rejected.",
+					),
+					&["reason"],
+				)?,
+				registry,
+			)?,
+			untracked_relay_parent_bitfields: prometheus::register(
+				prometheus::Counter::new(
+					"parachain_bitfields_of_untracked_relay_parents_total",
+					"Number of availability bitfields received for relay parents in our view \
+					bitfield distribution was not told to work on yet.",
+				)?,
+				registry,
+			)?,
+			tracked_relay_parents: prometheus::register(
+				prometheus::Gauge::new(
+					"parachain_bitfield_distribution_relay_parents",
+					"Number of relay parents bitfield distribution is working on.",
+				)?,
+				registry,
+			)?,
+			first_bitfield_latency: prometheus::register(
+				prometheus::Histogram::with_opts(
+					prometheus::HistogramOpts::new(
+						"parachain_bitfield_first_latency_seconds",
+						"Time from starting to work on a relay parent until its first availability bitfield is known.",
+					).buckets(LATENCY_BUCKETS.to_vec()),
+				)?,
+				registry,
+			)?,
+			quorum_latency: prometheus::register(
+				prometheus::Histogram::with_opts(
+					prometheus::HistogramOpts::new(
+						"parachain_bitfield_quorum_latency_seconds",
+						"Time from starting to work on a relay parent until availability bitfields \
+						of more than 2/3 of its validators are known.",
+					).buckets(LATENCY_BUCKETS.to_vec()),
+				)?,
+				registry,
+			)?,
+			quorum_timeouts: prometheus::register(
+				prometheus::Counter::new(
+					"parachain_bitfield_quorum_timeouts_total",
+					"Number of relay parents without availability bitfields of more than 2/3 \
+					of its validators within the quorum deadline.",
+				)?,
+				registry,
+			)?,
+			dropped_provisions: prometheus::register(
+				prometheus::Counter::new(
+					"parachain_bitfield_provisions_dropped_total",
+					"Number of availability bitfields never handed to the lagging provisioner, \
+					as they were superseded while buffered or the buffer was full.",
+				)?,
+				registry,
+			)?,
+		};
+
+		Ok(Metrics(Some(metrics)))
+	}
+
+	fn on_bitfield_relayed(&self) {
+		if let Some(metrics) = &self.0 {
+			metrics.relayed_bitfields.inc();
+		}
+	}
+
+	fn on_bitfield_received(&self) {
+		if let Some(metrics) = &self.0 {
+			metrics.received_bitfields.inc();
+		}
+	}
+
+	fn on_untracked_relay_parent_bitfield(&self) {
+		if let Some(metrics) = &self.0 {
+			metrics.untracked_relay_parent_bitfields.inc();
+		}
+	}
+
+	fn on_bitfield_rejected(&self, reason: RejectReason) {
+		if let Some(metrics) = &self.0 {
+			metrics.rejected_bitfields.with_label_values(&[reason.label()]).inc();
+		}
+	}
+
+	fn note_tracked_relay_parents(&self, count: usize) {
+		if let Some(metrics) = &self.0 {
+			metrics.tracked_relay_parents.set(count as u64);
+		}
+	}
+
+	fn on_first_bitfield(&self, latency: Duration) {
+		if let Some(metrics) = &self.0 {
+			metrics.first_bitfield_latency.observe(latency.as_secs_f64());
+		}
+	}
+
+	fn on_bitfield_quorum(&self, latency: Duration) {
+		if let Some(metrics) = &self.0 {
+			metrics.quorum_latency.observe(latency.as_secs_f64());
+		}
+	}
+
+	fn on_quorum_timeout(&self) {
+		if let Some(metrics) = &self.0 {
+			metrics.quorum_timeouts.inc();
+		}
+	}
+
+	fn on_provision_dropped(&self) {
+		if let Some(metrics) = &self.0 {
+			metrics.dropped_provisions.inc();
+		}
+	}
+}
+
+fn network_update_message(n: NetworkBridgeEvent) -> AllMessages {
+	AllMessages::BitfieldDistribution(BitfieldDistributionMessage::NetworkBridgeUpdate(n))
+}
+
+/// Verifies the signatures of bitfields, pluggable to spare tests the cryptography
+/// or to support other signature schemes.
+pub trait SignatureVerifier: Send + Sync {
+	/// Whether `signed_availability` is signed by `validator` within `signing_context`.
+	fn verify(
+		&self,
+		signed_availability: &SignedAvailabilityBitfield,
+		signing_context: &SigningContext,
+		validator: &ValidatorId,
+	) -> bool;
+
+	/// Verify a batch of signed bitfields, returning whether each signature is valid.
+	///
+	/// The validator keys offer no batch verification yet, so this is where one would be
+	/// plugged in; until then the signatures are verified one by one.
+	fn verify_batch(&self, batch: &[(&SignedAvailabilityBitfield, &SigningContext, &ValidatorId)]) -> Vec<bool> {
+		batch
+			.iter()
+			.map(|(signed_availability, signing_context, validator)| {
+				self.verify(signed_availability, signing_context, validator)
+			})
+			.collect()
+	}
+}
+
+/// Verifies signatures with `Signed::check_signature`, which is what live networks use.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct CheckSignature;
+
+impl SignatureVerifier for CheckSignature {
+	fn verify(
+		&self,
+		signed_availability: &SignedAvailabilityBitfield,
+		signing_context: &SigningContext,
+		validator: &ValidatorId,
+	) -> bool {
+		signed_availability.check_signature(signing_context, validator).is_ok()
+	}
+}
+
+/// The `SignatureVerifier` shared by the subsystem and its tracker, `CheckSignature` by default.
+#[derive(Clone)]
+struct SharedVerifier(Arc<dyn SignatureVerifier>);
+
+impl Default for SharedVerifier {
+	fn default() -> Self {
+		SharedVerifier(Arc::new(CheckSignature))
+	}
+}
+
+/// Tells which peers are current authorities, e.g. as found by the authority discovery,
+/// to accept bitfields from those only.
+pub trait AuthoritySet: Send + Sync {
+	/// Whether `peer` is a current authority.
+	fn is_authority(&self, peer: &PeerId) -> bool;
+}
+
+/// The bitfield distribution subsystem.
+pub struct BitfieldDistribution {
+	metrics: Metrics,
+	reputation: ReputationConfig,
+	config: Config,
+	signature_verifier: SharedVerifier,
+	authority_set: Option<Arc<dyn AuthoritySet>>,
+}
+
+impl BitfieldDistribution {
+	/// The protocol identifier used for bitfield distribution unless configured otherwise.
+	pub const DEFAULT_PROTOCOL_ID: ProtocolId = *b"bitd";
+
+	/// Create a new instance of the `BitfieldDistribution` subsystem.
+	pub fn new(metrics: Metrics) -> Self {
+		Self::with_reputation_config(metrics, ReputationConfig::default())
+	}
+
+	/// Create a new instance of the `BitfieldDistribution` subsystem
+	/// which applies the given reputation changes to peers.
+	pub fn with_reputation_config(metrics: Metrics, reputation: ReputationConfig) -> Self {
+		Self {
+			metrics,
+			reputation,
+			config: Config::default(),
+			signature_verifier: Default::default(),
+			authority_set: None,
+		}
+	}
+
+	/// Use the given tunable parameters instead of the defaults.
+	pub fn with_config(mut self, config: Config) -> Self {
+		self.config = config;
+		self
+	}
+
+	/// Verify the signatures of bitfields with the given verifier instead of `CheckSignature`.
+	pub fn with_signature_verifier(mut self, signature_verifier: impl SignatureVerifier + 'static) -> Self {
+		self.signature_verifier = SharedVerifier(Arc::new(signature_verifier));
+		self
+	}
+
+	/// Accept bitfields only from the peers which are current authorities according to `authority_set`,
+	/// those of any other peer are rejected with the `ReputationConfig::cost_not_authority`.
+	pub fn with_authority_set(mut self, authority_set: impl AuthoritySet + 'static) -> Self {
+		self.authority_set = Some(Arc::new(authority_set));
+		self
+	}
+
+	/// Start processing work as passed on from the Overseer.
+	async fn run<Context>(self, mut ctx: Context) -> Result<()>
+	where
+		Context: SubsystemContext<Message = BitfieldDistributionMessage>,
+	{
+		// startup: register the network protocol with the bridge.
+		register_event_producer(&mut ctx, &self.config).await?;
+
+		// work: process incoming messages from the overseer and process accordingly.
+		let mut tracker = Tracker {
+			config: self.config.clone(),
+			rng: FanOutRng::new(self.config.rng_seed),
+			signature_verifier: self.signature_verifier.clone(),
+			authority_set: self.authority_set.clone(),
+			..Default::default()
+		};
+		let (jittered_relays_tx, mut jittered_relays) = mpsc::unbounded();
+		tracker.jittered_relays = Some(jittered_relays_tx);
+		let (quorum_deadlines_tx, mut quorum_deadlines) = mpsc::unbounded();
+		tracker.quorum_deadlines = Some(quorum_deadlines_tx);
+		// a passed quorum deadline which woke us up, checked next
+		let mut due_deadline = None;
+		// the sender is kept, so the ticks don't end while peers are not rewarded for good standing
+		let (good_standing_tx, mut good_standing_ticks) = mpsc::unbounded();
+		let good_standing_interval = tracker.config.good_standing_interval;
+		if good_standing_interval > Duration::from_secs(0) {
+			let good_standing_tx = good_standing_tx.clone();
+			ctx.spawn("bitfield-good-standing", Box::pin(async move {
+				loop {
+					Delay::new(good_standing_interval).await;
+					if good_standing_tx.unbounded_send(()).is_err() {
+						break;
+					}
+				}
+			})).await.map_err(|_| BitfieldDistributionError::SpawnFailed("bitfield-good-standing"))?;
+		}
+		// a good standing tick which woke us up, rewarded next
+		let mut due_good_standing = None;
+		// a jittered relay which woke us up, sent next
+		let mut due_relay = None;
+		let mut regossip_timer = Delay::new(tracker.config.regossip_interval).fuse();
+		// armed while bitfields are buffered for the lagging provisioner
+		let mut provision_retry = future::Fuse::terminated();
+		// a message received while collecting a burst of gossip, handled next
+		let mut next_message = None;
+		loop {
+			// what was deferred by earlier iterations goes first
+			tracker.sends_this_iteration = 0;
+			send_deferred_gossip(&mut ctx, &mut tracker, &self.metrics).await?;
+			send_pending_provisions(&mut ctx, &mut tracker).await?;
+			continue_catch_up(&mut ctx, &mut tracker).await?;
+			report_coalesced_penalties(&mut ctx, &mut tracker).await?;
+			while let Some(relay) = due_relay.take().or_else(|| jittered_relays.try_next().ok().flatten()) {
+				let JitteredRelay { origin, validator, message } = relay;
+				forward_message(&mut ctx, &mut tracker, &self.metrics, Some(origin), validator, message).await?;
+			}
+			while let Some((relay_parent, started)) = due_deadline.take()
+				.or_else(|| quorum_deadlines.try_next().ok().flatten())
+			{
+				check_quorum_deadline(&tracker, &self.metrics, relay_parent, started);
+			}
+			if due_good_standing.take().or_else(|| good_standing_ticks.try_next().ok().flatten()).is_some() {
+				reward_good_standing(&mut ctx, &mut tracker, &self.reputation).await?;
+			}
+
+			if !tracker.pending_provisions.is_empty() && provision_retry.is_terminated() {
+				provision_retry = Delay::new(tracker.config.provisioner_retry_interval).fuse();
+			}
+			// whether the provision retry woke us up, rather than the regossip timer
+			let mut due_provision_retry = false;
+
+			let message = if let Some(message) = next_message.take() {
+				Some(message)
+			} else if tracker.is_processing() {
+				// there is more to send, so don't wait for the next message
+				match ctx.try_recv().await {
+					Ok(Some(message)) => Some(message),
+					Ok(None) => continue,
+					Err(()) => return Err(BitfieldDistributionError::ReceiveFailed),
+				}
+			} else {
+				let mut message = ctx.recv().fuse();
+				select! {
+					message = message => Some(message.map_err(|_| BitfieldDistributionError::ReceiveFailed)?),
+					relay = jittered_relays.next() => {
+						due_relay = relay;
+						None
+					}
+					deadline = quorum_deadlines.next() => {
+						due_deadline = deadline;
+						None
+					}
+					tick = good_standing_ticks.next() => {
+						due_good_standing = tick;
+						None
+					}
+					_ = provision_retry => {
+						due_provision_retry = true;
+						None
+					}
+					_ = regossip_timer => None,
+				}
+			};
+			let message = if let Some(message) = message {
+				message
+			} else {
+				if due_relay.is_none() && due_deadline.is_none() && due_good_standing.is_none() && !due_provision_retry {
+					regossip_own_bitfields(&mut ctx, &mut tracker, &self.metrics).await?;
+					regossip_timer = Delay::new(tracker.config.regossip_interval).fuse();
+				}
+				continue;
+			};
+			match message {
+				FromOverseer::Communication {
+					msg: BitfieldDistributionMessage::DistributeBitfield(hash, signed_availability, ack),
+				} => {
+					trace!(target: "bitd", "Processing DistributeBitfield");
+					let result = distribute_own_bitfield(
+						&mut ctx,
+						&mut tracker,
+						&self.metrics,
+						hash,
+						signed_availability,
+						false,
+					).await?;
+					if let Some(ack) = ack {
+						let _ = ack.send(result);
+					}
+				}
+				FromOverseer::Communication {
+					msg: BitfieldDistributionMessage::UpdateOwnBitfield(hash, signed_availability, ack),
+				} => {
+					trace!(target: "bitd", "Processing UpdateOwnBitfield");
+					let result = distribute_own_bitfield(
+						&mut ctx,
+						&mut tracker,
+						&self.metrics,
+						hash,
+						signed_availability,
+						true,
+					).await?;
+					if let Some(ack) = ack {
+						let _ = ack.send(result);
+					}
+				}
+				FromOverseer::Communication {
+					msg: BitfieldDistributionMessage::NetworkBridgeUpdate(NetworkBridgeEvent::PeerMessage(remote, bytes)),
+				} => {
+					trace!(target: "bitd", "Processing NetworkMessage");
+					// gossip received back to back has its signatures checked at once
+					let mut messages = vec![(remote, bytes)];
+					while messages.len() < tracker.config.max_verification_batch {
+						match ctx.try_recv().await {
+							Ok(Some(FromOverseer::Communication {
+								msg: BitfieldDistributionMessage::NetworkBridgeUpdate(
+									NetworkBridgeEvent::PeerMessage(remote, bytes),
+								),
+							})) => messages.push((remote, bytes)),
+							Ok(Some(message)) => {
+								next_message = Some(message);
+								break;
+							}
+							Ok(None) => break,
+							Err(()) => return Err(BitfieldDistributionError::ReceiveFailed),
+						}
+					}
+					if let Err(e) = handle_peer_messages(
+						&mut ctx,
+						&mut tracker,
+						&self.metrics,
+						&self.reputation,
+						messages,
+					).await {
+						warn!(target: "bitd", "Failed to handle incoming network messages: {:?}", e);
+					}
+				}
+				FromOverseer::Communication {
+					msg: BitfieldDistributionMessage::NetworkBridgeUpdate(event),
+				} => {
+					trace!(target: "bitd", "Processing NetworkMessage");
+					// a network message was received
+					if let Err(e) = handle_network_msg(
+						&mut ctx,
+						&mut tracker,
+						&self.metrics,
+						&self.reputation,
+						event,
+					).await {
+						warn!(target: "bitd", "Failed to handle incoming network messages: {:?}", e);
+					}
+				}
+				FromOverseer::Communication {
+					msg: BitfieldDistributionMessage::QueryCoverage(hash, tx),
+				} => {
+					trace!(target: "bitd", "Processing QueryCoverage");
+					let report = tracker.per_relay_parent.get(&hash).map(|job_data| CoverageReport {
+						validators: job_data.validator_set.len(),
+						missing: job_data.missing_validators(),
+					});
+					let _ = tx.send(report);
+				}
+				FromOverseer::Communication {
+					msg: BitfieldDistributionMessage::QueryPeerView(peer, tx),
+				} => {
+					trace!(target: "bitd", "Processing QueryPeerView");
+					let _ = tx.send(tracker.peer_views.get(&peer).cloned());
+				}
+				FromOverseer::Communication {
+					msg: BitfieldDistributionMessage::QueryAvailabilitySummary(hash, tx),
+				} => {
+					trace!(target: "bitd", "Processing QueryAvailabilitySummary");
+					let summary = tracker.per_relay_parent.get(&hash).map(|job_data| job_data.availability_summary());
+					let _ = tx.send(summary);
+				}
+				FromOverseer::Communication {
+					msg: BitfieldDistributionMessage::Status(tx),
+				} => {
+					trace!(target: "bitd", "Processing Status");
+					let _ = tx.send(tracker.status());
+				}
+				FromOverseer::Communication {
+					msg: BitfieldDistributionMessage::Subscribe(subscriber),
+				} => {
+					trace!(target: "bitd", "Processing Subscribe");
+					tracker.subscribers.push(subscriber);
+				}
+				FromOverseer::Communication {
+					msg: BitfieldDistributionMessage::ImportSnapshot(snapshot),
+				} => {
+					trace!(target: "bitd", "Processing ImportSnapshot");
+					import_snapshot(&mut tracker, &self.metrics, snapshot);
+				}
+				FromOverseer::Communication {
+					msg: BitfieldDistributionMessage::ForgetRelayParent(relay_parent),
+				} => {
+					trace!(target: "bitd", "Processing ForgetRelayParent");
+					tracker.forget_relay_parent(relay_parent);
+					self.metrics.note_tracked_relay_parents(tracker.per_relay_parent.len());
+				}
+				FromOverseer::Communication {
+					msg: BitfieldDistributionMessage::ValidateOnly(relay_parent, signed_availability, tx),
+				} => {
+					trace!(target: "bitd", "Processing ValidateOnly");
+					let _ = tx.send(tracker.check(relay_parent, &signed_availability, None));
+				}
+				FromOverseer::Communication {
+					msg: BitfieldDistributionMessage::CollectBitfields(hash, tx),
+				} => {
+					trace!(target: "bitd", "Processing CollectBitfields");
+					let bitfields = tracker.per_relay_parent
+						.get(&hash)
+						.map(|job_data| job_data.known_bitfields())
+						.unwrap_or_default();
+					let _ = tx.send(bitfields);
+				}
+				FromOverseer::Communication {
+					msg: BitfieldDistributionMessage::HasBitfield(hash, index, tx),
+				} => {
+					trace!(target: "bitd", "Processing HasBitfield");
+					let known = tracker.per_relay_parent
+						.get(&hash)
+						.map_or(false, |job_data| job_data.has_bitfield(index));
+					let _ = tx.send(known);
+				}
+				FromOverseer::Communication {
+					msg: BitfieldDistributionMessage::QueryBitfieldSources(hash, tx),
+				} => {
+					trace!(target: "bitd", "Processing QueryBitfieldSources");
+					let sources = tracker.per_relay_parent.get(&hash).map(|job_data| job_data.bitfield_sources());
+					let _ = tx.send(sources);
+				}
+				FromOverseer::Signal(OverseerSignal::StartWork(relay_parent)) => {
+					start_work(&mut ctx, &mut tracker, &self.metrics, &self.reputation, relay_parent).await?;
+				}
+				FromOverseer::Signal(OverseerSignal::StopWork(relay_parent)) => {
+					stop_work(&mut tracker, &self.metrics, relay_parent);
+				}
+				FromOverseer::Signal(OverseerSignal::ActiveLeaves(ActiveLeavesUpdate { activated, deactivated })) => {
+					// all at once, so no peer message sees some of them applied only
+					trace!(target: "bitd", "Stop {:?} and start {:?}", deactivated, activated);
+					for relay_parent in deactivated {
+						stop_work(&mut tracker, &self.metrics, relay_parent);
+					}
+					for relay_parent in activated {
+						start_work(&mut ctx, &mut tracker, &self.metrics, &self.reputation, relay_parent).await?;
+					}
+				}
+				FromOverseer::Signal(OverseerSignal::Conclude) => {
+					trace!(target: "bitd", "Conclude");
+					flush_deferred_gossip(&mut ctx, &mut tracker, &self.metrics).await?;
+					tracker.per_relay_parent.clear();
+					self.metrics.note_tracked_relay_parents(0);
+					return Ok(());
+				}
+			}
+		}
+	}
+}
+
+/// Distribute a bitfield we signed ourselves.
+///
+/// With `supersede`, it replaces the one we distributed before, if any,
+/// which must not have any bits the new one lacks. Without, it must be the same
+/// as the one we distributed before, if any.
+async fn distribute_own_bitfield<Context>(
+	ctx: &mut Context,
+	tracker: &mut Tracker,
+	metrics: &Metrics,
+	relay_parent: Hash,
+	signed_availability: SignedAvailabilityBitfield,
+	supersede: bool,
+) -> Result<Result<(), DistributeError>>
+where
+	Context: SubsystemContext<Message = BitfieldDistributionMessage>,
+{
+	let job_data = if let Some(job_data) = tracker.per_relay_parent.get_mut(&relay_parent) {
+		job_data
+	} else {
+		// the relay parent may have been stopped just before we got this
+		warn!(
+			target: "bitd",
+			"Asked to distribute a bitfield for relay parent {} we are not working on",
+			relay_parent
+		);
+		return Ok(Err(DistributeError::UnknownRelayParent));
+	};
+	let validator_index = signed_availability.validator_index();
+	let validator = if let Some(validator) = validator_at(&job_data.validator_set, validator_index) {
+		validator.clone()
+	} else {
+		warn!(
+			target: "bitd",
+			"Asked to distribute a bitfield of validator index {} which is not in the validator set of {}",
+			validator_index,
+			relay_parent
+		);
+		return Ok(Err(DistributeError::InvalidValidatorIndex));
+	};
+	if let Some((_, ref previous)) = job_data.own_bitfield {
+		let previous = previous.signed_availability.payload();
+		if supersede && !supersedes(signed_availability.payload(), previous) {
+			warn!(
+				target: "bitd",
+				"Asked to update our bitfield for relay parent {} with one lacking some of its bits",
+				relay_parent
+			);
+			return Ok(Err(DistributeError::NotSuperseding));
+		}
+		if !supersede && signed_availability.payload() != previous {
+			warn!(
+				target: "bitd",
+				"Asked to distribute a bitfield for relay parent {} differing from the one we distributed",
+				relay_parent
+			);
+			return Ok(Err(DistributeError::AlreadyDistributed));
+		}
+	}
+	let msg = BitfieldGossipMessage {
+		relay_parent,
+		signed_availability,
+	};
+	let differs = |known: &BitfieldGossipMessage| {
+		known.signed_availability.payload() != msg.signed_availability.payload()
+	};
+	let gossiped = job_data.one_per_validator.get(&validator).map(|(known, _)| differs(known));
+	let echoed = gossiped == Some(false);
+	let replaces_own = job_data.own_bitfield.as_ref().map_or(false, |(_, own)| differs(own));
+	if gossiped == Some(true) {
+		// A peer gossiped our earlier bitfield to us already, peers catching up
+		// get the new one instead.
+		job_data.insert_message(validator.clone(), msg.clone());
+		job_data.delivered_by.insert(validator.clone(), BitfieldSource::Local);
+	}
+	if gossiped == Some(true) || replaces_own {
+		// everyone needs the new one
+		for sent in job_data.message_sent_to_peer.values_mut() {
+			sent.remove(&validator);
+		}
+	}
+	job_data.bitfield_len.get_or_insert(msg.signed_availability.payload().0.len());
+	job_data.own_bitfield = Some((validator.clone(), msg.clone()));
+	job_data.last_activity = Some(Instant::now());
+	job_data.record_latencies(metrics);
+	if !has_enough_peers(&tracker.peer_views, &tracker.peer_roles, &tracker.config) {
+		trace!(target: "bitd", "Holding back our bitfield for {:?} until more peers connect", relay_parent);
+		tracker.own_bitfields_held = true;
+	}
+	if echoed {
+		// A peer gossiped our bitfield to us before we got to distribute it.
+		// The provisioner knows it already, so only peers lacking it need it.
+		trace!(target: "bitd", "Our bitfield for {:?} was gossiped to us already", relay_parent);
+		send_own_bitfield_to_lacking_peers(
+			ctx,
+			job_data,
+			&tracker.peer_views,
+			&tracker.peer_roles,
+			metrics,
+			&tracker.config,
+		).await?;
+	} else {
+		relay_message(ctx, tracker, metrics, None, validator, msg).await?;
+	}
+	Ok(Ok(()))
+}
+
+/// The validator at the (untrusted) `index` of a validator set, if there is one.
+///
+/// All lookups of a validator by the index of its bitfield go through here, so an index
+/// not fitting a `usize`, as much as one beyond the set, is never used for indexing.
+fn validator_at(validator_set: &[ValidatorId], index: ValidatorIndex) -> Option<&ValidatorId> {
+	usize::try_from(index).ok().and_then(|index| validator_set.get(index))
+}
+
+/// Whether bitfield `new` has all the bits of `old` set and some more,
+/// as only ever more candidates become available.
+fn supersedes(new: &AvailabilityBitfield, old: &AvailabilityBitfield) -> bool {
+	new.0.len() == old.0.len()
+		&& new != old
+		&& old.0.iter().zip(new.0.iter()).all(|(old, new)| !*old || *new)
+}
+
+/// Remember the bitfields of a snapshot for the relay parents in our view we work on,
+/// to be sent to peers catching up.
+fn import_snapshot(
+	tracker: &mut Tracker,
+	metrics: &Metrics,
+	snapshot: Vec<(Hash, Vec<SignedAvailabilityBitfield>)>,
+) {
+	for (relay_parent, bitfields) in snapshot {
+		let job_data = match tracker.per_relay_parent.get_mut(&relay_parent) {
+			Some(job_data) if tracker.view.contains(&relay_parent) => job_data,
+			_ => {
+				debug!(
+					target: "bitd",
+					"Not importing {} bitfields of relay parent {:?} we don't work on",
+					bitfields.len(),
+					relay_parent
+				);
+				continue;
+			}
+		};
+		for signed_availability in bitfields {
+			let validator_index = signed_availability.validator_index();
+			let verifier = &*tracker.signature_verifier.0;
+			if let Err(reason) = job_data.import_bitfield(verifier, relay_parent, signed_availability) {
+				debug!(
+					target: "bitd",
+					"Not importing bitfield of validator at index {} for relay parent {:?}: {}",
+					validator_index,
+					relay_parent,
+					reason.label()
+				);
+				metrics.on_bitfield_rejected(reason);
+			}
+		}
+	}
+}
+
+/// Register the network protocol with the network bridge, retrying with
+/// an exponential backoff up to `Config::max_registration_attempts` times.
+async fn register_event_producer<Context>(ctx: &mut Context, config: &Config) -> Result<()>
+where
+	Context: SubsystemContext<Message = BitfieldDistributionMessage>,
+{
+	let mut backoff = config.registration_backoff;
+	let mut attempt = 1;
+	loop {
+		let result = ctx.send_message(AllMessages::NetworkBridge(
+			NetworkBridgeMessage::RegisterEventProducer(
+				config.protocol_id,
+				network_update_message,
+			),
+		))
+		.await;
+
+		match result {
+			Ok(()) => return Ok(()),
+			Err(e) if attempt < config.max_registration_attempts => {
+				warn!(
+					target: "bitd",
+					"Registering with the network bridge failed at attempt {} of {}: {:?}, retrying in {:?}",
+					attempt,
+					config.max_registration_attempts,
+					e,
+					backoff,
+				);
+				Delay::new(backoff).await;
+				backoff *= 2;
+				attempt += 1;
+			}
+			Err(e) => {
+				warn!(
+					target: "bitd",
+					"Registering with the network bridge failed at attempt {} of {}: {:?}, giving up",
+					attempt,
+					config.max_registration_attempts,
+					e,
+				);
+				return Err(BitfieldDistributionError::RegistrationFailed(attempt));
+			}
+		}
+	}
+}
+
+/// Modify the reputation of a peer based on its behaviour.
+async fn modify_reputation<Context>(
+	ctx: &mut Context,
+	tracker: &mut Tracker,
+	peer: PeerId,
+	rep: ReputationChange,
+) -> Result<()>
+where
+	Context: SubsystemContext<Message = BitfieldDistributionMessage>,
+{
+	if !tracker.note_reputation_change(&peer, rep, Instant::now()) {
+		trace!(target: "bitd", "Holding back reputation change of {:?} for peer {:?}", rep, peer);
+		return Ok(());
+	}
+	trace!(target: "bitd", "Reputation change of {:?} for peer {:?}", rep, peer);
+	ctx.send_message(AllMessages::NetworkBridge(
+		NetworkBridgeMessage::ReportPeer(peer, rep),
+	))
+	.await.map_err(|_| BitfieldDistributionError::SendFailed)
+}
+
+/// Report the penalties held back whose `penalty_coalescing_window` ended.
+async fn report_coalesced_penalties<Context>(ctx: &mut Context, tracker: &mut Tracker) -> Result<()>
+where
+	Context: SubsystemContext<Message = BitfieldDistributionMessage>,
+{
+	let penalties = tracker.take_coalesced_penalties(Instant::now());
+	if penalties.is_empty() {
+		return Ok(());
+	}
+	trace!(target: "bitd", "Reporting {} coalesced penalties", penalties.len());
+	ctx.send_messages(
+		penalties
+			.into_iter()
+			.map(|(peer, rep)| AllMessages::NetworkBridge(NetworkBridgeMessage::ReportPeer(peer, rep)))
+			.collect::<Vec<_>>(),
+	)
+	.await.map_err(|_| BitfieldDistributionError::SendFailed)
+}
+
+/// Reward the peers in good standing once a `good_standing_interval` ended.
+async fn reward_good_standing<Context>(
+	ctx: &mut Context,
+	tracker: &mut Tracker,
+	reputation: &ReputationConfig,
+) -> Result<()>
+where
+	Context: SubsystemContext<Message = BitfieldDistributionMessage>,
+{
+	let peers = tracker.take_peers_in_good_standing();
+	if peers.is_empty() {
+		return Ok(());
+	}
+	trace!(target: "bitd", "Rewarding {} peers in good standing", peers.len());
+	ctx.send_messages(
+		peers
+			.into_iter()
+			.map(|peer| AllMessages::NetworkBridge(NetworkBridgeMessage::ReportPeer(peer, reputation.gain_good_standing)))
+			.collect::<Vec<_>>(),
+	)
+	.await.map_err(|_| BitfieldDistributionError::SendFailed)
+}
+
+/// Start working on `relay_parent`, and process the bitfields of it gossiped to us before.
+async fn start_work<Context>(
+	ctx: &mut Context,
+	tracker: &mut Tracker,
+	metrics: &Metrics,
+	reputation: &ReputationConfig,
+	relay_parent: Hash,
+) -> Result<()>
+where
+	Context: SubsystemContext<Message = BitfieldDistributionMessage>,
+{
+	trace!(target: "bitd", "Start {:?}", relay_parent);
+	if tracker.per_relay_parent.contains_key(&relay_parent) {
+		debug!(target: "bitd", "Already working on relay parent {:?}", relay_parent);
+		return Ok(());
+	}
+	// signals may be reordered, a `StartWork` right after the `StopWork` is a late one
+	if tracker.stopped_recently(&relay_parent) {
+		debug!(target: "bitd", "Ignoring late start of relay parent {:?} stopped already", relay_parent);
+		return Ok(());
+	}
+	tracker.forgotten.remove(&relay_parent);
+	// query basic system parameters once
+	let basics = match query_basics(
+		ctx,
+		&mut tracker.validators_by_session,
+		relay_parent,
+	).await {
+		Ok(basics) => basics,
+		Err(BitfieldDistributionError::RuntimeApiCanceled) => {
+			warn!(
+				target: "bitd",
+				"Runtime API request for relay parent {} was canceled, not working on it",
+				relay_parent
+			);
+			return Ok(());
+		}
+		Err(e) => return Err(e),
+	};
+
+	let now = Instant::now();
+	let _ = tracker.per_relay_parent.insert(
+		relay_parent,
+		PerRelayParentData {
+			signing_context: basics.signing_context,
+			validator_set: basics.validators,
+			last_activity: Some(now),
+			started: Some(now),
+			// one bit per core, otherwise the first bitfield tells
+			bitfield_len: basics.availability_cores,
+			..Default::default()
+		},
+	);
+	tracker.evict_inactive_relay_parents();
+	metrics.note_tracked_relay_parents(tracker.per_relay_parent.len());
+
+	if let Some(quorum_deadlines) = tracker.quorum_deadlines.clone() {
+		let deadline = tracker.config.quorum_deadline;
+		if deadline > Duration::from_secs(0) {
+			let (timer, abort_handle) = future::abortable(Delay::new(deadline));
+			if let Some(job_data) = tracker.per_relay_parent.get_mut(&relay_parent) {
+				job_data.quorum_deadline = Some(abort_handle);
+			}
+			ctx.spawn("bitfield-quorum-deadline", Box::pin(async move {
+				if timer.await.is_ok() {
+					let _ = quorum_deadlines.unbounded_send((relay_parent, now));
+				}
+			})).await.map_err(|_| BitfieldDistributionError::SpawnFailed("bitfield-quorum-deadline"))?;
+		}
+	}
+
+	// our view may have contained the relay parent before we were told to work on it
+	let early_messages = tracker.early_messages.remove(&relay_parent).unwrap_or_default();
+	for (origin, message) in early_messages {
+		if let Err(e) = process_incoming_peer_message(
+			ctx,
+			tracker,
+			metrics,
+			reputation,
+			origin,
+			message,
+			None,
+		).await {
+			warn!(target: "bitd", "Failed to handle early bitfield gossip: {:?}", e);
+		}
+	}
+	Ok(())
+}
+
+/// Stop working on `relay_parent`.
+fn stop_work(tracker: &mut Tracker, metrics: &Metrics, relay_parent: Hash) {
+	trace!(target: "bitd", "Stop {:?}", relay_parent);
+	// @todo assumption: it is good enough to prevent additional work from being
+	// scheduled, the individual futures are supposedly completed quickly
+	if let Some(quorum_deadline) = tracker.per_relay_parent.remove(&relay_parent)
+		.and_then(|job_data| job_data.quorum_deadline)
+	{
+		quorum_deadline.abort();
+	}
+	// even if not started yet, so a late `StartWork` is ignored
+	tracker.note_stopped(relay_parent);
+	tracker.early_messages.remove(&relay_parent);
+	tracker.pending_provisions.retain(|provision| provision.relay_parent() != relay_parent);
+	metrics.note_tracked_relay_parents(tracker.per_relay_parent.len());
+}
+
+/// Log the validators a bitfield of is still missing once the `quorum_deadline` of `relay_parent`
+/// passed without a quorum, unless we stopped working on it in-between.
+///
+/// Returns the indices of the missing validators if the deadline was missed.
+fn check_quorum_deadline(
+	tracker: &Tracker,
+	metrics: &Metrics,
+	relay_parent: Hash,
+	started: Instant,
+) -> Option<Vec<ValidatorIndex>> {
+	let job_data = tracker.per_relay_parent.get(&relay_parent)?;
+	// the deadline of an earlier time we worked on the relay parent is not of interest
+	if job_data.started != Some(started) || job_data.quorum_at.is_some() {
+		return None;
+	}
+	let missing = job_data.missing_validators();
+	tracing::warn!(
+		target: "bitd",
+		"No quorum of bitfields for relay parent {:?} within {:?}, missing those of validators {:?}",
+		relay_parent,
+		tracker.config.quorum_deadline,
+		missing,
+	);
+	metrics.on_quorum_timeout();
+	Some(missing)
+}
+
+/// Distribute a given valid and signature checked bitfield message.
+///
+/// `origin` is the peer we received it from, which never gets it back,
+/// or `None` if the source is this node.
+#[tracing::instrument(
+	level = "trace",
+	target = "bitd",
+	skip(ctx, tracker, metrics, origin, validator, message),
+	fields(
+		relay_parent = ?message.relay_parent,
+		validator_index = message.signed_availability.validator_index(),
+	),
+)]
+async fn relay_message<Context>(
+	ctx: &mut Context,
+	tracker: &mut Tracker,
+	metrics: &Metrics,
+	origin: Option<PeerId>,
+	validator: ValidatorId,
+	message: BitfieldGossipMessage,
+) -> Result<()>
+where
+	Context: SubsystemContext<Message = BitfieldDistributionMessage>,
+{
+	// notify the overseer about a new and valid signed bitfield
+	let relay_parent = message.relay_parent;
+	provision(ctx, tracker, metrics, Provision::Bitfield(relay_parent, message.signed_availability.clone())).await?;
+	// once it completes the quorum, or the first time after that if it was reached otherwise
+	let notify_quorum = tracker.config.notify_quorum;
+	let quorum_reached = tracker.per_relay_parent.get_mut(&relay_parent).map_or(false, |job_data| {
+		notify_quorum && job_data.quorum_at.is_some() && !std::mem::replace(&mut job_data.quorum_notified, true)
+	});
+	if quorum_reached {
+		provision(ctx, tracker, metrics, Provision::Quorum(relay_parent)).await?;
+	}
+	tracker.notify_subscribers(message.relay_parent, &message.signed_availability);
+	tracker.replay_cache.note(
+		ReplayCache::key(message.relay_parent, &message.signed_availability),
+		Instant::now(),
+		tracker.config.replay_window,
+		tracker.config.max_replay_cache,
+	);
+
+	// our own bitfield is sent once enough peers are connected
+	if origin.is_none() && !has_enough_peers(&tracker.peer_views, &tracker.peer_roles, &tracker.config) {
+		return Ok(());
+	}
+
+	match (origin, tracker.jittered_relays.clone()) {
+		(Some(origin), Some(jittered_relays)) if tracker.config.max_relay_jitter > Duration::from_secs(0) => {
+			// the peer we got it from has it already, however long the relay takes
+			if let Some(job_data) = tracker.per_relay_parent.get_mut(&message.relay_parent) {
+				job_data.message_sent_to_peer.entry(origin.clone()).or_default().insert(validator.clone());
+			}
+			let delay = tracker.config.max_relay_jitter.mul_f64(tracker.rng.0.gen());
+			trace!(target: "bitd", "Relaying bitfield of relay parent {:?} in {:?}", message.relay_parent, delay);
+			let relay = JitteredRelay { origin, validator, message };
+			ctx.spawn("bitfield-relay-jitter", Box::pin(async move {
+				Delay::new(delay).await;
+				let _ = jittered_relays.unbounded_send(relay);
+			})).await.map_err(|_| BitfieldDistributionError::SpawnFailed("bitfield-relay-jitter"))
+		}
+		(origin, _) => forward_message(ctx, tracker, metrics, origin, validator, message).await,
+	}
+}
+
+/// Relay a bitfield message handed to the provisioner already to a random subset of the interested peers.
+async fn forward_message<Context>(
+	ctx: &mut Context,
+	tracker: &mut Tracker,
+	metrics: &Metrics,
+	origin: Option<PeerId>,
+	validator: ValidatorId,
+	message: BitfieldGossipMessage,
+) -> Result<()>
+where
+	Context: SubsystemContext<Message = BitfieldDistributionMessage>,
+{
+	let job_data = if let Some(job_data) = tracker.per_relay_parent.get_mut(&message.relay_parent) {
+		job_data
+	} else {
+		trace!(target: "bitd", "Not relaying a bitfield of relay parent {:?} we don't work on", message.relay_parent);
+		return Ok(());
+	};
+	if !tracker.config.gossips(message.signed_availability.payload()) {
+		trace!(target: "bitd", "Not relaying an empty bitfield of relay parent {:?}", message.relay_parent);
+		return Ok(());
+	}
+	let message_sent_to_peer = &mut (job_data.message_sent_to_peer);
+
+	// pass on the bitfield distribution to a random subset of interested peers
+	let mut interested_peers = interested_peers(
+		&tracker.peer_views,
+		&tracker.peer_roles,
+		&message.relay_parent,
+		origin.as_ref(),
+	);
+
+	// the peers are kept in a `HashMap`, so bring them into a stable order
+	// for the same randomness to pick the same peers
+	interested_peers.sort_by(|a, b| a.as_bytes().cmp(b.as_bytes()));
+
+	let fan_out = tracker.config.fan_out.limit(interested_peers.len());
+	let _ = interested_peers.partial_shuffle(&mut tracker.rng.0, fan_out);
+	interested_peers.truncate(fan_out);
+
+	for peer in interested_peers.iter().chain(origin.iter()) {
+		// track the message as sent for this peer, or as known by the one we got it from
+		message_sent_to_peer
+			.entry(peer.clone())
+			.or_default()
+			.insert(validator.clone());
+	}
+
+	if interested_peers.is_empty() {
+		trace!(
+			target: "bitd",
+			"No peers are interested in gossip for relay parent {:?}",
+			message.relay_parent
+		);
+	} else {
+		let deferred = DeferredSend {
+			relay_parent: message.relay_parent,
+			validator,
+			peers: interested_peers,
+			bytes: tracker.config.wire_bytes(message.encode_versioned()),
+		};
+		send_gossip(ctx, tracker, metrics, deferred).await?;
+	}
+	Ok(())
+}
+
+/// The full peers other than `origin` having `relay_parent` in their view.
+///
+/// Room for all connected peers is reserved up front, so relaying to many of them
+/// doesn't reallocate over and over.
+fn interested_peers(
+	peer_views: &HashMap<PeerId, View>,
+	peer_roles: &HashMap<PeerId, ObservedRole>,
+	relay_parent: &Hash,
+	origin: Option<&PeerId>,
+) -> Vec<PeerId> {
+	let mut interested_peers = Vec::with_capacity(peer_views.len());
+	interested_peers.extend(
+		peer_views
+			.iter()
+			.filter(|(peer, view)| {
+				view.contains(relay_parent) && !is_light(peer_roles, peer) && origin != Some(*peer)
+			})
+			.map(|(peer, _)| peer.clone()),
+	);
+	interested_peers
+}
+
+/// A relayed bitfield which is yet to be sent to the network bridge.
+#[derive(Debug, Clone)]
+struct DeferredSend {
+	relay_parent: Hash,
+	validator: ValidatorId,
+	/// The peers the bitfield is tracked as sent to already.
+	peers: Vec<PeerId>,
+	bytes: Vec<u8>,
+}
+
+/// Send a relayed bitfield to the network bridge, unless this iteration
+/// sent `max_sends_per_iteration` ones already, which defers it.
+async fn send_gossip<Context>(
+	ctx: &mut Context,
+	tracker: &mut Tracker,
+	metrics: &Metrics,
+	deferred: DeferredSend,
+) -> Result<()>
+where
+	Context: SubsystemContext<Message = BitfieldDistributionMessage>,
+{
+	if tracker.sends_this_iteration >= tracker.config.max_sends_per_iteration {
+		trace!(target: "bitd", "Deferring a bitfield relay, {} are deferred already", tracker.deferred_sends.len());
+		if tracker.deferred_sends.len() >= tracker.config.max_deferred_sends {
+			debug!(target: "bitd", "More than {} bitfield relays deferred, dropping the oldest", tracker.config.max_deferred_sends);
+			if let Some(oldest) = tracker.deferred_sends.pop_front() {
+				tracker.drop_deferred_send(oldest);
+			}
+		}
+		tracker.deferred_sends.push_back(deferred);
+		return Ok(());
+	}
+
+	let DeferredSend { peers, bytes, .. } = deferred;
+	let peers = tracker.still_tracked(peers);
+	if peers.is_empty() {
+		return Ok(());
+	}
+
+	tracker.sends_this_iteration += 1;
+	ctx.send_message(AllMessages::NetworkBridge(
+		NetworkBridgeMessage::SendMessage(
+			peers,
+			tracker.config.protocol_id,
+			bytes,
+		),
+	))
+	.await.map_err(|_| BitfieldDistributionError::SendFailed)?;
+	metrics.on_bitfield_relayed();
+	Ok(())
+}
+
+/// Send as many deferred bitfield relays as this iteration allows.
+async fn send_deferred_gossip<Context>(
+	ctx: &mut Context,
+	tracker: &mut Tracker,
+	metrics: &Metrics,
+) -> Result<()>
+where
+	Context: SubsystemContext<Message = BitfieldDistributionMessage>,
+{
+	while tracker.sends_this_iteration < tracker.config.max_sends_per_iteration {
+		let deferred = match tracker.deferred_sends.pop_front() {
+			Some(deferred) => deferred,
+			None => break,
+		};
+		send_gossip(ctx, tracker, metrics, deferred).await?;
+	}
+	Ok(())
+}
+
+/// What is handed to the provisioner.
+#[derive(Debug, Clone)]
+enum Provision {
+	/// A new and valid bitfield of the relay parent.
+	Bitfield(Hash, SignedAvailabilityBitfield),
+	/// Bitfields of more than 2/3 of the validators of the relay parent were handed over.
+	Quorum(Hash),
+}
+
+impl Provision {
+	fn relay_parent(&self) -> Hash {
+		match self {
+			Provision::Bitfield(relay_parent, _) | Provision::Quorum(relay_parent) => *relay_parent,
+		}
+	}
+
+	fn into_message(self) -> ProvisionerMessage {
+		match self {
+			Provision::Bitfield(relay_parent, signed) => {
+				ProvisionerMessage::ProvisionableData(ProvisionableData::Bitfield(relay_parent, signed))
+			}
+			Provision::Quorum(relay_parent) => ProvisionerMessage::AvailabilityQuorum(relay_parent),
+		}
+	}
+}
+
+/// Hand `provision` to the provisioner after those buffered before, buffering it as well
+/// should the queue to the overseer be full.
+async fn provision<Context>(
+	ctx: &mut Context,
+	tracker: &mut Tracker,
+	metrics: &Metrics,
+	provision: Provision,
+) -> Result<()>
+where
+	Context: SubsystemContext<Message = BitfieldDistributionMessage>,
+{
+	if tracker.pending_provisions.is_empty() && try_provision(ctx, provision.clone()).await? {
+		return Ok(());
+	}
+	trace!(target: "bitd", "Buffering {:?} for the provisioner", provision);
+	tracker.buffer_provision(metrics, provision);
+	Ok(())
+}
+
+/// Hand `provision` to the provisioner unless the queue to the overseer is full,
+/// returning whether it was handed over.
+///
+/// A send is never abandoned half way, a message might be handed over already while
+/// its send is still pending, and would be handed over twice then.
+async fn try_provision<Context>(ctx: &mut Context, provision: Provision) -> Result<bool>
+where
+	Context: SubsystemContext<Message = BitfieldDistributionMessage>,
+{
+	ctx.try_send_message(AllMessages::Provisioner(provision.into_message()))
+		.await
+		.map_err(|_| BitfieldDistributionError::SendFailed)
+}
+
+/// Hand what is buffered for the provisioner over, as long as the queue to the overseer has room.
+async fn send_pending_provisions<Context>(ctx: &mut Context, tracker: &mut Tracker) -> Result<()>
+where
+	Context: SubsystemContext<Message = BitfieldDistributionMessage>,
+{
+	while let Some(provision) = tracker.pending_provisions.front().cloned() {
+		if !try_provision(ctx, provision).await? {
+			break;
+		}
+		tracker.pending_provisions.pop_front();
+	}
+	Ok(())
+}
+
+/// Send all deferred bitfield relays, regardless of the per-iteration cap.
+async fn flush_deferred_gossip<Context>(
+	ctx: &mut Context,
+	tracker: &mut Tracker,
+	metrics: &Metrics,
+) -> Result<()>
+where
+	Context: SubsystemContext<Message = BitfieldDistributionMessage>,
+{
+	trace!(target: "bitd", "Flushing {} deferred bitfield relays", tracker.deferred_sends.len());
+	while let Some(DeferredSend { peers, bytes, .. }) = tracker.deferred_sends.pop_front() {
+		let peers = tracker.still_tracked(peers);
+		if peers.is_empty() {
+			continue;
+		}
+		ctx.send_message(AllMessages::NetworkBridge(
+			NetworkBridgeMessage::SendMessage(
+				peers,
+				tracker.config.protocol_id,
+				bytes,
+			),
+		))
+		.await.map_err(|_| BitfieldDistributionError::SendFailed)?;
+		metrics.on_bitfield_relayed();
+	}
+	Ok(())
+}
+
+/// Whether the peer is a light client, which has no use for bitfields.
+fn is_light(peer_roles: &HashMap<PeerId, ObservedRole>, peer: &PeerId) -> bool {
+	peer_roles.get(peer).map_or(false, ObservedRole::is_light)
+}
+
+/// Whether enough full peers are connected to send them our own bitfields.
+fn has_enough_peers(
+	peer_views: &HashMap<PeerId, View>,
+	peer_roles: &HashMap<PeerId, ObservedRole>,
+	config: &Config,
+) -> bool {
+	let full_peers = peer_views.keys().filter(|peer| !is_light(peer_roles, peer)).count();
+	full_peers >= config.min_peers_for_own_bitfields
+}
+
+/// Send our own bitfields held back for too few peers being connected,
+/// once there are enough.
+async fn release_own_bitfields<Context>(
+	ctx: &mut Context,
+	tracker: &mut Tracker,
+	metrics: &Metrics,
+) -> Result<()>
+where
+	Context: SubsystemContext<Message = BitfieldDistributionMessage>,
+{
+	if !tracker.own_bitfields_held || !has_enough_peers(&tracker.peer_views, &tracker.peer_roles, &tracker.config) {
+		return Ok(());
+	}
+	debug!(target: "bitd", "Enough peers connected, sending our bitfields held back");
+	tracker.own_bitfields_held = false;
+	regossip_own_bitfields(ctx, tracker, metrics).await
+}
+
+/// Send our own bitfields to all interested peers which did not get them yet,
+/// e.g. because they connected after we distributed them.
+async fn regossip_own_bitfields<Context>(
+	ctx: &mut Context,
+	tracker: &mut Tracker,
+	metrics: &Metrics,
+) -> Result<()>
+where
+	Context: SubsystemContext<Message = BitfieldDistributionMessage>,
+{
+	for job_data in tracker.per_relay_parent.values_mut() {
+		send_own_bitfield_to_lacking_peers(
+			ctx,
+			job_data,
+			&tracker.peer_views,
+			&tracker.peer_roles,
+			metrics,
+			&tracker.config,
+		).await?;
+	}
+	Ok(())
+}
+
+/// Send our own bitfield of a relay parent to all interested peers which did not get it yet.
+async fn send_own_bitfield_to_lacking_peers<Context>(
+	ctx: &mut Context,
+	job_data: &mut PerRelayParentData,
+	peer_views: &HashMap<PeerId, View>,
+	peer_roles: &HashMap<PeerId, ObservedRole>,
+	metrics: &Metrics,
+	config: &Config,
+) -> Result<()>
+where
+	Context: SubsystemContext<Message = BitfieldDistributionMessage>,
+{
+	if !has_enough_peers(peer_views, peer_roles, config) {
+		return Ok(());
+	}
+	let (validator, message) = match &job_data.own_bitfield {
+		Some((_, message)) if !config.gossips(message.signed_availability.payload()) => return Ok(()),
+		Some(own_bitfield) => own_bitfield,
+		None => return Ok(()),
+	};
+
+	let lacking_peers = peer_views
+		.iter()
+		.filter(|(peer, view)| {
+			view.contains(&message.relay_parent)
+				&& !is_light(peer_roles, peer)
+				&& job_data.needs_message(peer, validator)
+		})
+		.map(|(peer, _)| peer.clone())
+		.collect::<Vec<PeerId>>();
+
+	if lacking_peers.is_empty() {
+		return Ok(());
+	}
+
+	trace!(
+		target: "bitd",
+		"Sending our bitfield for relay parent {:?} to {} peers",
+		message.relay_parent,
+		lacking_peers.len()
+	);
+	for peer in lacking_peers.iter() {
+		job_data.message_sent_to_peer
+			.entry(peer.clone())
+			.or_default()
+			.insert(validator.clone());
+	}
+	ctx.send_message(AllMessages::NetworkBridge(
+		NetworkBridgeMessage::SendMessage(
+			lacking_peers,
+			config.protocol_id,
+			config.wire_bytes(message.encode_versioned()),
+		),
+	))
+	.await.map_err(|_| BitfieldDistributionError::SendFailed)?;
+	metrics.on_bitfield_relayed();
+	Ok(())
+}
+
+/// Handle an incoming message from a peer.
+///
+/// Checks are ordered by cost, so that the cheap ones reject garbage before
+/// any cryptographic work is done: relay parent relevance, validator set
+/// and index lookups and deduplication come first, signature verification last.
+/// The latter is skipped if `signature_valid` was determined along with others already.
+///
+/// Messages are processed one at a time, so of several peers delivering the same new bitfield,
+/// the one whose message is processed first earns `gain_valid_message_first`, even if all of
+/// their signatures were checked in a single batch. Its identical copies each earn `gain_valid_message`,
+/// unless the bitfield was relayed to their peer in the meantime, as we can't tell them from an echo.
+#[tracing::instrument(
+	level = "trace",
+	target = "bitd",
+	skip(ctx, tracker, metrics, reputation, origin, message, signature_valid),
+	fields(
+		relay_parent = ?message.relay_parent,
+		validator_index = message.signed_availability.validator_index(),
+		peer_id = ?origin,
+	),
+)]
+async fn process_incoming_peer_message<Context>(
+	ctx: &mut Context,
+	tracker: &mut Tracker,
+	metrics: &Metrics,
+	reputation: &ReputationConfig,
+	origin: PeerId,
+	message: BitfieldGossipMessage,
+	signature_valid: Option<bool>,
+) -> Result<()>
+where
+	Context: SubsystemContext<Message = BitfieldDistributionMessage>,
+{
+	if !tracker.accepts_bitfields_of(&origin) {
+		trace!(target: "bitd", "Rejecting bitfield of peer {:?} which is no current authority", origin);
+		metrics.on_bitfield_rejected(RejectReason::NotAuthority);
+		return modify_reputation(ctx, tracker, origin, reputation.cost_not_authority).await;
+	}
+
+	let relay_parent = message.relay_parent;
+	let validator_index = message.signed_availability.validator_index();
+	let verdict = tracker.check(relay_parent, &message.signed_availability, signature_valid);
+
+	match verdict {
+		ValidationVerdict::NotInterested => {
+			// peers may still be propagating bitfields of relay parents we just stopped working on
+			if tracker.stopped_recently(&relay_parent) {
+				trace!(target: "bitd", "Ignoring bitfield for recently stopped relay parent {:?}", relay_parent);
+				return Ok(());
+			}
+			// we don't care about this, not part of our view or forgotten already
+			metrics.on_bitfield_rejected(RejectReason::NotInterested);
+			return modify_reputation(ctx, tracker, origin, reputation.cost_not_interested).await;
+		}
+		ValidationVerdict::NotWorkedOn => {
+			// Ignore anything the overseer did not tell this subsystem to work on, unless it
+			// will shortly, as the relay parent is in our view already.
+			// So something is off on our side, not the peer's.
+			metrics.on_untracked_relay_parent_bitfield();
+			let early_messages = tracker.early_messages.entry(relay_parent).or_default();
+			if early_messages.len() < tracker.config.max_early_messages {
+				trace!(
+					target: "bitd",
+					"Buffering bitfield for relay parent {:?} we were not told to work on yet",
+					relay_parent
+				);
+				early_messages.push((origin, message));
+				return Ok(());
+			}
+			metrics.on_bitfield_rejected(RejectReason::UntrackedRelayParent);
+			return modify_reputation(ctx, tracker, origin, reputation.cost_relay_parent_unknown_but_in_view).await;
+		}
+		_ => {}
+	}
+
+	// all other verdicts are of a relay parent we work on
+	let job_data = match tracker.per_relay_parent.get_mut(&relay_parent) {
+		Some(job_data) => job_data,
+		None => return Ok(()),
+	};
+	if verdict == ValidationVerdict::ContextMismatch {
+		warn!(
+			target: "bitd",
+			"Signing context of relay parent {:?} is of {:?}",
+			relay_parent,
+			job_data.signing_context.parent_hash
+		);
+		metrics.on_bitfield_rejected(RejectReason::ContextMismatch);
+		return modify_reputation(ctx, tracker, origin, reputation.cost_context_mismatch).await;
+	}
+
+	// shared rather than borrowed from the tracker, which the penalties below need,
+	// so the public key is only cloned once the bitfield is known to be new
+	let validator_set = job_data.validator_set.clone();
+	let validator = match validator_at(&validator_set, validator_index) {
+		Some(validator) => validator,
+		None if validator_set.is_empty() => {
+			trace!(target: "bitd", "Validator set for {:?} is empty", relay_parent);
+			return modify_reputation(ctx, tracker, origin, reputation.cost_missing_peer_session_key).await;
+		}
+		None => {
+			metrics.on_bitfield_rejected(RejectReason::ValidatorIndexInvalid);
+			return modify_reputation(ctx, tracker, origin, reputation.cost_validator_index_invalid).await;
+		}
+	};
+
+	match verdict {
+		ValidationVerdict::Accepted => {}
+		ValidationVerdict::Known => {
+			trace!(
+				target: "bitd",
+				"Already received a message for validator at index {}",
+				validator_index
+			);
+			// An exact copy of the already checked message is valid too, just not new.
+			// Only reward it once per peer, which then obviously has the message;
+			// any further copy of the same peer, a superseded or a replayed bitfield is ignored, without a penalty.
+			let exact_copy = job_data.known_message_of(validator)
+				.map_or(false, |known| known.signed_availability == message.signed_availability);
+			if exact_copy {
+				let known_by_peer = job_data.message_sent_to_peer.entry(origin.clone()).or_default();
+				if known_by_peer.insert(validator.clone()) {
+					tracker.note_valid_bitfield(&origin);
+					return modify_reputation(ctx, tracker, origin, reputation.gain_valid_message).await;
+				}
+			}
+			return Ok(());
+		}
+		ValidationVerdict::Equivocation if job_data.equivocators.contains(validator) => {
+			trace!(
+				target: "bitd",
+				"Received another bitfield of validator at index {}, which equivocated already",
+				validator_index
+			);
+			return modify_reputation(ctx, tracker, origin, reputation.cost_repeated_equivocation).await;
+		}
+		ValidationVerdict::Equivocation => {
+			warn!(
+				target: "bitd",
+				"Validator at index {} signed two different bitfields for relay parent {:?}",
+				validator_index,
+				relay_parent
+			);
+			job_data.equivocators.insert(validator.clone());
+			return modify_reputation(ctx, tracker, origin, reputation.cost_equivocation).await;
+		}
+		ValidationVerdict::WrongSession => {
+			metrics.on_bitfield_rejected(RejectReason::WrongSession);
+			return modify_reputation(ctx, tracker, origin, reputation.cost_wrong_session).await;
+		}
+		ValidationVerdict::InvalidSignature => {
+			metrics.on_bitfield_rejected(RejectReason::SignatureInvalid);
+			return modify_reputation(ctx, tracker, origin, reputation.cost_signature_invalid).await;
+		}
+		ValidationVerdict::InconsistentLength => {
+			debug!(
+				target: "bitd",
+				"Bitfield of validator at index {} has {} bits, but others of relay parent {:?} have {:?}",
+				validator_index,
+				message.signed_availability.payload().0.len(),
+				relay_parent,
+				job_data.bitfield_len,
+			);
+			metrics.on_bitfield_rejected(RejectReason::InconsistentLength);
+			return modify_reputation(ctx, tracker, origin, reputation.cost_inconsistent_bitfield_length).await;
+		}
+		ValidationVerdict::ValidatorSetMismatch => {
+			warn!(
+				target: "bitd",
+				"Already know {} bitfields for the {} validators of relay parent {:?}, the validator set is stale",
+				job_data.one_per_validator.len(),
+				job_data.validator_set.len(),
+				relay_parent,
+			);
+			metrics.on_bitfield_rejected(RejectReason::ValidatorSetMismatch);
+			return Ok(());
+		}
+		// dealt with above
+		ValidationVerdict::NotInterested
+		| ValidationVerdict::NotWorkedOn
+		| ValidationVerdict::ContextMismatch
+		| ValidationVerdict::InvalidIndex => return Ok(()),
+	}
+
+	// remember this one
+	let validator = validator.clone();
+	if job_data.known_message_of(&validator).is_some() {
+		// it supersedes the one we know, so everyone needs it
+		for sent in job_data.message_sent_to_peer.values_mut() {
+			sent.remove(&validator);
+		}
+	}
+	job_data.bitfield_len.get_or_insert(message.signed_availability.payload().0.len());
+	job_data.insert_message(validator.clone(), message.clone());
+	job_data.delivered_by.insert(validator.clone(), BitfieldSource::Peer(origin.clone()));
+	job_data.last_activity = Some(Instant::now());
+	job_data.record_latencies(metrics);
+
+	tracker.note_valid_bitfield(&origin);
+	modify_reputation(ctx, tracker, origin.clone(), reputation.gain_valid_message_first).await?;
+
+	relay_message(ctx, tracker, metrics, Some(origin), validator, message).await
+}
+
+/// Deal with network bridge updates and track what needs to be tracked
+/// which depends on the message type received.
+async fn handle_network_msg<Context>(
+	ctx: &mut Context,
+	tracker: &mut Tracker,
+	metrics: &Metrics,
+	reputation: &ReputationConfig,
+	bridge_message: NetworkBridgeEvent,
+) -> Result<()>
+where
+	Context: SubsystemContext<Message = BitfieldDistributionMessage>,
+{
+	match bridge_message {
+		NetworkBridgeEvent::PeerConnected(peerid, role) => {
+			// insert if none already present
+			tracker.peer_views.entry(peerid.clone()).or_default();
+			tracker.peer_roles.insert(peerid.clone(), role);
+			tracker.evicted_peers.remove(&peerid);
+			tracker.note_peer_activity(&peerid);
+		}
+		NetworkBridgeEvent::PeerDisconnected(peerid) => {
+			// get rid of superfluous data
+			tracker.forget_peer(&peerid);
+			tracker.evicted_peers.remove(&peerid);
+		}
+		NetworkBridgeEvent::PeerViewChange(peerid, view) => {
+			// peers are tracked from `PeerConnected` until `PeerDisconnected`,
+			// views of any other peer would never be cleaned up
+			if !tracker.note_peer_activity(&peerid) {
+				trace!(target: "bitd", "View change of peer {:?} which is not connected", &peerid);
+				return modify_reputation(ctx, tracker, peerid, reputation.cost_view_change_not_connected).await;
+			}
+			// not even worth capping, the peer keeps its previous view
+			if view.0.len() > tracker.config.max_acceptable_view_len {
+				debug!(target: "bitd", "Ignoring view of peer {:?} with {} relay parents", &peerid, view.0.len());
+				return modify_reputation(ctx, tracker, peerid, reputation.cost_oversized_view).await;
+			}
+			if !tracker.note_peer_view_change(&peerid) {
+				// the view is still tracked, as we would otherwise send the peer the wrong bitfields
+				debug!(target: "bitd", "Peer {:?} changes its view too often", &peerid);
+				modify_reputation(ctx, tracker, peerid.clone(), reputation.cost_view_flapping).await?;
+			}
+			let view = if view.0.len() > tracker.config.max_peer_view_len {
+				debug!(target: "bitd", "Peer {:?} has {} relay parents in its view", &peerid, view.0.len());
+				modify_reputation(ctx, tracker, peerid.clone(), reputation.cost_view_beyond_quota).await?;
+				tracker.cap_peer_view(view)
+			} else {
+				view
+			};
+			catch_up_messages(ctx, tracker, reputation, peerid, view).await?;
+			// the view of a peer connecting just before is known by now
+			release_own_bitfields(ctx, tracker, metrics).await?;
+		}
+		NetworkBridgeEvent::OurViewChange(view) => {
+			let old_view = std::mem::replace(&mut (tracker.view), view);
+
+			for new in tracker.view.difference(&old_view) {
+				if !tracker.per_relay_parent.contains_key(&new) {
+					debug!(
+						target: "bitd",
+						"Our view contains {} but the overseer did not tell us to work on this yet",
+						&new
+					);
+				}
+			}
+
+			let view = &tracker.view;
+			tracker.early_messages.retain(|relay_parent, _| view.contains(relay_parent));
+			tracker.forgotten.retain(|relay_parent| view.contains(relay_parent));
+		}
+		NetworkBridgeEvent::PeerMessage(remote, bytes) => {
+			handle_peer_messages(ctx, tracker, metrics, reputation, vec![(remote, bytes)]).await?;
+		}
+	}
+	Ok(())
+}
+
+/// Decode a gossip message of a peer into the bitfields it carries, after the cheap checks against floods.
+async fn decode_peer_message<Context>(
+	ctx: &mut Context,
+	tracker: &mut Tracker,
+	metrics: &Metrics,
+	reputation: &ReputationConfig,
+	remote: PeerId,
+	bytes: Vec<u8>,
+	bitfields: &mut Vec<(PeerId, BitfieldGossipMessage)>,
+) -> Result<()>
+where
+	Context: SubsystemContext<Message = BitfieldDistributionMessage>,
+{
+	tracker.note_peer_activity(&remote);
+
+	// check this before spending any effort on the message
+	if !tracker.note_peer_message(&remote) {
+		trace!(target: "bitd", "Peer {:?} exceeded the flood threshold", &remote);
+		return modify_reputation(ctx, tracker, remote, reputation.cost_apparent_flood).await;
+	}
+
+	match try_decode_gossip(&bytes, tracker.config.max_message_size, tracker.config.compress_gossip) {
+		Ok(gossiped_bitfields) => {
+			if tracker.sample_trace() {
+				if let [_] = gossiped_bitfields.as_slice() {
+					tracing::trace!(target: "bitd", "Received bitfield gossip from peer {:?}", &remote);
+				} else {
+					tracing::trace!(
+						target: "bitd",
+						"Received {} bitfields gossip from peer {:?}",
+						gossiped_bitfields.len(),
+						&remote
+					);
+				}
+			}
+			tracker.undecodable_messages.remove(&remote);
+			for (i, gossiped_bitfield) in gossiped_bitfields.into_iter().enumerate() {
+				// every bitfield of a batch counts towards the flood threshold
+				if i > 0 && !tracker.note_peer_message(&remote) {
+					trace!(target: "bitd", "Peer {:?} exceeded the flood threshold", &remote);
+					return modify_reputation(ctx, tracker, remote, reputation.cost_apparent_flood).await;
+				}
+				metrics.on_bitfield_received();
+				bitfields.push((remote.clone(), gossiped_bitfield));
+			}
+		}
+		Err(DecodeError::Oversized) => {
+			trace!(target: "bitd", "Peer {:?} sent a message of {} bytes", &remote, bytes.len());
+			metrics.on_bitfield_rejected(RejectReason::Oversized);
+			return modify_reputation(ctx, tracker, remote, reputation.cost_oversized_message).await;
+		}
+		Err(DecodeError::UnsupportedVersion) => {
+			metrics.on_bitfield_rejected(RejectReason::UnsupportedVersion);
+			return modify_reputation(ctx, tracker, remote, reputation.cost_unsupported_version).await;
+		}
+		Err(DecodeError::Undecodable) => {
+			metrics.on_bitfield_rejected(RejectReason::Undecodable);
+			let undecodable = tracker.undecodable_messages.entry(remote.clone()).or_default();
+			*undecodable += 1;
+			if *undecodable > tracker.config.max_undecodable_messages {
+				warn!(target: "bitd", "Peer {:?} sent {} undecodable messages in a row", &remote, undecodable);
+				return modify_reputation(ctx, tracker, remote, reputation.cost_undecodable_stream).await;
+			}
+			return modify_reputation(ctx, tracker, remote, reputation.cost_message_not_decodable).await;
+		}
+	}
+	Ok(())
+}
+
+/// Process the bitfields of gossip messages received back to back,
+/// checking all their signatures at once first.
+async fn handle_peer_messages<Context>(
+	ctx: &mut Context,
+	tracker: &mut Tracker,
+	metrics: &Metrics,
+	reputation: &ReputationConfig,
+	messages: Vec<(PeerId, Vec<u8>)>,
+) -> Result<()>
+where
+	Context: SubsystemContext<Message = BitfieldDistributionMessage>,
+{
+	let mut bitfields = Vec::new();
+	for (remote, bytes) in messages {
+		decode_peer_message(ctx, tracker, metrics, reputation, remote, bytes, &mut bitfields).await?;
+	}
+
+	let signatures_valid = check_signatures(tracker, &bitfields);
+	for ((origin, message), signature_valid) in bitfields.into_iter().zip(signatures_valid) {
+		process_incoming_peer_message(ctx, tracker, metrics, reputation, origin, message, signature_valid).await?;
+	}
+	Ok(())
+}
+
+/// Check the signatures of the given bitfields against the signing contexts of their relay parents,
+/// all at once.
+///
+/// Yields `None` for a bitfield which fails the cheap checks of `Tracker::check` already,
+/// or which turns out to be known without checking its signature.
+fn check_signatures(tracker: &Tracker, bitfields: &[(PeerId, BitfieldGossipMessage)]) -> Vec<Option<bool>> {
+	let mut batch = Vec::new();
+	let mut positions = Vec::new();
+	for (position, (origin, message)) in bitfields.iter().enumerate() {
+		// rejected before their signature is of any interest
+		if !tracker.accepts_bitfields_of(origin) {
+			continue;
+		}
+		if let Ok((job_data, validator, _)) = tracker.precheck(message.relay_parent, &message.signed_availability) {
+			batch.push((&message.signed_availability, &job_data.signing_context, validator));
+			positions.push(position);
+		}
+	}
+
+	let mut signatures_valid = vec![None; bitfields.len()];
+	for (position, valid) in positions.into_iter().zip(tracker.signature_verifier.0.verify_batch(&batch)) {
+		signatures_valid[position] = Some(valid);
+	}
+	signatures_valid
+}
+
+/// The relay parents added to and removed from a view by a view change.
+#[derive(Debug, Default, PartialEq, Eq)]
+struct ViewDiff {
+	added: Vec<Hash>,
+	removed: Vec<Hash>,
+}
+
+impl ViewDiff {
+	fn new(old: &View, new: &View) -> Self {
+		ViewDiff {
+			added: new.difference(old).cloned().collect(),
+			removed: old.difference(new).cloned().collect(),
+		}
+	}
+}
+
+// Send the difference between two views which were not sent
+// to that particular peer.
+#[tracing::instrument(
+	level = "trace",
+	target = "bitd",
+	skip(ctx, tracker, reputation, origin, view),
+	fields(peer_id = ?origin, relay_parents = ?view.0),
+)]
+async fn catch_up_messages<Context>(
+	ctx: &mut Context,
+	tracker: &mut Tracker,
+	reputation: &ReputationConfig,
+	origin: PeerId,
+	view: View,
+) -> Result<()>
+where
+	Context: SubsystemContext<Message = BitfieldDistributionMessage>,
+{
+	let current = if let Some(current) = tracker.peer_views.get_mut(&origin) {
+		current
+	} else {
+		return Ok(());
+	};
+
+	let ViewDiff { added: delta_vec, removed } = ViewDiff::new(&*current, &view);
+
+	*current = view;
+
+	// the peer is not interested in these anymore, so forget what we sent it
+	for relay_parent in removed.iter() {
+		if let Some(job_data) = tracker.per_relay_parent.get_mut(relay_parent) {
+			job_data.message_sent_to_peer.remove(&origin);
+		}
+	}
+
+	if !delta_vec.is_empty() {
+		let useful = delta_vec
+			.iter()
+			.any(|relay_parent| tracker.per_relay_parent.contains_key(relay_parent));
+		let known = useful || delta_vec.iter().any(|relay_parent| tracker.view.contains(relay_parent));
+
+		if useful {
+			tracker.useless_view_changes.remove(&origin);
+			// only rewarded once per relay parent, so toggling a view back and forth earns nothing
+			let mut rewarded = false;
+			for relay_parent in delta_vec.iter() {
+				if let Some(job_data) = tracker.per_relay_parent.get_mut(relay_parent) {
+					rewarded |= job_data.view_change_rewarded.insert(origin.clone());
+				}
+			}
+			if rewarded {
+				modify_reputation(ctx, tracker, origin.clone(), reputation.gain_useful_view_change).await?;
+			}
+		} else if !known {
+			let useless = tracker.useless_view_changes.entry(origin.clone()).or_default();
+			*useless += 1;
+			if *useless > tracker.config.max_useless_view_changes {
+				modify_reputation(ctx, tracker, origin.clone(), reputation.cost_useless_view_change).await?;
+			}
+		}
+	}
+
+	if tracker.config.observer || is_light(&tracker.peer_roles, &origin) {
+		return Ok(());
+	}
+
+	// Send all messages we've seen before and the peer is now interested
+	// in to that peer.
+
+	let limit = tracker.config.max_catch_up_messages;
+	let (delta_set, capped) = catch_up_batch(tracker, &origin, delta_vec.iter(), limit);
+	if capped {
+		debug!(
+			target: "bitd",
+			"Peer {:?} needs more than {} bitfields to catch up on, sending the rest later",
+			origin,
+			limit,
+		);
+		tracker.pending_catch_up.entry(origin.clone()).or_default().extend(delta_vec);
+	}
+
+	send_tracked_gossip_messages(ctx, tracker, origin, delta_set).await
+}
+
+/// A bitfield to send to a peer: its relay parent, its validator and its plain encoding.
+type TrackedMessage = (Hash, ValidatorId, Arc<Vec<u8>>);
+
+/// The bitfields of `relay_parents` which `peer` still needs, at most `limit` of them,
+/// and whether any were left out.
+///
+/// Lower validator indices come first, the bitfields of the same validator are
+/// ordered by relay parent.
+fn catch_up_batch<'a>(
+	tracker: &Tracker,
+	peer: &PeerId,
+	relay_parents: impl IntoIterator<Item = &'a Hash>,
+	limit: usize,
+) -> (Vec<TrackedMessage>, bool) {
+	let mut needed: Vec<(ValidatorIndex, Hash, ValidatorId, Arc<Vec<u8>>)> = relay_parents
+		.into_iter()
+		.filter_map(|relay_parent| {
+			// A relay parent is in the peers view, which is not in ours, ignore those.
+			let job_data = tracker.per_relay_parent.get(relay_parent)?;
+			// Send all jointly known messages for a validator (given the current relay parent)
+			// to the peer...
+			Some(
+				job_data.one_per_validator
+					.iter()
+					.filter(move |(validator, (message, _encoded))| {
+						// ..except for the ones the peer already has, or doesn't need at all
+						job_data.needs_message(peer, validator)
+							&& tracker.config.gossips(message.signed_availability.payload())
+					})
+					.map(move |(validator, (message, encoded))| {
+						(
+							message.signed_availability.validator_index(),
+							*relay_parent,
+							validator.clone(),
+							encoded.clone(),
+						)
+					}),
+			)
+		})
+		.flatten()
+		.collect();
+	needed.sort_by_key(|(validator_index, relay_parent, _, _)| (*validator_index, *relay_parent));
+
+	let capped = needed.len() > limit;
+	let batch = needed
+		.into_iter()
+		.take(limit)
+		.map(|(_, relay_parent, validator, encoded)| (relay_parent, validator, encoded))
+		.collect();
+	(batch, capped)
+}
+
+/// Send the next batch of bitfields to every peer which needed more than
+/// `max_catch_up_messages` to catch up on its view change.
+async fn continue_catch_up<Context>(
+	ctx: &mut Context,
+	tracker: &mut Tracker,
+) -> Result<()>
+where
+	Context: SubsystemContext<Message = BitfieldDistributionMessage>,
+{
+	let limit = tracker.config.max_catch_up_messages;
+	for (peer, mut relay_parents) in std::mem::take(&mut tracker.pending_catch_up) {
+		// relay parents which left the view of the peer meanwhile are not needed anymore
+		match tracker.peer_views.get(&peer) {
+			Some(view) => relay_parents.retain(|relay_parent| view.contains(relay_parent)),
+			None => continue,
+		}
+		let (batch, capped) = catch_up_batch(tracker, &peer, relay_parents.iter(), limit);
+		if capped {
+			tracker.pending_catch_up.insert(peer.clone(), relay_parents);
+		}
+		send_tracked_gossip_messages(ctx, tracker, peer, batch).await?;
+	}
+	Ok(())
+}
+
+/// Send gossip messages to a peer, batched into a single network message,
+/// and track them in the per relay parent data.
+async fn send_tracked_gossip_messages<Context>(
+	ctx: &mut Context,
+	tracker: &mut Tracker,
+	dest: PeerId,
+	messages: Vec<TrackedMessage>,
+) -> Result<()>
+where
+	Context: SubsystemContext<Message = BitfieldDistributionMessage>,
+{
+	let mut batch = Vec::with_capacity(messages.len());
+	for (relay_parent, validator, encoded) in messages {
+		let job_data = if let Some(job_data) = tracker.per_relay_parent.get_mut(&relay_parent) {
+			job_data
+		} else {
+			continue;
+		};
+
+		job_data.message_sent_to_peer
+			.entry(dest.clone())
+			.or_default()
+			.insert(validator);
+		batch.push(encoded);
+	}
+
+	if batch.is_empty() {
+		return Ok(());
+	}
+	ctx.send_message(AllMessages::NetworkBridge(
+		NetworkBridgeMessage::SendMessage(
+			vec![dest],
+			tracker.config.protocol_id,
+			tracker.config.wire_bytes(VersionedBitfieldGossipMessage::encode_from_parts(&batch)),
+		),
+	))
+	.await.map_err(|_| BitfieldDistributionError::SendFailed)?;
+
+	Ok(())
+}
+
+impl<C> Subsystem<C> for BitfieldDistribution
+where
+	C: SubsystemContext<Message = BitfieldDistributionMessage> + Sync + Send,
+{
+	fn start(self, ctx: C) -> SpawnedSubsystem {
+		SpawnedSubsystem {
+			name: "bitfield-distribution",
+			future: Box::pin(async move {
+				if let Err(e) = self.run(ctx).await {
+					warn!(target: "bitd", "Bitfield distribution failed: {:?}", e);
+				}
+			}),
+		}
+	}
+}
+
+/// Why the bitfield distribution failed to do something.
+///
+/// Only `RuntimeApiCanceled` concerns a single relay parent, all others end the subsystem
+/// and are logged once it did.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum BitfieldDistributionError {
+	/// The runtime API subsystem dropped the response sender of a query, e.g. because
+	/// the relay parent is no longer available.
+	RuntimeApiCanceled,
+	/// Registering with the network bridge failed this many times in a row.
+	RegistrationFailed(usize),
+	/// A message could not be sent to the overseer.
+	SendFailed,
+	/// No message could be received from the overseer.
+	ReceiveFailed,
+	/// The task of the given name could not be spawned.
+	SpawnFailed(&'static str),
+}
+
+impl From<oneshot::Canceled> for BitfieldDistributionError {
+	fn from(_: oneshot::Canceled) -> Self {
+		BitfieldDistributionError::RuntimeApiCanceled
+	}
+}
+
+type Result<T, E = BitfieldDistributionError> = std::result::Result<T, E>;
+
+/// The basic system parameters of a relay parent.
+#[derive(Debug)]
+struct Basics {
+	/// The validator set, shared with the other relay parents of the session.
+	validators: Arc<Vec<ValidatorId>>,
+	/// The signing context of bitfields.
+	signing_context: SigningContext,
+	/// The number of availability cores, which is the number of bits of every bitfield,
+	/// unless the runtime API subsystem could not tell.
+	availability_cores: Option<usize>,
+}
+
+/// Send all the runtime API `requests` for `relay_parent` at once,
+/// so their answers can be awaited concurrently.
+async fn request_runtime_apis<Context>(
+	ctx: &mut Context,
+	relay_parent: Hash,
+	requests: Vec<RuntimeApiRequest>,
+) -> Result<()>
+where
+	Context: SubsystemContext<Message = BitfieldDistributionMessage>,
+{
+	ctx.send_messages(
+		requests
+			.into_iter()
+			.map(|request| AllMessages::RuntimeApi(RuntimeApiMessage::Request(relay_parent, request)))
+			.collect::<Vec<_>>(),
+	)
+	.await.map_err(|_| BitfieldDistributionError::SendFailed)
+}
+
+/// Query the validator set, signing context and number of availability cores for a particular relay parent.
+///
+/// All of them are queried at once, so `StartWork` takes a single round-trip to the runtime API subsystem.
+/// The validator set is constant within a session, so `validators_by_session` shares it with the other
+/// relay parents of its session.
+async fn query_basics<Context>(
+	ctx: &mut Context,
+	validators_by_session: &mut HashMap<SessionIndex, Arc<Vec<ValidatorId>>>,
+	relay_parent: Hash,
+) -> Result<Basics>
+where
+	Context: SubsystemContext<Message = BitfieldDistributionMessage>,
+{
+	let (signing_tx, signing_rx) = oneshot::channel();
+	let (validators_tx, validators_rx) = oneshot::channel();
+	let (roster_tx, roster_rx) = oneshot::channel();
+	request_runtime_apis(ctx, relay_parent, vec![
+		RuntimeApiRequest::SigningContext(signing_tx),
+		RuntimeApiRequest::Validators(validators_tx),
+		RuntimeApiRequest::ValidatorGroups(roster_tx),
+	]).await?;
+	let (signing_context, validators, roster) = future::join3(signing_rx, validators_rx, roster_rx).await;
+	let signing_context = signing_context?;
+	let validators = note_session_validators(validators_by_session, signing_context.session_index, validators?);
+	let availability_cores = roster.ok().map(|roster| roster.availability_cores.len());
+
+	Ok(Basics { validators, signing_context, availability_cores })
+}
+
+/// Remember the validator set of a session, returning it shared with the other relay parents of the session.
+fn note_session_validators(
+	validators_by_session: &mut HashMap<SessionIndex, Arc<Vec<ValidatorId>>>,
+	session_index: SessionIndex,
+	validators: Vec<ValidatorId>,
+) -> Arc<Vec<ValidatorId>> {
+	// relay parents of older sessions are not started anymore
+	validators_by_session.retain(|session, _| *session + 1 >= session_index);
+	match validators_by_session.get(&session_index) {
+		Some(known) if **known == validators => known.clone(),
+		_ => {
+			let validators = Arc::new(validators);
+			validators_by_session.insert(session_index, validators.clone());
+			validators
+		}
+	}
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+	use assert_matches::assert_matches;
+	use bitvec::bitvec;
+	use futures::{channel::mpsc, executor, future, Future};
+	use polkadot_primitives::v1::{AvailabilityBitfield, Signed};
+	use polkadot_subsystem::messages::SchedulerRoster;
+	use polkadot_subsystem::{SubsystemError, SubsystemResult};
+	use test_harness::{test_harness, test_harness_of, TestHarness};
+	use polkadot_subsystem::test_helpers::{
+		make_subsystem_context, TestSubsystemContext, TestSubsystemContextHandle,
+	};
+	use sp_keyring::Sr25519Keyring;
+	use std::pin::Pin;
+	use std::sync::{atomic::{AtomicBool, AtomicUsize, Ordering}, Arc, Mutex};
+	use std::task::Poll;
+	use std::time::Duration;
+
+	const TIMEOUT: Duration = Duration::from_millis(500);
+
+	macro_rules! view {
+		( $( $hash:expr ),* $(,)? ) => [
+			View(vec![ $( $hash.clone() ),* ])
+		];
+	}
+
+	macro_rules! peers {
+		( $( $peer:expr ),* $(,)? ) => [
+			vec![ $( $peer.clone() ),* ]
+		];
+	}
+
+	/// Resolve `fut`, or `None` if it does not resolve within `duration`.
+	async fn timeout<F: Future>(fut: F, duration: Duration) -> Option<F::Output> {
+		futures::pin_mut!(fut);
+		match future::select(fut, futures_timer::Delay::new(duration)).await {
+			future::Either::Left((output, _)) => Some(output),
+			future::Either::Right(_) => None,
+		}
+	}
+
+	/// What becomes of a message sent through a `HookedContext`.
+	enum Hooked {
+		/// Sent on to the test context.
+		Sent,
+		/// Sent on to the test context after a delay.
+		Delayed(Duration),
+		/// Never sent, as the queue to the overseer is full.
+		Full,
+		/// Not sent, failing the send.
+		Failed,
+	}
+
+	/// A test subsystem context calling `on_send` for every message sent to it, and awaiting
+	/// `on_recv` before every receive.
+	struct HookedContext {
+		ctx: TestSubsystemContext<BitfieldDistributionMessage, sp_core::testing::SpawnBlockingExecutor>,
+		on_send: Box<dyn FnMut(&AllMessages) -> Hooked + Send + Sync>,
+		on_recv: Box<dyn FnMut() -> future::BoxFuture<'static, ()> + Send + Sync>,
+	}
+
+	impl HookedContext {
+		fn new(
+			ctx: TestSubsystemContext<BitfieldDistributionMessage, sp_core::testing::SpawnBlockingExecutor>,
+		) -> Self {
+			Self {
+				ctx,
+				on_send: Box::new(|_| Hooked::Sent),
+				on_recv: Box::new(|| future::ready(()).boxed()),
+			}
+		}
+
+		fn on_send(mut self, on_send: impl FnMut(&AllMessages) -> Hooked + Send + Sync + 'static) -> Self {
+			self.on_send = Box::new(on_send);
+			self
+		}
+
+		fn on_recv(mut self, on_recv: impl FnMut() -> future::BoxFuture<'static, ()> + Send + Sync + 'static) -> Self {
+			self.on_recv = Box::new(on_recv);
+			self
+		}
+	}
+
+	#[async_trait::async_trait]
+	impl SubsystemContext for HookedContext {
+		type Message = BitfieldDistributionMessage;
+
+		async fn try_recv(&mut self) -> Result<Option<FromOverseer<Self::Message>>, ()> {
+			(self.on_recv)().await;
+			self.ctx.try_recv().await
+		}
+
+		async fn recv(&mut self) -> SubsystemResult<FromOverseer<Self::Message>> {
+			(self.on_recv)().await;
+			self.ctx.recv().await
+		}
+
+		async fn spawn(&mut self, name: &'static str, s: Pin<Box<dyn Future<Output = ()> + Send>>)
+			-> SubsystemResult<()>
+		{
+			self.ctx.spawn(name, s).await
+		}
+
+		async fn send_message(&mut self, msg: AllMessages) -> SubsystemResult<()> {
+			match (self.on_send)(&msg) {
+				Hooked::Sent => {}
+				Hooked::Delayed(delay) => Delay::new(delay).await,
+				Hooked::Full => future::pending().await,
+				Hooked::Failed => return Err(SubsystemError),
+			}
+			self.ctx.send_message(msg).await
+		}
+
+		async fn try_send_message(&mut self, msg: AllMessages) -> SubsystemResult<bool> {
+			match (self.on_send)(&msg) {
+				Hooked::Sent | Hooked::Delayed(_) => self.ctx.try_send_message(msg).await,
+				Hooked::Full => Ok(false),
+				Hooked::Failed => Err(SubsystemError),
+			}
+		}
+
+		async fn send_messages<T>(&mut self, msgs: T) -> SubsystemResult<()>
+			where T: IntoIterator<Item = AllMessages> + Send, T::IntoIter: Send
+		{
+			for msg in msgs {
+				self.send_message(msg).await?;
+			}
+			Ok(())
+		}
+	}
+
+	/// Fail the first `failures` sends.
+	fn failing(mut failures: usize) -> impl FnMut(&AllMessages) -> Hooked + Send + Sync {
+		move |_| if failures > 0 {
+			failures -= 1;
+			Hooked::Failed
+		} else {
+			Hooked::Sent
+		}
+	}
+
+	/// Yield to the executor once, so the test gets to send messages while the subsystem
+	/// has deferred work.
+	fn yield_once() -> future::BoxFuture<'static, ()> {
+		let mut yielded = false;
+		future::poll_fn(move |cx| {
+			if yielded {
+				Poll::Ready(())
+			} else {
+				yielded = true;
+				cx.waker().wake_by_ref();
+				Poll::Pending
+			}
+		})
+		.boxed()
+	}
+
+	/// Wrap a network bridge event into a message for the subsystem.
+	fn network_event(event: NetworkBridgeEvent) -> FromOverseer<BitfieldDistributionMessage> {
+		FromOverseer::Communication {
+			msg: BitfieldDistributionMessage::NetworkBridgeUpdate(event),
+		}
+	}
+
+	/// Wait until the subsystem handled all messages sent to it so far, and sent all it had to.
+	async fn await_quiescence(handle: &mut TestSubsystemContextHandle<BitfieldDistributionMessage>) {
+		handle.await_idle().await;
+	}
+
+	/// Signal `StartWork` for `relay_parent` and answer the resulting runtime API queries,
+	/// leaving the number of availability cores unknown.
+	async fn start_work(
+		handle: &mut TestSubsystemContextHandle<BitfieldDistributionMessage>,
+		relay_parent: Hash,
+		validators: Vec<ValidatorId>,
+		signing_context: SigningContext,
+	) {
+		handle.send(FromOverseer::Signal(OverseerSignal::StartWork(relay_parent))).await;
+		expect_basics_queries(handle, relay_parent, validators, signing_context).await;
+	}
+
+	/// Expect the queries of the basics of `relay_parent` we started to work on,
+	/// and answer them, but with no roster.
+	async fn expect_basics_queries(
+		handle: &mut TestSubsystemContextHandle<BitfieldDistributionMessage>,
+		relay_parent: Hash,
+		validators: Vec<ValidatorId>,
+		signing_context: SigningContext,
+	) {
+		assert_matches!(
+			handle.recv().await,
+			AllMessages::RuntimeApi(
+				RuntimeApiMessage::Request(hash, RuntimeApiRequest::SigningContext(tx))
+			) => {
+				assert_eq!(hash, relay_parent);
+				tx.send(signing_context).unwrap();
+			}
+		);
+		assert_matches!(
+			handle.recv().await,
+			AllMessages::RuntimeApi(
+				RuntimeApiMessage::Request(hash, RuntimeApiRequest::Validators(tx))
+			) => {
+				assert_eq!(hash, relay_parent);
+				tx.send(validators).unwrap();
+			}
+		);
+		expect_roster_query(handle, relay_parent, None).await;
+	}
+
+	/// Expect the query of the availability cores of `relay_parent`, and answer it
+	/// with that many cores, if any.
+	async fn expect_roster_query(
+		handle: &mut TestSubsystemContextHandle<BitfieldDistributionMessage>,
+		relay_parent: Hash,
+		availability_cores: Option<usize>,
+	) {
+		assert_matches!(
+			handle.recv().await,
+			AllMessages::RuntimeApi(
+				RuntimeApiMessage::Request(hash, RuntimeApiRequest::ValidatorGroups(tx))
+			) => {
+				assert_eq!(hash, relay_parent);
+				if let Some(availability_cores) = availability_cores {
+					tx.send(SchedulerRoster {
+						validator_groups: Vec::new(),
+						scheduled: Vec::new(),
+						upcoming: Vec::new(),
+						availability_cores: vec![None; availability_cores],
+					}).unwrap();
+				}
+			}
+		);
+	}
+
+	/// A span recorded by the `SpanRecorder`.
+	#[derive(Debug, Clone)]
+	struct RecordedSpan {
+		name: &'static str,
+		parent: Option<u64>,
+		fields: HashMap<String, String>,
+	}
+
+	/// A tracing subscriber recording all spans with their fields, and the messages of all events.
+	#[derive(Default, Clone)]
+	struct SpanRecorder {
+		spans: Arc<Mutex<Vec<RecordedSpan>>>,
+		entered: Arc<Mutex<Vec<u64>>>,
+		events: Arc<Mutex<Vec<String>>>,
+	}
+
+	struct FieldVisitor<'a>(&'a mut HashMap<String, String>);
+
+	impl tracing::field::Visit for FieldVisitor<'_> {
+		fn record_debug(&mut self, field: &tracing::field::Field, value: &dyn std::fmt::Debug) {
+			self.0.insert(field.name().to_owned(), format!("{:?}", value));
+		}
+	}
+
+	impl tracing::Subscriber for SpanRecorder {
+		fn enabled(&self, _: &tracing::Metadata<'_>) -> bool {
+			true
+		}
+
+		fn new_span(&self, attrs: &tracing::span::Attributes<'_>) -> tracing::span::Id {
+			let parent = if attrs.is_contextual() {
+				self.entered.lock().unwrap().last().cloned()
+			} else {
+				attrs.parent().map(|id| id.into_u64())
+			};
+			let mut fields = HashMap::new();
+			attrs.record(&mut FieldVisitor(&mut fields));
+
+			let mut spans = self.spans.lock().unwrap();
+			spans.push(RecordedSpan { name: attrs.metadata().name(), parent, fields });
+			tracing::span::Id::from_u64(spans.len() as u64)
+		}
+
+		fn record(&self, span: &tracing::span::Id, values: &tracing::span::Record<'_>) {
+			let mut spans = self.spans.lock().unwrap();
+			values.record(&mut FieldVisitor(&mut spans[span.into_u64() as usize - 1].fields));
+		}
+
+		fn record_follows_from(&self, _: &tracing::span::Id, _: &tracing::span::Id) {}
+
+		fn event(&self, event: &tracing::Event<'_>) {
+			let mut fields = HashMap::new();
+			event.record(&mut FieldVisitor(&mut fields));
+			self.events.lock().unwrap().push(fields.remove("message").unwrap_or_default());
+		}
+
+		fn enter(&self, span: &tracing::span::Id) {
+			self.entered.lock().unwrap().push(span.into_u64());
+		}
+
+		fn exit(&self, _: &tracing::span::Id) {
+			self.entered.lock().unwrap().pop();
+		}
+	}
+
+	/// Driving the real subsystem through the messages of the overseer, end to end.
+	mod test_harness {
+		use super::*;
+
+		/// The overseer side of a subsystem started by `test_harness`.
+		pub(super) struct TestHarness {
+			handle: TestSubsystemContextHandle<BitfieldDistributionMessage>,
+		}
+
+		impl TestHarness {
+			/// Start working on `relay_parent`, answering the queries this causes, and make it our view.
+			pub(super) async fn activate(
+				&mut self,
+				relay_parent: Hash,
+				validators: Vec<ValidatorId>,
+				signing_context: SigningContext,
+			) {
+				start_work(&mut self.handle, relay_parent, validators, signing_context).await;
+				self.network_event(NetworkBridgeEvent::OurViewChange(view![relay_parent])).await;
+			}
+
+			/// Connect a full peer holding `view`.
+			pub(super) async fn connect_peer(&mut self, peer: &PeerId, view: View) {
+				self.network_event(NetworkBridgeEvent::PeerConnected(peer.clone(), ObservedRole::Full)).await;
+				self.network_event(NetworkBridgeEvent::PeerViewChange(peer.clone(), view)).await;
+			}
+
+			/// Send a network bridge event to the subsystem.
+			pub(super) async fn network_event(&mut self, event: NetworkBridgeEvent) {
+				self.handle.send(network_event(event)).await;
+			}
+
+			/// Send a message of another subsystem to the subsystem.
+			pub(super) async fn send(&mut self, msg: BitfieldDistributionMessage) {
+				self.handle.send(FromOverseer::Communication { msg }).await;
+			}
+
+			/// The next message the subsystem sent.
+			pub(super) async fn recv(&mut self) -> AllMessages {
+				timeout(self.handle.recv(), TIMEOUT).await.expect("the subsystem sends a message")
+			}
+
+			/// Expect the next message sent to be a reputation change of `peer`, and return it.
+			pub(super) async fn expect_report(&mut self, peer: &PeerId) -> ReputationChange {
+				match self.recv().await {
+					AllMessages::NetworkBridge(NetworkBridgeMessage::ReportPeer(reported, rep)) => {
+						assert_eq!(&reported, peer);
+						rep
+					}
+					message => panic!("expected a reputation change of {:?}, got {:?}", peer, message),
+				}
+			}
+
+			/// Expect the subsystem to have nothing more to send for what it was sent so far.
+			pub(super) async fn expect_quiescence(&mut self) {
+				await_quiescence(&mut self.handle).await;
+				assert_matches!(self.handle.try_recv(), None);
+			}
+		}
+
+		/// Run `test` against the subsystem started with `config`, once registered with the network bridge.
+		///
+		/// The subsystem is dropped once the test is done; concluding before is a failure.
+		pub(super) fn test_harness<T: Future<Output = ()>>(config: Config, test: impl FnOnce(TestHarness) -> T) {
+			test_harness_of(BitfieldDistribution::new(Default::default()).with_config(config), test)
+		}
+
+		/// Run `test` against the given subsystem, like `test_harness`.
+		pub(super) fn test_harness_of<T: Future<Output = ()>>(
+			subsystem: BitfieldDistribution,
+			test: impl FnOnce(TestHarness) -> T,
+		) {
+			let pool = sp_core::testing::SpawnBlockingExecutor::new();
+			let (ctx, mut handle) =
+				make_subsystem_context::<BitfieldDistributionMessage, _>(pool);
+
+			let subsystem = subsystem
+				.start(ctx)
+				.future
+				.map(|_| panic!("the subsystem concluded early"));
+
+			let test_fut = async move {
+				assert_matches!(
+					handle.recv().await,
+					AllMessages::NetworkBridge(NetworkBridgeMessage::RegisterEventProducer(..))
+				);
+				test(TestHarness { handle }).await;
+			};
+
+			futures::pin_mut!(test_fut);
+			futures::pin_mut!(subsystem);
+			executor::block_on(future::select(test_fut, subsystem));
+		}
+	}
+
+	/// A tracker that is aware of a single relay parent and
+	/// a set of peers which are all interested in it.
+	fn prewarmed_tracker(
+		validator: ValidatorId,
+		signing_context: SigningContext,
+		hash: Hash,
+		peers: Vec<PeerId>,
+	) -> Tracker {
+		Tracker {
+			per_relay_parent: vec![(
+				hash,
+				PerRelayParentData {
+					signing_context,
+					validator_set: Arc::new(vec![validator]),
+					one_per_validator: HashMap::new(),
+					delivered_by: HashMap::new(),
+					message_sent_to_peer: HashMap::new(),
+					view_change_rewarded: HashSet::new(),
+					own_bitfield: None,
+					last_activity: None,
+					started: None,
+					first_bitfield_at: None,
+					quorum_at: None,
+					quorum_deadline: None,
+					quorum_notified: false,
+					bitfield_len: None,
+					equivocators: HashSet::new(),
+				},
+			)]
+			.into_iter()
+			.collect(),
+			peer_views: peers
+				.into_iter()
+				.map(|peer| (peer, view!(hash)))
+				.collect(),
+			view: view!(hash),
+			..Default::default()
+		}
+	}
+
+	#[test]
+	fn messages_needed_by_peers() {
+		let peer_new = PeerId::random();
+		let peer_partial = PeerId::random();
+		let peer_full = PeerId::random();
+
+		let validators: Vec<ValidatorId> = vec![
+			Sr25519Keyring::Alice.public().into(),
+			Sr25519Keyring::Bob.public().into(),
+		];
+
+		let mut job_data = PerRelayParentData::default();
+		job_data.message_sent_to_peer.insert(peer_partial.clone(), vec![validators[0].clone()].into_iter().collect());
+		job_data.message_sent_to_peer.insert(peer_full.clone(), validators.iter().cloned().collect());
+
+		for validator in &validators {
+			assert!(job_data.needs_message(&peer_new, validator));
+			assert!(!job_data.needs_message(&peer_full, validator));
+		}
+		assert!(!job_data.needs_message(&peer_partial, &validators[0]));
+		assert!(job_data.needs_message(&peer_partial, &validators[1]));
+	}
+
+	#[test]
+	fn missing_validators_are_computed() {
+		let hash_a: Hash = [0; 32].into();
+
+		let signing_context = SigningContext {
+			session_index: 1,
+			parent_hash: hash_a,
+		};
+
+		let keyrings = [
+			Sr25519Keyring::Alice,
+			Sr25519Keyring::Bob,
+			Sr25519Keyring::Charlie,
+			Sr25519Keyring::Dave,
+		];
+		let validators: Vec<ValidatorId> = keyrings.iter().map(|k| k.public().into()).collect();
+
+		let message = |index: usize| BitfieldGossipMessage {
+			relay_parent: hash_a,
+			signed_availability: Signed::<AvailabilityBitfield>::sign(
+				AvailabilityBitfield(bitvec![bitvec::order::Lsb0, u8; 1u8; 32]),
+				&signing_context,
+				index as ValidatorIndex,
+				&keyrings[index].pair().into(),
+			),
+		};
+
+		let mut job_data = PerRelayParentData {
+			signing_context: signing_context.clone(),
+			validator_set: Arc::new(validators.clone()),
+			..Default::default()
+		};
+		assert_eq!(job_data.missing_validators(), vec![0, 1, 2, 3]);
+
+		job_data.insert_message(validators[1].clone(), message(1));
+		job_data.insert_message(validators[3].clone(), message(3));
+		assert_eq!(job_data.missing_validators(), vec![0, 2]);
+
+		// our own bitfield counts, too
+		job_data.own_bitfield = Some((validators[0].clone(), message(0)));
+		assert_eq!(job_data.missing_validators(), vec![2]);
+	}
+
+	#[test]
+	fn availability_is_aggregated() {
+		let hash_a: Hash = [0; 32].into();
+
+		let signing_context = SigningContext {
+			session_index: 1,
+			parent_hash: hash_a,
+		};
+
+		let keyrings = [
+			Sr25519Keyring::Alice,
+			Sr25519Keyring::Bob,
+			Sr25519Keyring::Charlie,
+			Sr25519Keyring::Dave,
+		];
+		let validators: Vec<ValidatorId> = keyrings.iter().map(|k| k.public().into()).collect();
+
+		// six cores, of which the given ones are available
+		let message = |index: usize, available: &[usize]| {
+			let mut bits = bitvec![bitvec::order::Lsb0, u8; 0u8; 6];
+			for core in available {
+				bits.set(*core, true);
+			}
+			BitfieldGossipMessage {
+				relay_parent: hash_a,
+				signed_availability: Signed::<AvailabilityBitfield>::sign(
+					AvailabilityBitfield(bits),
+					&signing_context,
+					index as ValidatorIndex,
+					&keyrings[index].pair().into(),
+				),
+			}
+		};
+
+		let mut job_data = PerRelayParentData {
+			signing_context: signing_context.clone(),
+			validator_set: Arc::new(validators.clone()),
+			bitfield_len: Some(6),
+			..Default::default()
+		};
+		assert_eq!(
+			job_data.availability_summary(),
+			AvailabilitySummary { cores: 6, available_cores: 0, validators: 0 },
+		);
+
+		job_data.insert_message(validators[1].clone(), message(1, &[0, 1]));
+		job_data.insert_message(validators[2].clone(), message(2, &[1, 2]));
+		job_data.own_bitfield = Some((validators[0].clone(), message(0, &[5])));
+
+		// cores 0, 1, 2 and 5, the overlapping core 1 counting once
+		assert_eq!(
+			job_data.availability_summary(),
+			AvailabilitySummary { cores: 6, available_cores: 4, validators: 3 },
+		);
+	}
+
+	#[test]
+	fn spans_carry_relay_parent_validator_and_peer() {
+		let hash_a: Hash = [0; 32].into();
+		let peer_a = PeerId::random();
+		let peer_b = PeerId::random();
+
+		let signing_context = SigningContext {
+			session_index: 1,
+			parent_hash: hash_a,
+		};
+
+		let validator: ValidatorId = Sr25519Keyring::Alice.public().into();
+
+		let msg = BitfieldGossipMessage {
+			relay_parent: hash_a,
+			signed_availability: Signed::<AvailabilityBitfield>::sign(
+				AvailabilityBitfield(bitvec![bitvec::order::Lsb0, u8; 1u8; 32]),
+				&signing_context,
+				0,
+				&Sr25519Keyring::Alice.pair().into(),
+			),
+		};
+
+		let pool = sp_core::testing::SpawnBlockingExecutor::new();
+		let (mut ctx, mut handle) =
+			make_subsystem_context::<BitfieldDistributionMessage, _>(pool);
+
+		let mut tracker = prewarmed_tracker(validator, signing_context, hash_a, peers![peer_a, peer_b]);
+
+		let recorder = SpanRecorder::default();
+		tracing::subscriber::with_default(recorder.clone(), || {
+			executor::block_on(async {
+				process_incoming_peer_message(
+					&mut ctx,
+					&mut tracker,
+					&Default::default(),
+					&Default::default(),
+					peer_b.clone(),
+					msg,
+					None,
+				)
+				.await
+				.unwrap();
+
+				// report, provisioning and relay
+				assert_matches!(handle.recv().await, AllMessages::NetworkBridge(NetworkBridgeMessage::ReportPeer(..)));
+				assert_matches!(handle.recv().await, AllMessages::Provisioner(_));
+				assert_matches!(handle.recv().await, AllMessages::NetworkBridge(NetworkBridgeMessage::SendMessage(..)));
+			});
+		});
+
+		let spans = recorder.spans.lock().unwrap();
+		let (process_id, process) = spans
+			.iter()
+			.enumerate()
+			.find(|(_, span)| span.name == "process_incoming_peer_message")
+			.expect("span of processing the message exists");
+		assert_eq!(process.fields["relay_parent"], format!("{:?}", hash_a));
+		assert_eq!(process.fields["validator_index"], "0");
+		assert_eq!(process.fields["peer_id"], format!("{:?}", peer_b));
+
+		let relay = spans
+			.iter()
+			.find(|span| span.name == "relay_message")
+			.expect("span of relaying the message exists");
+		assert_eq!(relay.parent, Some(process_id as u64 + 1));
+		assert_eq!(relay.fields["relay_parent"], format!("{:?}", hash_a));
+	}
+
+	#[test]
+	fn received_messages_are_traced_sampled() {
+		let hash_a: Hash = [0; 32].into();
+		let peer_a = PeerId::random();
+
+		let signing_context = SigningContext {
+			session_index: 1,
+			parent_hash: hash_a,
+		};
+
+		let validator: ValidatorId = Sr25519Keyring::Alice.public().into();
+
+		let msg = BitfieldGossipMessage {
+			relay_parent: hash_a,
+			signed_availability: Signed::<AvailabilityBitfield>::sign(
+				AvailabilityBitfield(bitvec![bitvec::order::Lsb0, u8; 1u8; 32]),
+				&signing_context,
+				0,
+				&Sr25519Keyring::Alice.pair().into(),
+			),
+		};
+
+		let pool = sp_core::testing::SpawnBlockingExecutor::new();
+		let (mut ctx, _handle) =
+			make_subsystem_context::<BitfieldDistributionMessage, _>(pool);
+
+		let mut tracker = prewarmed_tracker(validator, signing_context, hash_a, peers![peer_a]);
+		tracker.config.trace_sampling = 4;
+
+		let registry = Registry::new();
+		let metrics = Metrics::register(Some(&registry)).unwrap();
+
+		let recorder = SpanRecorder::default();
+		tracing::subscriber::with_default(recorder.clone(), || {
+			executor::block_on(async {
+				for _ in 0..12 {
+					handle_network_msg(
+						&mut ctx,
+						&mut tracker,
+						&metrics,
+						&Default::default(),
+						NetworkBridgeEvent::PeerMessage(peer_a.clone(), msg.encode_versioned()),
+					)
+					.await
+					.unwrap();
+				}
+			});
+		});
+
+		let traced = recorder.events
+			.lock()
+			.unwrap()
+			.iter()
+			.filter(|message| message.starts_with("Received bitfield gossip"))
+			.count();
+		assert_eq!(traced, 3);
+		assert_eq!(counter_value(&registry, "parachain_bitfields_received_total", None), 12);
+	}
+
+	#[test]
+	fn receive_invalid_signature() {
+		let hash_a: Hash = [0; 32].into();
+
+		let peer_b = PeerId::random();
+
+		let signing_context = SigningContext {
+			session_index: 1,
+			parent_hash: hash_a,
+		};
+
+		// another validator not part of the validator set
+		let malicious = Sr25519Keyring::Ferdie.pair().into();
+		let validator = Sr25519Keyring::Alice.public().into();
+
+		let payload = AvailabilityBitfield(bitvec![bitvec::order::Lsb0, u8; 1u8; 32]);
+		let signed = Signed::<AvailabilityBitfield>::sign(payload, &signing_context, 0, &malicious);
+
+		let msg = BitfieldGossipMessage {
+			relay_parent: hash_a,
+			signed_availability: signed,
+		};
+
+		test_harness(Default::default(), |mut harness| async move {
+			harness.activate(hash_a, vec![validator], signing_context).await;
+			harness.connect_peer(&peer_b, view![hash_a]).await;
+			assert_eq!(harness.expect_report(&peer_b).await, GAIN_USEFUL_VIEW_CHANGE);
+
+			harness.network_event(NetworkBridgeEvent::PeerMessage(peer_b.clone(), msg.encode_versioned())).await;
+
+			// reputation change due to invalid signature
+			assert_eq!(harness.expect_report(&peer_b).await, COST_SIGNATURE_INVALID);
+			harness.expect_quiescence().await;
+		});
+	}
+
+	#[test]
+	fn custom_signature_verifier_is_used() {
+		/// Takes any signature for a valid one.
+		struct AcceptAll;
+
+		impl SignatureVerifier for AcceptAll {
+			fn verify(&self, _: &SignedAvailabilityBitfield, _: &SigningContext, _: &ValidatorId) -> bool {
+				true
+			}
+		}
+
+		let hash_a: Hash = [0; 32].into();
+
+		let peer_a = PeerId::random();
+		let peer_b = PeerId::random();
+
+		let signing_context = SigningContext {
+			session_index: 1,
+			parent_hash: hash_a,
+		};
+
+		let validator: ValidatorId = Sr25519Keyring::Alice.public().into();
+
+		// signed by someone else than the validator
+		let msg = BitfieldGossipMessage {
+			relay_parent: hash_a,
+			signed_availability: Signed::<AvailabilityBitfield>::sign(
+				AvailabilityBitfield(bitvec![bitvec::order::Lsb0, u8; 1u8; 32]),
+				&signing_context,
+				0,
+				&Sr25519Keyring::Ferdie.pair().into(),
+			),
+		};
+
+		let subsystem = BitfieldDistribution::new(Default::default()).with_signature_verifier(AcceptAll);
+		test_harness_of(subsystem, |mut harness| async move {
+			harness.activate(hash_a, vec![validator], signing_context).await;
+			for peer in &[&peer_a, &peer_b] {
+				harness.connect_peer(peer, view![hash_a]).await;
+				assert_eq!(harness.expect_report(peer).await, GAIN_USEFUL_VIEW_CHANGE);
+			}
+
+			harness.network_event(NetworkBridgeEvent::PeerMessage(peer_a.clone(), msg.encode_versioned())).await;
+
+			assert_eq!(harness.expect_report(&peer_a).await, GAIN_VALID_MESSAGE_FIRST);
+			assert_matches!(harness.recv().await, AllMessages::Provisioner(_));
+			assert_matches!(
+				harness.recv().await,
+				AllMessages::NetworkBridge(NetworkBridgeMessage::SendMessage(peers, _, _)) => {
+					assert_eq!(peers, vec![peer_b.clone()]);
+				}
+			);
+			harness.expect_quiescence().await;
+		});
+	}
+
+	#[test]
+	fn only_bitfields_of_authorities_are_accepted() {
+		/// The authorities of the test.
+		struct Authorities(HashSet<PeerId>);
+
+		impl AuthoritySet for Authorities {
+			fn is_authority(&self, peer: &PeerId) -> bool {
+				self.0.contains(peer)
+			}
+		}
+
+		let hash_a: Hash = [0; 32].into();
+
+		let authority = PeerId::random();
+		let other = PeerId::random();
+
+		let signing_context = SigningContext {
+			session_index: 1,
+			parent_hash: hash_a,
+		};
+
+		let validator: ValidatorId = Sr25519Keyring::Alice.public().into();
+
+		let msg = BitfieldGossipMessage {
+			relay_parent: hash_a,
+			signed_availability: Signed::<AvailabilityBitfield>::sign(
+				AvailabilityBitfield(bitvec![bitvec::order::Lsb0, u8; 1u8; 32]),
+				&signing_context,
+				0,
+				&Sr25519Keyring::Alice.pair().into(),
+			),
+		};
+
+		let authorities = Authorities(vec![authority.clone()].into_iter().collect());
+		let subsystem = BitfieldDistribution::new(Default::default()).with_authority_set(authorities);
+		test_harness_of(subsystem, |mut harness| async move {
+			harness.activate(hash_a, vec![validator], signing_context).await;
+			for peer in &[&authority, &other] {
+				harness.connect_peer(peer, view![hash_a]).await;
+				assert_eq!(harness.expect_report(peer).await, GAIN_USEFUL_VIEW_CHANGE);
+			}
+
+			harness.network_event(NetworkBridgeEvent::PeerMessage(other.clone(), msg.encode_versioned())).await;
+			assert_eq!(harness.expect_report(&other).await, COST_NOT_AUTHORITY);
+
+			harness.network_event(NetworkBridgeEvent::PeerMessage(authority.clone(), msg.encode_versioned())).await;
+			assert_eq!(harness.expect_report(&authority).await, GAIN_VALID_MESSAGE_FIRST);
+			assert_matches!(
+				harness.recv().await,
+				AllMessages::Provisioner(ProvisionerMessage::ProvisionableData(
+					ProvisionableData::Bitfield(_, signed)
+				)) => {
+					assert_eq!(signed, msg.signed_availability);
+				}
+			);
+			// the other peer is sent bitfields nonetheless
+			assert_matches!(
+				harness.recv().await,
+				AllMessages::NetworkBridge(NetworkBridgeMessage::SendMessage(peers, _, _)) => {
+					assert_eq!(peers, vec![other.clone()]);
+				}
+			);
+			harness.expect_quiescence().await;
+		});
+	}
+
+	#[test]
+	fn reaching_the_quorum_is_notified_once() {
+		let hash_a: Hash = [0; 32].into();
+
+		let peer_a = PeerId::random();
+
+		let signing_context = SigningContext {
+			session_index: 1,
+			parent_hash: hash_a,
+		};
+
+		let keyrings = [
+			Sr25519Keyring::Alice,
+			Sr25519Keyring::Bob,
+			Sr25519Keyring::Charlie,
+			Sr25519Keyring::Dave,
+		];
+		let validators: Vec<ValidatorId> = keyrings.iter().map(|k| k.public().into()).collect();
+
+		let messages: Vec<_> = keyrings
+			.iter()
+			.enumerate()
+			.map(|(index, keyring)| BitfieldGossipMessage {
+				relay_parent: hash_a,
+				signed_availability: Signed::<AvailabilityBitfield>::sign(
+					AvailabilityBitfield(bitvec![bitvec::order::Lsb0, u8; 1u8; 32]),
+					&signing_context,
+					index as ValidatorIndex,
+					&keyring.pair().into(),
+				),
+			})
+			.collect();
+
+		let config = Config { notify_quorum: true, ..Default::default() };
+		test_harness(config, |mut harness| async move {
+			harness.activate(hash_a, validators, signing_context).await;
+			harness.connect_peer(&peer_a, view![hash_a]).await;
+			assert_eq!(harness.expect_report(&peer_a).await, GAIN_USEFUL_VIEW_CHANGE);
+
+			for (index, msg) in messages.iter().enumerate() {
+				harness.network_event(NetworkBridgeEvent::PeerMessage(peer_a.clone(), msg.encode_versioned())).await;
+				assert_eq!(harness.expect_report(&peer_a).await, GAIN_VALID_MESSAGE_FIRST);
+				assert_matches!(
+					harness.recv().await,
+					AllMessages::Provisioner(ProvisionerMessage::ProvisionableData(
+						ProvisionableData::Bitfield(_, signed)
+					)) => {
+						assert_eq!(signed, msg.signed_availability);
+					}
+				);
+				// the third of four bitfields is more than 2/3 of them
+				if index == 2 {
+					assert_matches!(
+						harness.recv().await,
+						AllMessages::Provisioner(ProvisionerMessage::AvailabilityQuorum(hash)) => {
+							assert_eq!(hash, hash_a);
+						}
+					);
+				}
+				harness.expect_quiescence().await;
+			}
+		});
+	}
+
+	#[test]
+	fn valid_bitfield_is_provisioned_and_relayed() {
+		let hash_a: Hash = [0; 32].into();
+
+		let peer_a = PeerId::random();
+		let peer_b = PeerId::random();
+
+		let signing_context = SigningContext {
+			session_index: 1,
+			parent_hash: hash_a,
+		};
+
+		let validator: ValidatorId = Sr25519Keyring::Alice.public().into();
+
+		let msg = BitfieldGossipMessage {
+			relay_parent: hash_a,
+			signed_availability: Signed::<AvailabilityBitfield>::sign(
+				AvailabilityBitfield(bitvec![bitvec::order::Lsb0, u8; 1u8; 32]),
+				&signing_context,
+				0,
+				&Sr25519Keyring::Alice.pair().into(),
+			),
+		};
+
+		test_harness(Default::default(), |mut harness| async move {
+			harness.activate(hash_a, vec![validator], signing_context).await;
+			for peer in &[&peer_a, &peer_b] {
+				harness.connect_peer(peer, view![hash_a]).await;
+				assert_eq!(harness.expect_report(peer).await, GAIN_USEFUL_VIEW_CHANGE);
+			}
+
+			harness.network_event(NetworkBridgeEvent::PeerMessage(peer_a.clone(), msg.encode_versioned())).await;
+
+			assert_eq!(harness.expect_report(&peer_a).await, GAIN_VALID_MESSAGE_FIRST);
+			assert_matches!(
+				harness.recv().await,
+				AllMessages::Provisioner(ProvisionerMessage::ProvisionableData(
+					ProvisionableData::Bitfield(hash, signed)
+				)) => {
+					assert_eq!(hash, hash_a);
+					assert_eq!(signed, msg.signed_availability);
+				}
+			);
+			// to the other peer only
+			assert_matches!(
+				harness.recv().await,
+				AllMessages::NetworkBridge(NetworkBridgeMessage::SendMessage(peers, _, bytes)) => {
+					assert_eq!(peers, vec![peer_b.clone()]);
+					assert_eq!(bytes, msg.encode_versioned());
+				}
+			);
+			harness.expect_quiescence().await;
+		});
+	}
+
+	#[test]
+	fn missing_validators_are_logged_once_the_quorum_deadline_passed() {
+		let hash_a: Hash = [0; 32].into();
+		let hash_b: Hash = [1; 32].into();
+
+		let peer_a = PeerId::random();
+
+		let keyrings = [Sr25519Keyring::Alice, Sr25519Keyring::Bob, Sr25519Keyring::Charlie];
+		let validators: Vec<ValidatorId> = keyrings.iter().map(|k| k.public().into()).collect();
+
+		let signing_context = |parent_hash| SigningContext { session_index: 1, parent_hash };
+		let msg = |relay_parent, index: usize| BitfieldGossipMessage {
+			relay_parent,
+			signed_availability: Signed::<AvailabilityBitfield>::sign(
+				AvailabilityBitfield(bitvec![bitvec::order::Lsb0, u8; 1u8; 32]),
+				&signing_context(relay_parent),
+				index as ValidatorIndex,
+				&keyrings[index].pair().into(),
+			),
+		};
+
+		let registry = Registry::new();
+		let metrics = Metrics::register(Some(&registry)).unwrap();
+		let quorum_deadline = Duration::from_millis(300);
+		let subsystem = BitfieldDistribution::new(metrics).with_config(Config {
+			quorum_deadline,
+			..Default::default()
+		});
+
+		let recorder = SpanRecorder::default();
+		tracing::subscriber::with_default(recorder.clone(), || {
+			test_harness_of(subsystem, |mut harness| async move {
+				harness.activate(hash_a, validators.clone(), signing_context(hash_a)).await;
+				harness.activate(hash_b, validators.clone(), signing_context(hash_b)).await;
+				harness.network_event(NetworkBridgeEvent::OurViewChange(view![hash_a, hash_b])).await;
+				harness.connect_peer(&peer_a, view![hash_a, hash_b]).await;
+				assert_eq!(harness.expect_report(&peer_a).await, GAIN_USEFUL_VIEW_CHANGE);
+
+				// a single bitfield of `hash_a`, all of `hash_b` which is a quorum well in time
+				let messages = std::iter::once(msg(hash_a, 0)).chain((0..3).map(|index| msg(hash_b, index)));
+				for message in messages {
+					harness.network_event(NetworkBridgeEvent::PeerMessage(peer_a.clone(), message.encode_versioned())).await;
+					assert_eq!(harness.expect_report(&peer_a).await, GAIN_VALID_MESSAGE_FIRST);
+					assert_matches!(harness.recv().await, AllMessages::Provisioner(_));
+				}
+
+				Delay::new(quorum_deadline * 2).await;
+				harness.expect_quiescence().await;
+			});
+		});
+
+		let timeouts: Vec<String> = recorder.events
+			.lock()
+			.unwrap()
+			.iter()
+			.filter(|message| message.starts_with("No quorum of bitfields"))
+			.cloned()
+			.collect();
+		assert_eq!(timeouts.len(), 1);
+		assert!(timeouts[0].contains(&format!("{:?}", hash_a)));
+		assert!(timeouts[0].ends_with("missing those of validators [1, 2]"));
+		assert_eq!(counter_value(&registry, "parachain_bitfield_quorum_timeouts_total", None), 1);
+	}
+
+	#[test]
+	fn invalid_signature_in_batch_only_penalizes_its_sender() {
+		let hash_a: Hash = [0; 32].into();
+		let peer_a = PeerId::random();
+		let peer_b = PeerId::random();
+		let peer_c = PeerId::random();
+
+		let signing_context = SigningContext {
+			session_index: 1,
+			parent_hash: hash_a,
+		};
+
+		let keyrings = [Sr25519Keyring::Alice, Sr25519Keyring::Bob, Sr25519Keyring::Charlie];
+		let validators: Vec<ValidatorId> = keyrings.iter().map(|k| k.public().into()).collect();
+
+		// peer b sends a bitfield of validator 1 signed by someone else
+		let signers = [Sr25519Keyring::Alice, Sr25519Keyring::Ferdie, Sr25519Keyring::Charlie];
+		let messages: Vec<_> = vec![peer_a.clone(), peer_b.clone(), peer_c.clone()]
+			.into_iter()
+			.zip(signers.iter())
+			.enumerate()
+			.map(|(index, (peer, signer))| {
+				let msg = BitfieldGossipMessage {
+					relay_parent: hash_a,
+					signed_availability: Signed::<AvailabilityBitfield>::sign(
+						AvailabilityBitfield(bitvec![bitvec::order::Lsb0, u8; 1u8; 32]),
+						&signing_context,
+						index as ValidatorIndex,
+						&signer.pair().into(),
+					),
+				};
+				(peer, msg.encode_versioned())
+			})
+			.collect();
+
+		let pool = sp_core::testing::SpawnBlockingExecutor::new();
+		let (mut ctx, mut handle) =
+			make_subsystem_context::<BitfieldDistributionMessage, _>(pool);
+
+		let mut tracker = prewarmed_tracker(
+			validators[0].clone(),
+			signing_context,
+			hash_a,
+			peers![peer_a, peer_b, peer_c],
+		);
+		tracker.per_relay_parent.get_mut(&hash_a).unwrap().validator_set = Arc::new(validators);
+
+		executor::block_on(async move {
+			handle_peer_messages(
+				&mut ctx,
+				&mut tracker,
+				&Default::default(),
+				&Default::default(),
+				messages,
+			)
+			.await
+			.unwrap();
+
+			let mut reports = Vec::new();
+			let mut provisioned = Vec::new();
+			while let Some(message) = handle.try_recv() {
+				match message {
+					AllMessages::NetworkBridge(NetworkBridgeMessage::ReportPeer(peer, rep)) => {
+						reports.push((peer, rep));
+					}
+					AllMessages::Provisioner(ProvisionerMessage::ProvisionableData(
+						ProvisionableData::Bitfield(_, signed),
+					)) => provisioned.push(signed.validator_index()),
+					_ => {}
+				}
+			}
+			assert_eq!(reports, vec![
+				(peer_a, GAIN_VALID_MESSAGE_FIRST),
+				(peer_b, COST_SIGNATURE_INVALID),
+				(peer_c, GAIN_VALID_MESSAGE_FIRST),
+			]);
+			assert_eq!(provisioned, vec![0, 2]);
+
+			let job_data = tracker.per_relay_parent.get(&hash_a).unwrap();
+			assert_eq!(job_data.missing_validators(), vec![1]);
+		});
+	}
+
+	#[test]
+	fn receive_bitfield_of_previous_session() {
+		let hash_a: Hash = [0; 32].into();
+		let hash_b: Hash = [1; 32].into();
+		let peer_a = PeerId::random();
+
+		let validator: ValidatorId = Sr25519Keyring::Alice.public().into();
+
+		// validly signed, but for the session before the one of the relay parent
+		let msg = BitfieldGossipMessage {
+			relay_parent: hash_a,
+			signed_availability: Signed::<AvailabilityBitfield>::sign(
+				AvailabilityBitfield(bitvec![bitvec::order::Lsb0, u8; 1u8; 32]),
+				&SigningContext { session_index: 1, parent_hash: hash_a },
+				0,
+				&Sr25519Keyring::Alice.pair().into(),
+			),
+		};
+
+		let registry = Registry::new();
+		let metrics = Metrics::register(Some(&registry)).unwrap();
+
+		let pool = sp_core::testing::SpawnBlockingExecutor::new();
+		let (mut ctx, mut handle) =
+			make_subsystem_context::<BitfieldDistributionMessage, _>(pool);
+
+		let signing_context = SigningContext { session_index: 2, parent_hash: hash_a };
+		let mut tracker = prewarmed_tracker(validator.clone(), signing_context, hash_a, peers![peer_a]);
+
+		executor::block_on(async move {
+			// only told apart from an invalid signature while the previous session is worked on
+			for (previous_session, expected) in vec![(false, COST_SIGNATURE_INVALID), (true, COST_WRONG_SESSION)] {
+				if previous_session {
+					tracker.per_relay_parent.insert(hash_b, PerRelayParentData {
+						signing_context: SigningContext { session_index: 1, parent_hash: hash_b },
+						validator_set: Arc::new(vec![validator.clone()]),
+						..Default::default()
+					});
+				}
+				process_incoming_peer_message(
+					&mut ctx,
+					&mut tracker,
+					&metrics,
+					&Default::default(),
+					peer_a.clone(),
+					msg.clone(),
+					None,
+				)
+				.await
+				.unwrap();
+
+				assert_matches!(
+					handle.recv().await,
+					AllMessages::NetworkBridge(
+						NetworkBridgeMessage::ReportPeer(peer, rep)
+					) => {
+						assert_eq!(peer, peer_a);
+						assert_eq!(rep, expected);
+					}
+				);
+			}
+			assert!(tracker.per_relay_parent[&hash_a].one_per_validator.is_empty());
+		});
+
+		assert_eq!(
+			counter_value(&registry, "parachain_bitfields_rejected_total", Some("wrong_session")),
+			1,
+		);
+	}
+
+	#[test]
+	fn receive_bitfield_of_inconsistent_length() {
+		let hash_a: Hash = [0; 32].into();
+		let peer_a = PeerId::random();
+
+		let signing_context = SigningContext {
+			session_index: 1,
+			parent_hash: hash_a,
+		};
+
+		let validators: Vec<ValidatorId> = vec![
+			Sr25519Keyring::Alice.public().into(),
+			Sr25519Keyring::Bob.public().into(),
+		];
+
+		let message = |index: ValidatorIndex, keyring: Sr25519Keyring, bits: usize| BitfieldGossipMessage {
+			relay_parent: hash_a,
+			signed_availability: Signed::<AvailabilityBitfield>::sign(
+				AvailabilityBitfield(bitvec![bitvec::order::Lsb0, u8; 1u8; bits]),
+				&signing_context,
+				index,
+				&keyring.pair().into(),
+			),
+		};
+
+		let pool = sp_core::testing::SpawnBlockingExecutor::new();
+		let (mut ctx, mut handle) =
+			make_subsystem_context::<BitfieldDistributionMessage, _>(pool);
+
+		let mut tracker = prewarmed_tracker(validators[0].clone(), signing_context.clone(), hash_a, peers![]);
+		tracker.per_relay_parent.get_mut(&hash_a).unwrap().validator_set = Arc::new(validators.clone());
+
+		executor::block_on(async move {
+			for msg in vec![message(0, Sr25519Keyring::Alice, 32), message(1, Sr25519Keyring::Bob, 16)] {
+				process_incoming_peer_message(
+					&mut ctx,
+					&mut tracker,
+					&Default::default(),
+					&Default::default(),
+					peer_a.clone(),
+					msg,
+					None,
+				)
+				.await
+				.unwrap();
+			}
+
+			assert_matches!(
+				handle.recv().await,
+				AllMessages::NetworkBridge(NetworkBridgeMessage::ReportPeer(_, rep)) => {
+					assert_eq!(rep, GAIN_VALID_MESSAGE_FIRST);
+				}
+			);
+			assert_matches!(handle.recv().await, AllMessages::Provisioner(_));
+			assert_matches!(
+				handle.recv().await,
+				AllMessages::NetworkBridge(NetworkBridgeMessage::ReportPeer(peer, rep)) => {
+					assert_eq!(peer, peer_a);
+					assert_eq!(rep, COST_INCONSISTENT_BITFIELD_LENGTH);
+				}
+			);
+
+			let job_data = &tracker.per_relay_parent[&hash_a];
+			assert_eq!(job_data.one_per_validator.len(), 1);
+			assert!(job_data.one_per_validator.contains_key(&validators[0]));
+		});
+	}
+
+	#[test]
+	fn receive_invalid_validator_index() {
+		let hash_a: Hash = [0; 32].into();
+		let hash_b: Hash = [1; 32].into();
+
+		let peer_a = PeerId::random();
+		let peer_b = PeerId::random();
+		assert_ne!(peer_a, peer_b);
+
+		let signing_context = SigningContext {
+			session_index: 1,
+			parent_hash: hash_a,
+		};
+
+		let validator: ValidatorId = Sr25519Keyring::Alice.public().into();
+
+		let payload = AvailabilityBitfield(bitvec![bitvec::order::Lsb0, u8; 1u8; 32]);
+		let signed = Signed::<AvailabilityBitfield>::sign(
+			payload,
+			&signing_context,
+			42,
+			&Sr25519Keyring::Alice.pair().into(),
+		);
+
+		let msg = BitfieldGossipMessage {
+			relay_parent: hash_a,
+			signed_availability: signed,
+		};
+
+		let pool = sp_core::testing::SpawnBlockingExecutor::new();
+		let (mut ctx, mut handle) =
+			make_subsystem_context::<BitfieldDistributionMessage, _>(pool);
+
+		let mut tracker = prewarmed_tracker(validator, signing_context, hash_a, peers![peer_a, peer_b]);
+		tracker.peer_views.insert(peer_a.clone(), view!(hash_b));
+
+		executor::block_on(async move {
+			handle_network_msg(
+				&mut ctx,
+				&mut tracker,
+				&Default::default(),
+				&Default::default(),
+				NetworkBridgeEvent::PeerMessage(peer_b.clone(), msg.encode_versioned()),
+			)
+			.await
+			.unwrap();
+
+			// reputation change due to invalid validator index
+			assert_matches!(
+				handle.recv().await,
+				AllMessages::NetworkBridge(
+					NetworkBridgeMessage::ReportPeer(peer, rep)
+				) => {
+					assert_eq!(peer, peer_b);
+					assert_eq!(rep, COST_VALIDATOR_INDEX_INVALID)
+				}
+			);
+		});
+	}
+
+	/// Read the value of a counter from the registry, optionally filtered by a label value.
+	fn counter_value(registry: &Registry, name: &str, label: Option<&str>) -> u64 {
+		registry
+			.gather()
+			.into_iter()
+			.find(|family| family.get_name() == name)
+			.and_then(|family| {
+				family
+					.get_metric()
+					.iter()
+					.find(|metric| {
+						label.map_or(true, |label| {
+							metric.get_label().iter().any(|pair| pair.get_value() == label)
+						})
+					})
+					.map(|metric| metric.get_counter().get_value() as u64)
+			})
+			.unwrap_or(0)
+	}
+
+	fn histogram_sample_count(registry: &Registry, name: &str) -> u64 {
+		registry
+			.gather()
+			.into_iter()
+			.find(|family| family.get_name() == name)
+			.map(|family| family.get_metric()[0].get_histogram().get_sample_count())
+			.unwrap_or(0)
+	}
+
+	#[test]
+	fn out_of_range_index_is_rejected_before_signature_check() {
+		let hash_a: Hash = [0; 32].into();
+		let peer_a = PeerId::random();
+
+		let signing_context = SigningContext {
+			session_index: 1,
+			parent_hash: hash_a,
+		};
+
+		let validator: ValidatorId = Sr25519Keyring::Alice.public().into();
+
+		let registry = Registry::new();
+		let metrics = Metrics::register(Some(&registry)).unwrap();
+
+		let pool = sp_core::testing::SpawnBlockingExecutor::new();
+		let (mut ctx, mut handle) =
+			make_subsystem_context::<BitfieldDistributionMessage, _>(pool);
+
+		let mut tracker = prewarmed_tracker(validator, signing_context.clone(), hash_a, peers![peer_a]);
+		// every penalty is reported on its own
+		tracker.config.penalty_coalescing_window = Duration::from_secs(0);
+
+		const SPAM: u32 = 1_000;
+
+		executor::block_on(async move {
+			// none of these is signed by the one validator of the set,
+			// so a signature check would reject each of them as well
+			for validator_index in 1..=SPAM {
+				let msg = BitfieldGossipMessage {
+					relay_parent: hash_a,
+					signed_availability: Signed::<AvailabilityBitfield>::sign(
+						AvailabilityBitfield(bitvec![bitvec::order::Lsb0, u8; 1u8; 32]),
+						&signing_context,
+						validator_index,
+						&Sr25519Keyring::Ferdie.pair().into(),
+					),
+				};
+				process_incoming_peer_message(
+					&mut ctx,
+					&mut tracker,
+					&metrics,
+					&Default::default(),
+					peer_a.clone(),
+					msg,
+					None,
+				)
+				.await
+				.unwrap();
+
+				assert_matches!(
+					handle.recv().await,
+					AllMessages::NetworkBridge(
+						NetworkBridgeMessage::ReportPeer(peer, rep)
+					) => {
+						assert_eq!(peer, peer_a);
+						assert_eq!(rep, COST_VALIDATOR_INDEX_INVALID);
+					}
+				);
+			}
+		});
+
+		assert_eq!(
+			counter_value(&registry, "parachain_bitfields_rejected_total", Some("validator_index_invalid")),
+			SPAM as u64,
+		);
+		assert_eq!(
+			counter_value(&registry, "parachain_bitfields_rejected_total", Some("signature_invalid")),
+			0,
+		);
+	}
+
+	#[test]
+	fn bitfield_of_mismatching_signing_context_is_rejected() {
+		let hash_a: Hash = [0; 32].into();
+		let hash_b: Hash = [1; 32].into();
+		let peer_a = PeerId::random();
+
+		let validator: ValidatorId = Sr25519Keyring::Alice.public().into();
+
+		// the context stored for `hash_a` is of `hash_b`, which the bitfield is signed for
+		let signing_context = SigningContext { session_index: 1, parent_hash: hash_b };
+		let msg = BitfieldGossipMessage {
+			relay_parent: hash_a,
+			signed_availability: Signed::<AvailabilityBitfield>::sign(
+				AvailabilityBitfield(bitvec![bitvec::order::Lsb0, u8; 1u8; 32]),
+				&signing_context,
+				0,
+				&Sr25519Keyring::Alice.pair().into(),
+			),
+		};
+
+		let registry = Registry::new();
+		let metrics = Metrics::register(Some(&registry)).unwrap();
+
+		let pool = sp_core::testing::SpawnBlockingExecutor::new();
+		let (mut ctx, mut handle) =
+			make_subsystem_context::<BitfieldDistributionMessage, _>(pool);
+
+		let mut tracker = prewarmed_tracker(validator, signing_context, hash_a, peers![peer_a]);
+
+		executor::block_on(async move {
+			handle_peer_messages(
+				&mut ctx,
+				&mut tracker,
+				&metrics,
+				&Default::default(),
+				vec![(peer_a.clone(), msg.encode_versioned())],
+			)
+			.await
+			.unwrap();
+
+			assert_matches!(
+				handle.recv().await,
+				AllMessages::NetworkBridge(
+					NetworkBridgeMessage::ReportPeer(peer, rep)
+				) => {
+					assert_eq!(peer, peer_a);
+					assert_eq!(rep, COST_CONTEXT_MISMATCH);
+				}
+			);
+			assert!(handle.try_recv().is_none());
+			assert!(tracker.per_relay_parent[&hash_a].one_per_validator.is_empty());
+		});
+
+		assert_eq!(
+			counter_value(&registry, "parachain_bitfields_rejected_total", Some("context_mismatch")),
+			1,
+		);
+	}
+
+	#[test]
+	fn out_of_range_index_is_rejected_without_signing_context() {
+		let hash_a: Hash = [0; 32].into();
+		let peer_a = PeerId::random();
+
+		let validator: ValidatorId = Sr25519Keyring::Alice.public().into();
+
+		// signed for a session the relay parent is not part of,
+		// so any use of the signing context would reject the signature
+		let msg = BitfieldGossipMessage {
+			relay_parent: hash_a,
+			signed_availability: Signed::<AvailabilityBitfield>::sign(
+				AvailabilityBitfield(bitvec![bitvec::order::Lsb0, u8; 1u8; 32]),
+				&SigningContext { session_index: 2, parent_hash: hash_a },
+				1,
+				&Sr25519Keyring::Bob.pair().into(),
+			),
+		};
+
+		let pool = sp_core::testing::SpawnBlockingExecutor::new();
+		let (mut ctx, mut handle) =
+			make_subsystem_context::<BitfieldDistributionMessage, _>(pool);
+
+		let signing_context = SigningContext { session_index: 1, parent_hash: hash_a };
+		let mut tracker = prewarmed_tracker(validator, signing_context, hash_a, peers![peer_a]);
+
+		executor::block_on(async move {
+			process_incoming_peer_message(
+				&mut ctx,
+				&mut tracker,
+				&Default::default(),
+				&Default::default(),
+				peer_a.clone(),
+				msg,
+				None,
+			)
+			.await
+			.unwrap();
+
+			assert_matches!(
+				handle.recv().await,
+				AllMessages::NetworkBridge(
+					NetworkBridgeMessage::ReportPeer(peer, rep)
+				) => {
+					assert_eq!(peer, peer_a);
+					assert_eq!(rep, COST_VALIDATOR_INDEX_INVALID);
+				}
+			);
+		});
+	}
+
+	#[test]
+	fn metrics_track_received_relayed_and_rejected_bitfields() {
+		let hash_a: Hash = [0; 32].into();
+
+		let peer_a = PeerId::random();
+		let peer_b = PeerId::random();
+
+		let signing_context = SigningContext {
+			session_index: 1,
+			parent_hash: hash_a,
+		};
+
+		let validator: ValidatorId = Sr25519Keyring::Alice.public().into();
+		let payload = AvailabilityBitfield(bitvec![bitvec::order::Lsb0, u8; 1u8; 32]);
+
+		let valid = BitfieldGossipMessage {
+			relay_parent: hash_a,
+			signed_availability: Signed::<AvailabilityBitfield>::sign(
+				payload.clone(),
+				&signing_context,
+				0,
+				&Sr25519Keyring::Alice.pair().into(),
+			),
+		};
+		let invalid = BitfieldGossipMessage {
+			relay_parent: hash_a,
+			signed_availability: Signed::<AvailabilityBitfield>::sign(
+				payload,
+				&signing_context,
+				0,
+				&Sr25519Keyring::Ferdie.pair().into(),
+			),
+		};
+
+		let registry = Registry::new();
+		let metrics = Metrics::register(Some(&registry)).unwrap();
+
+		let pool = sp_core::testing::SpawnBlockingExecutor::new();
+		let (mut ctx, mut handle) =
+			make_subsystem_context::<BitfieldDistributionMessage, _>(pool);
+
+		let mut tracker = prewarmed_tracker(validator, signing_context, hash_a, peers![peer_a, peer_b]);
+
+		executor::block_on(async move {
+			for bytes in vec![invalid.encode_versioned(), vec![0x01, 0xFF, 0x00], valid.encode_versioned()] {
+				handle_network_msg(
+					&mut ctx,
+					&mut tracker,
+					&metrics,
+					&Default::default(),
+					NetworkBridgeEvent::PeerMessage(peer_b.clone(), bytes),
+				)
+				.await
+				.unwrap();
+			}
+
+			// two reports, then the reward, the provisioner notification and the relay
+			for _ in 0..5 {
+				let _ = handle.recv().await;
+			}
+		});
+
+		assert_eq!(counter_value(&registry, "parachain_bitfields_received_total", None), 2);
+		assert_eq!(counter_value(&registry, "parachain_bitfields_relayed_total", None), 1);
+		assert_eq!(
+			counter_value(&registry, "parachain_bitfields_rejected_total", Some("signature_invalid")),
+			1,
+		);
+		assert_eq!(
+			counter_value(&registry, "parachain_bitfields_rejected_total", Some("undecodable")),
+			1,
+		);
+		assert_eq!(
+			counter_value(&registry, "parachain_bitfields_rejected_total", Some("not_interested")),
+			0,
+		);
+	}
+
+	#[test]
+	fn first_bitfield_and_quorum_latencies_are_recorded() {
+		let hash_a: Hash = [0; 32].into();
+		let peer_a = PeerId::random();
+
+		let signing_context = SigningContext {
+			session_index: 1,
+			parent_hash: hash_a,
+		};
+
+		let keyrings = [Sr25519Keyring::Alice, Sr25519Keyring::Bob, Sr25519Keyring::Charlie];
+		let validators: Vec<ValidatorId> = keyrings.iter().map(|k| k.public().into()).collect();
+
+		let messages: Vec<_> = keyrings
+			.iter()
+			.enumerate()
+			.map(|(index, keyring)| BitfieldGossipMessage {
+				relay_parent: hash_a,
+				signed_availability: Signed::<AvailabilityBitfield>::sign(
+					AvailabilityBitfield(bitvec![bitvec::order::Lsb0, u8; 1u8; 32]),
+					&signing_context,
+					index as ValidatorIndex,
+					&keyring.pair().into(),
+				),
+			})
+			.collect();
+
+		let registry = Registry::new();
+		let metrics = Metrics::register(Some(&registry)).unwrap();
+
+		let pool = sp_core::testing::SpawnBlockingExecutor::new();
+		let (ctx, mut handle) =
+			make_subsystem_context::<BitfieldDistributionMessage, _>(pool);
+
+		let subsystem = BitfieldDistribution::new(metrics).start(ctx).future;
+
+		let test_fut = async {
+			assert_matches!(
+				handle.recv().await,
+				AllMessages::NetworkBridge(NetworkBridgeMessage::RegisterEventProducer(..))
+			);
+
+			start_work(&mut handle, hash_a, validators, signing_context).await;
+			handle.send(network_event(NetworkBridgeEvent::OurViewChange(view![hash_a]))).await;
+			handle.send(network_event(NetworkBridgeEvent::PeerConnected(peer_a.clone(), ObservedRole::Full))).await;
+
+			let mut messages = messages.into_iter();
+			for message in messages.by_ref().take(2) {
+				handle.send(network_event(
+					NetworkBridgeEvent::PeerMessage(peer_a.clone(), message.encode_versioned()),
+				)).await;
+			}
+			await_quiescence(&mut handle).await;
+
+			// 2 out of 3 are no quorum yet
+			assert_eq!(histogram_sample_count(&registry, "parachain_bitfield_first_latency_seconds"), 1);
+			assert_eq!(histogram_sample_count(&registry, "parachain_bitfield_quorum_latency_seconds"), 0);
+
+			let message = messages.next().unwrap();
+			handle.send(network_event(
+				NetworkBridgeEvent::PeerMessage(peer_a.clone(), message.encode_versioned()),
+			)).await;
+			await_quiescence(&mut handle).await;
+
+			assert_eq!(histogram_sample_count(&registry, "parachain_bitfield_first_latency_seconds"), 1);
+			assert_eq!(histogram_sample_count(&registry, "parachain_bitfield_quorum_latency_seconds"), 1);
+
+			handle.send(FromOverseer::Signal(OverseerSignal::Conclude)).await;
+		};
+
+		executor::block_on(future::join(test_fut, subsystem));
+	}
+
+	#[test]
+	fn bitfields_of_untracked_relay_parent_in_view_are_counted_and_mildly_penalized() {
+		let hash_a: Hash = [0; 32].into();
+		let peer_b = PeerId::random();
+
+		let signing_context = SigningContext {
+			session_index: 1,
+			parent_hash: hash_a,
+		};
+
+		let messages: Vec<_> = vec![Sr25519Keyring::Alice, Sr25519Keyring::Bob]
+			.into_iter()
+			.enumerate()
+			.map(|(index, key)| BitfieldGossipMessage {
+				relay_parent: hash_a,
+				signed_availability: Signed::<AvailabilityBitfield>::sign(
+					AvailabilityBitfield(bitvec![bitvec::order::Lsb0, u8; 1u8; 32]),
+					&signing_context,
+					index as ValidatorIndex,
+					&key.pair().into(),
+				),
+			})
+			.collect();
+
+		let registry = Registry::new();
+		let metrics = Metrics::register(Some(&registry)).unwrap();
+
+		let pool = sp_core::testing::SpawnBlockingExecutor::new();
+		let (mut ctx, mut handle) =
+			make_subsystem_context::<BitfieldDistributionMessage, _>(pool);
+
+		// in our view, but no `StartWork` yet
+		let mut tracker = Tracker {
+			view: view![hash_a],
+			config: Config { max_early_messages: 1, ..Default::default() },
+			..Default::default()
+		};
+		tracker.peer_views.insert(peer_b.clone(), view![hash_a]);
+
+		executor::block_on(async move {
+			for message in messages {
+				handle_network_msg(
+					&mut ctx,
+					&mut tracker,
+					&metrics,
+					&Default::default(),
+					NetworkBridgeEvent::PeerMessage(peer_b.clone(), message.encode_versioned()),
+				)
+				.await
+				.unwrap();
+			}
+
+			// the first one is buffered, only the second one is penalized
+			assert_matches!(
+				handle.recv().await,
+				AllMessages::NetworkBridge(NetworkBridgeMessage::ReportPeer(peer, rep)) => {
+					assert_eq!(peer, peer_b);
+					assert_eq!(rep, COST_RELAY_PARENT_UNKNOWN_BUT_IN_VIEW);
+				}
+			);
+			assert!(timeout(handle.recv(), TIMEOUT).await.is_none());
+		});
+
+		assert_eq!(
+			counter_value(&registry, "parachain_bitfields_of_untracked_relay_parents_total", None),
+			2,
+		);
+		assert_eq!(
+			counter_value(&registry, "parachain_bitfields_rejected_total", Some("untracked_relay_parent")),
+			1,
+		);
+		assert_eq!(
+			counter_value(&registry, "parachain_bitfields_rejected_total", Some("not_interested")),
+			0,
+		);
+	}
+
+	#[test]
+	fn receive_undecodable_message() {
+		let hash_a: Hash = [0; 32].into();
+		let peer_a = PeerId::random();
+
+		let signing_context = SigningContext {
+			session_index: 1,
+			parent_hash: hash_a,
+		};
+
+		let validator: ValidatorId = Sr25519Keyring::Alice.public().into();
+
+		let pool = sp_core::testing::SpawnBlockingExecutor::new();
+		let (mut ctx, mut handle) =
+			make_subsystem_context::<BitfieldDistributionMessage, _>(pool);
+
+		let mut tracker = prewarmed_tracker(validator, signing_context, hash_a, peers![peer_a]);
+
+		executor::block_on(async move {
+			handle_network_msg(
+				&mut ctx,
+				&mut tracker,
+				&Default::default(),
+				&Default::default(),
+				NetworkBridgeEvent::PeerMessage(peer_a.clone(), vec![0x01, 0xDE, 0xAD]),
+			)
+			.await
+			.unwrap();
+
+			assert_matches!(
+				handle.recv().await,
+				AllMessages::NetworkBridge(
+					NetworkBridgeMessage::ReportPeer(peer, rep)
+				) => {
+					assert_eq!(peer, peer_a);
+					assert_eq!(rep, COST_MESSAGE_NOT_DECODABLE);
+					assert_eq!(rep.reason, "Bitfield message not decodable");
+					assert_ne!(rep.reason, COST_NOT_INTERESTED.reason);
+				}
+			);
+		});
+	}
+
+	#[test]
+	fn flooding_peer_is_penalized() {
+		let hash_a: Hash = [0; 32].into();
+
+		let peer_a = PeerId::random();
+		let peer_b = PeerId::random();
+
+		let signing_context = SigningContext {
+			session_index: 1,
+			parent_hash: hash_a,
+		};
+
+		let validator: ValidatorId = Sr25519Keyring::Alice.public().into();
+
+		let msg = BitfieldGossipMessage {
+			relay_parent: hash_a,
+			signed_availability: Signed::<AvailabilityBitfield>::sign(
+				AvailabilityBitfield(bitvec![bitvec::order::Lsb0, u8; 1u8; 32]),
+				&signing_context,
+				0,
+				&Sr25519Keyring::Alice.pair().into(),
+			),
+		};
+
+		let pool = sp_core::testing::SpawnBlockingExecutor::new();
+		let (mut ctx, mut handle) =
+			make_subsystem_context::<BitfieldDistributionMessage, _>(pool);
+
+		let mut tracker = prewarmed_tracker(validator, signing_context, hash_a, peers![peer_a, peer_b]);
+		tracker.config = Config {
+			flood_window: Duration::from_secs(60),
+			flood_threshold: 10,
+			// every penalty is reported on its own
+			penalty_coalescing_window: Duration::from_secs(0),
+			..Default::default()
+		};
+
+		executor::block_on(async move {
+			for _ in 0..100 {
+				handle_network_msg(
+					&mut ctx,
+					&mut tracker,
+					&Default::default(),
+					&Default::default(),
+					NetworkBridgeEvent::PeerMessage(peer_a.clone(), msg.encode_versioned()),
+				)
+				.await
+				.unwrap();
+			}
+
+			// the first message is new and relayed, the following nine are
+			// silent duplicates and all the remaining ones are penalized.
+			let mut floods = 0;
+			for _ in 0..93 {
+				match handle.recv().await {
+					AllMessages::NetworkBridge(NetworkBridgeMessage::ReportPeer(peer, rep))
+						if rep == COST_APPARENT_FLOOD =>
+					{
+						assert_eq!(peer, peer_a);
+						floods += 1;
+					}
+					AllMessages::NetworkBridge(NetworkBridgeMessage::ReportPeer(_, rep)) => {
+						assert_eq!(rep, GAIN_VALID_MESSAGE_FIRST);
+					}
+					AllMessages::NetworkBridge(NetworkBridgeMessage::SendMessage(..)) => {}
+					AllMessages::Provisioner(_) => {}
+					other => panic!("Unexpected message: {:?}", other),
+				}
+			}
+			assert_eq!(floods, 90);
+		});
+	}
+
+	#[test]
+	fn flapping_view_is_penalized() {
+		let hash_a: Hash = [0; 32].into();
+
+		let peer_a = PeerId::random();
+
+		let signing_context = SigningContext {
+			session_index: 1,
+			parent_hash: hash_a,
+		};
+
+		let validator: ValidatorId = Sr25519Keyring::Alice.public().into();
+
+		let pool = sp_core::testing::SpawnBlockingExecutor::new();
+		let (mut ctx, mut handle) =
+			make_subsystem_context::<BitfieldDistributionMessage, _>(pool);
+
+		let mut tracker = prewarmed_tracker(validator, signing_context, hash_a, peers![peer_a]);
+		tracker.config = Config {
+			view_change_window: Duration::from_secs(60),
+			max_view_changes: 3,
+			// every penalty is reported on its own
+			penalty_coalescing_window: Duration::from_secs(0),
+			..Default::default()
+		};
+
+		executor::block_on(async move {
+			// leave and rejoin the relay parent we work on over and over again
+			for i in 0..10 {
+				let view = if i % 2 == 0 { view![] } else { view![hash_a] };
+				handle_network_msg(
+					&mut ctx,
+					&mut tracker,
+					&Default::default(),
+					&Default::default(),
+					NetworkBridgeEvent::PeerViewChange(peer_a.clone(), view),
+				)
+				.await
+				.unwrap();
+			}
+
+			let mut flaps = 0;
+			let mut rewards = 0;
+			while let Some(msg) = timeout(handle.recv(), TIMEOUT).await {
+				match msg {
+					AllMessages::NetworkBridge(NetworkBridgeMessage::ReportPeer(peer, rep))
+						if rep == COST_VIEW_FLAPPING =>
+					{
+						assert_eq!(peer, peer_a);
+						flaps += 1;
+					}
+					AllMessages::NetworkBridge(NetworkBridgeMessage::ReportPeer(peer, rep)) => {
+						assert_eq!(peer, peer_a);
+						assert_eq!(rep, GAIN_USEFUL_VIEW_CHANGE);
+						rewards += 1;
+					}
+					other => panic!("Unexpected message: {:?}", other),
+				}
+			}
+			// every view change beyond the third one is penalized, but still tracked,
+			// while rejoining the relay parent is only ever rewarded the first time
+			assert_eq!(flaps, 7);
+			assert_eq!(rewards, 1);
+		});
+	}
+
+	#[test]
+	fn useful_view_change_is_rewarded() {
+		let hash_a: Hash = [0; 32].into();
+		let peer_a = PeerId::random();
+
+		let signing_context = SigningContext {
+			session_index: 1,
+			parent_hash: hash_a,
+		};
+
+		let validator: ValidatorId = Sr25519Keyring::Alice.public().into();
+
+		let pool = sp_core::testing::SpawnBlockingExecutor::new();
+		let (mut ctx, mut handle) =
+			make_subsystem_context::<BitfieldDistributionMessage, _>(pool);
+
+		let mut tracker = prewarmed_tracker(validator, signing_context, hash_a, peers![]);
+
+		executor::block_on(async move {
+			for event in vec![
+				NetworkBridgeEvent::PeerConnected(peer_a.clone(), ObservedRole::Full),
+				NetworkBridgeEvent::PeerViewChange(peer_a.clone(), view![hash_a]),
+			] {
+				handle_network_msg(
+					&mut ctx,
+					&mut tracker,
+					&Default::default(),
+					&Default::default(),
+					event,
+				)
+				.await
+				.unwrap();
+			}
+
+			assert_matches!(
+				handle.recv().await,
+				AllMessages::NetworkBridge(
+					NetworkBridgeMessage::ReportPeer(peer, rep)
+				) => {
+					assert_eq!(peer, peer_a);
+					assert_eq!(rep, GAIN_USEFUL_VIEW_CHANGE);
+				}
+			);
+		});
+	}
+
+	#[test]
+	fn toggling_view_is_rewarded_once() {
+		let hash_a: Hash = [0; 32].into();
+		let peer_a = PeerId::random();
+
+		let signing_context = SigningContext {
+			session_index: 1,
+			parent_hash: hash_a,
+		};
+
+		let validator: ValidatorId = Sr25519Keyring::Alice.public().into();
+
+		let pool = sp_core::testing::SpawnBlockingExecutor::new();
+		let (mut ctx, mut handle) =
+			make_subsystem_context::<BitfieldDistributionMessage, _>(pool);
+
+		let mut tracker = prewarmed_tracker(validator, signing_context, hash_a, peers![]);
+
+		executor::block_on(async move {
+			let mut events = vec![NetworkBridgeEvent::PeerConnected(peer_a.clone(), ObservedRole::Full)];
+			for _ in 0..3 {
+				events.push(NetworkBridgeEvent::PeerViewChange(peer_a.clone(), view![hash_a]));
+				events.push(NetworkBridgeEvent::PeerViewChange(peer_a.clone(), view![]));
+			}
+			for event in events {
+				handle_network_msg(
+					&mut ctx,
+					&mut tracker,
+					&Default::default(),
+					&Default::default(),
+					event,
+				)
+				.await
+				.unwrap();
+			}
+
+			assert_matches!(
+				handle.recv().await,
+				AllMessages::NetworkBridge(
+					NetworkBridgeMessage::ReportPeer(peer, rep)
+				) => {
+					assert_eq!(peer, peer_a);
+					assert_eq!(rep, GAIN_USEFUL_VIEW_CHANGE);
+				}
+			);
+			assert_matches!(handle.try_recv(), None);
+		});
+	}
+
+	#[test]
+	fn catch_up_is_batched_per_peer() {
+		let hashes: Vec<Hash> = (0..3u8).map(|i| [i; 32].into()).collect();
+		let peer_a = PeerId::random();
+
+		let validator: ValidatorId = Sr25519Keyring::Alice.public().into();
+
+		let messages: Vec<BitfieldGossipMessage> = hashes
+			.iter()
+			.map(|hash| {
+				let signing_context = SigningContext {
+					session_index: 1,
+					parent_hash: *hash,
+				};
+				BitfieldGossipMessage {
+					relay_parent: *hash,
+					signed_availability: Signed::<AvailabilityBitfield>::sign(
+						AvailabilityBitfield(bitvec![bitvec::order::Lsb0, u8; 1u8; 32]),
+						&signing_context,
+						0,
+						&Sr25519Keyring::Alice.pair().into(),
+					),
+				}
+			})
+			.collect();
+
+		let pool = sp_core::testing::SpawnBlockingExecutor::new();
+		let (mut ctx, mut handle) =
+			make_subsystem_context::<BitfieldDistributionMessage, _>(pool);
+
+		let mut tracker = Tracker::default();
+		for message in messages.iter() {
+			let mut job_data = PerRelayParentData {
+				signing_context: SigningContext {
+					session_index: 1,
+					parent_hash: message.relay_parent,
+				},
+				validator_set: Arc::new(vec![validator.clone()]),
+				..Default::default()
+			};
+			job_data.insert_message(validator.clone(), message.clone());
+			tracker.per_relay_parent.insert(message.relay_parent, job_data);
+		}
+		tracker.view = View(hashes.clone());
+		tracker.peer_views.insert(peer_a.clone(), View::default());
+
+		executor::block_on(async move {
+			handle_network_msg(
+				&mut ctx,
+				&mut tracker,
+				&Default::default(),
+				&Default::default(),
+				NetworkBridgeEvent::PeerViewChange(peer_a.clone(), View(hashes.clone())),
+			)
+			.await
+			.unwrap();
+
+			assert_matches!(
+				handle.recv().await,
+				AllMessages::NetworkBridge(NetworkBridgeMessage::ReportPeer(_, rep)) => {
+					assert_eq!(rep, GAIN_USEFUL_VIEW_CHANGE);
+				}
+			);
+
+			// all three bitfields in a single network message
+			assert_matches!(
+				handle.recv().await,
+				AllMessages::NetworkBridge(
+					NetworkBridgeMessage::SendMessage(peers, _, bytes)
+				) => {
+					assert_eq!(peers, vec![peer_a.clone()]);
+					let batch = match VersionedBitfieldGossipMessage::decode(&mut bytes.as_slice()) {
+						Ok(VersionedBitfieldGossipMessage::V2(batch)) => batch,
+						other => panic!("Expected a batch, got {:?}", other),
+					};
+					assert_eq!(batch.len(), messages.len());
+					for message in messages.iter() {
+						assert!(batch.contains(message));
+					}
+				}
+			);
+			assert!(timeout(handle.recv(), TIMEOUT).await.is_none());
+
+			for hash in hashes.iter() {
+				assert!(tracker.per_relay_parent[hash].message_sent_to_peer[&peer_a].contains(&validator));
+			}
+		});
+	}
+
+	#[test]
+	fn relay_parents_beyond_quota_are_not_tracked_for_peer() {
+		let hash_a: Hash = [0; 32].into();
+		let unknown: Vec<Hash> = (1..=5u8).map(|i| [i; 32].into()).collect();
+		let peer_a = PeerId::random();
+
+		let signing_context = SigningContext {
+			session_index: 1,
+			parent_hash: hash_a,
+		};
+
+		let validator: ValidatorId = Sr25519Keyring::Alice.public().into();
+
+		let pool = sp_core::testing::SpawnBlockingExecutor::new();
+		let (mut ctx, mut handle) =
+			make_subsystem_context::<BitfieldDistributionMessage, _>(pool);
+
+		let mut tracker = prewarmed_tracker(validator, signing_context, hash_a, peers![peer_a]);
+		tracker.config.max_peer_view_len = 3;
+
+		// the relay parent we work on comes last
+		let mut relay_parents = unknown.clone();
+		relay_parents.push(hash_a);
+
+		executor::block_on(async move {
+			handle_network_msg(
+				&mut ctx,
+				&mut tracker,
+				&Default::default(),
+				&Default::default(),
+				NetworkBridgeEvent::PeerViewChange(peer_a.clone(), View(relay_parents)),
+			)
+			.await
+			.unwrap();
+
+			assert_matches!(
+				handle.recv().await,
+				AllMessages::NetworkBridge(
+					NetworkBridgeMessage::ReportPeer(peer, rep)
+				) => {
+					assert_eq!(peer, peer_a);
+					assert_eq!(rep, COST_VIEW_BEYOND_QUOTA);
+				}
+			);
+
+			assert_eq!(tracker.peer_views.get(&peer_a), Some(&View(vec![hash_a, unknown[0], unknown[1]])));
+		});
+	}
+
+	#[test]
+	fn oversized_view_is_ignored() {
+		let hash_a: Hash = [0; 32].into();
+		let peer_a = PeerId::random();
+
+		let signing_context = SigningContext {
+			session_index: 1,
+			parent_hash: hash_a,
+		};
+
+		let validator: ValidatorId = Sr25519Keyring::Alice.public().into();
+
+		let msg = BitfieldGossipMessage {
+			relay_parent: hash_a,
+			signed_availability: Signed::<AvailabilityBitfield>::sign(
+				AvailabilityBitfield(bitvec![bitvec::order::Lsb0, u8; 1u8; 32]),
+				&signing_context,
+				0,
+				&Sr25519Keyring::Alice.pair().into(),
+			),
+		};
+
+		let pool = sp_core::testing::SpawnBlockingExecutor::new();
+		let (mut ctx, mut handle) =
+			make_subsystem_context::<BitfieldDistributionMessage, _>(pool);
+
+		// there is a message `peer_a` would be interested in
+		let mut tracker = prewarmed_tracker(validator.clone(), signing_context, hash_a, peers![peer_a]);
+		tracker.peer_views.insert(peer_a.clone(), view![]);
+		tracker
+			.per_relay_parent
+			.get_mut(&hash_a)
+			.unwrap()
+			.insert_message(validator, msg);
+		tracker.config.max_acceptable_view_len = 100;
+
+		let mut relay_parents = vec![hash_a];
+		relay_parents.extend((1..=100u8).map(|i| Hash::from([i; 32])));
+
+		executor::block_on(async move {
+			handle_network_msg(
+				&mut ctx,
+				&mut tracker,
+				&Default::default(),
+				&Default::default(),
+				NetworkBridgeEvent::PeerViewChange(peer_a.clone(), View(relay_parents)),
+			)
+			.await
+			.unwrap();
+
+			assert_matches!(
+				handle.recv().await,
+				AllMessages::NetworkBridge(
+					NetworkBridgeMessage::ReportPeer(peer, rep)
+				) => {
+					assert_eq!(peer, peer_a);
+					assert_eq!(rep, COST_OVERSIZED_VIEW);
+				}
+			);
+			// no catch-up, now or later
+			assert!(handle.try_recv().is_none());
+			assert!(tracker.pending_catch_up.is_empty());
+			assert_eq!(tracker.peer_views.get(&peer_a), Some(&view![]));
+		});
+	}
+
+	#[test]
+	fn view_change_of_unconnected_peer_is_ignored() {
+		let hash_a: Hash = [0; 32].into();
+		let peer_a = PeerId::random();
+		let peer_b = PeerId::random();
+
+		let signing_context = SigningContext {
+			session_index: 1,
+			parent_hash: hash_a,
+		};
+
+		let validator: ValidatorId = Sr25519Keyring::Alice.public().into();
+
+		let msg = BitfieldGossipMessage {
+			relay_parent: hash_a,
+			signed_availability: Signed::<AvailabilityBitfield>::sign(
+				AvailabilityBitfield(bitvec![bitvec::order::Lsb0, u8; 1u8; 32]),
+				&signing_context,
+				0,
+				&Sr25519Keyring::Alice.pair().into(),
+			),
+		};
+
+		let pool = sp_core::testing::SpawnBlockingExecutor::new();
+		let (mut ctx, mut handle) =
+			make_subsystem_context::<BitfieldDistributionMessage, _>(pool);
+
+		// there is a message `peer_b` would be interested in
+		let mut tracker = prewarmed_tracker(validator.clone(), signing_context, hash_a, peers![peer_a]);
+		tracker
+			.per_relay_parent
+			.get_mut(&hash_a)
+			.unwrap()
+			.insert_message(validator, msg);
+
+		executor::block_on(async move {
+			handle_network_msg(
+				&mut ctx,
+				&mut tracker,
+				&Default::default(),
+				&Default::default(),
+				NetworkBridgeEvent::PeerViewChange(peer_b.clone(), view![hash_a]),
+			)
+			.await
+			.unwrap();
+
+			assert_matches!(
+				handle.recv().await,
+				AllMessages::NetworkBridge(
+					NetworkBridgeMessage::ReportPeer(peer, rep)
+				) => {
+					assert_eq!(peer, peer_b);
+					assert_eq!(rep, COST_VIEW_CHANGE_NOT_CONNECTED);
+				}
+			);
+			// no catch-up gossip
+			assert!(timeout(handle.recv(), TIMEOUT).await.is_none());
+			assert!(!tracker.peer_views.contains_key(&peer_b));
+		});
+	}
+
+	#[test]
+	fn repeated_useless_view_changes_are_penalized() {
+		let hash_a: Hash = [0; 32].into();
+		let peer_a = PeerId::random();
+
+		let signing_context = SigningContext {
+			session_index: 1,
+			parent_hash: hash_a,
+		};
+
+		let validator: ValidatorId = Sr25519Keyring::Alice.public().into();
+
+		let pool = sp_core::testing::SpawnBlockingExecutor::new();
+		let (mut ctx, mut handle) =
+			make_subsystem_context::<BitfieldDistributionMessage, _>(pool);
+
+		let mut tracker = prewarmed_tracker(validator, signing_context, hash_a, peers![peer_a]);
+		let tolerated = tracker.config.max_useless_view_changes;
+
+		executor::block_on(async move {
+			// none of these relay parents is known to us
+			for i in 0..=tolerated {
+				let unknown: Hash = [i as u8 + 1; 32].into();
+				handle_network_msg(
+					&mut ctx,
+					&mut tracker,
+					&Default::default(),
+					&Default::default(),
+					NetworkBridgeEvent::PeerViewChange(peer_a.clone(), view![unknown]),
+				)
+				.await
+				.unwrap();
+			}
+
+			// only the view change exceeding the tolerated ones is penalized
+			assert_matches!(
+				handle.recv().await,
+				AllMessages::NetworkBridge(
+					NetworkBridgeMessage::ReportPeer(peer, rep)
+				) => {
+					assert_eq!(peer, peer_a);
+					assert_eq!(rep, COST_USELESS_VIEW_CHANGE);
+				}
+			);
+			assert!(timeout(handle.recv(), TIMEOUT).await.is_none());
+		});
+	}
+
+	#[test]
+	fn versioned_message_round_trip() {
+		let hash_a: Hash = [0; 32].into();
+
+		let signing_context = SigningContext {
+			session_index: 1,
+			parent_hash: hash_a,
+		};
+
+		let msg = BitfieldGossipMessage {
+			relay_parent: hash_a,
+			signed_availability: Signed::<AvailabilityBitfield>::sign(
+				AvailabilityBitfield(bitvec![bitvec::order::Lsb0, u8; 1u8; 32]),
+				&signing_context,
+				0,
+				&Sr25519Keyring::Alice.pair().into(),
+			),
+		};
+
+		let bytes = msg.encode_versioned();
+		assert_eq!(bytes[0], 1);
+		assert_eq!(&bytes[1..], &msg.encode()[..]);
+		assert_eq!(
+			VersionedBitfieldGossipMessage::decode(&mut bytes.as_slice()),
+			Ok(VersionedBitfieldGossipMessage::V1(msg)),
+		);
+	}
+
+	#[test]
+	fn encoding_from_parts_matches_encoding() {
+		let messages: Vec<BitfieldGossipMessage> = (0..3u8)
+			.map(|i| {
+				let hash: Hash = [i; 32].into();
+				let signing_context = SigningContext {
+					session_index: 1,
+					parent_hash: hash,
+				};
+				BitfieldGossipMessage {
+					relay_parent: hash,
+					signed_availability: Signed::<AvailabilityBitfield>::sign(
+						AvailabilityBitfield(bitvec![bitvec::order::Lsb0, u8; 1u8; 32]),
+						&signing_context,
+						0,
+						&Sr25519Keyring::Alice.pair().into(),
+					),
+				}
+			})
+			.collect();
+		let parts: Vec<Arc<Vec<u8>>> = messages.iter().map(|msg| Arc::new(msg.encode())).collect();
+
+		assert_eq!(
+			VersionedBitfieldGossipMessage::encode_from_parts(&parts[..1]),
+			messages[0].encode_versioned(),
+		);
+		assert_eq!(
+			VersionedBitfieldGossipMessage::encode_from_parts(&parts),
+			VersionedBitfieldGossipMessage::V2(messages).encode(),
+		);
+	}
+
+	/// A message of a large validator set with only one bit set.
+	fn mostly_empty_message(
+		relay_parent: Hash,
+		signing_context: &SigningContext,
+		validator_index: ValidatorIndex,
+		keyring: Sr25519Keyring,
+	) -> BitfieldGossipMessage {
+		let mut bits = bitvec![bitvec::order::Lsb0, u8; 0u8; 1024];
+		bits.set(7, true);
+		BitfieldGossipMessage {
+			relay_parent,
+			signed_availability: Signed::<AvailabilityBitfield>::sign(
+				AvailabilityBitfield(bits),
+				signing_context,
+				validator_index,
+				&keyring.pair().into(),
+			),
+		}
+	}
+
+	#[test]
+	fn compressed_messages_round_trip() {
+		let hash_a: Hash = [0; 32].into();
+		let signing_context = SigningContext {
+			session_index: 1,
+			parent_hash: hash_a,
+		};
+		let messages: Vec<_> = vec![Sr25519Keyring::Alice, Sr25519Keyring::Bob]
+			.into_iter()
+			.enumerate()
+			.map(|(i, keyring)| mostly_empty_message(hash_a, &signing_context, i as ValidatorIndex, keyring))
+			.collect();
+
+		let plain = vec![
+			VersionedBitfieldGossipMessage::V1(messages[0].clone()),
+			VersionedBitfieldGossipMessage::V2(messages),
+		];
+		for message in plain {
+			let bytes = VersionedBitfieldGossipMessage::compress(message.encode());
+			assert_eq!(bytes[0], 3);
+			assert!(bytes.len() < message.encode().len());
+			assert_matches!(
+				VersionedBitfieldGossipMessage::decode_all(&bytes)
+					.and_then(|compressed| compressed.decompressed(1024 * 1024)),
+				Ok(decompressed) => assert_eq!(decompressed, message)
+			);
+			// never decompressed beyond the given size
+			assert!(
+				VersionedBitfieldGossipMessage::decode_all(&bytes)
+					.and_then(|compressed| compressed.decompressed(message.encode().len() - 1))
+					.is_err()
+			);
+		}
+
+		// a small message doesn't get room for the maximum size
+		let compressed = zstd::block::compress(&[0; 64], VersionedBitfieldGossipMessage::COMPRESSION_LEVEL).unwrap();
+		let bytes = VersionedBitfieldGossipMessage::decompress(&compressed, 1024 * 1024).unwrap();
+		assert_eq!(bytes, vec![0; 64]);
+		assert!(bytes.capacity() < 64 * 1024);
+
+		// compressing twice doesn't make for a valid message
+		let message = VersionedBitfieldGossipMessage::Compressed(
+			zstd::block::compress(&[3, 0], VersionedBitfieldGossipMessage::COMPRESSION_LEVEL).unwrap(),
+		);
+		assert!(message.decompressed(1024).is_err());
+
+		// incompressible messages are sent as they are
+		let msg = BitfieldGossipMessage {
+			relay_parent: hash_a,
+			signed_availability: Signed::<AvailabilityBitfield>::sign(
+				AvailabilityBitfield(bitvec![bitvec::order::Lsb0, u8; 1u8; 32]),
+				&signing_context,
+				0,
+				&Sr25519Keyring::Alice.pair().into(),
+			),
+		};
+		assert_eq!(VersionedBitfieldGossipMessage::compress(msg.encode_versioned()), msg.encode_versioned());
+	}
+
+	#[test]
+	fn compressed_messages_are_only_understood_when_compressing() {
+		let hash_a: Hash = [0; 32].into();
+		let peer_a = PeerId::random();
+		let peer_b = PeerId::random();
+		let peer_c = PeerId::random();
+
+		let signing_context = SigningContext {
+			session_index: 1,
+			parent_hash: hash_a,
+		};
+
+		let keyrings = [Sr25519Keyring::Alice, Sr25519Keyring::Bob];
+		let validators: Vec<ValidatorId> = keyrings.iter().map(|k| k.public().into()).collect();
+		let messages: Vec<_> = keyrings
+			.iter()
+			.enumerate()
+			.map(|(i, keyring)| mostly_empty_message(hash_a, &signing_context, i as ValidatorIndex, *keyring))
+			.collect();
+
+		// `peer_a` compresses its messages, `peer_b` does not
+		let received = vec![
+			(peer_a.clone(), VersionedBitfieldGossipMessage::compress(messages[0].encode_versioned())),
+			(peer_b.clone(), messages[1].encode_versioned()),
+		];
+
+		for compress_gossip in vec![false, true] {
+			let pool = sp_core::testing::SpawnBlockingExecutor::new();
+			let (mut ctx, mut handle) =
+				make_subsystem_context::<BitfieldDistributionMessage, _>(pool);
+
+			let mut tracker = prewarmed_tracker(
+				validators[0].clone(),
+				signing_context.clone(),
+				hash_a,
+				peers![peer_a, peer_b, peer_c],
+			);
+			tracker.per_relay_parent.get_mut(&hash_a).unwrap().validator_set = Arc::new(validators.clone());
+			tracker.config.compress_gossip = compress_gossip;
+
+			let received = received.clone();
+			let messages = messages.clone();
+			let (peer_a, peer_b) = (peer_a.clone(), peer_b.clone());
+			executor::block_on(async move {
+				handle_peer_messages(
+					&mut ctx,
+					&mut tracker,
+					&Default::default(),
+					&Default::default(),
+					received,
+				)
+				.await
+				.unwrap();
+
+				let mut reports = Vec::new();
+				let mut relayed = Vec::new();
+				while let Some(message) = handle.try_recv() {
+					match message {
+						AllMessages::NetworkBridge(NetworkBridgeMessage::ReportPeer(peer, rep)) => {
+							reports.push((peer, rep));
+						}
+						AllMessages::NetworkBridge(NetworkBridgeMessage::SendMessage(_, _, bytes)) => {
+							relayed.push(bytes);
+						}
+						_ => {}
+					}
+				}
+				let rep_a = if compress_gossip { GAIN_VALID_MESSAGE_FIRST } else { COST_UNSUPPORTED_VERSION };
+				assert_eq!(reports, vec![
+					(peer_a, rep_a),
+					(peer_b, GAIN_VALID_MESSAGE_FIRST),
+				]);
+
+				// relayed in the configured format, whichever one they were received in
+				let expected: Vec<_> = messages
+					.iter()
+					.skip(if compress_gossip { 0 } else { 1 })
+					.map(|message| tracker.config.wire_bytes(message.encode_versioned()))
+					.collect();
+				assert_eq!(relayed, expected);
+				assert!(relayed.iter().all(|bytes| (bytes[0] == 3) == compress_gossip));
+			});
+		}
+	}
+
+	#[test]
+	fn receive_unsupported_version() {
+		let hash_a: Hash = [0; 32].into();
+		let peer_a = PeerId::random();
+
+		let signing_context = SigningContext {
+			session_index: 1,
+			parent_hash: hash_a,
+		};
+
+		let validator: ValidatorId = Sr25519Keyring::Alice.public().into();
+
+		let msg = BitfieldGossipMessage {
+			relay_parent: hash_a,
+			signed_availability: Signed::<AvailabilityBitfield>::sign(
+				AvailabilityBitfield(bitvec![bitvec::order::Lsb0, u8; 1u8; 32]),
+				&signing_context,
+				0,
+				&Sr25519Keyring::Alice.pair().into(),
+			),
+		};
+
+		// a perfectly fine message, but claiming to be of a future version
+		let mut bytes = msg.encode_versioned();
+		bytes[0] = 4;
+
+		let registry = Registry::new();
+		let metrics = Metrics::register(Some(&registry)).unwrap();
+
+		let pool = sp_core::testing::SpawnBlockingExecutor::new();
+		let (mut ctx, mut handle) =
+			make_subsystem_context::<BitfieldDistributionMessage, _>(pool);
+
+		let mut tracker = prewarmed_tracker(validator, signing_context, hash_a, peers![peer_a]);
+
+		executor::block_on(async move {
+			handle_network_msg(
+				&mut ctx,
+				&mut tracker,
+				&metrics,
+				&Default::default(),
+				NetworkBridgeEvent::PeerMessage(peer_a.clone(), bytes),
+			)
+			.await
+			.unwrap();
+
+			assert_matches!(
+				handle.recv().await,
+				AllMessages::NetworkBridge(
+					NetworkBridgeMessage::ReportPeer(peer, rep)
+				) => {
+					assert_eq!(peer, peer_a);
+					assert_eq!(rep, COST_UNSUPPORTED_VERSION);
+				}
+			);
+		});
+
+		assert_eq!(
+			counter_value(&registry, "parachain_bitfields_rejected_total", Some("unsupported_version")),
+			1,
+		);
+		assert_eq!(
+			counter_value(&registry, "parachain_bitfields_rejected_total", Some("undecodable")),
+			0,
+		);
+	}
+
+	#[test]
+	fn receive_oversized_message() {
+		let hash_a: Hash = [0; 32].into();
+		let peer_a = PeerId::random();
+
+		let signing_context = SigningContext {
+			session_index: 1,
+			parent_hash: hash_a,
+		};
+
+		let validator: ValidatorId = Sr25519Keyring::Alice.public().into();
+
+		let registry = Registry::new();
+		let metrics = Metrics::register(Some(&registry)).unwrap();
+
+		let pool = sp_core::testing::SpawnBlockingExecutor::new();
+		let (mut ctx, mut handle) =
+			make_subsystem_context::<BitfieldDistributionMessage, _>(pool);
+
+		let mut tracker = prewarmed_tracker(validator, signing_context, hash_a, peers![peer_a]);
+		tracker.config = Config { max_message_size: 100, ..Default::default() };
+
+		executor::block_on(async move {
+			handle_network_msg(
+				&mut ctx,
+				&mut tracker,
+				&metrics,
+				&Default::default(),
+				NetworkBridgeEvent::PeerMessage(peer_a.clone(), vec![0xFF; 101]),
+			)
+			.await
+			.unwrap();
+
+			assert_matches!(
+				handle.recv().await,
+				AllMessages::NetworkBridge(
+					NetworkBridgeMessage::ReportPeer(peer, rep)
+				) => {
+					assert_eq!(peer, peer_a);
+					assert_eq!(rep, COST_OVERSIZED_MESSAGE);
+				}
+			);
+		});
+
+		assert_eq!(
+			counter_value(&registry, "parachain_bitfields_rejected_total", Some("oversized")),
+			1,
+		);
+		// not even attempted to decode
+		assert_eq!(
+			counter_value(&registry, "parachain_bitfields_rejected_total", Some("undecodable")),
+			0,
+		);
+	}
+
+	#[test]
+	fn receive_message_with_trailing_bytes() {
+		let hash_a: Hash = [0; 32].into();
+		let peer_a = PeerId::random();
+
+		let signing_context = SigningContext {
+			session_index: 1,
+			parent_hash: hash_a,
+		};
+
+		let validator: ValidatorId = Sr25519Keyring::Alice.public().into();
+
+		let msg = BitfieldGossipMessage {
+			relay_parent: hash_a,
+			signed_availability: Signed::<AvailabilityBitfield>::sign(
+				AvailabilityBitfield(bitvec![bitvec::order::Lsb0, u8; 1u8; 32]),
+				&signing_context,
+				0,
+				&Sr25519Keyring::Alice.pair().into(),
+			),
+		};
+
+		// a perfectly fine message, with some junk appended
+		let mut bytes = msg.encode_versioned();
+		bytes.extend_from_slice(&[0xDE, 0xAD, 0xBE, 0xEF]);
+
+		let pool = sp_core::testing::SpawnBlockingExecutor::new();
+		let (mut ctx, mut handle) =
+			make_subsystem_context::<BitfieldDistributionMessage, _>(pool);
+
+		let mut tracker = prewarmed_tracker(validator, signing_context, hash_a, peers![peer_a]);
+
+		executor::block_on(async move {
+			handle_network_msg(
+				&mut ctx,
+				&mut tracker,
+				&Default::default(),
+				&Default::default(),
+				NetworkBridgeEvent::PeerMessage(peer_a.clone(), bytes),
+			)
+			.await
+			.unwrap();
+
+			assert_matches!(
+				handle.recv().await,
+				AllMessages::NetworkBridge(
+					NetworkBridgeMessage::ReportPeer(peer, rep)
+				) => {
+					assert_eq!(peer, peer_a);
+					assert_eq!(rep, COST_MESSAGE_NOT_DECODABLE);
+				}
+			);
+			assert!(tracker.per_relay_parent[&hash_a].one_per_validator.is_empty());
+		});
+	}
+
+	#[test]
+	fn duplicate_message() {
+		let hash_a: Hash = [0; 32].into();
+
+		let peer_b = PeerId::random();
+		let peer_c = PeerId::random();
+
+		let signing_context = SigningContext {
+			session_index: 1,
+			parent_hash: hash_a,
+		};
+
+		let validator: ValidatorId = Sr25519Keyring::Alice.public().into();
+
+		let payload = AvailabilityBitfield(bitvec![bitvec::order::Lsb0, u8; 1u8; 32]);
+		let signed = Signed::<AvailabilityBitfield>::sign(
+			payload,
+			&signing_context,
+			0,
+			&Sr25519Keyring::Alice.pair().into(),
+		);
+
+		let msg = BitfieldGossipMessage {
+			relay_parent: hash_a,
+			signed_availability: signed,
+		};
+
+		// every reputation change is reported on its own
+		let config = Config { penalty_coalescing_window: Duration::from_secs(0), ..Default::default() };
+
+		test_harness(config, |mut harness| async move {
+			harness.activate(hash_a, vec![validator], signing_context).await;
+			harness.connect_peer(&peer_b, view![hash_a]).await;
+			assert_eq!(harness.expect_report(&peer_b).await, GAIN_USEFUL_VIEW_CHANGE);
+			// not interested in the relay parent, so it is not relayed the bitfield
+			harness.connect_peer(&peer_c, view![]).await;
+
+			harness.network_event(NetworkBridgeEvent::PeerMessage(peer_b.clone(), msg.encode_versioned())).await;
+			assert_eq!(harness.expect_report(&peer_b).await, GAIN_VALID_MESSAGE_FIRST);
+			assert_matches!(harness.recv().await, AllMessages::Provisioner(_));
+
+			// the same peer delivering it again is neither rewarded nor penalized
+			harness.network_event(NetworkBridgeEvent::PeerMessage(peer_b.clone(), msg.encode_versioned())).await;
+			harness.expect_quiescence().await;
+
+			// another peer delivering it is rewarded once
+			for _ in 0..2 {
+				harness.network_event(NetworkBridgeEvent::PeerMessage(peer_c.clone(), msg.encode_versioned())).await;
+			}
+			assert_eq!(harness.expect_report(&peer_c).await, GAIN_VALID_MESSAGE);
+			harness.expect_quiescence().await;
+		});
+	}
+
+	#[test]
+	fn bitfields_beyond_validator_set_size_are_rejected() {
+		let hash_a: Hash = [0; 32].into();
+		let peer_a = PeerId::random();
+
+		let signing_context = SigningContext {
+			session_index: 1,
+			parent_hash: hash_a,
+		};
+
+		let validator: ValidatorId = Sr25519Keyring::Alice.public().into();
+		let stale_validator: ValidatorId = Sr25519Keyring::Bob.public().into();
+
+		let message = |keyring: Sr25519Keyring| BitfieldGossipMessage {
+			relay_parent: hash_a,
+			signed_availability: Signed::<AvailabilityBitfield>::sign(
+				AvailabilityBitfield(bitvec![bitvec::order::Lsb0, u8; 1u8; 32]),
+				&signing_context,
+				0,
+				&keyring.pair().into(),
+			),
+		};
+
+		let registry = Registry::new();
+		let metrics = Metrics::register(Some(&registry)).unwrap();
+
+		let pool = sp_core::testing::SpawnBlockingExecutor::new();
+		let (mut ctx, mut handle) =
+			make_subsystem_context::<BitfieldDistributionMessage, _>(pool);
+
+		// a set of a single validator, which already has a bitfield of one not part of it
+		let mut tracker = prewarmed_tracker(validator.clone(), signing_context.clone(), hash_a, peers![peer_a]);
+		tracker.per_relay_parent.get_mut(&hash_a).unwrap()
+			.insert_message(stale_validator, message(Sr25519Keyring::Bob));
+
+		executor::block_on(async move {
+			process_incoming_peer_message(
+				&mut ctx,
+				&mut tracker,
+				&metrics,
+				&Default::default(),
+				peer_a.clone(),
+				message(Sr25519Keyring::Alice),
+				None,
+			)
+			.await
+			.unwrap();
+
+			// neither provisioned nor resulting in a penalty, it's not the peer's fault
+			assert!(handle.try_recv().is_none());
+			let job_data = &tracker.per_relay_parent[&hash_a];
+			assert_eq!(job_data.one_per_validator.len(), 1);
+			assert!(!job_data.one_per_validator.contains_key(&validator));
+		});
+
+		assert_eq!(
+			counter_value(&registry, "parachain_bitfields_rejected_total", Some("validator_set_mismatch")),
+			1,
+		);
+	}
+
+	#[test]
+	fn repeated_penalties_are_coalesced() {
+		let hash_a: Hash = [0; 32].into();
+
+		let peer_a = PeerId::random();
+		let peer_b = PeerId::random();
+
+		let signing_context = SigningContext {
+			session_index: 1,
+			parent_hash: hash_a,
+		};
+
+		let validator: ValidatorId = Sr25519Keyring::Alice.public().into();
+
+		let pool = sp_core::testing::SpawnBlockingExecutor::new();
+		let (mut ctx, mut handle) =
+			make_subsystem_context::<BitfieldDistributionMessage, _>(pool);
+
+		let mut tracker = prewarmed_tracker(validator, signing_context, hash_a, peers![peer_a, peer_b]);
+		let window = tracker.config.penalty_coalescing_window;
+
+		executor::block_on(async move {
+			for peer in vec![&peer_a, &peer_a, &peer_a, &peer_b, &peer_a] {
+				handle_network_msg(
+					&mut ctx,
+					&mut tracker,
+					&Default::default(),
+					&Default::default(),
+					NetworkBridgeEvent::PeerMessage(peer.clone(), vec![1, 0xFF, 0xFF]),
+				)
+				.await
+				.unwrap();
+			}
+
+			// only the first penalty of each peer is reported right away
+			for expected in vec![&peer_a, &peer_b] {
+				assert_matches!(
+					handle.recv().await,
+					AllMessages::NetworkBridge(NetworkBridgeMessage::ReportPeer(peer, rep)) => {
+						assert_eq!(&peer, expected);
+						assert_eq!(rep, COST_MESSAGE_NOT_DECODABLE);
+					}
+				);
+			}
+			assert!(handle.try_recv().is_none());
+
+			// the ones held back are summed up once the window ends
+			assert!(tracker.take_coalesced_penalties(Instant::now()).is_empty());
+			let coalesced = tracker.take_coalesced_penalties(Instant::now() + window);
+			assert_eq!(coalesced, vec![(
+				peer_a.clone(),
+				ReputationChange::new(COST_MESSAGE_NOT_DECODABLE.value * 3, COST_MESSAGE_NOT_DECODABLE.reason),
+			)]);
+			assert!(tracker.recent_penalties.is_empty());
+		});
+	}
+
+	#[test]
+	fn only_peers_without_penalties_are_rewarded_for_good_standing() {
+		let hash_a: Hash = [0; 32].into();
+
+		let peer_a = PeerId::random();
+		let peer_b = PeerId::random();
+
+		let signing_context = SigningContext {
+			session_index: 1,
+			parent_hash: hash_a,
+		};
+
+		let keyrings = [Sr25519Keyring::Alice, Sr25519Keyring::Bob];
+		let validators: Vec<ValidatorId> = keyrings.iter().map(|k| k.public().into()).collect();
+
+		let valid: Vec<_> = keyrings
+			.iter()
+			.enumerate()
+			.map(|(index, keyring)| BitfieldGossipMessage {
+				relay_parent: hash_a,
+				signed_availability: Signed::<AvailabilityBitfield>::sign(
+					AvailabilityBitfield(bitvec![bitvec::order::Lsb0, u8; 1u8; 32]),
+					&signing_context,
+					index as ValidatorIndex,
+					&keyring.pair().into(),
+				),
+			}.encode_versioned())
+			.collect();
+
+		let pool = sp_core::testing::SpawnBlockingExecutor::new();
+		let (mut ctx, mut handle) =
+			make_subsystem_context::<BitfieldDistributionMessage, _>(pool);
+
+		let mut tracker = prewarmed_tracker(
+			validators[0].clone(),
+			signing_context,
+			hash_a,
+			peers![peer_a, peer_b],
+		);
+		tracker.per_relay_parent.get_mut(&hash_a).unwrap().validator_set = Arc::new(validators);
+		tracker.config.good_standing_interval = Duration::from_secs(60);
+
+		executor::block_on(async move {
+			// both deliver a valid bitfield, but the second one misbehaves as well
+			let messages = vec![
+				(&peer_a, valid[0].clone()),
+				(&peer_b, valid[1].clone()),
+				(&peer_b, vec![1, 0xFF, 0xFF]),
+			];
+			for (peer, bytes) in messages {
+				handle_network_msg(
+					&mut ctx,
+					&mut tracker,
+					&Default::default(),
+					&Default::default(),
+					NetworkBridgeEvent::PeerMessage(peer.clone(), bytes),
+				)
+				.await
+				.unwrap();
+			}
+			while handle.try_recv().is_some() {}
+
+			// the end of the interval
+			reward_good_standing(&mut ctx, &mut tracker, &Default::default()).await.unwrap();
+			assert_matches!(
+				handle.try_recv(),
+				Some(AllMessages::NetworkBridge(NetworkBridgeMessage::ReportPeer(peer, rep))) => {
+					assert_eq!(peer, peer_a);
+					assert_eq!(rep, GAIN_GOOD_STANDING);
+				}
+			);
+			assert!(handle.try_recv().is_none());
+
+			// nobody delivered anything in the next one
+			reward_good_standing(&mut ctx, &mut tracker, &Default::default()).await.unwrap();
+			assert!(handle.try_recv().is_none());
+		});
+	}
+
+	#[test]
+	fn room_for_all_peers_is_reserved_for_interested_peers() {
+		let hash_a: Hash = [0; 32].into();
+		let hash_b: Hash = [1; 32].into();
+		let origin = PeerId::random();
+		let light = PeerId::random();
+
+		let mut peer_views: HashMap<PeerId, View> = (0..200)
+			.map(|_| PeerId::random())
+			.enumerate()
+			.map(|(i, peer)| (peer, if i % 4 == 0 { view![hash_b] } else { view![hash_a] }))
+			.collect();
+		peer_views.insert(origin.clone(), view![hash_a]);
+		peer_views.insert(light.clone(), view![hash_a]);
+		let peer_roles = vec![(light, ObservedRole::Light)].into_iter().collect();
+
+		let interested = interested_peers(&peer_views, &peer_roles, &hash_a, Some(&origin));
+		assert_eq!(interested.len(), 150);
+		assert!(!interested.contains(&origin));
+		// room for all peers was reserved up front, rather than grown as they were collected
+		assert!(interested.capacity() >= peer_views.len());
+	}
+
+	#[test]
+	fn validator_indices_are_bounds_checked() {
+		let hash_a: Hash = [0; 32].into();
+		let peer_a = PeerId::random();
+
+		let signing_context = SigningContext {
+			session_index: 1,
+			parent_hash: hash_a,
+		};
+
+		let validator: ValidatorId = Sr25519Keyring::Alice.public().into();
+		let validator_set = vec![validator.clone()];
+
+		assert_eq!(validator_at(&validator_set, 0), Some(&validator));
+		assert_eq!(validator_at(&validator_set, 1), None);
+		assert_eq!(validator_at(&validator_set, ValidatorIndex::MAX), None);
+		assert_eq!(validator_at(&[], 0), None);
+
+		let msg = BitfieldGossipMessage {
+			relay_parent: hash_a,
+			signed_availability: Signed::<AvailabilityBitfield>::sign(
+				AvailabilityBitfield(bitvec![bitvec::order::Lsb0, u8; 1u8; 32]),
+				&signing_context,
+				ValidatorIndex::MAX,
+				&Sr25519Keyring::Alice.pair().into(),
+			),
+		};
+
+		let pool = sp_core::testing::SpawnBlockingExecutor::new();
+		let (mut ctx, mut handle) =
+			make_subsystem_context::<BitfieldDistributionMessage, _>(pool);
+
+		let mut tracker = prewarmed_tracker(validator, signing_context, hash_a, peers![peer_a]);
+		assert_eq!(tracker.check(hash_a, &msg.signed_availability, None), ValidationVerdict::InvalidIndex);
+
+		executor::block_on(async move {
+			handle_network_msg(
+				&mut ctx,
+				&mut tracker,
+				&Default::default(),
+				&Default::default(),
+				NetworkBridgeEvent::PeerMessage(peer_a.clone(), msg.encode_versioned()),
+			)
+			.await
+			.unwrap();
+
+			assert_matches!(
+				handle.recv().await,
+				AllMessages::NetworkBridge(NetworkBridgeMessage::ReportPeer(peer, rep)) => {
+					assert_eq!(peer, peer_a);
+					assert_eq!(rep, COST_VALIDATOR_INDEX_INVALID);
+				}
+			);
+		});
+	}
+
+	#[test]
+	fn validation_verdicts() {
+		let hash_a: Hash = [0; 32].into();
+		let hash_b: Hash = [1; 32].into();
+		let peer_a = PeerId::random();
+
+		let signing_context = SigningContext {
+			session_index: 1,
+			parent_hash: hash_a,
+		};
+
+		let validator: ValidatorId = Sr25519Keyring::Alice.public().into();
+
+		let sign = |bits: &[usize], len: usize, session_index, index, keyring: Sr25519Keyring| {
+			let mut bitfield = bitvec![bitvec::order::Lsb0, u8; 0u8; len];
+			for bit in bits {
+				bitfield.set(*bit, true);
+			}
+			Signed::<AvailabilityBitfield>::sign(
+				AvailabilityBitfield(bitfield),
+				&SigningContext { session_index, parent_hash: hash_a },
+				index,
+				&keyring.pair().into(),
+			)
+		};
+		let new = sign(&[0, 1], 32, 1, 0, Sr25519Keyring::Alice);
+
+		let mut tracker = prewarmed_tracker(validator.clone(), signing_context, hash_a, peers![peer_a]);
+
+		assert_eq!(tracker.check(hash_a, &new, None), ValidationVerdict::Accepted);
+		assert_eq!(tracker.check(hash_b, &new, None), ValidationVerdict::NotInterested);
+		assert_eq!(
+			tracker.check(hash_a, &sign(&[0], 32, 1, 1, Sr25519Keyring::Alice), None),
+			ValidationVerdict::InvalidIndex,
+		);
+		assert_eq!(
+			tracker.check(hash_a, &sign(&[0], 32, 1, 0, Sr25519Keyring::Bob), None),
+			ValidationVerdict::InvalidSignature,
+		);
+		assert_eq!(
+			tracker.check(hash_a, &sign(&[0], 32, 0, 0, Sr25519Keyring::Alice), None),
+			ValidationVerdict::InvalidSignature,
+		);
+		let hash_c: Hash = [2; 32].into();
+		tracker.per_relay_parent.insert(hash_c, PerRelayParentData {
+			signing_context: SigningContext { session_index: 0, parent_hash: hash_c },
+			validator_set: Arc::new(vec![validator.clone()]),
+			..Default::default()
+		});
+		assert_eq!(
+			tracker.check(hash_a, &sign(&[0], 32, 0, 0, Sr25519Keyring::Alice), None),
+			ValidationVerdict::WrongSession,
+		);
+		tracker.per_relay_parent.remove(&hash_c);
+
+		tracker.view = view![hash_a, hash_b];
+		assert_eq!(tracker.check(hash_b, &new, None), ValidationVerdict::NotWorkedOn);
+
+		let job_data = tracker.per_relay_parent.get_mut(&hash_a).unwrap();
+		job_data.signing_context.parent_hash = hash_b;
+		assert_eq!(tracker.check(hash_a, &new, None), ValidationVerdict::ContextMismatch);
+
+		let job_data = tracker.per_relay_parent.get_mut(&hash_a).unwrap();
+		job_data.signing_context.parent_hash = hash_a;
+		job_data.bitfield_len = Some(16);
+		assert_eq!(tracker.check(hash_a, &new, None), ValidationVerdict::InconsistentLength);
+
+		// someone else's bitfield fills the stale validator set
+		let job_data = tracker.per_relay_parent.get_mut(&hash_a).unwrap();
+		job_data.bitfield_len = Some(32);
+		job_data.insert_message(
+			Sr25519Keyring::Bob.public().into(),
+			BitfieldGossipMessage { relay_parent: hash_a, signed_availability: new.clone() },
+		);
+		assert_eq!(tracker.check(hash_a, &new, None), ValidationVerdict::ValidatorSetMismatch);
+
+		let job_data = tracker.per_relay_parent.get_mut(&hash_a).unwrap();
+		job_data.one_per_validator.clear();
+		job_data.insert_message(
+			validator.clone(),
+			BitfieldGossipMessage { relay_parent: hash_a, signed_availability: new.clone() },
+		);
+		assert_eq!(tracker.check(hash_a, &new, None), ValidationVerdict::Known);
+		// a superseded bitfield is no news, a superseding one is
+		assert_eq!(
+			tracker.check(hash_a, &sign(&[0], 32, 1, 0, Sr25519Keyring::Alice), None),
+			ValidationVerdict::Known,
+		);
+		assert_eq!(
+			tracker.check(hash_a, &sign(&[0, 1, 2], 32, 1, 0, Sr25519Keyring::Alice), None),
+			ValidationVerdict::Accepted,
+		);
+		assert_eq!(
+			tracker.check(hash_a, &sign(&[2], 32, 1, 0, Sr25519Keyring::Alice), None),
+			ValidationVerdict::Equivocation,
+		);
+
+		// the same goes for our own bitfield
+		let job_data = tracker.per_relay_parent.get_mut(&hash_a).unwrap();
+		job_data.one_per_validator.clear();
+		job_data.own_bitfield = Some((
+			validator.clone(),
+			BitfieldGossipMessage { relay_parent: hash_a, signed_availability: new.clone() },
+		));
+		assert_eq!(
+			tracker.check(hash_a, &sign(&[0], 32, 1, 0, Sr25519Keyring::Alice), None),
+			ValidationVerdict::Known,
+		);
+		assert_eq!(
+			tracker.check(hash_a, &sign(&[2], 32, 1, 0, Sr25519Keyring::Alice), None),
+			ValidationVerdict::Equivocation,
+		);
+
+		let job_data = tracker.per_relay_parent.get_mut(&hash_a).unwrap();
+		job_data.equivocators.insert(validator);
+		assert_eq!(
+			tracker.check(hash_a, &sign(&[0, 1, 2], 32, 1, 0, Sr25519Keyring::Alice), None),
+			ValidationVerdict::Equivocation,
+		);
+	}
+
+	#[test]
+	fn first_processed_of_simultaneous_bitfields_wins() {
+		let hash_a: Hash = [0; 32].into();
+		let peer_a = PeerId::random();
+		let peer_b = PeerId::random();
+
+		let signing_context = SigningContext {
+			session_index: 1,
+			parent_hash: hash_a,
+		};
+
+		let validator: ValidatorId = Sr25519Keyring::Alice.public().into();
+
+		let msg = BitfieldGossipMessage {
+			relay_parent: hash_a,
+			signed_availability: Signed::<AvailabilityBitfield>::sign(
+				AvailabilityBitfield(bitvec![bitvec::order::Lsb0, u8; 1u8; 32]),
+				&signing_context,
+				0,
+				&Sr25519Keyring::Alice.pair().into(),
+			),
+		};
+
+		let pool = sp_core::testing::SpawnBlockingExecutor::new();
+		let (mut ctx, mut handle) =
+			make_subsystem_context::<BitfieldDistributionMessage, _>(pool);
+
+		let mut tracker = prewarmed_tracker(validator, signing_context, hash_a, peers![peer_a]);
+		// not relayed the bitfield of `peer_a`, which would make it indistinguishable from an echo
+		tracker.peer_views.insert(peer_b.clone(), view![]);
+
+		executor::block_on(async move {
+			// both received back to back, so their signatures are checked at once
+			handle_peer_messages(
+				&mut ctx,
+				&mut tracker,
+				&Default::default(),
+				&Default::default(),
+				vec![(peer_a.clone(), msg.encode_versioned()), (peer_b.clone(), msg.encode_versioned())],
+			)
+			.await
+			.unwrap();
+
+			let mut reports = Vec::new();
+			while let Some(message) = handle.try_recv() {
+				if let AllMessages::NetworkBridge(NetworkBridgeMessage::ReportPeer(peer, rep)) = message {
+					reports.push((peer, rep));
+				}
+			}
+			assert_eq!(reports, vec![
+				(peer_a.clone(), GAIN_VALID_MESSAGE_FIRST),
+				(peer_b.clone(), GAIN_VALID_MESSAGE),
+			]);
+			assert_eq!(
+				tracker.per_relay_parent[&hash_a].delivered_by.values().collect::<Vec<_>>(),
+				vec![&BitfieldSource::Peer(peer_a.clone())],
+			);
+		});
+	}
+
+	#[test]
+	fn validate_only_has_no_side_effects() {
+		let hash_a: Hash = [0; 32].into();
+		let peer_b = PeerId::random();
+
+		let signing_context = SigningContext {
+			session_index: 1,
+			parent_hash: hash_a,
+		};
+
+		let validator: ValidatorId = Sr25519Keyring::Alice.public().into();
+
+		let signed = Signed::<AvailabilityBitfield>::sign(
+			AvailabilityBitfield(bitvec![bitvec::order::Lsb0, u8; 1u8; 32]),
+			&signing_context,
+			0,
+			&Sr25519Keyring::Alice.pair().into(),
+		);
+
+		let pool = sp_core::testing::SpawnBlockingExecutor::new();
+		let (ctx, mut handle) =
+			make_subsystem_context::<BitfieldDistributionMessage, _>(pool);
+
+		let subsystem = BitfieldDistribution::new(Default::default()).start(ctx).future;
+
+		let test_fut = async move {
+			assert_matches!(
+				handle.recv().await,
+				AllMessages::NetworkBridge(NetworkBridgeMessage::RegisterEventProducer(..))
+			);
+
+			start_work(&mut handle, hash_a, vec![validator], signing_context).await;
+			handle.send(network_event(NetworkBridgeEvent::OurViewChange(view![hash_a]))).await;
+			handle.send(network_event(NetworkBridgeEvent::PeerConnected(peer_b.clone(), ObservedRole::Full))).await;
+			handle.send(network_event(NetworkBridgeEvent::PeerViewChange(peer_b.clone(), view![hash_a]))).await;
+			assert_matches!(
+				handle.recv().await,
+				AllMessages::NetworkBridge(NetworkBridgeMessage::ReportPeer(_, rep)) => {
+					assert_eq!(rep, GAIN_USEFUL_VIEW_CHANGE);
+				}
+			);
+
+			// a new bitfield, twice, as it isn't remembered
+			for _ in 0..2 {
+				let (tx, rx) = oneshot::channel();
+				handle.send(FromOverseer::Communication {
+					msg: BitfieldDistributionMessage::ValidateOnly(hash_a, signed.clone(), tx),
+				}).await;
+				assert_eq!(timeout(rx, TIMEOUT).await, Some(Ok(ValidationVerdict::Accepted)));
+			}
+
+			let (tx, rx) = oneshot::channel();
+			handle.send(FromOverseer::Communication {
+				msg: BitfieldDistributionMessage::Status(tx),
+			}).await;
+			assert_eq!(timeout(rx, TIMEOUT).await.unwrap().unwrap().bitfields, 0);
+
+			// neither handed to the provisioner nor relayed
+			await_quiescence(&mut handle).await;
+			assert!(handle.try_recv().is_none());
+
+			handle.send(FromOverseer::Signal(OverseerSignal::Conclude)).await;
+		};
+
+		executor::block_on(future::join(test_fut, subsystem));
+	}
+
+	#[test]
+	fn repeated_bitfields_of_a_validator_hold_no_key_copies() {
+		let hash_a: Hash = [0; 32].into();
+		let peer_a = PeerId::random();
+		let peer_b = PeerId::random();
+
+		let signing_context = SigningContext {
+			session_index: 1,
+			parent_hash: hash_a,
+		};
+
+		let validator: ValidatorId = Sr25519Keyring::Alice.public().into();
+
+		let msg = BitfieldGossipMessage {
+			relay_parent: hash_a,
+			signed_availability: Signed::<AvailabilityBitfield>::sign(
+				AvailabilityBitfield(bitvec![bitvec::order::Lsb0, u8; 1u8; 32]),
+				&signing_context,
+				0,
+				&Sr25519Keyring::Alice.pair().into(),
+			),
+		};
+
+		let pool = sp_core::testing::SpawnBlockingExecutor::new();
+		let (mut ctx, mut handle) =
+			make_subsystem_context::<BitfieldDistributionMessage, _>(pool);
+
+		let mut tracker = prewarmed_tracker(validator.clone(), signing_context, hash_a, peers![peer_a, peer_b]);
+		tracker.config.penalty_coalescing_window = Duration::from_secs(0);
+
+		executor::block_on(async move {
+			let mut reports = Vec::new();
+			for peer in vec![&peer_a, &peer_b, &peer_b, &peer_a] {
+				handle_network_msg(
+					&mut ctx,
+					&mut tracker,
+					&Default::default(),
+					&Default::default(),
+					NetworkBridgeEvent::PeerMessage(peer.clone(), msg.encode_versioned()),
+				)
+				.await
+				.unwrap();
+				while let Some(message) = handle.try_recv() {
+					if let AllMessages::NetworkBridge(NetworkBridgeMessage::ReportPeer(peer, rep)) = message {
+						reports.push((peer, rep));
+					}
+				}
+			}
+
+			// the first one is new, and relayed to the other peer, so none of the copies is rewarded
+			assert_eq!(reports, vec![(peer_a.clone(), GAIN_VALID_MESSAGE_FIRST)]);
+
+			let job_data = &tracker.per_relay_parent[&hash_a];
+			assert_eq!(job_data.known_message_of(&validator), Some(&msg));
+			// the validator set is back to being referenced by the tracker only
+			assert_eq!(Arc::strong_count(&job_data.validator_set), 1);
+		});
+	}
+
+	#[test]
+	fn gossip_seed_corpus_decodes() {
+		let hash_a: Hash = [0; 32].into();
+
+		let signing_context = SigningContext {
+			session_index: 1,
+			parent_hash: hash_a,
+		};
+
+		let msg = BitfieldGossipMessage {
+			relay_parent: hash_a,
+			signed_availability: Signed::<AvailabilityBitfield>::sign(
+				AvailabilityBitfield(bitvec![bitvec::order::Lsb0, u8; 1u8; 32]),
+				&signing_context,
+				0,
+				&Sr25519Keyring::Alice.pair().into(),
+			),
+		};
+		let batch = vec![msg.clone(); 8];
+		let max_message_size = Config::default().max_message_size;
+
+		let v1 = msg.encode_versioned();
+		let v2 = VersionedBitfieldGossipMessage::V2(batch.clone()).encode();
+		let compressed = VersionedBitfieldGossipMessage::compress(v2.clone());
+		assert_eq!(compressed[0], 3);
+		let corpus = vec![
+			(v1.clone(), Ok(vec![msg.clone()])),
+			(v2.clone(), Ok(batch)),
+			(compressed.clone(), Ok(vec![msg; 8])),
+			(VersionedBitfieldGossipMessage::V2(Vec::new()).encode(), Ok(Vec::new())),
+		];
+		for (bytes, expected) in corpus {
+			assert_eq!(try_decode_gossip(&bytes, max_message_size, true), expected);
+		}
+		// unless compression is enabled
+		assert_eq!(try_decode_gossip(&compressed, max_message_size, false), Err(DecodeError::UnsupportedVersion));
+
+		let mut trailing = v1.clone();
+		trailing.push(0);
+		let nested = VersionedBitfieldGossipMessage::Compressed(
+			zstd::block::compress(&compressed, 3).unwrap(),
+		).encode();
+		let rejected = vec![
+			(Vec::new(), DecodeError::Undecodable),
+			(trailing, DecodeError::Undecodable),
+			(v1[..v1.len() - 1].to_vec(), DecodeError::Undecodable),
+			(nested, DecodeError::Undecodable),
+			(vec![9, 0xFF], DecodeError::UnsupportedVersion),
+			(vec![1; max_message_size + 1], DecodeError::Oversized),
+		];
+		for (bytes, expected) in rejected {
+			assert_eq!(try_decode_gossip(&bytes, max_message_size, true), Err(expected));
+		}
+		// decompressing to more than the maximum is rejected as well
+		assert_eq!(try_decode_gossip(&compressed, v2.len() - 1, true), Err(DecodeError::Undecodable));
+	}
+
+	#[test]
+	fn stream_of_undecodable_messages_is_escalated() {
+		let hash_a: Hash = [0; 32].into();
+		let peer_a = PeerId::random();
+
+		let signing_context = SigningContext {
+			session_index: 1,
+			parent_hash: hash_a,
+		};
+
+		let validator: ValidatorId = Sr25519Keyring::Alice.public().into();
+
+		let valid = BitfieldGossipMessage {
+			relay_parent: hash_a,
+			signed_availability: Signed::<AvailabilityBitfield>::sign(
+				AvailabilityBitfield(bitvec![bitvec::order::Lsb0, u8; 1u8; 32]),
+				&signing_context,
+				0,
+				&Sr25519Keyring::Alice.pair().into(),
+			),
+		};
+
+		let pool = sp_core::testing::SpawnBlockingExecutor::new();
+		let (mut ctx, mut handle) =
+			make_subsystem_context::<BitfieldDistributionMessage, _>(pool);
+
+		let mut tracker = prewarmed_tracker(validator, signing_context, hash_a, peers![peer_a]);
+		// every penalty is reported on its own
+		tracker.config.penalty_coalescing_window = Duration::from_secs(0);
+		let tolerated = tracker.config.max_undecodable_messages;
+
+		executor::block_on(async move {
+			// a decodable message resets the count
+			let mut messages = vec![vec![1, 0xFF, 0xFF]; tolerated];
+			messages.push(valid.encode_versioned());
+			messages.extend(vec![vec![1, 0xFF, 0xFF]; tolerated + 1]);
+
+			let mut reports = Vec::new();
+			for bytes in messages {
+				handle_network_msg(
+					&mut ctx,
+					&mut tracker,
+					&Default::default(),
+					&Default::default(),
+					NetworkBridgeEvent::PeerMessage(peer_a.clone(), bytes),
+				)
+				.await
+				.unwrap();
+				while let Some(message) = handle.try_recv() {
+					if let AllMessages::NetworkBridge(NetworkBridgeMessage::ReportPeer(peer, rep)) = message {
+						assert_eq!(peer, peer_a);
+						reports.push(rep);
+					}
+				}
+			}
+
+			let mut expected = vec![COST_MESSAGE_NOT_DECODABLE; tolerated];
+			expected.push(GAIN_VALID_MESSAGE_FIRST);
+			expected.extend(vec![COST_MESSAGE_NOT_DECODABLE; tolerated]);
+			expected.push(COST_UNDECODABLE_STREAM);
+			assert_eq!(reports, expected);
+		});
+	}
+
+	#[test]
+	fn relay_message_fails_if_provisioner_is_gone() {
+		let hash_a: Hash = [0; 32].into();
+		let peer_a = PeerId::random();
+
+		let signing_context = SigningContext {
+			session_index: 1,
+			parent_hash: hash_a,
+		};
+
+		let validator: ValidatorId = Sr25519Keyring::Alice.public().into();
+
+		let payload = AvailabilityBitfield(bitvec![bitvec::order::Lsb0, u8; 1u8; 32]);
+		let signed = Signed::<AvailabilityBitfield>::sign(
+			payload,
+			&signing_context,
+			0,
+			&Sr25519Keyring::Alice.pair().into(),
+		);
+
+		let msg = BitfieldGossipMessage {
+			relay_parent: hash_a,
+			signed_availability: signed,
+		};
+
+		let pool = sp_core::testing::SpawnBlockingExecutor::new();
+		let (ctx, _handle) =
+			make_subsystem_context::<BitfieldDistributionMessage, _>(pool);
+		let mut ctx = HookedContext::new(ctx).on_send(|_| Hooked::Failed);
+		let mut tracker = prewarmed_tracker(validator.clone(), signing_context, hash_a, peers![peer_a]);
+
+		executor::block_on(async move {
+			let result = relay_message(
+				&mut ctx,
+				&mut tracker,
+				&Default::default(),
+				None,
+				validator,
+				msg,
+			).await;
+
+			assert_eq!(result, Err(BitfieldDistributionError::SendFailed));
+		});
+	}
+
+	#[test]
+	fn light_clients_are_not_relayed_to() {
+		let hash_a: Hash = [0; 32].into();
+		let peer_full = PeerId::random();
+		let peer_light = PeerId::random();
+
+		let signing_context = SigningContext {
+			session_index: 1,
+			parent_hash: hash_a,
+		};
+
+		let validator: ValidatorId = Sr25519Keyring::Alice.public().into();
+
+		let msg = BitfieldGossipMessage {
+			relay_parent: hash_a,
+			signed_availability: Signed::<AvailabilityBitfield>::sign(
+				AvailabilityBitfield(bitvec![bitvec::order::Lsb0, u8; 1u8; 32]),
+				&signing_context,
+				0,
+				&Sr25519Keyring::Alice.pair().into(),
+			),
+		};
+
+		let pool = sp_core::testing::SpawnBlockingExecutor::new();
+		let (mut ctx, mut handle) =
+			make_subsystem_context::<BitfieldDistributionMessage, _>(pool);
+
+		let mut tracker = prewarmed_tracker(validator.clone(), signing_context, hash_a, peers![]);
+
+		executor::block_on(async move {
+			for (peer, role) in vec![(&peer_full, ObservedRole::Full), (&peer_light, ObservedRole::Light)] {
+				for event in vec![
+					NetworkBridgeEvent::PeerConnected(peer.clone(), role),
+					NetworkBridgeEvent::PeerViewChange(peer.clone(), view![hash_a]),
+				] {
+					handle_network_msg(
+						&mut ctx,
+						&mut tracker,
+						&Default::default(),
+						&Default::default(),
+						event,
+					)
+					.await
+					.unwrap();
+				}
+				assert_matches!(
+					handle.recv().await,
+					AllMessages::NetworkBridge(NetworkBridgeMessage::ReportPeer(_, rep)) => {
+						assert_eq!(rep, GAIN_USEFUL_VIEW_CHANGE);
+					}
+				);
+			}
+
+			relay_message(
+				&mut ctx,
+				&mut tracker,
+				&Default::default(),
+				None,
+				validator.clone(),
+				msg,
+			).await.unwrap();
+
+			assert_matches!(handle.recv().await, AllMessages::Provisioner(_));
+			assert_matches!(
+				handle.recv().await,
+				AllMessages::NetworkBridge(NetworkBridgeMessage::SendMessage(peers, _, _)) => {
+					assert_eq!(peers, vec![peer_full.clone()]);
+				}
+			);
+			assert!(!tracker.per_relay_parent[&hash_a].message_sent_to_peer.contains_key(&peer_light));
+		});
+	}
+
+	#[test]
+	fn bitfield_is_not_relayed_back_to_origin() {
+		let hash_a: Hash = [0; 32].into();
+		let peer_a = PeerId::random();
+		let peer_b = PeerId::random();
+
+		let signing_context = SigningContext {
+			session_index: 1,
+			parent_hash: hash_a,
+		};
+
+		let validator: ValidatorId = Sr25519Keyring::Alice.public().into();
+
+		let msg = BitfieldGossipMessage {
+			relay_parent: hash_a,
+			signed_availability: Signed::<AvailabilityBitfield>::sign(
+				AvailabilityBitfield(bitvec![bitvec::order::Lsb0, u8; 1u8; 32]),
+				&signing_context,
+				0,
+				&Sr25519Keyring::Alice.pair().into(),
+			),
+		};
+
+		let pool = sp_core::testing::SpawnBlockingExecutor::new();
+		let (mut ctx, mut handle) =
+			make_subsystem_context::<BitfieldDistributionMessage, _>(pool);
+
+		let mut tracker = prewarmed_tracker(validator.clone(), signing_context, hash_a, peers![peer_a, peer_b]);
+
+		executor::block_on(async move {
+			handle_network_msg(
+				&mut ctx,
+				&mut tracker,
+				&Default::default(),
+				&Default::default(),
+				NetworkBridgeEvent::PeerMessage(peer_b.clone(), msg.encode_versioned()),
+			)
+			.await
+			.unwrap();
+
+			assert_matches!(
+				handle.recv().await,
+				AllMessages::NetworkBridge(NetworkBridgeMessage::ReportPeer(peer, rep)) => {
+					assert_eq!(peer, peer_b);
+					assert_eq!(rep, GAIN_VALID_MESSAGE_FIRST);
+				}
+			);
+			assert_matches!(handle.recv().await, AllMessages::Provisioner(_));
+			assert_matches!(
+				handle.recv().await,
+				AllMessages::NetworkBridge(NetworkBridgeMessage::SendMessage(peers, _, _)) => {
+					assert_eq!(peers, vec![peer_a.clone()]);
+				}
+			);
+
+			// the origin knows it, so catching up won't send it back either
+			assert!(tracker.per_relay_parent[&hash_a].message_sent_to_peer[&peer_b].contains(&validator));
+		});
+	}
+
+	#[test]
+	fn empty_bitfield_is_provisioned_but_not_relayed() {
+		let hash_a: Hash = [0; 32].into();
+		let peer_a = PeerId::random();
+		let peer_b = PeerId::random();
+
+		let signing_context = SigningContext {
+			session_index: 1,
+			parent_hash: hash_a,
+		};
+
+		let validator: ValidatorId = Sr25519Keyring::Alice.public().into();
+
+		let msg = BitfieldGossipMessage {
+			relay_parent: hash_a,
+			signed_availability: Signed::<AvailabilityBitfield>::sign(
+				AvailabilityBitfield(bitvec![bitvec::order::Lsb0, u8; 0u8; 32]),
+				&signing_context,
+				0,
+				&Sr25519Keyring::Alice.pair().into(),
+			),
+		};
+
+		let pool = sp_core::testing::SpawnBlockingExecutor::new();
+		let (mut ctx, mut handle) =
+			make_subsystem_context::<BitfieldDistributionMessage, _>(pool);
+
+		let mut tracker = prewarmed_tracker(validator, signing_context, hash_a, peers![peer_a, peer_b]);
+		tracker.config.skip_empty_bitfields = true;
+
+		executor::block_on(async move {
+			process_incoming_peer_message(
+				&mut ctx,
+				&mut tracker,
+				&Default::default(),
+				&Default::default(),
+				peer_a.clone(),
+				msg.clone(),
+				None,
+			)
+			.await
+			.unwrap();
+
+			assert_matches!(
+				handle.recv().await,
+				AllMessages::NetworkBridge(NetworkBridgeMessage::ReportPeer(peer, rep)) => {
+					assert_eq!(peer, peer_a);
+					assert_eq!(rep, GAIN_VALID_MESSAGE_FIRST);
+				}
+			);
+			assert_matches!(
+				handle.recv().await,
+				AllMessages::Provisioner(ProvisionerMessage::ProvisionableData(
+					ProvisionableData::Bitfield(_, bitfield)
+				)) => {
+					assert_eq!(bitfield, msg.signed_availability);
+				}
+			);
+			assert!(timeout(handle.recv(), TIMEOUT).await.is_none());
+		});
+	}
+
+	#[test]
+	fn relay_fan_out_is_capped() {
+		let hash_a: Hash = [0; 32].into();
+
+		let signing_context = SigningContext {
+			session_index: 1,
+			parent_hash: hash_a,
+		};
+
+		let validator: ValidatorId = Sr25519Keyring::Alice.public().into();
+
+		let payload = AvailabilityBitfield(bitvec![bitvec::order::Lsb0, u8; 1u8; 32]);
+		let msg = BitfieldGossipMessage {
+			relay_parent: hash_a,
+			signed_availability: Signed::<AvailabilityBitfield>::sign(
+				payload,
+				&signing_context,
+				0,
+				&Sr25519Keyring::Alice.pair().into(),
+			),
+		};
+
+		let peers = (0..100).map(|_| PeerId::random()).collect::<Vec<_>>();
+
+		let pool = sp_core::testing::SpawnBlockingExecutor::new();
+		let (mut ctx, mut handle) =
+			make_subsystem_context::<BitfieldDistributionMessage, _>(pool);
+
+		let mut tracker = prewarmed_tracker(validator.clone(), signing_context, hash_a, peers.clone());
+		tracker.config.fan_out = FanOut::Sqrt;
+
+		executor::block_on(async move {
+			relay_message(
+				&mut ctx,
+				&mut tracker,
+				&Default::default(),
+				None,
+				validator.clone(),
+				msg,
+			).await.unwrap();
+
+			assert_matches!(handle.recv().await, AllMessages::Provisioner(_));
+			assert_matches!(
+				handle.recv().await,
+				AllMessages::NetworkBridge(
+					NetworkBridgeMessage::SendMessage(receivers, _, _)
+				) => {
+					assert_eq!(receivers.len(), 10);
+					assert_eq!(receivers.iter().collect::<HashSet<_>>().len(), 10);
+					assert!(receivers.iter().all(|peer| peers.contains(peer)));
+
+					// only the chosen peers are tracked as having the message
+					let sent = &tracker.per_relay_parent[&hash_a].message_sent_to_peer;
+					assert_eq!(sent.len(), 10);
+					for peer in receivers.iter() {
+						assert!(sent[peer].contains(&validator));
+					}
+				}
+			);
+		});
+	}
+
+	#[test]
+	fn equivocation_is_penalized() {
+		let hash_a: Hash = [0; 32].into();
+
+		let peer_a = PeerId::random();
+		let peer_b = PeerId::random();
+
+		let signing_context = SigningContext {
+			session_index: 1,
+			parent_hash: hash_a,
+		};
+
+		let validator: ValidatorId = Sr25519Keyring::Alice.public().into();
+
+		// neither of the bitfields supersedes the other
+		let message = |bit| {
+			let mut bits = bitvec![bitvec::order::Lsb0, u8; 0u8; 32];
+			bits.set(bit, true);
+			BitfieldGossipMessage {
+				relay_parent: hash_a,
+				signed_availability: Signed::<AvailabilityBitfield>::sign(
+					AvailabilityBitfield(bits),
+					&signing_context,
+					0,
+					&Sr25519Keyring::Alice.pair().into(),
+				),
+			}
+		};
+		let first = message(0);
+		let second = message(1);
+
+		let pool = sp_core::testing::SpawnBlockingExecutor::new();
+		let (mut ctx, mut handle) =
+			make_subsystem_context::<BitfieldDistributionMessage, _>(pool);
+
+		let mut tracker = prewarmed_tracker(validator, signing_context, hash_a, peers![peer_a, peer_b]);
+
+		executor::block_on(async move {
+			handle_network_msg(
+				&mut ctx,
+				&mut tracker,
+				&Default::default(),
+				&Default::default(),
+				NetworkBridgeEvent::PeerMessage(peer_b.clone(), first.encode_versioned()),
+			)
+			.await
+			.unwrap();
+
+			assert_matches!(
+				handle.recv().await,
+				AllMessages::NetworkBridge(
+					NetworkBridgeMessage::ReportPeer(peer, rep)
+				) => {
+					assert_eq!(peer, peer_b);
+					assert_eq!(rep, GAIN_VALID_MESSAGE_FIRST)
+				}
+			);
+			assert_matches!(
+				handle.recv().await,
+				AllMessages::Provisioner(ProvisionerMessage::ProvisionableData(_))
+			);
+			assert_matches!(
+				handle.recv().await,
+				AllMessages::NetworkBridge(NetworkBridgeMessage::SendMessage(_, _, _))
+			);
+
+			handle_network_msg(
+				&mut ctx,
+				&mut tracker,
+				&Default::default(),
+				&Default::default(),
+				NetworkBridgeEvent::PeerMessage(peer_a.clone(), second.encode_versioned()),
+			)
+			.await
+			.unwrap();
+
+			assert_matches!(
+				handle.recv().await,
+				AllMessages::NetworkBridge(
+					NetworkBridgeMessage::ReportPeer(peer, rep)
+				) => {
+					assert_eq!(peer, peer_a);
+					assert_eq!(rep, COST_EQUIVOCATION)
+				}
+			);
+
+			// the first bitfield is retained
+			let job_data = tracker.per_relay_parent.get(&hash_a).unwrap();
+			assert_eq!(job_data.one_per_validator.values().next().map(|(message, _)| message), Some(&first));
+		});
+	}
+
+	#[test]
+	fn bitfield_of_equivocator_is_penalized() {
+		let hash_a: Hash = [0; 32].into();
+
+		let peer_a = PeerId::random();
+		let peer_b = PeerId::random();
+
+		let signing_context = SigningContext {
+			session_index: 1,
+			parent_hash: hash_a,
+		};
+
+		let validator: ValidatorId = Sr25519Keyring::Alice.public().into();
+
+		// none of the bitfields supersedes another
+		let message = |bit| {
+			let mut bits = bitvec![bitvec::order::Lsb0, u8; 0u8; 32];
+			bits.set(bit, true);
+			BitfieldGossipMessage {
+				relay_parent: hash_a,
+				signed_availability: Signed::<AvailabilityBitfield>::sign(
+					AvailabilityBitfield(bits),
+					&signing_context,
+					0,
+					&Sr25519Keyring::Alice.pair().into(),
+				),
+			}
+		};
+
+		let pool = sp_core::testing::SpawnBlockingExecutor::new();
+		let (mut ctx, mut handle) =
+			make_subsystem_context::<BitfieldDistributionMessage, _>(pool);
+
+		let mut tracker = prewarmed_tracker(validator, signing_context.clone(), hash_a, peers![peer_a, peer_b]);
+
+		executor::block_on(async move {
+			for (peer, bit) in vec![(&peer_b, 0), (&peer_a, 1), (&peer_b, 2)] {
+				handle_network_msg(
+					&mut ctx,
+					&mut tracker,
+					&Default::default(),
+					&Default::default(),
+					NetworkBridgeEvent::PeerMessage(peer.clone(), message(bit).encode_versioned()),
+				)
+				.await
+				.unwrap();
+			}
+
+			let mut reports = Vec::new();
+			while let Some(message) = handle.try_recv() {
+				if let AllMessages::NetworkBridge(NetworkBridgeMessage::ReportPeer(peer, rep)) = message {
+					reports.push((peer, rep));
+				}
+			}
+			assert_eq!(reports, vec![
+				(peer_b.clone(), GAIN_VALID_MESSAGE_FIRST),
+				(peer_a, COST_EQUIVOCATION),
+				(peer_b, COST_REPEATED_EQUIVOCATION),
+			]);
+		});
+	}
+
+	#[test]
+	fn disconnect_prunes_sent_messages_of_peer() {
+		let hash_a: Hash = [0; 32].into();
+		let hash_b: Hash = [1; 32].into();
+
+		let peer_a = PeerId::random();
+		let peer_b = PeerId::random();
+
+		let validator: ValidatorId = Sr25519Keyring::Alice.public().into();
+		let signing_context_a = SigningContext {
+			session_index: 1,
+			parent_hash: hash_a,
+		};
+		let signing_context_b = SigningContext {
+			session_index: 1,
+			parent_hash: hash_b,
+		};
+
+		let mut tracker = prewarmed_tracker(
+			validator.clone(),
+			signing_context_a.clone(),
+			hash_a,
+			peers![peer_a, peer_b],
+		);
+		tracker.per_relay_parent.insert(hash_b, PerRelayParentData {
+			signing_context: signing_context_b.clone(),
+			validator_set: Arc::new(vec![validator]),
+			..Default::default()
+		});
+		tracker.view = view![hash_a, hash_b];
+		tracker.peer_views.insert(peer_a.clone(), view![hash_a, hash_b]);
+
+		let messages = vec![(hash_a, signing_context_a), (hash_b, signing_context_b)]
+			.into_iter()
+			.map(|(relay_parent, signing_context)| BitfieldGossipMessage {
+				relay_parent,
+				signed_availability: Signed::<AvailabilityBitfield>::sign(
+					AvailabilityBitfield(bitvec![bitvec::order::Lsb0, u8; 1u8; 32]),
+					&signing_context,
+					0,
+					&Sr25519Keyring::Alice.pair().into(),
+				),
+			})
+			.collect::<Vec<_>>();
+
+		let pool = sp_core::testing::SpawnBlockingExecutor::new();
+		let (mut ctx, mut handle) =
+			make_subsystem_context::<BitfieldDistributionMessage, _>(pool);
+
+		executor::block_on(async move {
+			for message in messages {
+				handle_network_msg(
+					&mut ctx,
+					&mut tracker,
+					&Default::default(),
+					&Default::default(),
+					NetworkBridgeEvent::PeerMessage(peer_b.clone(), message.encode_versioned()),
+				)
+				.await
+				.unwrap();
+
+				// reward, provisioner notification and the relay to `peer_a`
+				for _ in 0..3 {
+					let _ = handle.recv().await;
+				}
+			}
+
+			for relay_parent in &[hash_a, hash_b] {
+				let job_data = tracker.per_relay_parent.get(relay_parent).unwrap();
+				assert!(job_data.message_sent_to_peer.contains_key(&peer_a));
+			}
+
+			handle_network_msg(
+				&mut ctx,
+				&mut tracker,
+				&Default::default(),
+				&Default::default(),
+				NetworkBridgeEvent::PeerDisconnected(peer_a.clone()),
+			)
+			.await
+			.unwrap();
+
+			assert!(!tracker.peer_views.contains_key(&peer_a));
+			for relay_parent in &[hash_a, hash_b] {
+				let job_data = tracker.per_relay_parent.get(relay_parent).unwrap();
+				assert!(!job_data.message_sent_to_peer.contains_key(&peer_a));
+			}
+		});
+	}
+
+	#[test]
+	fn view_diff_distinguishes_added_and_removed() {
+		let hash_a: Hash = [0; 32].into();
+		let hash_b: Hash = [1; 32].into();
+		let hash_c: Hash = [2; 32].into();
+
+		assert_eq!(
+			ViewDiff::new(&view![hash_a, hash_b], &view![hash_b, hash_c]),
+			ViewDiff { added: vec![hash_c], removed: vec![hash_a] },
+		);
+		assert_eq!(ViewDiff::new(&view![hash_a], &view![hash_a]), ViewDiff::default());
+	}
+
+	#[test]
+	fn shrinking_view_prunes_sent_messages_of_peer() {
+		let hash_a: Hash = [0; 32].into();
+		let hash_b: Hash = [1; 32].into();
+
+		let peer_a = PeerId::random();
+
+		let validator: ValidatorId = Sr25519Keyring::Alice.public().into();
+		let signing_context = SigningContext {
+			session_index: 1,
+			parent_hash: hash_a,
+		};
+
+		let mut tracker = prewarmed_tracker(validator.clone(), signing_context, hash_a, peers![]);
+		tracker.per_relay_parent.insert(hash_b, PerRelayParentData {
+			signing_context: SigningContext {
+				session_index: 1,
+				parent_hash: hash_b,
+			},
+			validator_set: Arc::new(vec![validator.clone()]),
+			..Default::default()
+		});
+		tracker.view = view![hash_a, hash_b];
+		tracker.peer_views.insert(peer_a.clone(), view![hash_a, hash_b]);
+		for job_data in tracker.per_relay_parent.values_mut() {
+			job_data.message_sent_to_peer
+				.entry(peer_a.clone())
+				.or_default()
+				.insert(validator.clone());
+		}
+
+		let pool = sp_core::testing::SpawnBlockingExecutor::new();
+		let (mut ctx, mut handle) =
+			make_subsystem_context::<BitfieldDistributionMessage, _>(pool);
+
+		executor::block_on(async move {
+			handle_network_msg(
+				&mut ctx,
+				&mut tracker,
+				&Default::default(),
+				&Default::default(),
+				NetworkBridgeEvent::PeerViewChange(peer_a.clone(), view![hash_a]),
+			)
+			.await
+			.unwrap();
+
+			assert!(tracker.per_relay_parent[&hash_a].message_sent_to_peer.contains_key(&peer_a));
+			assert!(!tracker.per_relay_parent[&hash_b].message_sent_to_peer.contains_key(&peer_a));
+
+			// nothing new for the peer
+			assert_matches!(timeout(handle.recv(), TIMEOUT).await, None);
+		});
+	}
+
+	#[test]
+	fn catch_up_shares_the_messages_the_peer_still_needs() {
+		let hash_a: Hash = [0; 32].into();
+		let peer_a = PeerId::random();
+
+		let signing_context = SigningContext {
+			session_index: 1,
+			parent_hash: hash_a,
+		};
+
+		let keyrings = [
+			Sr25519Keyring::Alice,
+			Sr25519Keyring::Bob,
+			Sr25519Keyring::Charlie,
+			Sr25519Keyring::Dave,
+		];
+		let validators: Vec<ValidatorId> = keyrings.iter().map(|k| k.public().into()).collect();
+
+		let mut tracker = prewarmed_tracker(validators[0].clone(), signing_context.clone(), hash_a, peers![peer_a]);
+		let job_data = tracker.per_relay_parent.get_mut(&hash_a).unwrap();
+		job_data.validator_set = Arc::new(validators.clone());
+		for (index, keyring) in keyrings.iter().enumerate() {
+			job_data.insert_message(validators[index].clone(), BitfieldGossipMessage {
+				relay_parent: hash_a,
+				signed_availability: Signed::<AvailabilityBitfield>::sign(
+					AvailabilityBitfield(bitvec![bitvec::order::Lsb0, u8; 1u8; 32]),
+					&signing_context,
+					index as ValidatorIndex,
+					&keyring.pair().into(),
+				),
+			});
+		}
+		// most were sent to the peer already
+		job_data.message_sent_to_peer.insert(
+			peer_a.clone(),
+			vec![validators[0].clone(), validators[1].clone(), validators[3].clone()].into_iter().collect(),
+		);
+
+		let (batch, capped) = catch_up_batch(&tracker, &peer_a, vec![hash_a].iter(), usize::MAX);
+		assert!(!capped);
+		assert_eq!(batch.len(), 1);
+
+		let (relay_parent, validator, encoded) = &batch[0];
+		assert_eq!(*relay_parent, hash_a);
+		assert_eq!(*validator, validators[2]);
+		// the encoding is shared with the known message instead of copied
+		let job_data = tracker.per_relay_parent.get(&hash_a).unwrap();
+		assert!(Arc::ptr_eq(encoded, &job_data.one_per_validator[&validators[2]].1));
+	}
+
+	#[test]
+	fn catch_up_is_ordered_by_validator_index() {
+		let hash_a: Hash = [0; 32].into();
+		let peer_a = PeerId::random();
+
+		let signing_context = SigningContext {
+			session_index: 1,
+			parent_hash: hash_a,
+		};
+
+		let keyrings = [
+			Sr25519Keyring::Alice,
+			Sr25519Keyring::Bob,
+			Sr25519Keyring::Charlie,
+			Sr25519Keyring::Dave,
+			Sr25519Keyring::Eve,
+			Sr25519Keyring::Ferdie,
+		];
+		let validators: Vec<ValidatorId> = keyrings.iter().map(|k| k.public().into()).collect();
+
+		let mut tracker = prewarmed_tracker(validators[0].clone(), signing_context.clone(), hash_a, peers![]);
+		tracker.peer_views.insert(peer_a.clone(), View::default());
+		let job_data = tracker.per_relay_parent.get_mut(&hash_a).unwrap();
+		job_data.validator_set = Arc::new(validators.clone());
+		for (index, keyring) in keyrings.iter().enumerate().rev() {
+			job_data.insert_message(validators[index].clone(), BitfieldGossipMessage {
+				relay_parent: hash_a,
+				signed_availability: Signed::<AvailabilityBitfield>::sign(
+					AvailabilityBitfield(bitvec![bitvec::order::Lsb0, u8; 1u8; 32]),
+					&signing_context,
+					index as ValidatorIndex,
+					&keyring.pair().into(),
+				),
+			});
+		}
+
+		let pool = sp_core::testing::SpawnBlockingExecutor::new();
+		let (mut ctx, mut handle) =
+			make_subsystem_context::<BitfieldDistributionMessage, _>(pool);
+
+		executor::block_on(async move {
+			handle_network_msg(
+				&mut ctx,
+				&mut tracker,
+				&Default::default(),
+				&Default::default(),
+				NetworkBridgeEvent::PeerViewChange(peer_a.clone(), view![hash_a]),
+			)
+			.await
+			.unwrap();
+
+			assert_matches!(
+				handle.recv().await,
+				AllMessages::NetworkBridge(NetworkBridgeMessage::ReportPeer(_, rep)) => {
+					assert_eq!(rep, GAIN_USEFUL_VIEW_CHANGE);
+				}
+			);
+			assert_matches!(
+				handle.recv().await,
+				AllMessages::NetworkBridge(NetworkBridgeMessage::SendMessage(_, _, bytes)) => {
+					assert_matches!(
+						VersionedBitfieldGossipMessage::decode(&mut &bytes[..]),
+						Ok(VersionedBitfieldGossipMessage::V2(messages)) => {
+							let indices: Vec<_> = messages
+								.iter()
+								.map(|message| message.signed_availability.validator_index())
+								.collect();
+							assert_eq!(indices, vec![0, 1, 2, 3, 4, 5]);
+						}
+					);
+				}
+			);
+		});
+	}
+
+	#[test]
+	fn catch_up_is_capped_per_view_change() {
+		let hashes: Vec<Hash> = (0..3u8).map(|i| [i; 32].into()).collect();
+
+		let peer_a = PeerId::random();
+
+		let validator: ValidatorId = Sr25519Keyring::Alice.public().into();
+
+		let mut tracker = Tracker {
+			view: View(hashes.clone()),
+			config: Config { max_catch_up_messages: 2, ..Default::default() },
+			..Default::default()
+		};
+		tracker.peer_views.insert(peer_a.clone(), View::default());
+		for hash in hashes.iter() {
+			let signing_context = SigningContext {
+				session_index: 1,
+				parent_hash: *hash,
+			};
+			let mut job_data = PerRelayParentData {
+				signing_context: signing_context.clone(),
+				validator_set: Arc::new(vec![validator.clone()]),
+				..Default::default()
+			};
+			job_data.insert_message(validator.clone(), BitfieldGossipMessage {
+				relay_parent: *hash,
+				signed_availability: Signed::<AvailabilityBitfield>::sign(
+					AvailabilityBitfield(bitvec![bitvec::order::Lsb0, u8; 1u8; 32]),
+					&signing_context,
+					0,
+					&Sr25519Keyring::Alice.pair().into(),
+				),
+			});
+			tracker.per_relay_parent.insert(*hash, job_data);
+		}
+
+		let pool = sp_core::testing::SpawnBlockingExecutor::new();
+		let (mut ctx, mut handle) =
+			make_subsystem_context::<BitfieldDistributionMessage, _>(pool);
+
+		executor::block_on(async move {
+			handle_network_msg(
+				&mut ctx,
+				&mut tracker,
+				&Default::default(),
+				&Default::default(),
+				NetworkBridgeEvent::PeerViewChange(peer_a.clone(), View(hashes.clone())),
+			)
+			.await
+			.unwrap();
+
+			assert_matches!(
+				handle.recv().await,
+				AllMessages::NetworkBridge(NetworkBridgeMessage::ReportPeer(_, rep)) => {
+					assert_eq!(rep, GAIN_USEFUL_VIEW_CHANGE);
+				}
+			);
+			assert_matches!(
+				handle.recv().await,
+				AllMessages::NetworkBridge(NetworkBridgeMessage::SendMessage(peers, _, bytes)) => {
+					assert_eq!(peers, vec![peer_a.clone()]);
+					assert_matches!(
+						VersionedBitfieldGossipMessage::decode(&mut &bytes[..]),
+						Ok(VersionedBitfieldGossipMessage::V2(messages)) => {
+							assert_eq!(messages.len(), 2);
+						}
+					);
+				}
+			);
+			assert_matches!(timeout(handle.recv(), TIMEOUT).await, None);
+
+			// the last one follows in the next iteration
+			continue_catch_up(&mut ctx, &mut tracker).await.unwrap();
+			assert_matches!(
+				handle.recv().await,
+				AllMessages::NetworkBridge(NetworkBridgeMessage::SendMessage(peers, _, bytes)) => {
+					assert_eq!(peers, vec![peer_a.clone()]);
+					assert_matches!(
+						VersionedBitfieldGossipMessage::decode(&mut &bytes[..]),
+						Ok(VersionedBitfieldGossipMessage::V1(_))
+					);
+				}
+			);
+			assert!(tracker.pending_catch_up.is_empty());
+			for hash in hashes.iter() {
+				assert!(tracker.per_relay_parent[hash].message_sent_to_peer[&peer_a].contains(&validator));
+			}
+		});
+	}
+
+	#[test]
+	fn start_distributes_own_bitfield() {
+		let hash_a: Hash = [0; 32].into();
+		let peer_a = PeerId::random();
+
+		let signing_context = SigningContext {
+			session_index: 1,
+			parent_hash: hash_a,
+		};
+
+		let validator: ValidatorId = Sr25519Keyring::Alice.public().into();
+
+		let payload = AvailabilityBitfield(bitvec![bitvec::order::Lsb0, u8; 1u8; 32]);
+		let signed = Signed::<AvailabilityBitfield>::sign(
+			payload,
+			&signing_context,
+			0,
+			&Sr25519Keyring::Alice.pair().into(),
+		);
+
+		let pool = sp_core::testing::SpawnBlockingExecutor::new();
+		let (ctx, mut handle) =
+			make_subsystem_context::<BitfieldDistributionMessage, _>(pool);
+
+		let subsystem = BitfieldDistribution::new(Default::default()).start(ctx).future;
+
+		let test_fut = async move {
+			assert_matches!(
+				handle.recv().await,
+				AllMessages::NetworkBridge(
+					NetworkBridgeMessage::RegisterEventProducer(protocol, _)
+				) => {
+					assert_eq!(protocol, BitfieldDistribution::DEFAULT_PROTOCOL_ID);
+				}
+			);
+
+			start_work(&mut handle, hash_a, vec![validator.clone()], signing_context.clone()).await;
+
+			handle.send(network_event(NetworkBridgeEvent::OurViewChange(view![hash_a]))).await;
+			handle.send(network_event(
+				NetworkBridgeEvent::PeerConnected(peer_a.clone(), ObservedRole::Full),
+			)).await;
+			handle.send(network_event(
+				NetworkBridgeEvent::PeerViewChange(peer_a.clone(), view![hash_a]),
+			)).await;
+
+			assert_matches!(
+				timeout(handle.recv(), TIMEOUT).await,
+				Some(AllMessages::NetworkBridge(
+					NetworkBridgeMessage::ReportPeer(peer, rep)
+				)) => {
+					assert_eq!(peer, peer_a);
+					assert_eq!(rep, GAIN_USEFUL_VIEW_CHANGE);
+				}
+			);
+			let (ack_tx, ack_rx) = oneshot::channel();
+			handle.send(FromOverseer::Communication {
+				msg: BitfieldDistributionMessage::DistributeBitfield(hash_a, signed.clone(), Some(ack_tx)),
+			}).await;
+
+			assert_matches!(
+				timeout(handle.recv(), TIMEOUT).await,
+				Some(AllMessages::Provisioner(ProvisionerMessage::ProvisionableData(
+					ProvisionableData::Bitfield(relay_parent, bitfield)
+				))) => {
+					assert_eq!(relay_parent, hash_a);
+					assert_eq!(bitfield, signed);
+				}
+			);
+
+			assert_matches!(
+				timeout(handle.recv(), TIMEOUT).await,
+				Some(AllMessages::NetworkBridge(
+					NetworkBridgeMessage::SendMessage(peers, protocol, bytes)
+				)) => {
+					assert_eq!(peers, vec![peer_a.clone()]);
+					assert_eq!(protocol, BitfieldDistribution::DEFAULT_PROTOCOL_ID);
+					assert_eq!(
+						bytes,
+						BitfieldGossipMessage {
+							relay_parent: hash_a,
+							signed_availability: signed.clone(),
+						}.encode_versioned(),
+					);
+				}
+			);
+
+			assert_eq!(timeout(ack_rx, TIMEOUT).await, Some(Ok(Ok(()))));
+
+			handle.send(FromOverseer::Signal(OverseerSignal::Conclude)).await;
+		};
+
+		executor::block_on(future::join(test_fut, subsystem));
+	}
+
+	#[test]
+	fn own_bitfield_update_is_redistributed() {
+		let hash_a: Hash = [0; 32].into();
+		let peer_a = PeerId::random();
+
+		let signing_context = SigningContext {
+			session_index: 1,
+			parent_hash: hash_a,
+		};
+
+		let validator: ValidatorId = Sr25519Keyring::Alice.public().into();
+
+		let sign = |bits: &[usize]| {
+			let mut payload = bitvec![bitvec::order::Lsb0, u8; 0u8; 32];
+			for bit in bits {
+				payload.set(*bit, true);
+			}
+			Signed::<AvailabilityBitfield>::sign(
+				AvailabilityBitfield(payload),
+				&signing_context,
+				0,
+				&Sr25519Keyring::Alice.pair().into(),
+			)
+		};
+		let initial = sign(&[0]);
+		let updated = sign(&[0, 1]);
+		let unrelated = sign(&[2]);
+		let initial_again = initial.clone();
+
+		let pool = sp_core::testing::SpawnBlockingExecutor::new();
+		let (ctx, mut handle) =
+			make_subsystem_context::<BitfieldDistributionMessage, _>(pool);
+
+		let subsystem = BitfieldDistribution::new(Default::default()).start(ctx).future;
+
+		let test_fut = async move {
+			assert_matches!(
+				handle.recv().await,
+				AllMessages::NetworkBridge(NetworkBridgeMessage::RegisterEventProducer(..))
+			);
+
+			start_work(&mut handle, hash_a, vec![validator.clone()], signing_context.clone()).await;
+
+			handle.send(network_event(NetworkBridgeEvent::OurViewChange(view![hash_a]))).await;
+			handle.send(network_event(
+				NetworkBridgeEvent::PeerConnected(peer_a.clone(), ObservedRole::Full),
+			)).await;
+			handle.send(network_event(
+				NetworkBridgeEvent::PeerViewChange(peer_a.clone(), view![hash_a]),
+			)).await;
+
+			assert_matches!(
+				timeout(handle.recv(), TIMEOUT).await,
+				Some(AllMessages::NetworkBridge(NetworkBridgeMessage::ReportPeer(_, rep))) => {
+					assert_eq!(rep, GAIN_USEFUL_VIEW_CHANGE);
+				}
+			);
+
+			for (signed, update) in vec![(initial, false), (updated, true)] {
+				let (ack_tx, ack_rx) = oneshot::channel();
+				let msg = if update {
+					BitfieldDistributionMessage::UpdateOwnBitfield(hash_a, signed.clone(), Some(ack_tx))
+				} else {
+					BitfieldDistributionMessage::DistributeBitfield(hash_a, signed.clone(), Some(ack_tx))
+				};
+				handle.send(FromOverseer::Communication { msg }).await;
+
+				assert_matches!(
+					timeout(handle.recv(), TIMEOUT).await,
+					Some(AllMessages::Provisioner(ProvisionerMessage::ProvisionableData(
+						ProvisionableData::Bitfield(_, bitfield)
+					))) => {
+						assert_eq!(bitfield, signed);
+					}
+				);
+				assert_matches!(
+					timeout(handle.recv(), TIMEOUT).await,
+					Some(AllMessages::NetworkBridge(
+						NetworkBridgeMessage::SendMessage(peers, _, bytes)
+					)) => {
+						assert_eq!(peers, vec![peer_a.clone()]);
+						assert_eq!(
+							bytes,
+							BitfieldGossipMessage {
+								relay_parent: hash_a,
+								signed_availability: signed,
+							}.encode_versioned(),
+						);
+					}
+				);
+				assert_eq!(timeout(ack_rx, TIMEOUT).await, Some(Ok(Ok(()))));
+			}
+
+			// an update dropping bits we already announced is refused
+			let (ack_tx, ack_rx) = oneshot::channel();
+			handle.send(FromOverseer::Communication {
+				msg: BitfieldDistributionMessage::UpdateOwnBitfield(hash_a, unrelated, Some(ack_tx)),
+			}).await;
+
+			assert_eq!(
+				timeout(ack_rx, TIMEOUT).await,
+				Some(Ok(Err(DistributeError::NotSuperseding))),
+			);
+			assert!(timeout(handle.recv(), TIMEOUT).await.is_none());
+
+			// so is distributing a different one without superseding
+			let (ack_tx, ack_rx) = oneshot::channel();
+			handle.send(FromOverseer::Communication {
+				msg: BitfieldDistributionMessage::DistributeBitfield(hash_a, initial_again, Some(ack_tx)),
+			}).await;
+
+			assert_eq!(
+				timeout(ack_rx, TIMEOUT).await,
+				Some(Ok(Err(DistributeError::AlreadyDistributed))),
+			);
+			assert!(timeout(handle.recv(), TIMEOUT).await.is_none());
+
+			handle.send(FromOverseer::Signal(OverseerSignal::Conclude)).await;
+		};
+
+		executor::block_on(future::join(test_fut, subsystem));
+	}
+
+	#[test]
+	fn basics_are_queried_in_a_single_round_trip() {
+		let hash_a: Hash = [0; 32].into();
+
+		let validator: ValidatorId = Sr25519Keyring::Alice.public().into();
+
+		let pool = sp_core::testing::SpawnBlockingExecutor::new();
+		let (ctx, mut handle) =
+			make_subsystem_context::<BitfieldDistributionMessage, _>(pool);
+
+		let subsystem = BitfieldDistribution::new(Default::default()).start(ctx).future;
+
+		let test_fut = async move {
+			assert_matches!(
+				handle.recv().await,
+				AllMessages::NetworkBridge(NetworkBridgeMessage::RegisterEventProducer(..))
+			);
+
+			start_work(&mut handle, hash_a, vec![validator], SigningContext { session_index: 1, parent_hash: hash_a })
+				.await;
+
+			// a single round-trip suffices
+			let (tx, rx) = oneshot::channel();
+			handle.send(FromOverseer::Communication {
+				msg: BitfieldDistributionMessage::QueryCoverage(hash_a, tx),
+			}).await;
+			assert_eq!(
+				timeout(rx, TIMEOUT).await,
+				Some(Ok(Some(CoverageReport { validators: 1, missing: vec![0] }))),
+			);
+			assert!(timeout(handle.recv(), TIMEOUT).await.is_none());
+
+			handle.send(FromOverseer::Signal(OverseerSignal::Conclude)).await;
+		};
+
+		executor::block_on(future::join(test_fut, subsystem));
+	}
+
+	#[test]
+	fn validator_set_is_shared_within_session() {
+		let hash_a: Hash = [0; 32].into();
+		let hash_b: Hash = [1; 32].into();
+		let hash_c: Hash = [2; 32].into();
+
+		let validators: Vec<ValidatorId> = vec![
+			Sr25519Keyring::Alice.public().into(),
+			Sr25519Keyring::Bob.public().into(),
+		];
+
+		let pool = sp_core::testing::SpawnBlockingExecutor::new();
+		let (mut ctx, mut handle) =
+			make_subsystem_context::<BitfieldDistributionMessage, _>(pool);
+
+		let mut validators_by_session = HashMap::new();
+
+		let queries = async {
+			let mut validator_sets = Vec::new();
+			for relay_parent in vec![hash_a, hash_b, hash_c] {
+				let basics = query_basics(&mut ctx, &mut validators_by_session, relay_parent)
+					.await
+					.unwrap();
+				validator_sets.push(basics.validators);
+			}
+			validator_sets
+		};
+		let answers = async {
+			for (relay_parent, session_index) in vec![(hash_a, 1), (hash_b, 1), (hash_c, 2)] {
+				let signing_context = SigningContext { session_index, parent_hash: relay_parent };
+				expect_basics_queries(&mut handle, relay_parent, validators.clone(), signing_context).await;
+			}
+		};
+
+		let (validator_sets, ()) = executor::block_on(future::join(queries, answers));
+
+		// no copy of the validators per relay parent, only one per session
+		assert!(Arc::ptr_eq(&validator_sets[0], &validator_sets[1]));
+		assert!(!Arc::ptr_eq(&validator_sets[1], &validator_sets[2]));
+		assert_eq!(*validator_sets[2], validators);
+	}
+
+	#[test]
+	fn basics_are_queried_concurrently() {
+		let hash_a: Hash = [0; 32].into();
+
+		let validators: Vec<ValidatorId> = vec![
+			Sr25519Keyring::Alice.public().into(),
+			Sr25519Keyring::Bob.public().into(),
+		];
+		let signing_context = SigningContext { session_index: 1, parent_hash: hash_a };
+
+		let pool = sp_core::testing::SpawnBlockingExecutor::new();
+		let (mut ctx, mut handle) =
+			make_subsystem_context::<BitfieldDistributionMessage, _>(pool);
+
+		let mut validators_by_session = HashMap::new();
+
+		let query = query_basics(&mut ctx, &mut validators_by_session, hash_a);
+		let answers = async {
+			// all requests are out before any is answered
+			let signing_tx = assert_matches!(
+				handle.recv().await,
+				AllMessages::RuntimeApi(
+					RuntimeApiMessage::Request(hash, RuntimeApiRequest::SigningContext(tx))
+				) => {
+					assert_eq!(hash, hash_a);
+					tx
+				}
+			);
+			let validators_tx = assert_matches!(
+				handle.recv().await,
+				AllMessages::RuntimeApi(
+					RuntimeApiMessage::Request(hash, RuntimeApiRequest::Validators(tx))
+				) => {
+					assert_eq!(hash, hash_a);
+					tx
+				}
+			);
+			expect_roster_query(&mut handle, hash_a, Some(5)).await;
+			validators_tx.send(validators.clone()).unwrap();
+			signing_tx.send(signing_context.clone()).unwrap();
+		};
+
+		let (basics, ()) = executor::block_on(future::join(query, answers));
+		let basics = basics.unwrap();
+
+		assert_eq!(*basics.validators, validators);
+		assert_eq!(basics.signing_context, signing_context);
+		assert_eq!(basics.availability_cores, Some(5));
+	}
+
+	#[test]
+	fn canceled_basics_query_is_reported() {
+		let hash_a: Hash = [0; 32].into();
+
+		let pool = sp_core::testing::SpawnBlockingExecutor::new();
+		let (mut ctx, mut handle) =
+			make_subsystem_context::<BitfieldDistributionMessage, _>(pool);
+
+		let mut validators_by_session = HashMap::new();
+
+		let query = query_basics(&mut ctx, &mut validators_by_session, hash_a);
+		let answers = async {
+			// the requests are dropped unanswered, as for a relay parent no longer available
+			assert_matches!(
+				handle.recv().await,
+				AllMessages::RuntimeApi(RuntimeApiMessage::Request(_, RuntimeApiRequest::SigningContext(_)))
+			);
+			assert_matches!(
+				handle.recv().await,
+				AllMessages::RuntimeApi(RuntimeApiMessage::Request(_, RuntimeApiRequest::Validators(_)))
+			);
+			assert_matches!(
+				handle.recv().await,
+				AllMessages::RuntimeApi(RuntimeApiMessage::Request(_, RuntimeApiRequest::ValidatorGroups(_)))
+			);
+		};
+
+		let (basics, ()) = executor::block_on(future::join(query, answers));
+
+		assert_eq!(basics.err(), Some(BitfieldDistributionError::RuntimeApiCanceled));
+	}
+
+	#[test]
+	fn registration_is_retried() {
+		let hash_a: Hash = [0; 32].into();
+
+		let validator: ValidatorId = Sr25519Keyring::Alice.public().into();
+
+		let pool = sp_core::testing::SpawnBlockingExecutor::new();
+		let (ctx, mut handle) =
+			make_subsystem_context::<BitfieldDistributionMessage, _>(pool);
+
+		let config = Config { registration_backoff: Duration::from_millis(1), ..Default::default() };
+		let subsystem = BitfieldDistribution::new(Default::default())
+			.with_config(config)
+			.start(HookedContext::new(ctx).on_send(failing(2)))
+			.future;
+
+		let test_fut = async move {
+			assert_matches!(
+				handle.recv().await,
+				AllMessages::NetworkBridge(NetworkBridgeMessage::RegisterEventProducer(..))
+			);
+
+			// and works on relay parents afterwards
+			start_work(&mut handle, hash_a, vec![validator], SigningContext { session_index: 1, parent_hash: hash_a })
+				.await;
+			let (tx, rx) = oneshot::channel();
+			handle.send(FromOverseer::Communication {
+				msg: BitfieldDistributionMessage::QueryCoverage(hash_a, tx),
+			}).await;
+			assert_eq!(
+				timeout(rx, TIMEOUT).await,
+				Some(Ok(Some(CoverageReport { validators: 1, missing: vec![0] }))),
+			);
+
+			handle.send(FromOverseer::Signal(OverseerSignal::Conclude)).await;
+		};
+
+		executor::block_on(future::join(test_fut, subsystem));
+	}
+
+	#[test]
+	fn registration_is_given_up_eventually() {
+		let pool = sp_core::testing::SpawnBlockingExecutor::new();
+		let (ctx, _handle) =
+			make_subsystem_context::<BitfieldDistributionMessage, _>(pool);
+
+		let config = Config {
+			max_registration_attempts: 3,
+			registration_backoff: Duration::from_millis(1),
+			..Default::default()
+		};
+		let subsystem = BitfieldDistribution::new(Default::default())
+			.with_config(config)
+			.run(HookedContext::new(ctx).on_send(failing(3)));
+
+		assert_eq!(executor::block_on(subsystem), Err(BitfieldDistributionError::RegistrationFailed(3)));
+	}
+
+	#[test]
+	fn canceled_runtime_api_request_does_not_stop_subsystem() {
+		let hash_a: Hash = [0; 32].into();
+		let hash_b: Hash = [1; 32].into();
+
+		let validator: ValidatorId = Sr25519Keyring::Alice.public().into();
+
+		let signed = Signed::<AvailabilityBitfield>::sign(
+			AvailabilityBitfield(bitvec![bitvec::order::Lsb0, u8; 1u8; 32]),
+			&SigningContext { session_index: 1, parent_hash: hash_b },
+			0,
+			&Sr25519Keyring::Alice.pair().into(),
+		);
+
+		let pool = sp_core::testing::SpawnBlockingExecutor::new();
+		let (ctx, mut handle) =
+			make_subsystem_context::<BitfieldDistributionMessage, _>(pool);
+
+		let subsystem = BitfieldDistribution::new(Default::default()).start(ctx).future;
+
+		let test_fut = async move {
+			assert_matches!(
+				handle.recv().await,
+				AllMessages::NetworkBridge(NetworkBridgeMessage::RegisterEventProducer(..))
+			);
+
+			handle.send(FromOverseer::Signal(OverseerSignal::StartWork(hash_a))).await;
+			for _ in 0..3 {
+				assert_matches!(
+					handle.recv().await,
+					AllMessages::RuntimeApi(RuntimeApiMessage::Request(hash, request)) => {
+						assert_eq!(hash, hash_a);
+						assert_matches!(
+							request,
+							RuntimeApiRequest::SigningContext(_)
+								| RuntimeApiRequest::Validators(_)
+								| RuntimeApiRequest::ValidatorGroups(_)
+						);
+					}
+				);
+			}
+
+			start_work(
+				&mut handle,
+				hash_b,
+				vec![validator],
+				SigningContext { session_index: 1, parent_hash: hash_b },
+			).await;
+
+			// `hash_a` was skipped, `hash_b` is worked on
+			let (ack_tx, ack_rx) = oneshot::channel();
+			handle.send(FromOverseer::Communication {
+				msg: BitfieldDistributionMessage::DistributeBitfield(hash_a, signed.clone(), Some(ack_tx)),
+			}).await;
+			assert_eq!(
+				timeout(ack_rx, TIMEOUT).await,
+				Some(Ok(Err(DistributeError::UnknownRelayParent))),
+			);
+
+			let (ack_tx, ack_rx) = oneshot::channel();
+			handle.send(FromOverseer::Communication {
+				msg: BitfieldDistributionMessage::DistributeBitfield(hash_b, signed, Some(ack_tx)),
+			}).await;
+			assert_matches!(
+				timeout(handle.recv(), TIMEOUT).await,
+				Some(AllMessages::Provisioner(_))
+			);
+			assert_eq!(timeout(ack_rx, TIMEOUT).await, Some(Ok(Ok(()))));
+
+			handle.send(FromOverseer::Signal(OverseerSignal::Conclude)).await;
+		};
+
+		executor::block_on(future::join(test_fut, subsystem));
+	}
+
+	#[test]
+	fn least_recently_active_relay_parent_is_evicted() {
+		let hash_a: Hash = [0; 32].into();
+		let hash_b: Hash = [1; 32].into();
+		let hash_c: Hash = [2; 32].into();
+
+		let signing_context = |parent_hash| SigningContext {
+			session_index: 1,
+			parent_hash,
+		};
+
+		let validators: Vec<ValidatorId> = vec![Sr25519Keyring::Alice.public().into()];
+
+		let signed = Signed::<AvailabilityBitfield>::sign(
+			AvailabilityBitfield(bitvec![bitvec::order::Lsb0, u8; 1u8; 32]),
+			&signing_context(hash_a),
+			0,
+			&Sr25519Keyring::Alice.pair().into(),
+		);
+
+		let pool = sp_core::testing::SpawnBlockingExecutor::new();
+		let (ctx, mut handle) =
+			make_subsystem_context::<BitfieldDistributionMessage, _>(pool);
+
+		let config = Config { max_relay_parents: 2, ..Default::default() };
+		let subsystem = BitfieldDistribution::new(Default::default())
+			.with_config(config)
+			.start(ctx)
+			.future;
+
+		let test_fut = async move {
+			assert_matches!(
+				handle.recv().await,
+				AllMessages::NetworkBridge(NetworkBridgeMessage::RegisterEventProducer(..))
+			);
+
+			start_work(&mut handle, hash_a, validators.clone(), signing_context(hash_a)).await;
+			start_work(&mut handle, hash_b, validators.clone(), signing_context(hash_b)).await;
+
+			// makes `hash_a` more recently active than `hash_b`
+			handle.send(FromOverseer::Communication {
+				msg: BitfieldDistributionMessage::DistributeBitfield(hash_a, signed, None),
+			}).await;
+			assert_matches!(handle.recv().await, AllMessages::Provisioner(_));
+
+			start_work(&mut handle, hash_c, validators, signing_context(hash_c)).await;
+
+			for (hash, tracked) in vec![(hash_a, true), (hash_b, false), (hash_c, true)] {
+				let (tx, rx) = oneshot::channel();
+				handle.send(FromOverseer::Communication {
+					msg: BitfieldDistributionMessage::QueryCoverage(hash, tx),
+				}).await;
+				assert_eq!(timeout(rx, TIMEOUT).await.unwrap().unwrap().is_some(), tracked);
+			}
+
+			handle.send(FromOverseer::Signal(OverseerSignal::Conclude)).await;
+		};
+
+		executor::block_on(future::join(test_fut, subsystem));
+	}
+
+	#[test]
+	fn query_coverage() {
+		let hash_a: Hash = [0; 32].into();
+		let hash_b: Hash = [1; 32].into();
+
+		let signing_context = SigningContext {
+			session_index: 1,
+			parent_hash: hash_a,
+		};
+
+		let validators: Vec<ValidatorId> = vec![
+			Sr25519Keyring::Alice.public().into(),
+			Sr25519Keyring::Bob.public().into(),
+		];
+
+		let signed = Signed::<AvailabilityBitfield>::sign(
+			AvailabilityBitfield(bitvec![bitvec::order::Lsb0, u8; 1u8; 32]),
+			&signing_context,
+			1,
+			&Sr25519Keyring::Bob.pair().into(),
+		);
+
+		let pool = sp_core::testing::SpawnBlockingExecutor::new();
+		let (ctx, mut handle) =
+			make_subsystem_context::<BitfieldDistributionMessage, _>(pool);
+
+		let subsystem = BitfieldDistribution::new(Default::default()).start(ctx).future;
+
+		let test_fut = async move {
+			assert_matches!(
+				handle.recv().await,
+				AllMessages::NetworkBridge(NetworkBridgeMessage::RegisterEventProducer(..))
+			);
+
+			start_work(&mut handle, hash_a, validators, signing_context).await;
+			handle.send(FromOverseer::Communication {
+				msg: BitfieldDistributionMessage::DistributeBitfield(hash_a, signed, None),
+			}).await;
+			assert_matches!(
+				timeout(handle.recv(), TIMEOUT).await,
+				Some(AllMessages::Provisioner(_))
+			);
+
+			let (tx, rx) = oneshot::channel();
+			handle.send(FromOverseer::Communication {
+				msg: BitfieldDistributionMessage::QueryCoverage(hash_a, tx),
+			}).await;
+			assert_eq!(
+				timeout(rx, TIMEOUT).await,
+				Some(Ok(Some(CoverageReport { validators: 2, missing: vec![0] }))),
+			);
+
+			let (tx, rx) = oneshot::channel();
+			handle.send(FromOverseer::Communication {
+				msg: BitfieldDistributionMessage::QueryCoverage(hash_b, tx),
+			}).await;
+			assert_eq!(timeout(rx, TIMEOUT).await, Some(Ok(None)));
+
+			handle.send(FromOverseer::Signal(OverseerSignal::Conclude)).await;
+		};
+
+		executor::block_on(future::join(test_fut, subsystem));
+	}
+
+	#[test]
+	fn same_rng_seed_relays_to_same_peers() {
+		let hash_a: Hash = [0; 32].into();
+
+		let signing_context = SigningContext {
+			session_index: 1,
+			parent_hash: hash_a,
+		};
+
+		let validators: Vec<ValidatorId> = vec![Sr25519Keyring::Alice.public().into()];
+
+		let signed = Signed::<AvailabilityBitfield>::sign(
+			AvailabilityBitfield(bitvec![bitvec::order::Lsb0, u8; 1u8; 32]),
+			&signing_context,
+			0,
+			&Sr25519Keyring::Alice.pair().into(),
+		);
+
+		let peers = (0..100).map(|_| PeerId::random()).collect::<Vec<_>>();
+
+		// the peers a freshly started subsystem relays our bitfield to
+		let relayed_to = |seed: [u8; 32]| {
+			let pool = sp_core::testing::SpawnBlockingExecutor::new();
+			let (ctx, mut handle) =
+				make_subsystem_context::<BitfieldDistributionMessage, _>(pool);
+
+			let config = Config { fan_out: FanOut::Sqrt, rng_seed: Some(seed), ..Default::default() };
+			let subsystem = BitfieldDistribution::new(Default::default())
+				.with_config(config)
+				.start(ctx)
+				.future;
+
+			let validators = validators.clone();
+			let signing_context = signing_context.clone();
+			let signed = signed.clone();
+			let peers = peers.clone();
+			let test_fut = async move {
+				assert_matches!(
+					handle.recv().await,
+					AllMessages::NetworkBridge(NetworkBridgeMessage::RegisterEventProducer(..))
+				);
+
+				start_work(&mut handle, hash_a, validators, signing_context).await;
+
+				for peer in peers.iter() {
+					handle.send(network_event(NetworkBridgeEvent::PeerConnected(peer.clone(), ObservedRole::Full))).await;
+					handle.send(network_event(NetworkBridgeEvent::PeerViewChange(peer.clone(), view![hash_a]))).await;
+					assert_matches!(
+						handle.recv().await,
+						AllMessages::NetworkBridge(NetworkBridgeMessage::ReportPeer(_, GAIN_USEFUL_VIEW_CHANGE))
+					);
+				}
+
+				handle.send(FromOverseer::Communication {
+					msg: BitfieldDistributionMessage::DistributeBitfield(hash_a, signed, None),
+				}).await;
+				assert_matches!(handle.recv().await, AllMessages::Provisioner(_));
+				let receivers = assert_matches!(
+					handle.recv().await,
+					AllMessages::NetworkBridge(NetworkBridgeMessage::SendMessage(receivers, _, _)) => receivers
+				);
+
+				handle.send(FromOverseer::Signal(OverseerSignal::Conclude)).await;
+				receivers
+			};
+
+			executor::block_on(future::join(test_fut, subsystem)).0
+		};
+
+		let first = relayed_to([7; 32]);
+		assert_eq!(first.len(), 10);
+		assert_eq!(first, relayed_to([7; 32]));
+	}
+
+	#[test]
+	fn collect_bitfields() {
+		let hash_a: Hash = [0; 32].into();
+		let hash_b: Hash = [1; 32].into();
+		let peer_a = PeerId::random();
+
+		let signing_context = SigningContext {
+			session_index: 1,
+			parent_hash: hash_a,
+		};
+
+		let keyrings = [
+			Sr25519Keyring::Alice,
+			Sr25519Keyring::Bob,
+			Sr25519Keyring::Charlie,
+			Sr25519Keyring::Dave,
+		];
+		let validators: Vec<ValidatorId> = keyrings.iter().map(|k| k.public().into()).collect();
+
+		let signed = |index: usize| Signed::<AvailabilityBitfield>::sign(
+			AvailabilityBitfield(bitvec![bitvec::order::Lsb0, u8; 1u8; 32]),
+			&signing_context,
+			index as ValidatorIndex,
+			&keyrings[index].pair().into(),
+		);
+		let bitfields: Vec<_> = (0..keyrings.len()).map(signed).collect();
+
+		let pool = sp_core::testing::SpawnBlockingExecutor::new();
+		let (ctx, mut handle) =
+			make_subsystem_context::<BitfieldDistributionMessage, _>(pool);
+
+		let subsystem = BitfieldDistribution::new(Default::default()).start(ctx).future;
+
+		let test_fut = async move {
+			assert_matches!(
+				handle.recv().await,
+				AllMessages::NetworkBridge(NetworkBridgeMessage::RegisterEventProducer(..))
+			);
+
+			start_work(&mut handle, hash_a, validators, signing_context).await;
+			handle.send(network_event(NetworkBridgeEvent::OurViewChange(view![hash_a]))).await;
+
+			// gossiped by a peer...
+			for index in [3, 1].iter() {
+				let msg = BitfieldGossipMessage {
+					relay_parent: hash_a,
+					signed_availability: bitfields[*index].clone(),
+				};
+				handle.send(network_event(NetworkBridgeEvent::PeerMessage(peer_a.clone(), msg.encode_versioned()))).await;
+				assert_matches!(
+					handle.recv().await,
+					AllMessages::NetworkBridge(NetworkBridgeMessage::ReportPeer(..))
+				);
+				assert_matches!(handle.recv().await, AllMessages::Provisioner(_));
+			}
+
+			// ...and our own one
+			handle.send(FromOverseer::Communication {
+				msg: BitfieldDistributionMessage::DistributeBitfield(hash_a, bitfields[0].clone(), None),
+			}).await;
+			assert_matches!(handle.recv().await, AllMessages::Provisioner(_));
+
+			let (tx, rx) = oneshot::channel();
+			handle.send(FromOverseer::Communication {
+				msg: BitfieldDistributionMessage::CollectBitfields(hash_a, tx),
+			}).await;
+			assert_eq!(
+				timeout(rx, TIMEOUT).await,
+				Some(Ok(vec![bitfields[0].clone(), bitfields[1].clone(), bitfields[3].clone()])),
+			);
+
+			let (tx, rx) = oneshot::channel();
+			handle.send(FromOverseer::Communication {
+				msg: BitfieldDistributionMessage::CollectBitfields(hash_b, tx),
+			}).await;
+			assert_eq!(timeout(rx, TIMEOUT).await, Some(Ok(Vec::new())));
+
+			handle.send(FromOverseer::Signal(OverseerSignal::Conclude)).await;
+		};
+
+		executor::block_on(future::join(test_fut, subsystem));
+	}
+
+	#[test]
+	fn has_bitfield_once_received() {
+		let hash_a: Hash = [0; 32].into();
+		let hash_b: Hash = [1; 32].into();
+
+		let peer_a = PeerId::random();
+
+		let signing_context = SigningContext {
+			session_index: 1,
+			parent_hash: hash_a,
+		};
+
+		let validators: Vec<ValidatorId> = vec![
+			Sr25519Keyring::Alice.public().into(),
+			Sr25519Keyring::Bob.public().into(),
+		];
+
+		let msg = BitfieldGossipMessage {
+			relay_parent: hash_a,
+			signed_availability: Signed::<AvailabilityBitfield>::sign(
+				AvailabilityBitfield(bitvec![bitvec::order::Lsb0, u8; 1u8; 32]),
+				&signing_context,
+				1,
+				&Sr25519Keyring::Bob.pair().into(),
+			),
+		};
+
+		test_harness(Default::default(), |mut harness| async move {
+			async fn has_bitfield(harness: &mut TestHarness, hash: Hash, index: ValidatorIndex) -> bool {
+				let (tx, rx) = oneshot::channel();
+				harness.send(BitfieldDistributionMessage::HasBitfield(hash, index, tx)).await;
+				timeout(rx, TIMEOUT).await.expect("the query is answered").unwrap()
+			}
+
+			harness.activate(hash_a, validators, signing_context).await;
+			harness.connect_peer(&peer_a, view![hash_a]).await;
+			assert_eq!(harness.expect_report(&peer_a).await, GAIN_USEFUL_VIEW_CHANGE);
+			assert!(!has_bitfield(&mut harness, hash_a, 1).await);
+
+			harness.network_event(NetworkBridgeEvent::PeerMessage(peer_a.clone(), msg.encode_versioned())).await;
+			assert_eq!(harness.expect_report(&peer_a).await, GAIN_VALID_MESSAGE_FIRST);
+			assert_matches!(harness.recv().await, AllMessages::Provisioner(_));
+
+			assert!(has_bitfield(&mut harness, hash_a, 1).await);
+			assert!(!has_bitfield(&mut harness, hash_a, 0).await);
+			// neither of a validator out of bounds nor of a relay parent not worked on
+			assert!(!has_bitfield(&mut harness, hash_a, 2).await);
+			assert!(!has_bitfield(&mut harness, hash_b, 1).await);
+			harness.expect_quiescence().await;
+		});
+	}
+
+	#[test]
+	fn observer_provisions_bitfields_without_gossiping() {
+		let hash_a: Hash = [0; 32].into();
+		let peer_a = PeerId::random();
+		let peer_b = PeerId::random();
+		let peer_c = PeerId::random();
+
+		let signing_context = SigningContext {
+			session_index: 1,
+			parent_hash: hash_a,
+		};
+
+		let validator: ValidatorId = Sr25519Keyring::Alice.public().into();
+
+		let msg = BitfieldGossipMessage {
+			relay_parent: hash_a,
+			signed_availability: Signed::<AvailabilityBitfield>::sign(
+				AvailabilityBitfield(bitvec![bitvec::order::Lsb0, u8; 1u8; 32]),
+				&signing_context,
+				0,
+				&Sr25519Keyring::Alice.pair().into(),
+			),
+		};
+
+		let pool = sp_core::testing::SpawnBlockingExecutor::new();
+		let (ctx, mut handle) =
+			make_subsystem_context::<BitfieldDistributionMessage, _>(pool);
+
+		let config = Config { observer: true, ..Default::default() };
+		let subsystem = BitfieldDistribution::new(Default::default())
+			.with_config(config)
+			.start(ctx)
+			.future;
+
+		let test_fut = async move {
+			assert_matches!(
+				handle.recv().await,
+				AllMessages::NetworkBridge(NetworkBridgeMessage::RegisterEventProducer(..))
+			);
+
+			start_work(&mut handle, hash_a, vec![validator], signing_context).await;
+			handle.send(network_event(NetworkBridgeEvent::OurViewChange(view![hash_a]))).await;
+			for peer in vec![peer_a.clone(), peer_b.clone()] {
+				handle.send(network_event(
+					NetworkBridgeEvent::PeerConnected(peer.clone(), ObservedRole::Full),
+				)).await;
+				handle.send(network_event(NetworkBridgeEvent::PeerViewChange(peer, view![hash_a]))).await;
+			}
+			handle.send(network_event(
+				NetworkBridgeEvent::PeerMessage(peer_a.clone(), msg.encode_versioned()),
+			)).await;
+			// a peer which would need to catch up on the bitfield
+			handle.send(network_event(
+				NetworkBridgeEvent::PeerConnected(peer_c.clone(), ObservedRole::Full),
+			)).await;
+			handle.send(network_event(NetworkBridgeEvent::PeerViewChange(peer_c.clone(), view![hash_a]))).await;
+			await_quiescence(&mut handle).await;
+
+			let mut provisioned = Vec::new();
+			while let Some(message) = handle.try_recv() {
+				match message {
+					AllMessages::Provisioner(ProvisionerMessage::ProvisionableData(
+						ProvisionableData::Bitfield(relay_parent, bitfield),
+					)) => provisioned.push((relay_parent, bitfield)),
+					AllMessages::NetworkBridge(NetworkBridgeMessage::SendMessage(..)) => {
+						panic!("an observer must not gossip")
+					}
+					_ => {}
+				}
+			}
+			assert_eq!(provisioned, vec![(hash_a, msg.signed_availability.clone())]);
+
+			handle.send(FromOverseer::Signal(OverseerSignal::Conclude)).await;
+		};
+
+		executor::block_on(future::join(test_fut, subsystem));
+	}
+
+	#[test]
+	fn imported_snapshot_is_caught_up_on() {
+		let hash_a: Hash = [0; 32].into();
+		let hash_b: Hash = [1; 32].into();
+		let peer_a = PeerId::random();
+
+		let signing_context = SigningContext {
+			session_index: 1,
+			parent_hash: hash_a,
+		};
+
+		let validators: Vec<ValidatorId> = vec![
+			Sr25519Keyring::Alice.public().into(),
+			Sr25519Keyring::Bob.public().into(),
+			Sr25519Keyring::Charlie.public().into(),
+		];
+
+		let signed = |validator_index: ValidatorIndex, keyring: Sr25519Keyring| {
+			Signed::<AvailabilityBitfield>::sign(
+				AvailabilityBitfield(bitvec![bitvec::order::Lsb0, u8; 1u8; 32]),
+				&signing_context,
+				validator_index,
+				&keyring.pair().into(),
+			)
+		};
+		let alice = signed(0, Sr25519Keyring::Alice);
+		let bob = signed(1, Sr25519Keyring::Bob);
+		// not signed by the validator at its index
+		let forged = signed(2, Sr25519Keyring::Dave);
+
+		let pool = sp_core::testing::SpawnBlockingExecutor::new();
+		let (ctx, mut handle) =
+			make_subsystem_context::<BitfieldDistributionMessage, _>(pool);
+
+		let subsystem = BitfieldDistribution::new(Default::default()).start(ctx).future;
+
+		let test_fut = async move {
+			assert_matches!(
+				handle.recv().await,
+				AllMessages::NetworkBridge(NetworkBridgeMessage::RegisterEventProducer(..))
+			);
+
+			start_work(&mut handle, hash_a, validators, signing_context).await;
+			handle.send(network_event(NetworkBridgeEvent::OurViewChange(view![hash_a]))).await;
+			handle.send(FromOverseer::Communication {
+				msg: BitfieldDistributionMessage::ImportSnapshot(vec![
+					(hash_a, vec![alice.clone(), bob.clone(), forged]),
+					(hash_b, vec![alice.clone()]),
+				]),
+			}).await;
+
+			let (tx, rx) = oneshot::channel();
+			handle.send(FromOverseer::Communication {
+				msg: BitfieldDistributionMessage::QueryBitfieldSources(hash_a, tx),
+			}).await;
+			assert_eq!(
+				timeout(rx, TIMEOUT).await,
+				Some(Ok(Some(vec![(0, BitfieldSource::Snapshot), (1, BitfieldSource::Snapshot)]))),
+			);
+
+			// a peer joining gets them without anyone having gossiped them to us
+			handle.send(network_event(NetworkBridgeEvent::PeerConnected(peer_a.clone(), ObservedRole::Full))).await;
+			handle.send(network_event(NetworkBridgeEvent::PeerViewChange(peer_a.clone(), view![hash_a]))).await;
+			assert_matches!(
+				handle.recv().await,
+				AllMessages::NetworkBridge(NetworkBridgeMessage::ReportPeer(_, rep)) => {
+					assert_eq!(rep, GAIN_USEFUL_VIEW_CHANGE);
+				}
+			);
+			assert_matches!(
+				handle.recv().await,
+				AllMessages::NetworkBridge(NetworkBridgeMessage::SendMessage(peers, _, bytes)) => {
+					assert_eq!(peers, vec![peer_a.clone()]);
+					assert_matches!(
+						VersionedBitfieldGossipMessage::decode(&mut &bytes[..]),
+						Ok(VersionedBitfieldGossipMessage::V2(messages)) => {
+							let bitfields: Vec<_> = messages
+								.into_iter()
+								.map(|message| message.signed_availability)
+								.collect();
+							assert_eq!(bitfields, vec![alice, bob]);
+						}
+					);
+				}
+			);
+
+			handle.send(FromOverseer::Signal(OverseerSignal::Conclude)).await;
+		};
+
+		executor::block_on(future::join(test_fut, subsystem));
+	}
+
+	#[test]
+	fn first_delivering_peer_is_recorded() {
+		let hash_a: Hash = [0; 32].into();
+		let peer_a = PeerId::random();
+		let peer_b = PeerId::random();
+
+		let signing_context = SigningContext {
+			session_index: 1,
+			parent_hash: hash_a,
+		};
+
+		let validators: Vec<ValidatorId> = vec![
+			Sr25519Keyring::Alice.public().into(),
+			Sr25519Keyring::Bob.public().into(),
+		];
+
+		let signed = |validator_index: ValidatorIndex, keyring: Sr25519Keyring| {
+			Signed::<AvailabilityBitfield>::sign(
+				AvailabilityBitfield(bitvec![bitvec::order::Lsb0, u8; 1u8; 32]),
+				&signing_context,
+				validator_index,
+				&keyring.pair().into(),
+			)
+		};
+		let own = signed(0, Sr25519Keyring::Alice);
+		let msg = BitfieldGossipMessage {
+			relay_parent: hash_a,
+			signed_availability: signed(1, Sr25519Keyring::Bob),
+		};
+
+		let pool = sp_core::testing::SpawnBlockingExecutor::new();
+		let (ctx, mut handle) =
+			make_subsystem_context::<BitfieldDistributionMessage, _>(pool);
+
+		let subsystem = BitfieldDistribution::new(Default::default()).start(ctx).future;
+
+		let test_fut = async move {
+			assert_matches!(
+				handle.recv().await,
+				AllMessages::NetworkBridge(NetworkBridgeMessage::RegisterEventProducer(..))
+			);
+
+			start_work(&mut handle, hash_a, validators, signing_context).await;
+			handle.send(network_event(NetworkBridgeEvent::OurViewChange(view![hash_a]))).await;
+			handle.send(FromOverseer::Communication {
+				msg: BitfieldDistributionMessage::DistributeBitfield(hash_a, own, None),
+			}).await;
+			// the second copy of the same bitfield is not the first delivery
+			for peer in vec![&peer_a, &peer_b] {
+				handle.send(network_event(
+					NetworkBridgeEvent::PeerConnected(peer.clone(), ObservedRole::Full),
+				)).await;
+				handle.send(network_event(
+					NetworkBridgeEvent::PeerMessage(peer.clone(), msg.encode_versioned()),
+				)).await;
+			}
+
+			let (tx, rx) = oneshot::channel();
+			handle.send(FromOverseer::Communication {
+				msg: BitfieldDistributionMessage::QueryBitfieldSources(hash_a, tx),
+			}).await;
+			assert_eq!(
+				timeout(rx, TIMEOUT).await,
+				Some(Ok(Some(vec![(0, BitfieldSource::Local), (1, BitfieldSource::Peer(peer_a.clone()))]))),
+			);
+
+			handle.send(FromOverseer::Signal(OverseerSignal::Conclude)).await;
+		};
+
+		executor::block_on(future::join(test_fut, subsystem));
+	}
+
+	#[test]
+	fn forgotten_relay_parent_is_not_interesting() {
+		let hash_a: Hash = [0; 32].into();
+		let peer_a = PeerId::random();
+
+		let signing_context = SigningContext {
+			session_index: 1,
+			parent_hash: hash_a,
+		};
+
+		let validator: ValidatorId = Sr25519Keyring::Alice.public().into();
+
+		let msg = BitfieldGossipMessage {
+			relay_parent: hash_a,
+			signed_availability: Signed::<AvailabilityBitfield>::sign(
+				AvailabilityBitfield(bitvec![bitvec::order::Lsb0, u8; 1u8; 32]),
+				&signing_context,
+				0,
+				&Sr25519Keyring::Alice.pair().into(),
+			),
+		};
+
+		let pool = sp_core::testing::SpawnBlockingExecutor::new();
+		let (ctx, mut handle) =
+			make_subsystem_context::<BitfieldDistributionMessage, _>(pool);
+
+		let subsystem = BitfieldDistribution::new(Default::default()).start(ctx).future;
+
+		let test_fut = async move {
+			assert_matches!(
+				handle.recv().await,
+				AllMessages::NetworkBridge(NetworkBridgeMessage::RegisterEventProducer(..))
+			);
+
+			start_work(&mut handle, hash_a, vec![validator], signing_context).await;
+			handle.send(network_event(NetworkBridgeEvent::OurViewChange(view![hash_a]))).await;
+			handle.send(network_event(
+				NetworkBridgeEvent::PeerConnected(peer_a.clone(), ObservedRole::Full),
+			)).await;
+			handle.send(network_event(NetworkBridgeEvent::PeerViewChange(peer_a.clone(), view![hash_a]))).await;
+			assert_matches!(
+				handle.recv().await,
+				AllMessages::NetworkBridge(NetworkBridgeMessage::ReportPeer(_, rep)) => {
+					assert_eq!(rep, GAIN_USEFUL_VIEW_CHANGE);
+				}
+			);
+
+			// forgetting twice is as good as once
+			for _ in 0..2 {
+				handle.send(FromOverseer::Communication {
+					msg: BitfieldDistributionMessage::ForgetRelayParent(hash_a),
+				}).await;
+			}
+
+			let (tx, rx) = oneshot::channel();
+			handle.send(FromOverseer::Communication {
+				msg: BitfieldDistributionMessage::QueryCoverage(hash_a, tx),
+			}).await;
+			assert_eq!(timeout(rx, TIMEOUT).await, Some(Ok(None)));
+
+			// still in our view, but neither provisioned nor buffered
+			handle.send(network_event(
+				NetworkBridgeEvent::PeerMessage(peer_a.clone(), msg.encode_versioned()),
+			)).await;
+			assert_matches!(
+				handle.recv().await,
+				AllMessages::NetworkBridge(NetworkBridgeMessage::ReportPeer(peer, rep)) => {
+					assert_eq!(peer, peer_a);
+					assert_eq!(rep, COST_NOT_INTERESTED);
+				}
+			);
+
+			handle.send(FromOverseer::Signal(OverseerSignal::Conclude)).await;
+		};
+
+		executor::block_on(future::join(test_fut, subsystem));
+	}
+
+	#[test]
+	fn idlest_peer_is_evicted_beyond_max_peers() {
+		let peer_a = PeerId::random();
+		let peer_b = PeerId::random();
+		let peer_c = PeerId::random();
+
+		let pool = sp_core::testing::SpawnBlockingExecutor::new();
+		let (mut ctx, mut handle) =
+			make_subsystem_context::<BitfieldDistributionMessage, _>(pool);
+
+		let mut tracker = Tracker::default();
+		tracker.config.max_peers = 2;
+
+		executor::block_on(async move {
+			for event in vec![
+				NetworkBridgeEvent::PeerConnected(peer_a.clone(), ObservedRole::Full),
+				NetworkBridgeEvent::PeerConnected(peer_b.clone(), ObservedRole::Full),
+				// `peer_b` is idle from now on
+				NetworkBridgeEvent::PeerViewChange(peer_a.clone(), view![]),
+				NetworkBridgeEvent::PeerConnected(peer_c.clone(), ObservedRole::Full),
+			] {
+				handle_network_msg(&mut ctx, &mut tracker, &Default::default(), &Default::default(), event)
+					.await
+					.unwrap();
+			}
+
+			let tracked: HashSet<_> = tracker.peer_views.keys().cloned().collect();
+			assert_eq!(tracked, vec![peer_a.clone(), peer_c.clone()].into_iter().collect());
+			assert!(!tracker.peer_roles.contains_key(&peer_b));
+
+			// `peer_b` is still connected and tracked again once it is active,
+			// which evicts `peer_a` instead
+			handle_network_msg(
+				&mut ctx,
+				&mut tracker,
+				&Default::default(),
+				&Default::default(),
+				NetworkBridgeEvent::PeerViewChange(peer_b.clone(), view![]),
+			)
+			.await
+			.unwrap();
+
+			let tracked: HashSet<_> = tracker.peer_views.keys().cloned().collect();
+			assert_eq!(tracked, vec![peer_b.clone(), peer_c.clone()].into_iter().collect());
+			assert_eq!(tracker.peer_roles.get(&peer_b), Some(&ObservedRole::Full));
+
+			// not penalized for a view change while untracked
+			assert!(timeout(handle.recv(), TIMEOUT).await.is_none());
+		});
+	}
+
+	#[test]
+	fn subscribers_are_notified_of_provisioned_bitfields() {
+		let hash_a: Hash = [0; 32].into();
+		let peer_a = PeerId::random();
+
+		let signing_context = SigningContext {
+			session_index: 1,
+			parent_hash: hash_a,
+		};
+
+		let validator: ValidatorId = Sr25519Keyring::Alice.public().into();
+
+		let msg = BitfieldGossipMessage {
+			relay_parent: hash_a,
+			signed_availability: Signed::<AvailabilityBitfield>::sign(
+				AvailabilityBitfield(bitvec![bitvec::order::Lsb0, u8; 1u8; 32]),
+				&signing_context,
+				0,
+				&Sr25519Keyring::Alice.pair().into(),
+			),
+		};
+
+		let pool = sp_core::testing::SpawnBlockingExecutor::new();
+		let (ctx, mut handle) =
+			make_subsystem_context::<BitfieldDistributionMessage, _>(pool);
+
+		let subsystem = BitfieldDistribution::new(Default::default()).start(ctx).future;
+
+		let test_fut = async move {
+			assert_matches!(
+				handle.recv().await,
+				AllMessages::NetworkBridge(NetworkBridgeMessage::RegisterEventProducer(..))
+			);
+
+			let (subscriber, mut subscription) = mpsc::channel(1);
+			handle.send(FromOverseer::Communication {
+				msg: BitfieldDistributionMessage::Subscribe(subscriber),
+			}).await;
+
+			start_work(&mut handle, hash_a, vec![validator], signing_context).await;
+			handle.send(network_event(NetworkBridgeEvent::OurViewChange(view![hash_a]))).await;
+			handle.send(network_event(
+				NetworkBridgeEvent::PeerConnected(peer_a.clone(), ObservedRole::Full),
+			)).await;
+			handle.send(network_event(NetworkBridgeEvent::PeerViewChange(peer_a.clone(), view![hash_a]))).await;
+			handle.send(network_event(
+				NetworkBridgeEvent::PeerMessage(peer_a.clone(), msg.encode_versioned()),
+			)).await;
+			await_quiescence(&mut handle).await;
+
+			assert_eq!(
+				subscription.try_next().unwrap(),
+				Some((hash_a, msg.signed_availability.clone())),
+			);
+
+			handle.send(FromOverseer::Signal(OverseerSignal::Conclude)).await;
+		};
+
+		executor::block_on(future::join(test_fut, subsystem));
+	}
+
+	#[test]
+	fn dead_subscribers_are_forgotten() {
+		let hash_a: Hash = [0; 32].into();
+
+		let signing_context = SigningContext {
+			session_index: 1,
+			parent_hash: hash_a,
+		};
+
+		let signed = Signed::<AvailabilityBitfield>::sign(
+			AvailabilityBitfield(bitvec![bitvec::order::Lsb0, u8; 1u8; 32]),
+			&signing_context,
+			0,
+			&Sr25519Keyring::Alice.pair().into(),
+		);
+
+		let (alive, mut subscription) = mpsc::channel(1);
+		let (dead, _) = mpsc::channel(1);
+
+		let mut tracker = Tracker { subscribers: vec![alive, dead], ..Default::default() };
+
+		tracker.notify_subscribers(hash_a, &signed);
+		assert_eq!(tracker.subscribers.len(), 1);
+		assert_eq!(subscription.try_next().unwrap(), Some((hash_a, signed.clone())));
+
+		// a subscriber not keeping up is kept
+		tracker.notify_subscribers(hash_a, &signed);
+		tracker.notify_subscribers(hash_a, &signed);
+		assert_eq!(tracker.subscribers.len(), 1);
+	}
+
+	#[test]
+	fn bitfield_received_before_start_work_is_processed_once_started() {
+		let hash_a: Hash = [0; 32].into();
+		let peer_a = PeerId::random();
+		let peer_b = PeerId::random();
+
+		let signing_context = SigningContext {
+			session_index: 1,
+			parent_hash: hash_a,
+		};
+
+		let validator: ValidatorId = Sr25519Keyring::Alice.public().into();
+
+		let msg = BitfieldGossipMessage {
+			relay_parent: hash_a,
+			signed_availability: Signed::<AvailabilityBitfield>::sign(
+				AvailabilityBitfield(bitvec![bitvec::order::Lsb0, u8; 1u8; 32]),
+				&signing_context,
+				0,
+				&Sr25519Keyring::Alice.pair().into(),
+			),
+		};
+
+		let pool = sp_core::testing::SpawnBlockingExecutor::new();
+		let (ctx, mut handle) =
+			make_subsystem_context::<BitfieldDistributionMessage, _>(pool);
+
+		let subsystem = BitfieldDistribution::new(Default::default()).start(ctx).future;
+
+		let test_fut = async move {
+			assert_matches!(
+				handle.recv().await,
+				AllMessages::NetworkBridge(NetworkBridgeMessage::RegisterEventProducer(..))
+			);
+
+			// the network bridge is ahead of the overseer
+			handle.send(network_event(NetworkBridgeEvent::OurViewChange(view![hash_a]))).await;
+			for peer in vec![peer_a.clone(), peer_b.clone()] {
+				handle.send(network_event(
+					NetworkBridgeEvent::PeerConnected(peer.clone(), ObservedRole::Full),
+				)).await;
+				handle.send(network_event(NetworkBridgeEvent::PeerViewChange(peer, view![hash_a]))).await;
+			}
+			handle.send(network_event(
+				NetworkBridgeEvent::PeerMessage(peer_a.clone(), msg.encode_versioned()),
+			)).await;
+
+			// neither penalized nor processed yet
+			assert!(timeout(handle.recv(), TIMEOUT).await.is_none());
+
+			start_work(&mut handle, hash_a, vec![validator], signing_context).await;
+
+			assert_matches!(
+				handle.recv().await,
+				AllMessages::NetworkBridge(NetworkBridgeMessage::ReportPeer(peer, rep)) => {
+					assert_eq!(peer, peer_a);
+					assert_eq!(rep, GAIN_VALID_MESSAGE_FIRST);
+				}
+			);
+			assert_matches!(
+				handle.recv().await,
+				AllMessages::Provisioner(ProvisionerMessage::ProvisionableData(
+					ProvisionableData::Bitfield(relay_parent, bitfield)
+				)) => {
+					assert_eq!(relay_parent, hash_a);
+					assert_eq!(bitfield, msg.signed_availability);
+				}
+			);
+			assert_matches!(
+				handle.recv().await,
+				AllMessages::NetworkBridge(NetworkBridgeMessage::SendMessage(peers, _, _)) => {
+					assert_eq!(peers, vec![peer_b.clone()]);
+				}
+			);
+
+			handle.send(FromOverseer::Signal(OverseerSignal::Conclude)).await;
+		};
+
+		executor::block_on(future::join(test_fut, subsystem));
+	}
+
+	#[test]
+	fn status() {
+		let hash_a: Hash = [0; 32].into();
+		let hash_b: Hash = [1; 32].into();
+		let peer_a = PeerId::random();
+		let peer_b = PeerId::random();
+
+		let signing_context = SigningContext {
+			session_index: 1,
+			parent_hash: hash_a,
+		};
+
+		let keyrings = [Sr25519Keyring::Alice, Sr25519Keyring::Bob, Sr25519Keyring::Charlie];
+		let validators: Vec<ValidatorId> = keyrings.iter().map(|k| k.public().into()).collect();
+
+		let bitfields: Vec<_> = keyrings
+			.iter()
+			.enumerate()
+			.map(|(index, keyring)| Signed::<AvailabilityBitfield>::sign(
+				AvailabilityBitfield(bitvec![bitvec::order::Lsb0, u8; 1u8; 32]),
+				&signing_context,
+				index as ValidatorIndex,
+				&keyring.pair().into(),
+			))
+			.collect();
+
+		let pool = sp_core::testing::SpawnBlockingExecutor::new();
+		let (ctx, mut handle) =
+			make_subsystem_context::<BitfieldDistributionMessage, _>(pool);
+
+		let subsystem = BitfieldDistribution::new(Default::default()).start(ctx).future;
+
+		let test_fut = async move {
+			assert_matches!(
+				handle.recv().await,
+				AllMessages::NetworkBridge(NetworkBridgeMessage::RegisterEventProducer(..))
+			);
+
+			start_work(&mut handle, hash_a, validators.clone(), signing_context.clone()).await;
+			start_work(&mut handle, hash_b, validators, SigningContext {
+				parent_hash: hash_b,
+				..signing_context
+			}).await;
+			handle.send(network_event(NetworkBridgeEvent::OurViewChange(view![hash_a, hash_b]))).await;
+			for peer in vec![peer_a.clone(), peer_b.clone()] {
+				handle.send(network_event(
+					NetworkBridgeEvent::PeerConnected(peer.clone(), ObservedRole::Full),
+				)).await;
+				handle.send(network_event(NetworkBridgeEvent::PeerViewChange(peer, view![hash_a]))).await;
+				assert_matches!(
+					handle.recv().await,
+					AllMessages::NetworkBridge(NetworkBridgeMessage::ReportPeer(_, rep)) => {
+						assert_eq!(rep, GAIN_USEFUL_VIEW_CHANGE);
+					}
+				);
+			}
+
+			// our own bitfield is gossiped to us before we distribute it
+			for signed_availability in bitfields.iter().take(2) {
+				let msg = BitfieldGossipMessage {
+					relay_parent: hash_a,
+					signed_availability: signed_availability.clone(),
+				};
+				handle.send(network_event(NetworkBridgeEvent::PeerMessage(peer_a.clone(), msg.encode_versioned()))).await;
+				assert_matches!(
+					handle.recv().await,
+					AllMessages::NetworkBridge(NetworkBridgeMessage::ReportPeer(..))
+				);
+				assert_matches!(handle.recv().await, AllMessages::Provisioner(_));
+				assert_matches!(
+					handle.recv().await,
+					AllMessages::NetworkBridge(NetworkBridgeMessage::SendMessage(..))
+				);
+			}
+			handle.send(FromOverseer::Communication {
+				msg: BitfieldDistributionMessage::DistributeBitfield(hash_a, bitfields[0].clone(), None),
+			}).await;
+
+			let (tx, rx) = oneshot::channel();
+			handle.send(FromOverseer::Communication {
+				msg: BitfieldDistributionMessage::Status(tx),
+			}).await;
+			let report = timeout(rx, TIMEOUT).await.unwrap().unwrap();
+			assert_eq!(
+				report,
+				StatusReport {
+					relay_parents: 2,
+					peers: 2,
+					bitfields: 2,
+					processing: false,
+				},
+			);
+			assert_eq!(StatusReport::decode(&mut &report.encode()[..]), Ok(report));
+
+			handle.send(FromOverseer::Signal(OverseerSignal::Conclude)).await;
+		};
+
+		executor::block_on(future::join(test_fut, subsystem));
+	}
+
+	#[test]
+	fn relays_beyond_the_per_iteration_cap_are_deferred() {
+		let hash_a: Hash = [0; 32].into();
+		let peer_a = PeerId::random();
+		let peer_b = PeerId::random();
+
+		let signing_context = SigningContext {
+			session_index: 1,
+			parent_hash: hash_a,
+		};
+
+		let keyrings = [
+			Sr25519Keyring::Alice,
+			Sr25519Keyring::Bob,
+			Sr25519Keyring::Charlie,
+			Sr25519Keyring::Dave,
+		];
+		let validators: Vec<ValidatorId> = keyrings.iter().map(|k| k.public().into()).collect();
+
+		let batch: Vec<_> = keyrings
+			.iter()
+			.enumerate()
+			.map(|(index, keyring)| BitfieldGossipMessage {
+				relay_parent: hash_a,
+				signed_availability: Signed::<AvailabilityBitfield>::sign(
+					AvailabilityBitfield(bitvec![bitvec::order::Lsb0, u8; 1u8; 32]),
+					&signing_context,
+					index as ValidatorIndex,
+					&keyring.pair().into(),
+				),
+			})
+			.collect();
+
+		let pool = sp_core::testing::SpawnBlockingExecutor::new();
+		let (ctx, mut handle) =
+			make_subsystem_context::<BitfieldDistributionMessage, _>(pool);
+
+		let config = Config { max_sends_per_iteration: 2, ..Default::default() };
+		let subsystem = BitfieldDistribution::new(Default::default())
+			.with_config(config)
+			.start(ctx)
+			.future;
+
+		let test_fut = async move {
+			assert_matches!(
+				handle.recv().await,
+				AllMessages::NetworkBridge(NetworkBridgeMessage::RegisterEventProducer(..))
+			);
+
+			start_work(&mut handle, hash_a, validators, signing_context).await;
+			handle.send(network_event(NetworkBridgeEvent::OurViewChange(view![hash_a]))).await;
+			handle.send(network_event(NetworkBridgeEvent::PeerConnected(peer_b.clone(), ObservedRole::Full))).await;
+			handle.send(network_event(NetworkBridgeEvent::PeerViewChange(peer_b.clone(), view![hash_a]))).await;
+			assert_matches!(
+				handle.recv().await,
+				AllMessages::NetworkBridge(NetworkBridgeMessage::ReportPeer(_, rep)) => {
+					assert_eq!(rep, GAIN_USEFUL_VIEW_CHANGE);
+				}
+			);
+
+			// four new bitfields in a single message
+			let bytes = VersionedBitfieldGossipMessage::V2(batch).encode();
+			handle.send(network_event(NetworkBridgeEvent::PeerMessage(peer_a.clone(), bytes))).await;
+
+			// only the first two are relayed right away...
+			for relayed in vec![true, true, false, false] {
+				assert_matches!(
+					handle.recv().await,
+					AllMessages::NetworkBridge(NetworkBridgeMessage::ReportPeer(peer, rep)) => {
+						assert_eq!(peer, peer_a);
+						assert_eq!(rep, GAIN_VALID_MESSAGE_FIRST);
+					}
+				);
+				assert_matches!(handle.recv().await, AllMessages::Provisioner(_));
+				if relayed {
+					assert_matches!(
+						handle.recv().await,
+						AllMessages::NetworkBridge(NetworkBridgeMessage::SendMessage(peers, _, _)) => {
+							assert_eq!(peers, vec![peer_b.clone()]);
+						}
+					);
+				}
+			}
+
+			// ...the others in the following iteration
+			for _ in 0..2 {
+				assert_matches!(
+					handle.recv().await,
+					AllMessages::NetworkBridge(NetworkBridgeMessage::SendMessage(peers, _, _)) => {
+						assert_eq!(peers, vec![peer_b.clone()]);
+					}
+				);
+			}
+
+			handle.send(FromOverseer::Signal(OverseerSignal::Conclude)).await;
+		};
+
+		executor::block_on(future::join(test_fut, subsystem));
+	}
+
+	#[test]
+	fn deferred_relays_are_flushed_on_conclude() {
+		let hash_a: Hash = [0; 32].into();
+		let peer_a = PeerId::random();
+		let peer_b = PeerId::random();
+
+		let signing_context = SigningContext {
+			session_index: 1,
+			parent_hash: hash_a,
+		};
+
+		let keyrings = [
+			Sr25519Keyring::Alice,
+			Sr25519Keyring::Bob,
+			Sr25519Keyring::Charlie,
+			Sr25519Keyring::Dave,
+		];
+		let validators: Vec<ValidatorId> = keyrings.iter().map(|k| k.public().into()).collect();
+
+		let batch: Vec<_> = keyrings
+			.iter()
+			.enumerate()
+			.map(|(index, keyring)| BitfieldGossipMessage {
+				relay_parent: hash_a,
+				signed_availability: Signed::<AvailabilityBitfield>::sign(
+					AvailabilityBitfield(bitvec![bitvec::order::Lsb0, u8; 1u8; 32]),
+					&signing_context,
+					index as ValidatorIndex,
+					&keyring.pair().into(),
+				),
+			})
+			.collect();
+
+		let pool = sp_core::testing::SpawnBlockingExecutor::new();
+		let (ctx, mut handle) =
+			make_subsystem_context::<BitfieldDistributionMessage, _>(pool);
+
+		let config = Config { max_sends_per_iteration: 1, ..Default::default() };
+		let subsystem = BitfieldDistribution::new(Default::default())
+			.with_config(config)
+			.start(HookedContext::new(ctx).on_recv(yield_once))
+			.future;
+
+		let test_fut = async move {
+			assert_matches!(
+				handle.recv().await,
+				AllMessages::NetworkBridge(NetworkBridgeMessage::RegisterEventProducer(..))
+			);
+
+			start_work(&mut handle, hash_a, validators, signing_context).await;
+			handle.send(network_event(NetworkBridgeEvent::OurViewChange(view![hash_a]))).await;
+			handle.send(network_event(NetworkBridgeEvent::PeerConnected(peer_b.clone(), ObservedRole::Full))).await;
+			handle.send(network_event(NetworkBridgeEvent::PeerViewChange(peer_b.clone(), view![hash_a]))).await;
+			assert_matches!(
+				handle.recv().await,
+				AllMessages::NetworkBridge(NetworkBridgeMessage::ReportPeer(_, rep)) => {
+					assert_eq!(rep, GAIN_USEFUL_VIEW_CHANGE);
+				}
+			);
+
+			// conclude while most of the relays are still deferred
+			let bytes = VersionedBitfieldGossipMessage::V2(batch).encode();
+			handle.send(network_event(NetworkBridgeEvent::PeerMessage(peer_a.clone(), bytes))).await;
+			handle.send(FromOverseer::Signal(OverseerSignal::Conclude)).await;
+
+			// a report and a provisioner message for each, plus the relays
+			let mut relayed = 0;
+			for _ in 0..12 {
+				if let AllMessages::NetworkBridge(NetworkBridgeMessage::SendMessage(peers, _, _)) = handle.recv().await {
+					assert_eq!(peers, vec![peer_b.clone()]);
+					relayed += 1;
+				}
+			}
+			assert_eq!(relayed, 4);
+		};
+
+		executor::block_on(future::join(test_fut, subsystem));
+	}
+
+	#[test]
+	fn deferred_relay_skips_disconnected_peers() {
+		let hash_a: Hash = [0; 32].into();
+		let peer_a = PeerId::random();
+		let peer_b = PeerId::random();
+		let peer_c = PeerId::random();
+
+		let signing_context = SigningContext {
+			session_index: 1,
+			parent_hash: hash_a,
+		};
+
+		let validator: ValidatorId = Sr25519Keyring::Alice.public().into();
+
+		let msg = BitfieldGossipMessage {
+			relay_parent: hash_a,
+			signed_availability: Signed::<AvailabilityBitfield>::sign(
+				AvailabilityBitfield(bitvec![bitvec::order::Lsb0, u8; 1u8; 32]),
+				&signing_context,
+				0,
+				&Sr25519Keyring::Alice.pair().into(),
+			),
+		};
+
+		let pool = sp_core::testing::SpawnBlockingExecutor::new();
+		let (mut ctx, mut handle) =
+			make_subsystem_context::<BitfieldDistributionMessage, _>(pool);
+
+		let mut tracker = prewarmed_tracker(
+			validator.clone(),
+			signing_context,
+			hash_a,
+			peers![peer_a, peer_b, peer_c],
+		);
+		// every relay is deferred
+		tracker.config.max_sends_per_iteration = 0;
+
+		executor::block_on(async move {
+			for event in vec![
+				NetworkBridgeEvent::PeerMessage(peer_a.clone(), msg.encode_versioned()),
+				NetworkBridgeEvent::PeerDisconnected(peer_c.clone()),
+			] {
+				handle_network_msg(&mut ctx, &mut tracker, &Default::default(), &Default::default(), event)
+					.await
+					.unwrap();
+			}
+			assert_eq!(tracker.deferred_sends.len(), 1);
+			while handle.try_recv().is_some() {}
+
+			tracker.config.max_sends_per_iteration = 1;
+			send_deferred_gossip(&mut ctx, &mut tracker, &Default::default()).await.unwrap();
+
+			assert_matches!(
+				handle.recv().await,
+				AllMessages::NetworkBridge(NetworkBridgeMessage::SendMessage(peers, _, bytes)) => {
+					assert_eq!(peers, vec![peer_b.clone()]);
+					assert_eq!(bytes, msg.encode_versioned());
+				}
+			);
+			let sent = &tracker.per_relay_parent[&hash_a].message_sent_to_peer;
+			assert!(sent[&peer_b].contains(&validator));
+			assert!(!sent.contains_key(&peer_c));
+
+			// nothing at all is sent once all of the peers are gone
+			tracker.deferred_sends.push_back(DeferredSend {
+				relay_parent: hash_a,
+				validator: validator.clone(),
+				peers: vec![peer_c.clone()],
+				bytes: msg.encode_versioned(),
+			});
+			flush_deferred_gossip(&mut ctx, &mut tracker, &Default::default()).await.unwrap();
+			assert!(timeout(handle.recv(), TIMEOUT).await.is_none());
+		});
+	}
+
+	#[test]
+	fn oldest_deferred_relay_is_dropped_beyond_max_deferred_sends() {
+		let hash_a: Hash = [0; 32].into();
+		let peer_a = PeerId::random();
+
+		let signing_context = SigningContext {
+			session_index: 1,
+			parent_hash: hash_a,
+		};
+
+		let validator: ValidatorId = Sr25519Keyring::Alice.public().into();
+
+		let pool = sp_core::testing::SpawnBlockingExecutor::new();
+		let (mut ctx, mut handle) =
+			make_subsystem_context::<BitfieldDistributionMessage, _>(pool);
+
+		let mut tracker = prewarmed_tracker(validator.clone(), signing_context, hash_a, peers![peer_a]);
+		// every relay is deferred
+		tracker.config.max_sends_per_iteration = 0;
+		tracker.config.max_deferred_sends = 2;
+
+		executor::block_on(async move {
+			for bytes in vec![vec![1], vec![2], vec![3]] {
+				let deferred = DeferredSend {
+					relay_parent: hash_a,
+					validator: validator.clone(),
+					peers: vec![peer_a.clone()],
+					bytes,
+				};
+				send_gossip(&mut ctx, &mut tracker, &Default::default(), deferred)
+					.await
+					.unwrap();
+			}
+			assert!(handle.try_recv().is_none());
+			// the receivers of the dropped one catch up on its relay parent instead
+			assert!(tracker.pending_catch_up[&peer_a].contains(&hash_a));
+
+			flush_deferred_gossip(&mut ctx, &mut tracker, &Default::default()).await.unwrap();
+			for expected in vec![vec![2], vec![3]] {
+				assert_matches!(
+					handle.recv().await,
+					AllMessages::NetworkBridge(NetworkBridgeMessage::SendMessage(_, _, bytes)) => {
+						assert_eq!(bytes, expected);
+					}
+				);
+			}
+			assert!(handle.try_recv().is_none());
+		});
+	}
+
+	#[test]
+	fn dropped_deferred_relay_is_sent_on_catch_up() {
+		let hash_a: Hash = [0; 32].into();
+		let peer_a = PeerId::random();
+		let peer_b = PeerId::random();
+
+		let signing_context = SigningContext {
+			session_index: 1,
+			parent_hash: hash_a,
+		};
+
+		let validator_a: ValidatorId = Sr25519Keyring::Alice.public().into();
+		let validator_b: ValidatorId = Sr25519Keyring::Bob.public().into();
+
+		let msgs: Vec<_> = vec![Sr25519Keyring::Alice, Sr25519Keyring::Bob]
+			.into_iter()
+			.enumerate()
+			.map(|(index, keyring)| BitfieldGossipMessage {
+				relay_parent: hash_a,
+				signed_availability: Signed::<AvailabilityBitfield>::sign(
+					AvailabilityBitfield(bitvec![bitvec::order::Lsb0, u8; 1u8; 32]),
+					&signing_context,
+					index as ValidatorIndex,
+					&keyring.pair().into(),
+				),
+			})
+			.collect();
+
+		let pool = sp_core::testing::SpawnBlockingExecutor::new();
+		let (mut ctx, mut handle) =
+			make_subsystem_context::<BitfieldDistributionMessage, _>(pool);
+
+		let mut tracker = prewarmed_tracker(
+			validator_a.clone(),
+			signing_context,
+			hash_a,
+			peers![peer_a, peer_b],
+		);
+		tracker.per_relay_parent.get_mut(&hash_a).unwrap().validator_set =
+			Arc::new(vec![validator_a.clone(), validator_b.clone()]);
+		// every relay is deferred, and only the latest one is kept
+		tracker.config.max_sends_per_iteration = 0;
+		tracker.config.max_deferred_sends = 1;
+
+		executor::block_on(async move {
+			for msg in msgs.iter() {
+				handle_network_msg(
+					&mut ctx,
+					&mut tracker,
+					&Default::default(),
+					&Default::default(),
+					NetworkBridgeEvent::PeerMessage(peer_b.clone(), msg.encode_versioned()),
+				)
+				.await
+				.unwrap();
+			}
+			while handle.try_recv().is_some() {}
+
+			assert_eq!(tracker.deferred_sends.len(), 1);
+			let sent = &tracker.per_relay_parent[&hash_a].message_sent_to_peer[&peer_a];
+			assert!(!sent.contains(&validator_a));
+			assert!(sent.contains(&validator_b));
+
+			continue_catch_up(&mut ctx, &mut tracker).await.unwrap();
+			assert_matches!(
+				handle.recv().await,
+				AllMessages::NetworkBridge(NetworkBridgeMessage::SendMessage(peers, _, bytes)) => {
+					assert_eq!(peers, vec![peer_a.clone()]);
+					assert_eq!(bytes, msgs[0].encode_versioned());
+				}
+			);
+			assert!(handle.try_recv().is_none());
+			assert!(tracker.pending_catch_up.is_empty());
+			assert!(tracker.per_relay_parent[&hash_a].message_sent_to_peer[&peer_a].contains(&validator_a));
+		});
+	}
+
+	#[test]
+	fn stop_work_is_processed_while_relays_are_queued_for_a_slow_bridge() {
+		let hash_a: Hash = [0; 32].into();
+		let peer_a = PeerId::random();
+		let peer_b = PeerId::random();
+
+		let signing_context = SigningContext {
+			session_index: 1,
+			parent_hash: hash_a,
+		};
+
+		let keyrings = [
+			Sr25519Keyring::Alice,
+			Sr25519Keyring::Bob,
+			Sr25519Keyring::Charlie,
+			Sr25519Keyring::Dave,
+		];
+		let validators: Vec<ValidatorId> = keyrings.iter().map(|k| k.public().into()).collect();
+
+		let batch: Vec<_> = keyrings
+			.iter()
+			.enumerate()
+			.map(|(index, keyring)| BitfieldGossipMessage {
+				relay_parent: hash_a,
+				signed_availability: Signed::<AvailabilityBitfield>::sign(
+					AvailabilityBitfield(bitvec![bitvec::order::Lsb0, u8; 1u8; 32]),
+					&signing_context,
+					index as ValidatorIndex,
+					&keyring.pair().into(),
+				),
+			})
+			.collect();
+
+		let pool = sp_core::testing::SpawnBlockingExecutor::new();
+		let (ctx, mut handle) =
+			make_subsystem_context::<BitfieldDistributionMessage, _>(pool);
+		let ctx = HookedContext::new(ctx).on_send(|msg| match msg {
+			AllMessages::NetworkBridge(NetworkBridgeMessage::SendMessage(..)) => Hooked::Delayed(Duration::from_millis(50)),
+			_ => Hooked::Sent,
+		});
+
+		let config = Config { max_sends_per_iteration: 1, ..Default::default() };
+		let subsystem = BitfieldDistribution::new(Default::default())
+			.with_config(config)
+			.start(ctx)
+			.future;
+
+		let test_fut = async move {
+			assert_matches!(
+				handle.recv().await,
+				AllMessages::NetworkBridge(NetworkBridgeMessage::RegisterEventProducer(..))
+			);
+
+			start_work(&mut handle, hash_a, validators, signing_context).await;
+			handle.send(network_event(NetworkBridgeEvent::OurViewChange(view![hash_a]))).await;
+			handle.send(network_event(NetworkBridgeEvent::PeerConnected(peer_b.clone(), ObservedRole::Full))).await;
+			handle.send(network_event(NetworkBridgeEvent::PeerViewChange(peer_b.clone(), view![hash_a]))).await;
+			assert_matches!(
+				handle.recv().await,
+				AllMessages::NetworkBridge(NetworkBridgeMessage::ReportPeer(_, rep)) => {
+					assert_eq!(rep, GAIN_USEFUL_VIEW_CHANGE);
+				}
+			);
+
+			// four relays, of which all but one are queued...
+			let bytes = VersionedBitfieldGossipMessage::V2(batch).encode();
+			handle.send(network_event(NetworkBridgeEvent::PeerMessage(peer_a.clone(), bytes))).await;
+			handle.send(FromOverseer::Signal(OverseerSignal::StopWork(hash_a))).await;
+			let (tx, rx) = oneshot::channel();
+			handle.send(FromOverseer::Communication {
+				msg: BitfieldDistributionMessage::Status(tx),
+			}).await;
+
+			// ...while the signal is processed already
+			let report = timeout(rx, TIMEOUT).await.unwrap().unwrap();
+			assert_eq!(report.relay_parents, 0);
+			assert!(report.processing);
+
+			let mut relayed = 0;
+			while relayed < 4 {
+				if let AllMessages::NetworkBridge(NetworkBridgeMessage::SendMessage(peers, _, _)) =
+					handle.recv().await
+				{
+					assert_eq!(peers, vec![peer_b.clone()]);
+					relayed += 1;
+				}
+			}
+
+			handle.send(FromOverseer::Signal(OverseerSignal::Conclude)).await;
+		};
+
+		executor::block_on(future::join(test_fut, subsystem));
+	}
+
+	#[test]
+	fn gossip_proceeds_while_the_provisioner_is_stuck() {
+		let hash_a: Hash = [0; 32].into();
+		let peer_b = PeerId::random();
+
+		let signing_context = SigningContext {
+			session_index: 1,
+			parent_hash: hash_a,
+		};
+
+		let validator: ValidatorId = Sr25519Keyring::Alice.public().into();
+
+		let mut bits = bitvec![bitvec::order::Lsb0, u8; 0u8; 32];
+		bits.set(0, true);
+		let sign = |bits| BitfieldGossipMessage {
+			relay_parent: hash_a,
+			signed_availability: Signed::<AvailabilityBitfield>::sign(
+				AvailabilityBitfield(bits),
+				&signing_context,
+				0,
+				&Sr25519Keyring::Alice.pair().into(),
+			),
+		};
+		// our own bitfield, updated while the first one is still buffered
+		let partial = sign(bits);
+		let superseding = sign(bitvec![bitvec::order::Lsb0, u8; 1u8; 32]);
+
+		let registry = Registry::new();
+		let metrics = Metrics::register(Some(&registry)).unwrap();
+
+		let pool = sp_core::testing::SpawnBlockingExecutor::new();
+		let (ctx, mut handle) =
+			make_subsystem_context::<BitfieldDistributionMessage, _>(pool);
+		let stuck = Arc::new(AtomicBool::new(true));
+		let attempts = Arc::new(AtomicUsize::new(0));
+		let ctx = {
+			let (stuck, attempts) = (stuck.clone(), attempts.clone());
+			// never any room for bitfields to the provisioner while stuck
+			HookedContext::new(ctx).on_send(move |msg| match msg {
+				AllMessages::Provisioner(_) => {
+					attempts.fetch_add(1, Ordering::SeqCst);
+					if stuck.load(Ordering::SeqCst) {
+						Hooked::Full
+					} else {
+						Hooked::Sent
+					}
+				}
+				_ => Hooked::Sent,
+			})
+		};
+
+		let provisioner_retry_interval = Config::default().provisioner_retry_interval;
+		let subsystem = BitfieldDistribution::new(metrics)
+			.start(ctx)
+			.future;
+
+		let test_fut = async move {
+			assert_matches!(
+				handle.recv().await,
+				AllMessages::NetworkBridge(NetworkBridgeMessage::RegisterEventProducer(..))
+			);
+
+			start_work(&mut handle, hash_a, vec![validator], signing_context.clone()).await;
+			handle.send(network_event(NetworkBridgeEvent::OurViewChange(view![hash_a]))).await;
+			handle.send(network_event(NetworkBridgeEvent::PeerConnected(peer_b.clone(), ObservedRole::Full))).await;
+			handle.send(network_event(NetworkBridgeEvent::PeerViewChange(peer_b.clone(), view![hash_a]))).await;
+			assert_matches!(
+				handle.recv().await,
+				AllMessages::NetworkBridge(NetworkBridgeMessage::ReportPeer(_, rep)) => {
+					assert_eq!(rep, GAIN_USEFUL_VIEW_CHANGE);
+				}
+			);
+
+			// both relayed regardless, the superseding one replacing the buffered one
+			for (gossip, update) in vec![(&partial, false), (&superseding, true)] {
+				let signed_availability = gossip.signed_availability.clone();
+				let msg = if update {
+					BitfieldDistributionMessage::UpdateOwnBitfield(hash_a, signed_availability, None)
+				} else {
+					BitfieldDistributionMessage::DistributeBitfield(hash_a, signed_availability, None)
+				};
+				handle.send(FromOverseer::Communication { msg }).await;
+				assert_matches!(
+					handle.recv().await,
+					AllMessages::NetworkBridge(NetworkBridgeMessage::SendMessage(peers, _, bytes)) => {
+						assert_eq!(peers, vec![peer_b.clone()]);
+						assert_eq!(bytes, gossip.encode_versioned());
+					}
+				);
+			}
+			assert_eq!(counter_value(&registry, "parachain_bitfield_provisions_dropped_total", None), 1);
+
+			// retried on a timer rather than in a busy loop
+			let before = attempts.load(Ordering::SeqCst);
+			Delay::new(provisioner_retry_interval * 2).await;
+			assert!(attempts.load(Ordering::SeqCst) - before <= 3);
+
+			// handed over, and only once, once the provisioner catches up
+			stuck.store(false, Ordering::SeqCst);
+			assert_matches!(
+				handle.recv().await,
+				AllMessages::Provisioner(ProvisionerMessage::ProvisionableData(
+					ProvisionableData::Bitfield(hash, signed)
+				)) => {
+					assert_eq!(hash, hash_a);
+					assert_eq!(signed, superseding.signed_availability);
+				}
+			);
+			await_quiescence(&mut handle).await;
+			assert_matches!(handle.try_recv(), None);
+
+			handle.send(FromOverseer::Signal(OverseerSignal::Conclude)).await;
+		};
+
+		executor::block_on(future::join(test_fut, subsystem));
+	}
+
+	#[test]
+	fn quiescence_is_reached_once_deferred_relays_are_sent() {
+		let hash_a: Hash = [0; 32].into();
+		let peer_a = PeerId::random();
+		let peer_b = PeerId::random();
+
+		let signing_context = SigningContext {
+			session_index: 1,
+			parent_hash: hash_a,
+		};
+
+		let keyrings = [
+			Sr25519Keyring::Alice,
+			Sr25519Keyring::Bob,
+			Sr25519Keyring::Charlie,
+			Sr25519Keyring::Dave,
+		];
+		let validators: Vec<ValidatorId> = keyrings.iter().map(|k| k.public().into()).collect();
+
+		let batch: Vec<_> = keyrings
+			.iter()
+			.enumerate()
+			.map(|(index, keyring)| BitfieldGossipMessage {
+				relay_parent: hash_a,
+				signed_availability: Signed::<AvailabilityBitfield>::sign(
+					AvailabilityBitfield(bitvec![bitvec::order::Lsb0, u8; 1u8; 32]),
+					&signing_context,
+					index as ValidatorIndex,
+					&keyring.pair().into(),
+				),
+			})
+			.collect();
+
+		let pool = sp_core::testing::SpawnBlockingExecutor::new();
+		let (ctx, mut handle) =
+			make_subsystem_context::<BitfieldDistributionMessage, _>(pool);
+
+		let config = Config { max_sends_per_iteration: 1, ..Default::default() };
+		let subsystem = BitfieldDistribution::new(Default::default())
+			.with_config(config)
+			.start(HookedContext::new(ctx).on_recv(yield_once))
+			.future;
+
+		let test_fut = async move {
+			assert_matches!(
+				handle.recv().await,
+				AllMessages::NetworkBridge(NetworkBridgeMessage::RegisterEventProducer(..))
+			);
+
+			start_work(&mut handle, hash_a, validators, signing_context).await;
+			handle.send(network_event(NetworkBridgeEvent::OurViewChange(view![hash_a]))).await;
+			handle.send(network_event(NetworkBridgeEvent::PeerConnected(peer_b.clone(), ObservedRole::Full))).await;
+			handle.send(network_event(NetworkBridgeEvent::PeerViewChange(peer_b.clone(), view![hash_a]))).await;
+
+			// query while most of the relays are still deferred
+			let bytes = VersionedBitfieldGossipMessage::V2(batch).encode();
+			handle.send(network_event(NetworkBridgeEvent::PeerMessage(peer_a.clone(), bytes))).await;
+			await_quiescence(&mut handle).await;
+
+			// everything was sent by now, no need to wait for anything
+			let mut messages = Vec::new();
+			while let Some(message) = handle.try_recv() {
+				messages.push(message);
+			}
+			// the view change reward, then a report and a provisioner message for each, plus the relays
+			assert_eq!(messages.len(), 13);
+			let relayed = messages
+				.iter()
+				.filter(|message| matches!(
+					message,
+					AllMessages::NetworkBridge(NetworkBridgeMessage::SendMessage(peers, _, _))
+						if peers == &vec![peer_b.clone()]
+				))
+				.count();
+			assert_eq!(relayed, 4);
+
+			handle.send(FromOverseer::Signal(OverseerSignal::Conclude)).await;
+		};
+
+		executor::block_on(future::join(test_fut, subsystem));
+	}
+
+	#[test]
+	fn relays_of_peer_bitfields_are_jittered() {
+		let hash_a: Hash = [0; 32].into();
+		let peer_a = PeerId::random();
+		let peer_b = PeerId::random();
+
+		let signing_context = SigningContext {
+			session_index: 1,
+			parent_hash: hash_a,
+		};
+
+		let validators: Vec<ValidatorId> = vec![
+			Sr25519Keyring::Alice.public().into(),
+			Sr25519Keyring::Bob.public().into(),
+		];
+
+		let signed = |validator_index: ValidatorIndex, keyring: Sr25519Keyring| {
+			Signed::<AvailabilityBitfield>::sign(
+				AvailabilityBitfield(bitvec![bitvec::order::Lsb0, u8; 1u8; 32]),
+				&signing_context,
+				validator_index,
+				&keyring.pair().into(),
+			)
+		};
+		let own = signed(0, Sr25519Keyring::Alice);
+		let msg = BitfieldGossipMessage {
+			relay_parent: hash_a,
+			signed_availability: signed(1, Sr25519Keyring::Bob),
+		};
+
+		let pool = sp_core::testing::SpawnBlockingExecutor::new();
+		let (ctx, mut handle) =
+			make_subsystem_context::<BitfieldDistributionMessage, _>(pool);
+
+		// the first draw of the seeded randomness delays the relay long enough to notice
+		let rng_seed = [0; 32];
+		let max_relay_jitter = Duration::from_millis(200);
+		let jitter = max_relay_jitter.mul_f64(ChaChaRng::from_seed(rng_seed).gen());
+		assert!(jitter > max_relay_jitter / 4);
+
+		let config = Config {
+			max_relay_jitter,
+			rng_seed: Some(rng_seed),
+			..Default::default()
+		};
+		let subsystem = BitfieldDistribution::new(Default::default())
+			.with_config(config)
+			.start(ctx)
+			.future;
+
+		let test_fut = async move {
+			assert_matches!(
+				handle.recv().await,
+				AllMessages::NetworkBridge(NetworkBridgeMessage::RegisterEventProducer(..))
+			);
+
+			start_work(&mut handle, hash_a, validators, signing_context).await;
+			handle.send(network_event(NetworkBridgeEvent::OurViewChange(view![hash_a]))).await;
+			handle.send(network_event(NetworkBridgeEvent::PeerConnected(peer_b.clone(), ObservedRole::Full))).await;
+			handle.send(network_event(NetworkBridgeEvent::PeerViewChange(peer_b.clone(), view![hash_a]))).await;
+			handle.send(network_event(NetworkBridgeEvent::PeerMessage(peer_a.clone(), msg.encode_versioned()))).await;
+			handle.send(FromOverseer::Communication {
+				msg: BitfieldDistributionMessage::DistributeBitfield(hash_a, own.clone(), None),
+			}).await;
+			await_quiescence(&mut handle).await;
+
+			// both are provisioned right away, but only our own is relayed yet
+			let mut provisioned = Vec::new();
+			let mut relayed = Vec::new();
+			while let Some(message) = handle.try_recv() {
+				match message {
+					AllMessages::Provisioner(ProvisionerMessage::ProvisionableData(
+						ProvisionableData::Bitfield(_, bitfield),
+					)) => provisioned.push(bitfield),
+					AllMessages::NetworkBridge(NetworkBridgeMessage::SendMessage(peers, _, bytes)) => {
+						relayed.push((peers, bytes))
+					}
+					_ => {}
+				}
+			}
+			assert_eq!(provisioned, vec![msg.signed_availability.clone(), own.clone()]);
+			let own_message = BitfieldGossipMessage { relay_parent: hash_a, signed_availability: own };
+			assert_eq!(relayed, vec![(vec![peer_b.clone()], own_message.encode_versioned())]);
+
+			// the other one follows
+			assert_matches!(
+				timeout(handle.recv(), TIMEOUT).await,
+				Some(AllMessages::NetworkBridge(NetworkBridgeMessage::SendMessage(peers, _, bytes))) => {
+					assert_eq!(peers, vec![peer_b.clone()]);
+					assert_eq!(bytes, msg.encode_versioned());
+				}
+			);
+
+			handle.send(FromOverseer::Signal(OverseerSignal::Conclude)).await;
+		};
+
+		executor::block_on(future::join(test_fut, subsystem));
+	}
+
+	#[test]
+	fn late_start_work_is_ignored() {
+		let hash_a: Hash = [0; 32].into();
+
+		let pool = sp_core::testing::SpawnBlockingExecutor::new();
+		let (ctx, mut handle) =
+			make_subsystem_context::<BitfieldDistributionMessage, _>(pool);
+
+		let subsystem = BitfieldDistribution::new(Default::default()).start(ctx).future;
+
+		let test_fut = async move {
+			assert_matches!(
+				handle.recv().await,
+				AllMessages::NetworkBridge(NetworkBridgeMessage::RegisterEventProducer(..))
+			);
+
+			// reordered signals of a relay parent we never worked on
+			handle.send(FromOverseer::Signal(OverseerSignal::StopWork(hash_a))).await;
+			handle.send(FromOverseer::Signal(OverseerSignal::StartWork(hash_a))).await;
+
+			let (tx, rx) = oneshot::channel();
+			handle.send(FromOverseer::Communication {
+				msg: BitfieldDistributionMessage::QueryCoverage(hash_a, tx),
+			}).await;
+			assert_eq!(timeout(rx, TIMEOUT).await, Some(Ok(None)));
+			// not even the runtime API was queried
+			assert!(handle.try_recv().is_none());
+
+			handle.send(FromOverseer::Signal(OverseerSignal::Conclude)).await;
+		};
+
+		executor::block_on(future::join(test_fut, subsystem));
+	}
+
+	#[test]
+	fn replayed_bitfield_of_restarted_relay_parent_is_ignored() {
+		let hash_a: Hash = [0; 32].into();
+		let peer_a = PeerId::random();
+		let peer_b = PeerId::random();
+
+		let signing_context = SigningContext {
+			session_index: 1,
+			parent_hash: hash_a,
+		};
+
+		let validator: ValidatorId = Sr25519Keyring::Alice.public().into();
+
+		let msg = BitfieldGossipMessage {
+			relay_parent: hash_a,
+			signed_availability: Signed::<AvailabilityBitfield>::sign(
+				AvailabilityBitfield(bitvec![bitvec::order::Lsb0, u8; 1u8; 32]),
+				&signing_context,
+				0,
+				&Sr25519Keyring::Alice.pair().into(),
+			),
+		};
+
+		let pool = sp_core::testing::SpawnBlockingExecutor::new();
+		let (ctx, mut handle) =
+			make_subsystem_context::<BitfieldDistributionMessage, _>(pool);
+
+		// worked on again right away
+		let config = Config { stopped_grace_period: Duration::from_secs(0), ..Default::default() };
+		let subsystem = BitfieldDistribution::new(Default::default()).with_config(config).start(ctx).future;
+
+		let test_fut = async move {
+			assert_matches!(
+				handle.recv().await,
+				AllMessages::NetworkBridge(NetworkBridgeMessage::RegisterEventProducer(..))
+			);
+
+			start_work(&mut handle, hash_a, vec![validator.clone()], signing_context.clone()).await;
+			handle.send(network_event(NetworkBridgeEvent::OurViewChange(view![hash_a]))).await;
+			for peer in vec![peer_a.clone(), peer_b.clone()] {
+				handle.send(network_event(NetworkBridgeEvent::PeerConnected(peer.clone(), ObservedRole::Full))).await;
+				handle.send(network_event(NetworkBridgeEvent::PeerViewChange(peer, view![hash_a]))).await;
+			}
+			await_quiescence(&mut handle).await;
+			while handle.try_recv().is_some() {}
+
+			handle.send(network_event(
+				NetworkBridgeEvent::PeerMessage(peer_a.clone(), msg.encode_versioned()),
+			)).await;
+			await_quiescence(&mut handle).await;
+			let mut relayed = false;
+			while let Some(message) = handle.try_recv() {
+				if let AllMessages::NetworkBridge(NetworkBridgeMessage::SendMessage(peers, _, _)) = message {
+					assert_eq!(peers, vec![peer_b.clone()]);
+					relayed = true;
+				}
+			}
+			assert!(relayed);
+
+			handle.send(FromOverseer::Signal(OverseerSignal::StopWork(hash_a))).await;
+			start_work(&mut handle, hash_a, vec![validator], signing_context).await;
+			await_quiescence(&mut handle).await;
+
+			// neither provisioned nor relayed again, and not penalized either
+			handle.send(network_event(
+				NetworkBridgeEvent::PeerMessage(peer_a.clone(), msg.encode_versioned()),
+			)).await;
+			await_quiescence(&mut handle).await;
+			assert!(handle.try_recv().is_none());
+
+			handle.send(FromOverseer::Signal(OverseerSignal::Conclude)).await;
+		};
+
+		executor::block_on(future::join(test_fut, subsystem));
+	}
+
+	#[test]
+	fn own_bitfield_is_held_back_until_enough_peers_connect() {
+		let hash_a: Hash = [0; 32].into();
+		let peer_a = PeerId::random();
+		let peer_b = PeerId::random();
+		let peer_light = PeerId::random();
+
+		let signing_context = SigningContext {
+			session_index: 1,
+			parent_hash: hash_a,
+		};
+
+		let validator: ValidatorId = Sr25519Keyring::Alice.public().into();
+		let signed = Signed::<AvailabilityBitfield>::sign(
+			AvailabilityBitfield(bitvec![bitvec::order::Lsb0, u8; 1u8; 32]),
+			&signing_context,
+			0,
+			&Sr25519Keyring::Alice.pair().into(),
+		);
+
+		let pool = sp_core::testing::SpawnBlockingExecutor::new();
+		let (ctx, mut handle) =
+			make_subsystem_context::<BitfieldDistributionMessage, _>(pool);
+
+		let config = Config { min_peers_for_own_bitfields: 2, ..Default::default() };
+		let subsystem = BitfieldDistribution::new(Default::default()).with_config(config).start(ctx).future;
+
+		let test_fut = async move {
+			assert_matches!(
+				handle.recv().await,
+				AllMessages::NetworkBridge(NetworkBridgeMessage::RegisterEventProducer(..))
+			);
+
+			start_work(&mut handle, hash_a, vec![validator], signing_context).await;
+			handle.send(network_event(NetworkBridgeEvent::OurViewChange(view![hash_a]))).await;
+
+			// a light client doesn't count
+			let connected = vec![
+				(peer_a.clone(), ObservedRole::Full),
+				(peer_light.clone(), ObservedRole::Light),
+			];
+			for (peer, role) in connected {
+				handle.send(network_event(NetworkBridgeEvent::PeerConnected(peer.clone(), role))).await;
+				handle.send(network_event(NetworkBridgeEvent::PeerViewChange(peer, view![hash_a]))).await;
+			}
+			handle.send(FromOverseer::Communication {
+				msg: BitfieldDistributionMessage::DistributeBitfield(hash_a, signed.clone(), None),
+			}).await;
+			await_quiescence(&mut handle).await;
+
+			// provisioned, but not sent to anyone yet
+			let mut provisioned = false;
+			while let Some(message) = handle.try_recv() {
+				match message {
+					AllMessages::Provisioner(ProvisionerMessage::ProvisionableData(
+						ProvisionableData::Bitfield(_, bitfield),
+					)) => {
+						assert_eq!(bitfield, signed);
+						provisioned = true;
+					}
+					AllMessages::NetworkBridge(NetworkBridgeMessage::SendMessage(..)) => {
+						panic!("our bitfield was sent with too few peers connected")
+					}
+					_ => {}
+				}
+			}
+			assert!(provisioned);
+
+			handle.send(network_event(NetworkBridgeEvent::PeerConnected(peer_b.clone(), ObservedRole::Full))).await;
+			handle.send(network_event(NetworkBridgeEvent::PeerViewChange(peer_b.clone(), view![hash_a]))).await;
+			await_quiescence(&mut handle).await;
+
+			let mut receivers = HashSet::new();
+			while let Some(message) = handle.try_recv() {
+				if let AllMessages::NetworkBridge(NetworkBridgeMessage::SendMessage(peers, _, _)) = message {
+					receivers.extend(peers);
+				}
+			}
+			assert_eq!(receivers, vec![peer_a.clone(), peer_b.clone()].into_iter().collect());
+
+			handle.send(FromOverseer::Signal(OverseerSignal::Conclude)).await;
+		};
+
+		executor::block_on(future::join(test_fut, subsystem));
+	}
+
+	#[test]
+	fn active_leaves_are_updated_at_once() {
+		let hash_a: Hash = [0; 32].into();
+		let hash_b: Hash = [1; 32].into();
+		let hash_c: Hash = [2; 32].into();
+
+		let validator: ValidatorId = Sr25519Keyring::Alice.public().into();
+		let signing_context = |parent_hash| SigningContext { session_index: 1, parent_hash };
+
+		let pool = sp_core::testing::SpawnBlockingExecutor::new();
+		let (ctx, mut handle) =
+			make_subsystem_context::<BitfieldDistributionMessage, _>(pool);
+
+		let subsystem = BitfieldDistribution::new(Default::default()).start(ctx).future;
+
+		let test_fut = async move {
+			assert_matches!(
+				handle.recv().await,
+				AllMessages::NetworkBridge(NetworkBridgeMessage::RegisterEventProducer(..))
+			);
+
+			start_work(&mut handle, hash_c, vec![validator.clone()], signing_context(hash_c)).await;
+
+			handle.send(FromOverseer::Signal(OverseerSignal::ActiveLeaves(ActiveLeavesUpdate {
+				activated: vec![hash_a, hash_b],
+				deactivated: vec![hash_c],
+			}))).await;
+			for hash in vec![hash_a, hash_b] {
+				expect_basics_queries(&mut handle, hash, vec![validator.clone()], signing_context(hash)).await;
+			}
+
+			for (hash, tracked) in vec![(hash_a, true), (hash_b, true), (hash_c, false)] {
+				let (tx, rx) = oneshot::channel();
+				handle.send(FromOverseer::Communication {
+					msg: BitfieldDistributionMessage::QueryCoverage(hash, tx),
+				}).await;
+				assert_matches!(timeout(rx, TIMEOUT).await, Some(Ok(coverage)) => {
+					assert_eq!(coverage.is_some(), tracked);
+				});
+			}
+
+			// stopped as if by `StopWork`, so a late start of it is ignored as well
+			handle.send(FromOverseer::Signal(OverseerSignal::StartWork(hash_c))).await;
+			await_quiescence(&mut handle).await;
+			assert!(handle.try_recv().is_none());
+
+			handle.send(FromOverseer::Signal(OverseerSignal::Conclude)).await;
+		};
+
+		executor::block_on(future::join(test_fut, subsystem));
+	}
+
+	#[test]
+	fn query_peer_view() {
+		let hash_a: Hash = [0; 32].into();
+		let hash_b: Hash = [1; 32].into();
+
+		let peer_a = PeerId::random();
+		let peer_b = PeerId::random();
+
+		let pool = sp_core::testing::SpawnBlockingExecutor::new();
+		let (ctx, mut handle) =
+			make_subsystem_context::<BitfieldDistributionMessage, _>(pool);
+
+		let subsystem = BitfieldDistribution::new(Default::default()).start(ctx).future;
+
+		let test_fut = async move {
+			assert_matches!(
+				handle.recv().await,
+				AllMessages::NetworkBridge(NetworkBridgeMessage::RegisterEventProducer(..))
+			);
+
+			handle.send(network_event(NetworkBridgeEvent::PeerConnected(peer_a.clone(), ObservedRole::Full))).await;
+			handle.send(network_event(NetworkBridgeEvent::PeerViewChange(peer_a.clone(), view![hash_a, hash_b]))).await;
+
+			let (tx, rx) = oneshot::channel();
+			handle.send(FromOverseer::Communication {
+				msg: BitfieldDistributionMessage::QueryPeerView(peer_a.clone(), tx),
+			}).await;
+			assert_eq!(timeout(rx, TIMEOUT).await, Some(Ok(Some(view![hash_a, hash_b]))));
+
+			let (tx, rx) = oneshot::channel();
+			handle.send(FromOverseer::Communication {
+				msg: BitfieldDistributionMessage::QueryPeerView(peer_b.clone(), tx),
+			}).await;
+			assert_eq!(timeout(rx, TIMEOUT).await, Some(Ok(None)));
+
+			handle.send(FromOverseer::Signal(OverseerSignal::Conclude)).await;
+		};
+
+		executor::block_on(future::join(test_fut, subsystem));
+	}
+
+	#[test]
+	fn duplicate_start_work_keeps_state() {
+		let hash_a: Hash = [0; 32].into();
+
+		let signing_context = SigningContext {
+			session_index: 1,
+			parent_hash: hash_a,
+		};
+
+		let validators: Vec<ValidatorId> = vec![
+			Sr25519Keyring::Alice.public().into(),
+			Sr25519Keyring::Bob.public().into(),
+		];
+
+		let signed = Signed::<AvailabilityBitfield>::sign(
+			AvailabilityBitfield(bitvec![bitvec::order::Lsb0, u8; 1u8; 32]),
+			&signing_context,
+			0,
+			&Sr25519Keyring::Alice.pair().into(),
+		);
+
+		let pool = sp_core::testing::SpawnBlockingExecutor::new();
+		let (ctx, mut handle) =
+			make_subsystem_context::<BitfieldDistributionMessage, _>(pool);
+
+		let subsystem = BitfieldDistribution::new(Default::default()).start(ctx).future;
+
+		let test_fut = async move {
+			assert_matches!(
+				handle.recv().await,
+				AllMessages::NetworkBridge(NetworkBridgeMessage::RegisterEventProducer(..))
+			);
+
+			start_work(&mut handle, hash_a, validators, signing_context).await;
+			handle.send(FromOverseer::Communication {
+				msg: BitfieldDistributionMessage::DistributeBitfield(hash_a, signed, None),
+			}).await;
+			assert_matches!(
+				timeout(handle.recv(), TIMEOUT).await,
+				Some(AllMessages::Provisioner(_))
+			);
+
+			// no runtime API requests for the second one
+			handle.send(FromOverseer::Signal(OverseerSignal::StartWork(hash_a))).await;
+			assert!(timeout(handle.recv(), TIMEOUT).await.is_none());
+
+			let (tx, rx) = oneshot::channel();
+			handle.send(FromOverseer::Communication {
+				msg: BitfieldDistributionMessage::QueryCoverage(hash_a, tx),
+			}).await;
+			assert_eq!(
+				timeout(rx, TIMEOUT).await,
+				Some(Ok(Some(CoverageReport { validators: 2, missing: vec![1] }))),
+			);
+
+			handle.send(FromOverseer::Signal(OverseerSignal::Conclude)).await;
+		};
+
+		executor::block_on(future::join(test_fut, subsystem));
+	}
+
+	#[test]
+	fn bitfields_of_recently_stopped_relay_parents_are_not_penalized() {
+		let hash_a: Hash = [0; 32].into();
+		let peer_a = PeerId::random();
+
+		let signing_context = SigningContext {
+			session_index: 1,
+			parent_hash: hash_a,
+		};
+
+		let validator: ValidatorId = Sr25519Keyring::Alice.public().into();
+
+		let msg = BitfieldGossipMessage {
+			relay_parent: hash_a,
+			signed_availability: Signed::<AvailabilityBitfield>::sign(
+				AvailabilityBitfield(bitvec![bitvec::order::Lsb0, u8; 1u8; 32]),
+				&signing_context,
+				0,
+				&Sr25519Keyring::Alice.pair().into(),
+			),
+		};
+
+		let pool = sp_core::testing::SpawnBlockingExecutor::new();
+		let (ctx, mut handle) =
+			make_subsystem_context::<BitfieldDistributionMessage, _>(pool);
+
+		let stopped_grace_period = Duration::from_millis(200);
+		let subsystem = BitfieldDistribution::new(Default::default())
+			.with_config(Config { stopped_grace_period, ..Default::default() })
+			.start(ctx)
+			.future;
+
+		let test_fut = async move {
+			assert_matches!(
+				handle.recv().await,
+				AllMessages::NetworkBridge(NetworkBridgeMessage::RegisterEventProducer(..))
+			);
+
+			start_work(&mut handle, hash_a, vec![validator], signing_context).await;
+			handle.send(network_event(NetworkBridgeEvent::OurViewChange(view![hash_a]))).await;
+			handle.send(network_event(
+				NetworkBridgeEvent::PeerConnected(peer_a.clone(), ObservedRole::Full),
+			)).await;
+
+			handle.send(FromOverseer::Signal(OverseerSignal::StopWork(hash_a))).await;
+			handle.send(network_event(NetworkBridgeEvent::OurViewChange(view![]))).await;
+
+			handle.send(network_event(
+				NetworkBridgeEvent::PeerMessage(peer_a.clone(), msg.encode_versioned()),
+			)).await;
+			assert!(timeout(handle.recv(), stopped_grace_period / 2).await.is_none());
+
+			Delay::new(stopped_grace_period).await;
+
+			handle.send(network_event(
+				NetworkBridgeEvent::PeerMessage(peer_a.clone(), msg.encode_versioned()),
+			)).await;
+			assert_matches!(
+				timeout(handle.recv(), TIMEOUT).await,
+				Some(AllMessages::NetworkBridge(
+					NetworkBridgeMessage::ReportPeer(peer, rep)
+				)) => {
+					assert_eq!(peer, peer_a);
+					assert_eq!(rep, COST_NOT_INTERESTED);
+				}
+			);
+
+			handle.send(FromOverseer::Signal(OverseerSignal::Conclude)).await;
+		};
+
+		executor::block_on(future::join(test_fut, subsystem));
+	}
+
+	#[test]
+	fn late_peer_receives_own_bitfield_on_regossip() {
+		let hash_a: Hash = [0; 32].into();
+		let peer_a = PeerId::random();
+
+		let signing_context = SigningContext {
+			session_index: 1,
+			parent_hash: hash_a,
+		};
+
+		let validator: ValidatorId = Sr25519Keyring::Alice.public().into();
+
+		let signed = Signed::<AvailabilityBitfield>::sign(
+			AvailabilityBitfield(bitvec![bitvec::order::Lsb0, u8; 1u8; 32]),
+			&signing_context,
+			0,
+			&Sr25519Keyring::Alice.pair().into(),
+		);
+
+		let pool = sp_core::testing::SpawnBlockingExecutor::new();
+		let (ctx, mut handle) =
+			make_subsystem_context::<BitfieldDistributionMessage, _>(pool);
+
+		let regossip_interval = Duration::from_millis(50);
+		let subsystem = BitfieldDistribution::new(Default::default())
+			.with_config(Config { regossip_interval, ..Default::default() })
+			.start(ctx)
+			.future;
+
+		let test_fut = async move {
+			assert_matches!(
+				handle.recv().await,
+				AllMessages::NetworkBridge(NetworkBridgeMessage::RegisterEventProducer(..))
+			);
+
+			start_work(&mut handle, hash_a, vec![validator], signing_context).await;
+			handle.send(network_event(NetworkBridgeEvent::OurViewChange(view![hash_a]))).await;
+
+			// nobody is around to receive it
+			handle.send(FromOverseer::Communication {
+				msg: BitfieldDistributionMessage::DistributeBitfield(hash_a, signed.clone(), None),
+			}).await;
+			assert_matches!(
+				timeout(handle.recv(), TIMEOUT).await,
+				Some(AllMessages::Provisioner(_))
+			);
+
+			handle.send(network_event(
+				NetworkBridgeEvent::PeerConnected(peer_a.clone(), ObservedRole::Full),
+			)).await;
+			handle.send(network_event(
+				NetworkBridgeEvent::PeerViewChange(peer_a.clone(), view![hash_a]),
+			)).await;
+			assert_matches!(
+				timeout(handle.recv(), TIMEOUT).await,
+				Some(AllMessages::NetworkBridge(NetworkBridgeMessage::ReportPeer(_, rep))) => {
+					assert_eq!(rep, GAIN_USEFUL_VIEW_CHANGE);
+				}
+			);
+
+			assert_matches!(
+				timeout(handle.recv(), TIMEOUT).await,
+				Some(AllMessages::NetworkBridge(
+					NetworkBridgeMessage::SendMessage(peers, _, bytes)
+				)) => {
+					assert_eq!(peers, vec![peer_a.clone()]);
+					assert_eq!(
+						bytes,
+						BitfieldGossipMessage {
+							relay_parent: hash_a,
+							signed_availability: signed.clone(),
+						}.encode_versioned(),
+					);
+				}
+			);
+
+			// it is not sent again on later ticks
+			assert!(timeout(handle.recv(), regossip_interval * 4).await.is_none());
+
+			handle.send(FromOverseer::Signal(OverseerSignal::Conclude)).await;
+		};
+
+		executor::block_on(future::join(test_fut, subsystem));
+	}
+
+	#[test]
+	fn own_bitfield_echoed_before_distribution_is_not_relayed_twice() {
+		let hash_a: Hash = [0; 32].into();
+		let peer_a = PeerId::random();
+		let peer_b = PeerId::random();
+
+		let signing_context = SigningContext {
+			session_index: 1,
+			parent_hash: hash_a,
+		};
+
+		let validator: ValidatorId = Sr25519Keyring::Alice.public().into();
+
+		let signed = Signed::<AvailabilityBitfield>::sign(
+			AvailabilityBitfield(bitvec![bitvec::order::Lsb0, u8; 1u8; 32]),
+			&signing_context,
+			0,
+			&Sr25519Keyring::Alice.pair().into(),
+		);
+		let msg = BitfieldGossipMessage {
+			relay_parent: hash_a,
+			signed_availability: signed.clone(),
+		};
+
+		let pool = sp_core::testing::SpawnBlockingExecutor::new();
+		let (ctx, mut handle) =
+			make_subsystem_context::<BitfieldDistributionMessage, _>(pool);
+
+		let subsystem = BitfieldDistribution::new(Default::default()).start(ctx).future;
+
+		let test_fut = async move {
+			assert_matches!(
+				handle.recv().await,
+				AllMessages::NetworkBridge(NetworkBridgeMessage::RegisterEventProducer(..))
+			);
+
+			start_work(&mut handle, hash_a, vec![validator], signing_context).await;
+			handle.send(network_event(NetworkBridgeEvent::OurViewChange(view![hash_a]))).await;
+			for peer in vec![peer_a.clone(), peer_b.clone()] {
+				handle.send(network_event(
+					NetworkBridgeEvent::PeerConnected(peer.clone(), ObservedRole::Full),
+				)).await;
+				handle.send(network_event(
+					NetworkBridgeEvent::PeerViewChange(peer, view![hash_a]),
+				)).await;
+				assert_matches!(
+					timeout(handle.recv(), TIMEOUT).await,
+					Some(AllMessages::NetworkBridge(NetworkBridgeMessage::ReportPeer(_, rep))) => {
+						assert_eq!(rep, GAIN_USEFUL_VIEW_CHANGE);
+					}
+				);
+			}
+
+			// our bitfield reaches us through `peer_a` first and is relayed to `peer_b`
+			handle.send(network_event(
+				NetworkBridgeEvent::PeerMessage(peer_a.clone(), msg.encode_versioned()),
+			)).await;
+			assert_matches!(
+				timeout(handle.recv(), TIMEOUT).await,
+				Some(AllMessages::NetworkBridge(NetworkBridgeMessage::ReportPeer(_, rep))) => {
+					assert_eq!(rep, GAIN_VALID_MESSAGE_FIRST);
+				}
+			);
+			assert_matches!(
+				timeout(handle.recv(), TIMEOUT).await,
+				Some(AllMessages::Provisioner(_))
+			);
+			assert_matches!(
+				timeout(handle.recv(), TIMEOUT).await,
+				Some(AllMessages::NetworkBridge(NetworkBridgeMessage::SendMessage(peers, _, _))) => {
+					assert_eq!(peers, vec![peer_b.clone()]);
+				}
+			);
+
+			// distributing it ourselves neither notifies the provisioner again
+			// nor sends it to peers which have it already
+			let (ack_tx, ack_rx) = oneshot::channel();
+			handle.send(FromOverseer::Communication {
+				msg: BitfieldDistributionMessage::DistributeBitfield(hash_a, signed, Some(ack_tx)),
+			}).await;
+			assert_eq!(timeout(ack_rx, TIMEOUT).await, Some(Ok(Ok(()))));
+
+			// a later echo is a plain duplicate
+			handle.send(network_event(
+				NetworkBridgeEvent::PeerMessage(peer_b.clone(), msg.encode_versioned()),
+			)).await;
+			assert!(timeout(handle.recv(), TIMEOUT).await.is_none());
+
+			handle.send(FromOverseer::Signal(OverseerSignal::Conclude)).await;
+		};
+
+		executor::block_on(future::join(test_fut, subsystem));
+	}
+
+	#[test]
+	fn own_bitfield_echoed_before_update_is_superseded() {
+		let hash_a: Hash = [0; 32].into();
+		let peer_a = PeerId::random();
+		let peer_b = PeerId::random();
+		let peer_c = PeerId::random();
+
+		let signing_context = SigningContext {
+			session_index: 1,
+			parent_hash: hash_a,
+		};
+
+		let validator: ValidatorId = Sr25519Keyring::Alice.public().into();
+
+		let message = |bits: &[usize]| {
+			let mut payload = bitvec![bitvec::order::Lsb0, u8; 0u8; 32];
+			for bit in bits {
+				payload.set(*bit, true);
+			}
+			BitfieldGossipMessage {
+				relay_parent: hash_a,
+				signed_availability: Signed::<AvailabilityBitfield>::sign(
+					AvailabilityBitfield(payload),
+					&signing_context,
+					0,
+					&Sr25519Keyring::Alice.pair().into(),
+				),
+			}
+		};
+		let initial = message(&[0]);
+		let updated = message(&[0, 1]);
+
+		let pool = sp_core::testing::SpawnBlockingExecutor::new();
+		let (ctx, mut handle) =
+			make_subsystem_context::<BitfieldDistributionMessage, _>(pool);
+
+		let subsystem = BitfieldDistribution::new(Default::default()).start(ctx).future;
+
+		let test_fut = async move {
+			assert_matches!(
+				handle.recv().await,
+				AllMessages::NetworkBridge(NetworkBridgeMessage::RegisterEventProducer(..))
+			);
+
+			start_work(&mut handle, hash_a, vec![validator], signing_context.clone()).await;
+			handle.send(network_event(NetworkBridgeEvent::OurViewChange(view![hash_a]))).await;
+			for peer in vec![peer_a.clone(), peer_b.clone()] {
+				handle.send(network_event(
+					NetworkBridgeEvent::PeerConnected(peer.clone(), ObservedRole::Full),
+				)).await;
+				handle.send(network_event(
+					NetworkBridgeEvent::PeerViewChange(peer, view![hash_a]),
+				)).await;
+				assert_matches!(
+					timeout(handle.recv(), TIMEOUT).await,
+					Some(AllMessages::NetworkBridge(NetworkBridgeMessage::ReportPeer(_, rep))) => {
+						assert_eq!(rep, GAIN_USEFUL_VIEW_CHANGE);
+					}
+				);
+			}
+
+			// our earlier bitfield reaches us through `peer_a` and is relayed to `peer_b`
+			handle.send(network_event(
+				NetworkBridgeEvent::PeerMessage(peer_a.clone(), initial.encode_versioned()),
+			)).await;
+			assert_matches!(
+				timeout(handle.recv(), TIMEOUT).await,
+				Some(AllMessages::NetworkBridge(NetworkBridgeMessage::ReportPeer(_, rep))) => {
+					assert_eq!(rep, GAIN_VALID_MESSAGE_FIRST);
+				}
+			);
+			assert_matches!(
+				timeout(handle.recv(), TIMEOUT).await,
+				Some(AllMessages::Provisioner(_))
+			);
+			assert_matches!(
+				timeout(handle.recv(), TIMEOUT).await,
+				Some(AllMessages::NetworkBridge(NetworkBridgeMessage::SendMessage(peers, _, _))) => {
+					assert_eq!(peers, vec![peer_b.clone()]);
+				}
+			);
+			let (ack_tx, ack_rx) = oneshot::channel();
+			handle.send(FromOverseer::Communication {
+				msg: BitfieldDistributionMessage::DistributeBitfield(
+					hash_a,
+					initial.signed_availability.clone(),
+					Some(ack_tx),
+				),
+			}).await;
+			assert_eq!(timeout(ack_rx, TIMEOUT).await, Some(Ok(Ok(()))));
+
+			// the update reaches both peers, although they have the earlier one
+			let (ack_tx, ack_rx) = oneshot::channel();
+			handle.send(FromOverseer::Communication {
+				msg: BitfieldDistributionMessage::UpdateOwnBitfield(
+					hash_a,
+					updated.signed_availability.clone(),
+					Some(ack_tx),
+				),
+			}).await;
+			assert_matches!(
+				timeout(handle.recv(), TIMEOUT).await,
+				Some(AllMessages::Provisioner(ProvisionerMessage::ProvisionableData(
+					ProvisionableData::Bitfield(_, bitfield)
+				))) => {
+					assert_eq!(bitfield, updated.signed_availability);
+				}
+			);
+			assert_matches!(
+				timeout(handle.recv(), TIMEOUT).await,
+				Some(AllMessages::NetworkBridge(NetworkBridgeMessage::SendMessage(mut peers, _, bytes))) => {
+					peers.sort_by(|a, b| a.as_bytes().cmp(b.as_bytes()));
+					let mut expected = vec![peer_a.clone(), peer_b.clone()];
+					expected.sort_by(|a, b| a.as_bytes().cmp(b.as_bytes()));
+					assert_eq!(peers, expected);
+					assert_eq!(bytes, updated.encode_versioned());
+				}
+			);
+			assert_eq!(timeout(ack_rx, TIMEOUT).await, Some(Ok(Ok(()))));
+
+			// a peer relaying the update back is no equivocation
+			handle.send(network_event(
+				NetworkBridgeEvent::PeerMessage(peer_b.clone(), updated.encode_versioned()),
+			)).await;
+			assert!(timeout(handle.recv(), TIMEOUT).await.is_none());
+
+			// and a peer catching up gets the update, not the earlier one
+			handle.send(network_event(
+				NetworkBridgeEvent::PeerConnected(peer_c.clone(), ObservedRole::Full),
+			)).await;
+			handle.send(network_event(
+				NetworkBridgeEvent::PeerViewChange(peer_c.clone(), view![hash_a]),
+			)).await;
+			assert_matches!(
+				timeout(handle.recv(), TIMEOUT).await,
+				Some(AllMessages::NetworkBridge(NetworkBridgeMessage::ReportPeer(_, rep))) => {
+					assert_eq!(rep, GAIN_USEFUL_VIEW_CHANGE);
+				}
+			);
+			assert_matches!(
+				timeout(handle.recv(), TIMEOUT).await,
+				Some(AllMessages::NetworkBridge(NetworkBridgeMessage::SendMessage(peers, _, bytes))) => {
+					assert_eq!(peers, vec![peer_c.clone()]);
+					assert_eq!(try_decode_gossip(&bytes, usize::MAX, false), Ok(vec![updated.clone()]));
+				}
+			);
+			assert!(timeout(handle.recv(), TIMEOUT).await.is_none());
+
+			handle.send(FromOverseer::Signal(OverseerSignal::Conclude)).await;
+		};
+
+		executor::block_on(future::join(test_fut, subsystem));
+	}
+
+	#[test]
+	fn first_delivery_earns_higher_gain() {
+		let hash_a: Hash = [0; 32].into();
+		let peer_a = PeerId::random();
+		let peer_b = PeerId::random();
+
+		let signing_context = SigningContext {
+			session_index: 1,
+			parent_hash: hash_a,
+		};
+
+		let validator: ValidatorId = Sr25519Keyring::Alice.public().into();
+
+		let msg = BitfieldGossipMessage {
+			relay_parent: hash_a,
+			signed_availability: Signed::<AvailabilityBitfield>::sign(
+				AvailabilityBitfield(bitvec![bitvec::order::Lsb0, u8; 1u8; 32]),
+				&signing_context,
+				0,
+				&Sr25519Keyring::Alice.pair().into(),
+			),
+		};
+
+		let pool = sp_core::testing::SpawnBlockingExecutor::new();
+		let (mut ctx, mut handle) =
+			make_subsystem_context::<BitfieldDistributionMessage, _>(pool);
+
+		// no peer is interested, so it is not relayed to `peer_b`
+		let mut tracker = prewarmed_tracker(validator, signing_context, hash_a, peers![]);
+
+		executor::block_on(async move {
+			for peer in vec![peer_a.clone(), peer_b.clone(), peer_b.clone()] {
+				process_incoming_peer_message(
+					&mut ctx,
+					&mut tracker,
+					&Default::default(),
+					&Default::default(),
+					peer,
+					msg.clone(),
+					None,
+				)
+				.await
+				.unwrap();
+			}
+
+			assert_matches!(
+				handle.recv().await,
+				AllMessages::NetworkBridge(NetworkBridgeMessage::ReportPeer(peer, rep)) => {
+					assert_eq!(peer, peer_a);
+					assert_eq!(rep, GAIN_VALID_MESSAGE_FIRST);
+				}
+			);
+			assert_matches!(handle.recv().await, AllMessages::Provisioner(_));
+
+			// the same message again is still valid, but rewarded only once
+			assert_matches!(
+				handle.recv().await,
+				AllMessages::NetworkBridge(NetworkBridgeMessage::ReportPeer(peer, rep)) => {
+					assert_eq!(peer, peer_b);
+					assert_eq!(rep, GAIN_VALID_MESSAGE);
+				}
+			);
+			assert!(timeout(handle.recv(), TIMEOUT).await.is_none());
+		});
+	}
+
+	#[test]
+	fn own_bitfield_echoed_after_distribution_is_a_duplicate() {
+		let hash_a: Hash = [0; 32].into();
+		let peer_a = PeerId::random();
+
+		let signing_context = SigningContext {
+			session_index: 1,
+			parent_hash: hash_a,
+		};
+
+		let validator: ValidatorId = Sr25519Keyring::Alice.public().into();
+
+		let msg = BitfieldGossipMessage {
+			relay_parent: hash_a,
+			signed_availability: Signed::<AvailabilityBitfield>::sign(
+				AvailabilityBitfield(bitvec![bitvec::order::Lsb0, u8; 1u8; 32]),
+				&signing_context,
+				0,
+				&Sr25519Keyring::Alice.pair().into(),
+			),
+		};
+
+		let pool = sp_core::testing::SpawnBlockingExecutor::new();
+		let (mut ctx, mut handle) =
+			make_subsystem_context::<BitfieldDistributionMessage, _>(pool);
+
+		let mut tracker = prewarmed_tracker(validator.clone(), signing_context, hash_a, peers![peer_a]);
+		tracker.per_relay_parent.get_mut(&hash_a).unwrap().own_bitfield = Some((validator, msg.clone()));
+
+		executor::block_on(async move {
+			handle_network_msg(
+				&mut ctx,
+				&mut tracker,
+				&Default::default(),
+				&Default::default(),
+				NetworkBridgeEvent::PeerMessage(peer_a.clone(), msg.encode_versioned()),
+			)
+			.await
+			.unwrap();
+
+			// a valid copy, but neither relayed nor handed to the provisioner
+			assert_matches!(
+				handle.recv().await,
+				AllMessages::NetworkBridge(NetworkBridgeMessage::ReportPeer(peer, rep)) => {
+					assert_eq!(peer, peer_a);
+					assert_eq!(rep, GAIN_VALID_MESSAGE);
+				}
+			);
+			assert!(timeout(handle.recv(), TIMEOUT).await.is_none());
+		});
+	}
+
+	#[test]
+	fn distribute_for_unknown_relay_parent_is_reported() {
+		let hash_a: Hash = [0; 32].into();
+
+		let signing_context = SigningContext {
+			session_index: 1,
+			parent_hash: hash_a,
+		};
+
+		let signed = Signed::<AvailabilityBitfield>::sign(
+			AvailabilityBitfield(bitvec![bitvec::order::Lsb0, u8; 1u8; 32]),
+			&signing_context,
+			0,
+			&Sr25519Keyring::Alice.pair().into(),
+		);
+
+		let pool = sp_core::testing::SpawnBlockingExecutor::new();
+		let (ctx, mut handle) =
+			make_subsystem_context::<BitfieldDistributionMessage, _>(pool);
+
+		let subsystem = BitfieldDistribution::new(Default::default()).start(ctx).future;
+
+		let test_fut = async move {
+			assert_matches!(
+				handle.recv().await,
+				AllMessages::NetworkBridge(NetworkBridgeMessage::RegisterEventProducer(..))
+			);
+
+			// no `StartWork` for `hash_a`
+			let (ack_tx, ack_rx) = oneshot::channel();
+			handle.send(FromOverseer::Communication {
+				msg: BitfieldDistributionMessage::DistributeBitfield(hash_a, signed, Some(ack_tx)),
+			}).await;
+
+			assert_eq!(
+				timeout(ack_rx, TIMEOUT).await,
+				Some(Ok(Err(DistributeError::UnknownRelayParent))),
+			);
+
+			handle.send(FromOverseer::Signal(OverseerSignal::Conclude)).await;
+		};
+
+		executor::block_on(future::join(test_fut, subsystem));
+	}
+
+	#[test]
+	fn distribute_for_stopped_relay_parent_does_not_panic() {
+		let hash_a: Hash = [0; 32].into();
+
+		let signing_context = SigningContext {
+			session_index: 1,
+			parent_hash: hash_a,
+		};
+
+		let validator: ValidatorId = Sr25519Keyring::Alice.public().into();
+
+		let signed = Signed::<AvailabilityBitfield>::sign(
+			AvailabilityBitfield(bitvec![bitvec::order::Lsb0, u8; 1u8; 32]),
+			&signing_context,
+			0,
+			&Sr25519Keyring::Alice.pair().into(),
+		);
+
+		let pool = sp_core::testing::SpawnBlockingExecutor::new();
+		let (ctx, mut handle) =
+			make_subsystem_context::<BitfieldDistributionMessage, _>(pool);
+
+		let subsystem = BitfieldDistribution::new(Default::default()).start(ctx).future;
+
+		let test_fut = async move {
+			assert_matches!(
+				handle.recv().await,
+				AllMessages::NetworkBridge(NetworkBridgeMessage::RegisterEventProducer(..))
+			);
+
+			start_work(&mut handle, hash_a, vec![validator], signing_context).await;
+			handle.send(FromOverseer::Signal(OverseerSignal::StopWork(hash_a))).await;
+
+			// raced with `StopWork`, no one waits for an answer
+			handle.send(FromOverseer::Communication {
+				msg: BitfieldDistributionMessage::DistributeBitfield(hash_a, signed, None),
+			}).await;
+
+			// the subsystem is still alive and processes further messages
+			handle.send(FromOverseer::Signal(OverseerSignal::Conclude)).await;
+		};
+
+		executor::block_on(future::join(test_fut, subsystem));
+	}
+
+	#[test]
+	fn distribute_for_unknown_validator_index_is_reported() {
+		let hash_a: Hash = [0; 32].into();
+
+		let signing_context = SigningContext {
+			session_index: 1,
+			parent_hash: hash_a,
+		};
+
+		let validator: ValidatorId = Sr25519Keyring::Alice.public().into();
+
+		let signed = Signed::<AvailabilityBitfield>::sign(
+			AvailabilityBitfield(bitvec![bitvec::order::Lsb0, u8; 1u8; 32]),
+			&signing_context,
+			1,
+			&Sr25519Keyring::Alice.pair().into(),
+		);
+
+		let pool = sp_core::testing::SpawnBlockingExecutor::new();
+		let (ctx, mut handle) =
+			make_subsystem_context::<BitfieldDistributionMessage, _>(pool);
+
+		let subsystem = BitfieldDistribution::new(Default::default()).start(ctx).future;
+
+		let test_fut = async move {
+			assert_matches!(
+				handle.recv().await,
+				AllMessages::NetworkBridge(NetworkBridgeMessage::RegisterEventProducer(..))
+			);
+
+			start_work(&mut handle, hash_a, vec![validator], signing_context).await;
+
+			let (ack_tx, ack_rx) = oneshot::channel();
+			handle.send(FromOverseer::Communication {
+				msg: BitfieldDistributionMessage::DistributeBitfield(hash_a, signed, Some(ack_tx)),
+			}).await;
+
+			assert_eq!(
+				timeout(ack_rx, TIMEOUT).await,
+				Some(Ok(Err(DistributeError::InvalidValidatorIndex))),
+			);
+
+			handle.send(FromOverseer::Signal(OverseerSignal::Conclude)).await;
+		};
+
+		executor::block_on(future::join(test_fut, subsystem));
+	}
+
+	#[test]
+	fn protocol_id_is_configurable() {
+		let hash_a: Hash = [0; 32].into();
+		let peer_a = PeerId::random();
+
+		let signing_context = SigningContext {
+			session_index: 1,
+			parent_hash: hash_a,
+		};
+
+		let validator: ValidatorId = Sr25519Keyring::Alice.public().into();
+
+		let signed = Signed::<AvailabilityBitfield>::sign(
+			AvailabilityBitfield(bitvec![bitvec::order::Lsb0, u8; 1u8; 32]),
+			&signing_context,
+			0,
+			&Sr25519Keyring::Alice.pair().into(),
+		);
+
+		let custom: ProtocolId = *b"bit2";
+
+		let pool = sp_core::testing::SpawnBlockingExecutor::new();
+		let (default_ctx, mut default_handle) =
+			make_subsystem_context::<BitfieldDistributionMessage, _>(pool.clone());
+		let (custom_ctx, mut custom_handle) =
+			make_subsystem_context::<BitfieldDistributionMessage, _>(pool);
+
+		let default_subsystem = BitfieldDistribution::new(Default::default()).start(default_ctx).future;
+		let custom_subsystem = BitfieldDistribution::new(Default::default())
+			.with_config(Config { protocol_id: custom, ..Default::default() })
+			.start(custom_ctx)
+			.future;
+
+		let test_fut = async move {
+			assert_matches!(
+				default_handle.recv().await,
+				AllMessages::NetworkBridge(
+					NetworkBridgeMessage::RegisterEventProducer(protocol, _)
+				) => {
+					assert_eq!(protocol, BitfieldDistribution::DEFAULT_PROTOCOL_ID);
+				}
+			);
+			assert_matches!(
+				custom_handle.recv().await,
+				AllMessages::NetworkBridge(
+					NetworkBridgeMessage::RegisterEventProducer(protocol, _)
+				) => {
+					assert_eq!(protocol, custom);
+				}
+			);
+
+			// gossip is sent under the configured protocol, too
+			start_work(&mut custom_handle, hash_a, vec![validator], signing_context).await;
+			custom_handle.send(network_event(NetworkBridgeEvent::OurViewChange(view![hash_a]))).await;
+			custom_handle.send(network_event(
+				NetworkBridgeEvent::PeerConnected(peer_a.clone(), ObservedRole::Full),
+			)).await;
+			custom_handle.send(network_event(
+				NetworkBridgeEvent::PeerViewChange(peer_a.clone(), view![hash_a]),
+			)).await;
+			assert_matches!(
+				timeout(custom_handle.recv(), TIMEOUT).await,
+				Some(AllMessages::NetworkBridge(NetworkBridgeMessage::ReportPeer(..)))
+			);
+			custom_handle.send(FromOverseer::Communication {
+				msg: BitfieldDistributionMessage::DistributeBitfield(hash_a, signed, None),
+			}).await;
+			assert_matches!(
+				timeout(custom_handle.recv(), TIMEOUT).await,
+				Some(AllMessages::Provisioner(_))
+			);
+			assert_matches!(
+				timeout(custom_handle.recv(), TIMEOUT).await,
+				Some(AllMessages::NetworkBridge(
+					NetworkBridgeMessage::SendMessage(_, protocol, _)
+				)) => {
+					assert_eq!(protocol, custom);
+				}
+			);
+
+			default_handle.send(FromOverseer::Signal(OverseerSignal::Conclude)).await;
+			custom_handle.send(FromOverseer::Signal(OverseerSignal::Conclude)).await;
+		};
+
+		executor::block_on(future::join3(test_fut, default_subsystem, custom_subsystem));
+	}
+
+	#[test]
+	fn custom_reputation_config_is_applied() {
+		let hash_a: Hash = [0; 32].into();
+		let peer_a = PeerId::random();
+
+		let signing_context = SigningContext {
+			session_index: 1,
+			parent_hash: hash_a,
+		};
+
+		let validator: ValidatorId = Sr25519Keyring::Alice.public().into();
+
+		let msg = BitfieldGossipMessage {
+			relay_parent: hash_a,
+			signed_availability: Signed::<AvailabilityBitfield>::sign(
+				AvailabilityBitfield(bitvec![bitvec::order::Lsb0, u8; 1u8; 32]),
+				&signing_context,
+				0,
+				&Sr25519Keyring::Ferdie.pair().into(),
+			),
+		};
+
+		let custom_cost = ReputationChange::new(-7, "Custom signature cost");
+		let reputation = ReputationConfig {
+			cost_signature_invalid: custom_cost,
+			..Default::default()
+		};
+
+		let pool = sp_core::testing::SpawnBlockingExecutor::new();
+		let (ctx, mut handle) =
+			make_subsystem_context::<BitfieldDistributionMessage, _>(pool);
+
+		let subsystem = BitfieldDistribution::with_reputation_config(Default::default(), reputation)
+			.start(ctx)
+			.future;
+
+		let test_fut = async move {
+			assert_matches!(
+				handle.recv().await,
+				AllMessages::NetworkBridge(NetworkBridgeMessage::RegisterEventProducer(_, _))
+			);
+
+			start_work(&mut handle, hash_a, vec![validator], signing_context).await;
+
+			handle.send(network_event(NetworkBridgeEvent::OurViewChange(view![hash_a]))).await;
+			handle.send(network_event(
+				NetworkBridgeEvent::PeerMessage(peer_a.clone(), msg.encode_versioned()),
+			)).await;
+
+			assert_matches!(
+				timeout(handle.recv(), TIMEOUT).await,
+				Some(AllMessages::NetworkBridge(
+					NetworkBridgeMessage::ReportPeer(peer, rep)
+				)) => {
+					assert_eq!(peer, peer_a);
+					assert_eq!(rep, custom_cost);
+				}
+			);
+
+			handle.send(FromOverseer::Signal(OverseerSignal::Conclude)).await;
+		};
+
+		executor::block_on(future::join(test_fut, subsystem));
+	}
+}
@@ -28,7 +28,7 @@ use sc_network::{
 use sp_runtime::ConsensusEngineId;
 
 use polkadot_subsystem::{
-	FromOverseer, OverseerSignal, Subsystem, SubsystemContext, SpawnedSubsystem, SubsystemError,
+	ActiveLeavesUpdate, FromOverseer, OverseerSignal, Subsystem, SubsystemContext, SpawnedSubsystem, SubsystemError,
 	SubsystemResult,
 };
 use polkadot_subsystem::messages::{NetworkBridgeEvent, NetworkBridgeMessage, AllMessages};
@@ -205,6 +205,7 @@ enum Action {
 	ReportPeer(PeerId, ReputationChange),
 	StartWork(Hash),
 	StopWork(Hash),
+	ActiveLeaves(ActiveLeavesUpdate),
 
 	PeerConnected(PeerId, ObservedRole),
 	PeerDisconnected(PeerId),
@@ -221,6 +222,8 @@ fn action_from_overseer_message(
 			=> Action::StartWork(relay_parent),
 		Ok(FromOverseer::Signal(OverseerSignal::StopWork(relay_parent)))
 			=> Action::StopWork(relay_parent),
+		Ok(FromOverseer::Signal(OverseerSignal::ActiveLeaves(update)))
+			=> Action::ActiveLeaves(update),
 		Ok(FromOverseer::Signal(OverseerSignal::Conclude)) => Action::Abort,
 		Ok(FromOverseer::Communication { msg }) => match msg {
 			NetworkBridgeMessage::RegisterEventProducer(protocol_id, message_producer)
@@ -422,6 +425,23 @@ async fn run_network<N: Network>(
 					}
 				}
 			}
+			Action::ActiveLeaves(ActiveLeavesUpdate { activated, deactivated }) => {
+				// a single view update for the whole batch
+				live_heads.retain(|h| !deactivated.contains(h));
+				live_heads.extend(activated);
+				if let Some(view_update)
+					= update_view(&peers, &live_heads, &mut net, &mut local_view).await?
+				{
+					if let Err(e) = dispatch_update_to_all(
+						view_update,
+						event_producers.values(),
+						&mut ctx,
+					).await {
+						log::warn!("Aborting - Failure to dispatch messages to overseer");
+						return Err(e)
+					}
+				}
+			}
 
 			Action::PeerConnected(peer, role) => {
 				match peers.entry(peer.clone()) {
@@ -21,7 +21,7 @@
 
 use polkadot_primitives::v1::{Hash, PoV, CandidateDescriptor};
 use polkadot_subsystem::{
-	OverseerSignal, SubsystemContext, Subsystem, SubsystemResult, FromOverseer, SpawnedSubsystem,
+	ActiveLeavesUpdate, OverseerSignal, SubsystemContext, Subsystem, SubsystemResult, FromOverseer, SpawnedSubsystem,
 };
 use polkadot_subsystem::messages::{
 	PoVDistributionMessage, NetworkBridgeEvent, ReputationChange as Rep, PeerId,
@@ -108,28 +108,49 @@ async fn handle_signal(
 	match signal {
 		OverseerSignal::Conclude => Ok(true),
 		OverseerSignal::StartWork(relay_parent) => {
-			let (vals_tx, vals_rx) = oneshot::channel();
-			ctx.send_message(AllMessages::RuntimeApi(RuntimeApiMessage::Request(
-				relay_parent,
-				RuntimeApiRequest::Validators(vals_tx),
-			))).await?;
-
-			state.relay_parent_state.insert(relay_parent, BlockBasedState {
-				known: HashMap::new(),
-				fetching: HashMap::new(),
-				n_validators: vals_rx.await?.len(),
-			});
+			start_work(state, ctx, relay_parent).await?;
 
 			Ok(false)
 		}
 		OverseerSignal::StopWork(relay_parent) => {
 			state.relay_parent_state.remove(&relay_parent);
 
+			Ok(false)
+		}
+		OverseerSignal::ActiveLeaves(ActiveLeavesUpdate { activated, deactivated }) => {
+			for relay_parent in deactivated {
+				state.relay_parent_state.remove(&relay_parent);
+			}
+			for relay_parent in activated {
+				start_work(state, ctx, relay_parent).await?;
+			}
+
 			Ok(false)
 		}
 	}
 }
 
+/// Start tracking the PoVs of a relay parent.
+async fn start_work(
+	state: &mut State,
+	ctx: &mut impl SubsystemContext<Message = PoVDistributionMessage>,
+	relay_parent: Hash,
+) -> SubsystemResult<()> {
+	let (vals_tx, vals_rx) = oneshot::channel();
+	ctx.send_message(AllMessages::RuntimeApi(RuntimeApiMessage::Request(
+		relay_parent,
+		RuntimeApiRequest::Validators(vals_tx),
+	))).await?;
+
+	state.relay_parent_state.insert(relay_parent, BlockBasedState {
+		known: HashMap::new(),
+		fetching: HashMap::new(),
+		n_validators: vals_rx.await?.len(),
+	});
+
+	Ok(())
+}
+
 /// Notify peers that we are awaiting a given PoV hash.
 ///
 /// This only notifies peers who have the relay parent in their view.
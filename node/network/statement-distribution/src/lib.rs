@@ -824,6 +824,36 @@ async fn handle_network_update(
 
 }
 
+/// Query the validators and session of a relay parent to start working on it.
+async fn activate_head(
+	ctx: &mut impl SubsystemContext<Message = StatementDistributionMessage>,
+	active_heads: &mut HashMap<Hash, ActiveHeadData>,
+	relay_parent: Hash,
+) -> SubsystemResult<()> {
+	let (validators, session_index) = {
+		let (val_tx, val_rx) = oneshot::channel();
+		let (session_tx, session_rx) = oneshot::channel();
+
+		let val_message = AllMessages::RuntimeApi(
+			RuntimeApiMessage::Request(relay_parent, RuntimeApiRequest::Validators(val_tx)),
+		);
+		let session_message = AllMessages::RuntimeApi(
+			RuntimeApiMessage::Request(relay_parent, RuntimeApiRequest::SigningContext(session_tx)),
+		);
+
+		ctx.send_messages(
+			std::iter::once(val_message).chain(std::iter::once(session_message))
+		).await?;
+
+		(val_rx.await?, session_rx.await?.session_index)
+	};
+
+	active_heads.entry(relay_parent)
+		.or_insert(ActiveHeadData::new(validators, session_index));
+
+	Ok(())
+}
+
 async fn run(
 	mut ctx: impl SubsystemContext<Message = StatementDistributionMessage>,
 ) -> SubsystemResult<()> {
@@ -841,30 +871,17 @@ async fn run(
 		let message = ctx.recv().await?;
 		match message {
 			FromOverseer::Signal(OverseerSignal::StartWork(relay_parent)) => {
-				let (validators, session_index) = {
-					let (val_tx, val_rx) = oneshot::channel();
-					let (session_tx, session_rx) = oneshot::channel();
-
-					let val_message = AllMessages::RuntimeApi(
-						RuntimeApiMessage::Request(relay_parent, RuntimeApiRequest::Validators(val_tx)),
-					);
-					let session_message = AllMessages::RuntimeApi(
-						RuntimeApiMessage::Request(relay_parent, RuntimeApiRequest::SigningContext(session_tx)),
-					);
-
-					ctx.send_messages(
-						std::iter::once(val_message).chain(std::iter::once(session_message))
-					).await?;
-
-					(val_rx.await?, session_rx.await?.session_index)
-				};
-
-				active_heads.entry(relay_parent)
-					.or_insert(ActiveHeadData::new(validators, session_index));
+				activate_head(&mut ctx, &mut active_heads, relay_parent).await?;
 			}
 			FromOverseer::Signal(OverseerSignal::StopWork(_relay_parent)) => {
 				// do nothing - we will handle this when our view changes.
 			}
+			FromOverseer::Signal(OverseerSignal::ActiveLeaves(update)) => {
+				// the deactivated ones are handled when our view changes, as for `StopWork`.
+				for relay_parent in update.activated {
+					activate_head(&mut ctx, &mut active_heads, relay_parent).await?;
+				}
+			}
 			FromOverseer::Signal(OverseerSignal::Conclude) => break,
 			FromOverseer::Communication { msg } => match msg {
 				StatementDistributionMessage::Share(relay_parent, statement) =>
@@ -83,7 +83,7 @@ use polkadot_subsystem::messages::{
 };
 pub use polkadot_subsystem::{
 	Subsystem, SubsystemContext, OverseerSignal, FromOverseer, SubsystemError, SubsystemResult,
-	SpawnedSubsystem,
+	SpawnedSubsystem, ActiveLeavesUpdate,
 };
 use polkadot_node_primitives::SpawnNamed;
 
@@ -296,6 +296,14 @@ impl<M: Send + 'static> SubsystemContext for OverseerSubsystemContext<M> {
 		Ok(())
 	}
 
+	async fn try_send_message(&mut self, msg: AllMessages) -> SubsystemResult<bool> {
+		match self.tx.try_send(ToOverseer::SubsystemMessage(msg)) {
+			Ok(()) => Ok(true),
+			Err(e) if e.is_full() => Ok(false),
+			Err(e) => Err(e.into_send_error().into()),
+		}
+	}
+
 	async fn send_messages<T>(&mut self, msgs: T) -> SubsystemResult<()>
 		where T: IntoIterator<Item = AllMessages> + Send, T::IntoIter: Send
 	{
@@ -711,12 +719,15 @@ where
 	/// Run the `Overseer`.
 	pub async fn run(mut self) -> SubsystemResult<()> {
 		let leaves = std::mem::take(&mut self.leaves);
+		let mut update = ActiveLeavesUpdate::default();
 
 		for leaf in leaves.into_iter() {
-			self.broadcast_signal(OverseerSignal::StartWork(leaf.0)).await?;
+			update.activated.push(leaf.0);
 			self.active_leaves.insert(leaf);
 		}
 
+		self.broadcast_active_leaves(update).await?;
+
 		loop {
 			while let Poll::Ready(Some(msg)) = poll!(&mut self.events_rx.next()) {
 				match msg {
@@ -760,35 +771,42 @@ where
 	}
 
 	async fn block_imported(&mut self, block: BlockInfo) -> SubsystemResult<()> {
+		let mut update = ActiveLeavesUpdate::default();
+
 		if let Some(parent) = self.active_leaves.take(&(block.parent_hash, block.number - 1)) {
-			self.broadcast_signal(OverseerSignal::StopWork(parent.0)).await?;
+			update.deactivated.push(parent.0);
 		}
 
 		if !self.active_leaves.contains(&(block.hash, block.number)) {
-			self.broadcast_signal(OverseerSignal::StartWork(block.hash)).await?;
+			update.activated.push(block.hash);
 			self.active_leaves.insert((block.hash, block.number));
 		}
 
-		Ok(())
+		self.broadcast_active_leaves(update).await
 	}
 
 	async fn block_finalized(&mut self, block: BlockInfo) -> SubsystemResult<()> {
-		let mut stop_these = Vec::new();
+		let mut update = ActiveLeavesUpdate::default();
 
 		self.active_leaves.retain(|(h, n)| {
 			if *n <= block.number {
-				stop_these.push(*h);
+				update.deactivated.push(*h);
 				false
 			} else {
 				true
 			}
 		});
 
-		for hash in stop_these.into_iter() {
-			self.broadcast_signal(OverseerSignal::StopWork(hash)).await?
+		self.broadcast_active_leaves(update).await
+	}
+
+	// Leaves started and stopped by the same event are signalled at once, and not at all if there are none.
+	async fn broadcast_active_leaves(&mut self, update: ActiveLeavesUpdate) -> SubsystemResult<()> {
+		if update.activated.is_empty() && update.deactivated.is_empty() {
+			return Ok(());
 		}
 
-		Ok(())
+		self.broadcast_signal(OverseerSignal::ActiveLeaves(update)).await
 	}
 
 	async fn broadcast_signal(&mut self, signal: OverseerSignal) -> SubsystemResult<()> {
@@ -1035,6 +1053,23 @@ mod tests {
 		}
 	}
 
+	// Checks that a message is sent without waiting only while the overseer's queue has room.
+	#[test]
+	fn try_send_message_is_sent_while_the_overseer_queue_has_room() {
+		let (_to_tx, to_rx) = mpsc::channel::<FromOverseer<CandidateBackingMessage>>(0);
+		let (from_tx, mut from_rx) = mpsc::channel(0);
+		let mut ctx = OverseerSubsystemContext { rx: to_rx, tx: from_tx };
+		let msg = || AllMessages::BitfieldDistribution(BitfieldDistributionMessage::ForgetRelayParent(Default::default()));
+
+		executor::block_on(async move {
+			assert_eq!(ctx.try_send_message(msg()).await, Ok(true));
+			assert_eq!(ctx.try_send_message(msg()).await, Ok(false));
+
+			assert!(from_rx.next().await.is_some());
+			assert_eq!(ctx.try_send_message(msg()).await, Ok(true));
+		});
+	}
+
 	// Checks that a minimal configuration of two jobs can run and exchange messages.
 	#[test]
 	fn overseer_works() {
@@ -1193,7 +1228,7 @@ mod tests {
 	}
 
 	// Tests that starting with a defined set of leaves and receiving
-	// notifications on imported blocks triggers expected `StartWork` and `StopWork` heartbeats.
+	// notifications on imported blocks triggers expected `ActiveLeaves` heartbeats.
 	#[test]
 	fn overseer_start_stop_works() {
 		let spawner = sp_core::testing::SpawnBlockingExecutor::new();
@@ -1250,11 +1285,18 @@ mod tests {
 			handler.block_imported(third_block).await.unwrap();
 
 			let expected_heartbeats = vec![
-				OverseerSignal::StartWork(first_block_hash),
-				OverseerSignal::StopWork(first_block_hash),
-				OverseerSignal::StartWork(second_block_hash),
-				OverseerSignal::StopWork(second_block_hash),
-				OverseerSignal::StartWork(third_block_hash),
+				OverseerSignal::ActiveLeaves(ActiveLeavesUpdate {
+					activated: vec![first_block_hash],
+					deactivated: vec![],
+				}),
+				OverseerSignal::ActiveLeaves(ActiveLeavesUpdate {
+					activated: vec![second_block_hash],
+					deactivated: vec![first_block_hash],
+				}),
+				OverseerSignal::ActiveLeaves(ActiveLeavesUpdate {
+					activated: vec![third_block_hash],
+					deactivated: vec![second_block_hash],
+				}),
 			];
 
 			loop {
@@ -1288,7 +1330,7 @@ mod tests {
 	}
 
 	// Tests that starting with a defined set of leaves and receiving
-	// notifications on imported blocks triggers expected `StartWork` and `StopWork` heartbeats.
+	// notifications on finalized blocks triggers expected `ActiveLeaves` heartbeats.
 	#[test]
 	fn overseer_finalize_works() {
 		let spawner = sp_core::testing::SpawnBlockingExecutor::new();
@@ -1346,11 +1388,18 @@ mod tests {
 			// this should stop work on both forks we started with earlier.
 			handler.block_finalized(third_block).await.unwrap();
 
+			let mut both_blocks = vec![first_block_hash, second_block_hash];
+			both_blocks.sort();
+
 			let expected_heartbeats = vec![
-				OverseerSignal::StartWork(first_block_hash),
-				OverseerSignal::StartWork(second_block_hash),
-				OverseerSignal::StopWork(first_block_hash),
-				OverseerSignal::StopWork(second_block_hash),
+				OverseerSignal::ActiveLeaves(ActiveLeavesUpdate {
+					activated: both_blocks.clone(),
+					deactivated: vec![],
+				}),
+				OverseerSignal::ActiveLeaves(ActiveLeavesUpdate {
+					activated: vec![],
+					deactivated: both_blocks,
+				}),
 			];
 
 			loop {
@@ -1378,15 +1427,16 @@ mod tests {
 				}
 			}
 
-			assert_eq!(ss5_results.len(), expected_heartbeats.len());
-			assert_eq!(ss6_results.len(), expected_heartbeats.len());
-
-			// Notifications on finality for multiple blocks at once
-			// may be received in different orders.
-			for expected in expected_heartbeats {
-				assert!(ss5_results.contains(&expected));
-				assert!(ss6_results.contains(&expected));
+			// Leaves started or stopped at once may be listed in any order.
+			for signal in ss5_results.iter_mut().chain(ss6_results.iter_mut()) {
+				if let OverseerSignal::ActiveLeaves(update) = signal {
+					update.activated.sort();
+					update.deactivated.sort();
+				}
 			}
+
+			assert_eq!(ss5_results, expected_heartbeats);
+			assert_eq!(ss6_results, expected_heartbeats);
 		});
 	}
 }
@@ -38,6 +38,15 @@ pub mod util;
 #[cfg(any(test, feature = "test-helpers"))]
 pub mod test_helpers;
 
+/// The relay-chain blocks an overseer starts and stops working on at once.
+#[derive(PartialEq, Clone, Debug, Default)]
+pub struct ActiveLeavesUpdate {
+	/// Relay-chain block hashes to start working on.
+	pub activated: Vec<Hash>,
+	/// Relay-chain block hashes to stop working on.
+	pub deactivated: Vec<Hash>,
+}
+
 /// Signals sent by an overseer to a subsystem.
 #[derive(PartialEq, Clone, Debug)]
 pub enum OverseerSignal {
@@ -45,6 +54,11 @@ pub enum OverseerSignal {
 	StartWork(Hash),
 	/// `Subsystem` should stop working on block-based work specified by the relay-chain block hash.
 	StopWork(Hash),
+	/// `Subsystem` should stop working on all of the deactivated blocks and then start working
+	/// on all of the activated ones, as a single step.
+	///
+	/// This is what the overseer sends on block import and finalization.
+	ActiveLeaves(ActiveLeavesUpdate),
 	/// Conclude the work of the `Overseer` and all `Subsystem`s.
 	Conclude,
 }
@@ -142,6 +156,19 @@ pub trait SubsystemContext: Send + 'static {
 	/// Send a direct message to some other `Subsystem`, routed based on message type.
 	async fn send_message(&mut self, msg: AllMessages) -> SubsystemResult<()>;
 
+	/// Send a direct message to some other `Subsystem` unless the queue to the [`Overseer`]
+	/// is full, returning whether it was sent. Contexts without such a queue send and wait.
+	///
+	/// This only tells about room in the queue to the [`Overseer`], not in the queue of the
+	/// receiving `Subsystem`: a lagging receiver fills it only once the overseer is stuck
+	/// routing messages to it.
+	///
+	/// [`Overseer`]: struct.Overseer.html
+	async fn try_send_message(&mut self, msg: AllMessages) -> SubsystemResult<bool> {
+		self.send_message(msg).await?;
+		Ok(true)
+	}
+
 	/// Send multiple direct messages to other `Subsystem`s, routed based on message type.
 	async fn send_messages<T>(&mut self, msgs: T) -> SubsystemResult<()>
 		where T: IntoIterator<Item = AllMessages> + Send, T::IntoIter: Send;
@@ -23,6 +23,7 @@
 //! Subsystems' APIs are defined separately from their implementation, leading to easier mocking.
 
 use futures::channel::{mpsc, oneshot};
+use parity_scale_codec::{Decode, Encode};
 
 use polkadot_primitives::v1::{
 	BlockNumber, Hash,
@@ -37,6 +38,9 @@ use polkadot_node_primitives::{
 
 use std::sync::Arc;
 
+#[cfg(feature = "serde")]
+use serde::{Serialize, Deserialize};
+
 pub use sc_network::{ObservedRole, ReputationChange, PeerId};
 
 /// A notification of a new backed candidate.
@@ -200,22 +204,182 @@ impl AvailabilityDistributionMessage {
 	}
 }
 
+/// Errors the bitfield distribution subsystem reports back
+/// to the sender of a `DistributeBitfield` or `UpdateOwnBitfield` message.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DistributeError {
+	/// The subsystem is not working on the given relay parent.
+	UnknownRelayParent,
+	/// The bitfield is signed with an index outside of the validator set of the relay parent.
+	InvalidValidatorIndex,
+	/// The bitfield lacks some of the bits of the one distributed before,
+	/// so it can not supersede it.
+	NotSuperseding,
+	/// A different bitfield was distributed before, which only `UpdateOwnBitfield` can supersede.
+	AlreadyDistributed,
+}
+
 /// Bitfield distribution message.
 #[derive(Debug)]
 pub enum BitfieldDistributionMessage {
 	/// Distribute a bitfield via gossip to other validators.
-	DistributeBitfield(Hash, SignedAvailabilityBitfield),
+	///
+	/// If a sender is given, it is resolved once the bitfield was relayed.
+	DistributeBitfield(
+		Hash,
+		SignedAvailabilityBitfield,
+		Option<oneshot::Sender<Result<(), DistributeError>>>,
+	),
+
+	/// Supersede the bitfield we distributed for a relay parent with one
+	/// having all of its bits and some more, and distribute that instead.
+	///
+	/// If a sender is given, it is resolved once the bitfield was relayed.
+	UpdateOwnBitfield(
+		Hash,
+		SignedAvailabilityBitfield,
+		Option<oneshot::Sender<Result<(), DistributeError>>>,
+	),
 
 	/// Event from the network bridge.
 	NetworkBridgeUpdate(NetworkBridgeEvent),
+
+	/// Get the validators we have no bitfield of yet for a relay parent,
+	/// or `None` if the relay parent is not worked on.
+	QueryCoverage(Hash, oneshot::Sender<Option<CoverageReport>>),
+
+	/// Get the view we believe a peer holds, or `None` if the peer is not connected.
+	QueryPeerView(PeerId, oneshot::Sender<Option<View>>),
+
+	/// Get all the bitfields we know of for a relay parent, including our own,
+	/// ordered by validator index.
+	CollectBitfields(Hash, oneshot::Sender<Vec<SignedAvailabilityBitfield>>),
+
+	/// Get whether we know a bitfield of the validator with the given index for a relay parent,
+	/// including our own. Spares collecting all of them for checking a single one.
+	HasBitfield(Hash, ValidatorIndex, oneshot::Sender<bool>),
+
+	/// Get where the bitfields we know of for a relay parent came from, ordered by validator index,
+	/// or `None` if the relay parent is not worked on.
+	QueryBitfieldSources(Hash, oneshot::Sender<Option<Vec<(ValidatorIndex, BitfieldSource)>>>),
+
+	/// Get how many availability cores are reported available by any of the bitfields
+	/// of a relay parent, or `None` if the relay parent is not worked on.
+	QueryAvailabilitySummary(Hash, oneshot::Sender<Option<AvailabilitySummary>>),
+
+	/// Get a summary of the state of the subsystem.
+	Status(oneshot::Sender<StatusReport>),
+
+	/// Remember the bitfields of a snapshot, e.g. taken before a restart of the subsystem,
+	/// for the relay parents in our view we work on. They are checked as if received from a peer.
+	ImportSnapshot(Vec<(Hash, Vec<SignedAvailabilityBitfield>)>),
+
+	/// Be notified of every bitfield handed to the provisioner from now on,
+	/// until the receiver is dropped.
+	Subscribe(mpsc::Sender<(Hash, SignedAvailabilityBitfield)>),
+
+	/// Drop all we know about a relay parent ahead of `StopWork`, e.g. once it is finalized,
+	/// and treat any further bitfield of it as not interesting.
+	ForgetRelayParent(Hash),
+
+	/// Check a bitfield of a relay parent as if received from a peer, without relaying or
+	/// remembering it nor reporting anyone, and tell what would be made of it.
+	ValidateOnly(Hash, SignedAvailabilityBitfield, oneshot::Sender<ValidationVerdict>),
+}
+
+/// The validators the bitfield distribution subsystem has seen bitfields of for a relay parent.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct CoverageReport {
+	/// The number of validators at the relay parent.
+	pub validators: usize,
+	/// The indices of the validators we have no bitfield of yet.
+	pub missing: Vec<ValidatorIndex>,
+}
+
+/// The availability of the cores of a relay parent, aggregated over all bitfields we know of.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct AvailabilitySummary {
+	/// The number of availability cores, the number of bits of every bitfield.
+	pub cores: usize,
+	/// The number of cores at least one of the bitfields has the bit of set.
+	pub available_cores: usize,
+	/// The number of validators we have a bitfield of, including ourselves.
+	pub validators: usize,
+}
+
+/// Where the bitfield distribution subsystem got a bitfield from.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum BitfieldSource {
+	/// Our own bitfield.
+	Local,
+	/// The peer which delivered the bitfield first.
+	Peer(PeerId),
+	/// An imported snapshot.
+	Snapshot,
+}
+
+/// What the bitfield distribution subsystem makes of a bitfield received from a peer.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ValidationVerdict {
+	/// A new valid bitfield, which is accepted and relayed.
+	Accepted,
+	/// A valid bitfield which is no news, as it is known already, superseded by
+	/// the known one, or was relayed by us recently.
+	Known,
+	/// The relay parent is not in our view, or was forgotten or stopped recently.
+	NotInterested,
+	/// The relay parent is in our view, but we were not told to work on it yet.
+	NotWorkedOn,
+	/// The signing context known for the relay parent is of another one.
+	ContextMismatch,
+	/// The validator index is not in the validator set of the relay parent.
+	InvalidIndex,
+	/// The signature is invalid.
+	InvalidSignature,
+	/// The signature is valid for the previous session only.
+	WrongSession,
+	/// The validator signed another bitfield, neither superseding the other, or did so before.
+	Equivocation,
+	/// The number of bits differs from the one of the other bitfields of the relay parent.
+	InconsistentLength,
+	/// A bitfield of every validator of the set is known already, so the set is stale.
+	ValidatorSetMismatch,
+}
+
+/// A summary of the state of the bitfield distribution subsystem.
+#[derive(Debug, Clone, PartialEq, Eq, Encode, Decode)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct StatusReport {
+	/// The number of relay parents worked on.
+	pub relay_parents: u32,
+	/// The number of connected peers.
+	pub peers: u32,
+	/// The number of bitfields known for all relay parents, including our own.
+	pub bitfields: u32,
+	/// Whether relays or catch-ups are pending, to be sent in the following iterations.
+	pub processing: bool,
 }
 
 impl BitfieldDistributionMessage {
 	/// If the current variant contains the relay parent hash, return it.
 	pub fn relay_parent(&self) -> Option<Hash> {
 		match self {
-			Self::DistributeBitfield(hash, _) => Some(*hash),
+			Self::DistributeBitfield(hash, _, _) => Some(*hash),
+			Self::UpdateOwnBitfield(hash, _, _) => Some(*hash),
 			Self::NetworkBridgeUpdate(_) => None,
+			Self::QueryCoverage(hash, _) => Some(*hash),
+			Self::QueryPeerView(_, _) => None,
+			Self::CollectBitfields(hash, _) => Some(*hash),
+			Self::HasBitfield(hash, _, _) => Some(*hash),
+			Self::QueryBitfieldSources(hash, _) => Some(*hash),
+			Self::QueryAvailabilitySummary(hash, _) => Some(*hash),
+			Self::Status(_) => None,
+			Self::ImportSnapshot(_) => None,
+			Self::Subscribe(_) => None,
+			Self::ForgetRelayParent(hash) => Some(*hash),
+			Self::ValidateOnly(hash, _, _) => Some(*hash),
 		}
 	}
 }
@@ -344,6 +508,9 @@ pub enum ProvisionerMessage {
 	RequestInherentData(Hash, oneshot::Sender<ProvisionerInherentData>),
 	/// This data should become part of a relay chain block
 	ProvisionableData(ProvisionableData),
+	/// Bitfields of more than 2/3 of the validators of the relay parent were provided,
+	/// sent once per relay parent right after the bitfield completing the quorum.
+	AvailabilityQuorum(Hash),
 }
 
 impl ProvisionerMessage {
@@ -353,6 +520,7 @@ impl ProvisionerMessage {
 			Self::RequestBlockAuthorshipData(hash, _) => Some(*hash),
 			Self::RequestInherentData(hash, _) => Some(*hash),
 			Self::ProvisionableData(_) => None,
+			Self::AvailabilityQuorum(hash) => Some(*hash),
 		}
 	}
 }
@@ -415,3 +583,24 @@ pub enum AllMessages {
 	#[cfg(test)]
 	Test(String),
 }
+
+#[cfg(all(test, feature = "serde"))]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn status_report_round_trips_through_json() {
+		let report = StatusReport {
+			relay_parents: 2,
+			peers: 5,
+			bitfields: 7,
+			processing: true,
+		};
+
+		let json = serde_json::to_string(&report).unwrap();
+		assert_eq!(json, r#"{"relay_parents":2,"peers":5,"bitfields":7,"processing":true}"#);
+		assert_eq!(serde_json::from_str::<StatusReport>(&json).unwrap(), report);
+		// the SCALE encoding is not affected
+		assert_eq!(StatusReport::decode(&mut &report.encode()[..]), Ok(report));
+	}
+}
@@ -147,11 +147,41 @@ pub fn single_item_sink<T>() -> (SingleItemSink<T>, SingleItemStream<T>) {
 	)
 }
 
+/// Whether a subsystem waits for its next message, and who waits for it to.
+#[derive(Default)]
+struct IdleState {
+	idle: bool,
+	waker: Option<Waker>,
+}
+
+/// Marks a subsystem as idle for as long as it waits for its next message.
+struct IdleGuard(Arc<Mutex<IdleState>>);
+
+impl IdleGuard {
+	fn new(state: Arc<Mutex<IdleState>>) -> Self {
+		{
+			let mut state = state.lock();
+			state.idle = true;
+			if let Some(waker) = state.waker.take() {
+				waker.wake();
+			}
+		}
+		IdleGuard(state)
+	}
+}
+
+impl Drop for IdleGuard {
+	fn drop(&mut self) {
+		self.0.lock().idle = false;
+	}
+}
+
 /// A test subsystem context.
 pub struct TestSubsystemContext<M, S> {
 	tx: mpsc::UnboundedSender<AllMessages>,
 	rx: SingleItemStream<FromOverseer<M>>,
 	spawn: S,
+	idle: Arc<Mutex<IdleState>>,
 }
 
 #[async_trait::async_trait]
@@ -167,6 +197,7 @@ impl<M: Send + 'static, S: SpawnNamed + Send + 'static> SubsystemContext for Tes
 	}
 
 	async fn recv(&mut self) -> SubsystemResult<FromOverseer<M>> {
+		let _idle = IdleGuard::new(self.idle.clone());
 		self.rx.next().await.ok_or(SubsystemError)
 	}
 
@@ -196,6 +227,7 @@ impl<M: Send + 'static, S: SpawnNamed + Send + 'static> SubsystemContext for Tes
 pub struct TestSubsystemContextHandle<M> {
 	tx: SingleItemSink<FromOverseer<M>>,
 	rx: mpsc::UnboundedReceiver<AllMessages>,
+	idle: Arc<Mutex<IdleState>>,
 }
 
 impl<M> TestSubsystemContextHandle<M> {
@@ -209,6 +241,25 @@ impl<M> TestSubsystemContextHandle<M> {
 	pub async fn recv(&mut self) -> AllMessages {
 		self.rx.next().await.expect("Test subsystem no longer live")
 	}
+
+	/// Receive the next message from the subsystem, if it has sent one already.
+	pub fn try_recv(&mut self) -> Option<AllMessages> {
+		self.rx.try_next().ok().flatten()
+	}
+
+	/// Wait until the subsystem waits for its next message, i.e. it handled all messages
+	/// sent to it so far and has nothing else to do right away.
+	pub async fn await_idle(&mut self) {
+		future::poll_fn(|cx| {
+			let mut state = self.idle.lock();
+			if state.idle {
+				Poll::Ready(())
+			} else {
+				state.waker = Some(cx.waker().clone());
+				Poll::Pending
+			}
+		}).await
+	}
 }
 
 /// Make a test subsystem context.
@@ -217,16 +268,19 @@ pub fn make_subsystem_context<M, S>(spawn: S)
 {
 	let (overseer_tx, overseer_rx) = single_item_sink();
 	let (all_messages_tx, all_messages_rx) = mpsc::unbounded();
+	let idle = Arc::new(Mutex::new(IdleState::default()));
 
 	(
 		TestSubsystemContext {
 			tx: all_messages_tx,
 			rx: overseer_rx,
 			spawn,
+			idle: idle.clone(),
 		},
 		TestSubsystemContextHandle {
 			tx: overseer_tx,
-			rx: all_messages_rx
+			rx: all_messages_rx,
+			idle,
 		},
 	)
 }
@@ -602,7 +602,7 @@ where
 		err_tx: &mut Option<mpsc::Sender<(Option<Hash>, JobsError<Job::Error>)>>
 	) -> bool {
 		use crate::FromOverseer::{Communication, Signal};
-		use crate::OverseerSignal::{Conclude, StartWork, StopWork};
+		use crate::OverseerSignal::{ActiveLeaves, Conclude, StartWork, StopWork};
 
 		match incoming {
 			Ok(Signal(StartWork(hash))) => {
@@ -619,6 +619,22 @@ where
 					return true;
 				}
 			}
+			Ok(Signal(ActiveLeaves(update))) => {
+				for hash in update.deactivated {
+					if let Err(e) = jobs.stop_job(hash).await {
+						log::error!("Failed to stop a job: {:?}", e);
+						Self::fwd_err(Some(hash), e.into(), err_tx).await;
+						return true;
+					}
+				}
+				for hash in update.activated {
+					if let Err(e) = jobs.spawn_job(hash, run_args.clone()) {
+						log::error!("Failed to spawn a job: {:?}", e);
+						Self::fwd_err(Some(hash), e.into(), err_tx).await;
+						return true;
+					}
+				}
+			}
 			Ok(Signal(Conclude)) => {
 				// Breaking the loop ends fn run, which drops `jobs`, which immediately drops all ongoing work.
 				// We can afford to wait a little while to shut them all down properly before doing that.
@@ -291,6 +291,13 @@ impl From<BitVec<bitvec::order::Lsb0, u8>> for AvailabilityBitfield {
 	}
 }
 
+impl AvailabilityBitfield {
+	/// The number of backed candidates this bitfield claims availability of.
+	pub fn count_ones(&self) -> usize {
+		self.0.count_ones()
+	}
+}
+
 /// A bitfield signed by a particular validator about the availability of pending candidates.
 pub type SignedAvailabilityBitfield = Signed<AvailabilityBitfield>;
 